@@ -0,0 +1,94 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Allocations made by the server to answer batches of operations, to compare
+//! the builds with and without the `arena` feature.
+//!
+//! Run it with `cargo bench --bench batches [--features arena]`. The server
+//! logs every batch to the standard output, and the result goes to the
+//! standard error.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    net::SocketAddr,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use tcp1::{AnswerBatch, Client, Operation, Server, ServerConfig};
+
+/// Batches sent, after a first one to warm up.
+const BATCHES: usize = 1000;
+
+thread_local! {
+    /// Whether the allocations of the thread are counted, only in that of the server
+    static COUNTED: Cell<bool> = const { Cell::new(false) };
+}
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator, counting the allocations of the server.
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if COUNTED.try_with(Cell::get).unwrap_or_default() {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn main() {
+    let mut server = Server::bind(ServerConfig::default()).unwrap();
+    let port = server.local_addr().unwrap().port();
+    thread::spawn(move || {
+        COUNTED.set(true);
+        server.run()
+    });
+
+    let mut client = Client::connect(SocketAddr::from(([127, 0, 0, 1], port)), None).unwrap();
+    let batch: Vec<Operation> = (0..AnswerBatch::MAX_RESULTS)
+        .map(|_| "1 + 1".parse().unwrap())
+        .collect();
+    client.send_batch(&batch).unwrap();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..BATCHES {
+        client.send_batch(&batch).unwrap();
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    client.close().unwrap();
+
+    eprintln!(
+        "{:.1} allocations per batch of {} operations",
+        allocations as f64 / BATCHES as f64,
+        batch.len()
+    );
+}
@@ -0,0 +1,101 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Throughput of the decoding of a stream of small frames: by the [`Decoder`],
+//! handing them to another thread as they are decoded, as the asynchronous
+//! client does, and by the [`TlvIterator`] on the whole buffer, building a
+//! [`tcp1::Tlv`] for every frame or not.
+//!
+//! Run it with `cargo bench --bench decode`.
+
+use std::{
+    hint::black_box,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use tcp1::{Decoder, Frame, Operation, TlvIterator};
+
+/// Frames in the stream.
+const FRAMES: usize = 2_000_000;
+
+/// Size of the reads from the socket that the stream is split into.
+const READ: usize = 4096;
+
+fn decoder(stream: &[u8]) -> usize {
+    let (frames, received) = mpsc::sync_channel(1024);
+    let consumer =
+        thread::spawn(move || received.iter().map(|frame: Frame| frame.data.len()).sum());
+    let mut decoder = Decoder::new();
+    for chunk in stream.chunks(READ) {
+        decoder.extend(chunk);
+        while let Ok(Some(frame)) = decoder.next_frame() {
+            frames.send(frame).unwrap();
+        }
+    }
+    drop(frames);
+
+    consumer.join().unwrap()
+}
+
+fn iterator(stream: &[u8]) -> usize {
+    TlvIterator::process(stream).map(|tlv| tlv.data.len()).sum()
+}
+
+fn raw(stream: &[u8]) -> usize {
+    (TlvIterator::process(stream).raw())
+        .map(|(_, data)| data.len())
+        .sum()
+}
+
+/// Best time of several runs of `decode`, that returns the bytes of data decoded.
+fn best(stream: &[u8], decode: fn(&[u8]) -> usize) -> Duration {
+    (0..5)
+        .map(|_| {
+            let start = Instant::now();
+            assert_eq!(black_box(decode(black_box(stream))), 2 * FRAMES);
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let operation: Operation = "3 + 4".parse().unwrap();
+    let frame = operation.encode();
+    let stream: Vec<u8> = (frame.iter().copied().cycle())
+        .take(frame.len() * FRAMES)
+        .collect();
+
+    for (name, decode) in [
+        ("Decoder", decoder as fn(&[u8]) -> usize),
+        ("TlvIterator", iterator),
+        ("TlvIterator::raw", raw),
+    ] {
+        let time = best(&stream, decode);
+        println!(
+            "{name}: {FRAMES} frames in {time:.2?}, {:.1} million per second",
+            FRAMES as f64 / time.as_secs_f64() / 1e6
+        );
+    }
+}
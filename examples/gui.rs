@@ -0,0 +1,270 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Windowed client of the calculator, built on the library [`Client`], as an
+//! example of how to integrate it in an application with a graphical interface.
+//!
+//! Run it with `cargo run --example gui --features gui`. The connection lives
+//! in a thread of its own, that takes the requests of the interface and hands
+//! back what happened through channels, so the window keeps responding while
+//! it waits for the server, for at most [`TIMEOUT`] each time.
+
+use std::{
+    net::ToSocketAddrs,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::Duration,
+};
+
+use eframe::egui;
+use tcp1::{Client, ClientError, Operation};
+
+/// Longest wait for an answer.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the interface asks the thread of the connection.
+enum Request {
+    /// The operation as typed, and parsed
+    Compute(String, Operation),
+    Close,
+}
+
+/// What the thread of the connection tells the interface.
+enum Event {
+    Connected(String),
+    /// The operation as typed, and its answer or rejection
+    Answered(String, String),
+    /// The connection ended, with the reason
+    Closed(String),
+}
+
+struct Connection {
+    requests: Sender<Request>,
+    events: Receiver<Event>,
+    /// Until the connection is established, and while an operation is sent
+    busy: bool,
+}
+
+struct App {
+    /// As typed, such as localhost:7777
+    server: String,
+    connection: Option<Connection>,
+    operation: String,
+    /// Operations sent and their outcome, the most recent last
+    history: Vec<String>,
+    /// The last error, shown until the next action
+    status: String,
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self {
+            server: "localhost:7777".to_string(),
+            connection: None,
+            operation: String::new(),
+            history: Vec::new(),
+            status: "Disconnected".to_string(),
+        }
+    }
+}
+
+/// Connects to `server` and sends it the requests until told to close, or
+/// the connection fails, waking up the interface after every event.
+fn serve(server: String, requests: Receiver<Request>, events: Sender<Event>, ctx: egui::Context) {
+    let send = |event| {
+        // Nobody listens once the window closed
+        let _ = events.send(event);
+        ctx.request_repaint();
+    };
+    let mut client = match connect(&server) {
+        Ok(client) => client,
+        Err(e) => return send(Event::Closed(e)),
+    };
+    send(Event::Connected(format!("Connected to {server}")));
+
+    for request in requests {
+        let (line, operation) = match request {
+            Request::Compute(line, operation) => (line, operation),
+            Request::Close => break,
+        };
+        match client.compute(operation) {
+            Ok(answer) => send(Event::Answered(line, answer.to_string())),
+            Err(ClientError::Rejected(rejection)) => {
+                send(Event::Answered(line, rejection.to_string()))
+            }
+            // The connection is no longer usable
+            Err(e) => {
+                return send(Event::Closed(format!(
+                    "Lost the connection to the server. {e}"
+                )))
+            }
+        }
+    }
+    send(Event::Closed(match client.close() {
+        Ok(()) => "Disconnected".to_string(),
+        Err(e) => format!("Disconnected without a goodbye. {e}"),
+    }));
+}
+
+fn connect(server: &str) -> Result<Client, String> {
+    let address = match server.to_socket_addrs().map(|mut addrs| addrs.next()) {
+        Ok(Some(address)) => address,
+        Ok(None) => return Err(format!("{server} has no address")),
+        Err(e) => return Err(format!("Could not resolve {server}. {e}")),
+    };
+    Client::connect_timeout(address, None, TIMEOUT)
+        .and_then(|mut client| {
+            client.set_timeout(Some(TIMEOUT))?;
+            Ok(client)
+        })
+        .map_err(|e| format!("Could not connect to the server. {e}"))
+}
+
+impl App {
+    fn connect(&mut self, ctx: &egui::Context) {
+        let (requests, received) = mpsc::channel();
+        let (sent, events) = mpsc::channel();
+        let server = self.server.clone();
+        let ctx = ctx.clone();
+        thread::spawn(move || serve(server, received, sent, ctx));
+        self.connection = Some(Connection {
+            requests,
+            events,
+            busy: true,
+        });
+        self.status = format!("Connecting to {}", self.server);
+    }
+
+    fn disconnect(&mut self) {
+        if let Some(connection) = &mut self.connection {
+            // Answered once the operation being sent, if any, is
+            let _ = connection.requests.send(Request::Close);
+            connection.busy = true;
+        }
+    }
+
+    fn send(&mut self) {
+        let Some(connection) = &mut self.connection else {
+            return;
+        };
+        let line = self.operation.trim().to_string();
+        let operation: Operation = match line.parse() {
+            Ok(operation) => operation,
+            Err(e) => {
+                self.status = format!("Could not parse the operation. {e}");
+                return;
+            }
+        };
+
+        if connection
+            .requests
+            .send(Request::Compute(line, operation))
+            .is_ok()
+        {
+            connection.busy = true;
+            self.operation.clear();
+            self.status = "Waiting for the answer".to_string();
+        }
+    }
+
+    /// Takes in what the thread of the connection did since the last frame.
+    fn receive(&mut self) {
+        let Some(connection) = &mut self.connection else {
+            return;
+        };
+        while let Ok(event) = connection.events.try_recv() {
+            match event {
+                Event::Connected(status) => {
+                    connection.busy = false;
+                    self.status = status;
+                }
+                Event::Answered(line, outcome) => {
+                    connection.busy = false;
+                    self.history.push(format!("{line}  →  {outcome}"));
+                    self.status.clear();
+                }
+                Event::Closed(status) => {
+                    self.connection = None;
+                    self.status = status;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.receive();
+        let connected = self.connection.is_some();
+        let ready = self
+            .connection
+            .as_ref()
+            .is_some_and(|connection| !connection.busy);
+        egui::TopBottomPanel::top("server").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Server:");
+                ui.add_enabled(!connected, egui::TextEdit::singleline(&mut self.server));
+                match connected {
+                    false if ui.button("Connect").clicked() => self.connect(ctx),
+                    true if ui
+                        .add_enabled(ready, egui::Button::new("Disconnect"))
+                        .clicked() =>
+                    {
+                        self.disconnect()
+                    }
+                    _ => (),
+                }
+            });
+        });
+        egui::TopBottomPanel::bottom("status").show(ctx, |ui| ui.label(&self.status));
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let entry = ui.add_enabled(
+                    ready,
+                    egui::TextEdit::singleline(&mut self.operation).hint_text("3 + 4"),
+                );
+                let entered = entry.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                if ui.add_enabled(ready, egui::Button::new("Send")).clicked() || entered {
+                    self.send();
+                    entry.request_focus();
+                }
+            });
+            ui.separator();
+            egui::ScrollArea::vertical()
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    for line in &self.history {
+                        ui.monospace(line);
+                    }
+                });
+        });
+    }
+}
+
+fn main() -> eframe::Result {
+    eframe::run_native(
+        "Remote TCP calculator",
+        eframe::NativeOptions::default(),
+        Box::new(|_| Ok(Box::<App>::default())),
+    )
+}
@@ -0,0 +1,80 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Scratch memory of a connection, for what the server builds while answering
+//! the frames of a read and drops right after.
+
+/// With the `arena` feature, a bump arena reset after every read, so that those
+/// allocations cost a pointer bump and are freed at once. Without it, they come
+/// from the global allocator as usual.
+#[derive(Debug, Default)]
+pub(crate) struct Scratch {
+    #[cfg(feature = "arena")]
+    bump: bumpalo::Bump,
+}
+
+#[cfg(feature = "arena")]
+pub(crate) type ScratchVec<'a, T> = bumpalo::collections::Vec<'a, T>;
+#[cfg(not(feature = "arena"))]
+pub(crate) type ScratchVec<'a, T> = std::vec::Vec<T>;
+
+impl Scratch {
+    #[cfg(feature = "arena")]
+    pub fn vec<T>(&self) -> ScratchVec<'_, T> {
+        bumpalo::collections::Vec::new_in(&self.bump)
+    }
+
+    #[cfg(not(feature = "arena"))]
+    pub fn vec<T>(&self) -> ScratchVec<'_, T> {
+        Vec::new()
+    }
+
+    /// Frees everything allocated since the last reset, keeping the memory.
+    pub fn reset(&mut self) {
+        #[cfg(feature = "arena")]
+        self.bump.reset();
+    }
+}
+
+#[cfg(all(test, feature = "arena"))]
+mod tests {
+    use super::Scratch;
+
+    #[test]
+    fn reuses_memory() {
+        let mut scratch = Scratch::default();
+        for _ in 0..3 {
+            let mut bytes = scratch.vec();
+            bytes.extend_from_slice(&[0u8; 1000]);
+            drop(bytes);
+            scratch.reset();
+        }
+        let allocated = scratch.bump.allocated_bytes();
+        for _ in 0..100 {
+            let mut bytes = scratch.vec();
+            bytes.extend_from_slice(&[0u8; 1000]);
+            drop(bytes);
+            scratch.reset();
+        }
+        assert_eq!(scratch.bump.allocated_bytes(), allocated);
+    }
+}
@@ -0,0 +1,118 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Running transcript of the frames exchanged in a session. The hashing is only
+//! done with the `audit` feature; otherwise the transcript records nothing.
+
+#[cfg(feature = "audit")]
+use sha2::{Digest, Sha256};
+
+#[cfg(feature = "audit")]
+use crate::AuditDigest;
+use crate::{Tlv, TlvIterator, TlvType};
+
+/// Hashes of the frames sent and received, in order. The audit frames are left
+/// out, so that both sides agree regardless of when the queries are answered.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Transcript {
+    #[cfg(feature = "audit")]
+    sent: Sha256,
+    #[cfg(feature = "audit")]
+    received: Sha256,
+}
+
+impl Transcript {
+    /// Records the frames in `bytes`, as written to the peer.
+    pub fn sent(&mut self, bytes: &[u8]) {
+        for tlv in TlvIterator::process(bytes) {
+            #[cfg(feature = "audit")]
+            if !is_audit(tlv.tag) {
+                self.sent.update(tlv.encode());
+            }
+            #[cfg(not(feature = "audit"))]
+            let _ = tlv;
+        }
+    }
+
+    /// Records a frame received from the peer.
+    pub fn received(&mut self, tlv: Tlv) {
+        #[cfg(feature = "audit")]
+        if !is_audit(tlv.tag) {
+            self.received.update(tlv.encode());
+        }
+        #[cfg(not(feature = "audit"))]
+        let _ = tlv;
+    }
+
+    #[cfg(feature = "audit")]
+    pub fn digest(&self) -> AuditDigest {
+        AuditDigest {
+            received: self.received.clone().finalize().into(),
+            sent: self.sent.clone().finalize().into(),
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "audit"), allow(dead_code))]
+fn is_audit(tag: TlvType) -> bool {
+    matches!(tag, TlvType::AuditQuery | TlvType::AuditDigest)
+}
+
+#[cfg(all(test, feature = "audit"))]
+mod tests {
+    use super::Transcript;
+    use crate::{AuditDigest, AuditQuery, Operation, Ping, Tlv, TlvIterator};
+
+    #[test]
+    fn both_ends_agree() {
+        let request = [
+            Ping(*b"12345678").encode(),
+            "3 + 4".parse::<Operation>().unwrap().encode(),
+        ]
+        .concat();
+
+        let mut client = Transcript::default();
+        let mut server = Transcript::default();
+        client.sent(&request);
+        client.sent(&AuditQuery.encode());
+        for tlv in TlvIterator::process(&request) {
+            server.received(tlv);
+        }
+        server.received(Tlv::try_from(&AuditQuery.encode()[..]).unwrap());
+
+        let AuditDigest { received, sent } = server.digest();
+        assert_eq!(received, client.digest().sent);
+        assert_eq!(sent, client.digest().received);
+        assert_ne!(received, sent);
+    }
+
+    #[test]
+    fn altered_frame() {
+        let mut client = Transcript::default();
+        let mut server = Transcript::default();
+        client.sent(&"3 + 4".parse::<Operation>().unwrap().encode());
+        server
+            .received(Tlv::try_from(&"3 + 5".parse::<Operation>().unwrap().encode()[..]).unwrap());
+
+        assert_ne!(server.digest().received, client.digest().sent);
+    }
+}
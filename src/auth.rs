@@ -0,0 +1,355 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Shared-secret authentication of the messages of a session: every message
+//! carries a fresh nonce and the HMAC-SHA256 of the nonce and the message under
+//! the secret, so that only those knowing the secret can produce it and a
+//! captured message cannot be sent again.
+//!
+//! The [`Auth`] frame uses them to prove that the client knows one of the
+//! secrets of the server, and the server that it knows it too.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    num::NonZeroUsize,
+    str::FromStr,
+};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::{TCPLibError, Tlv, TlvError, TlvType};
+
+/// Bytes of a nonce. Random ones this long do not repeat in practice.
+pub const NONCE_LEN: usize = 16;
+
+/// Bytes of a MAC.
+pub const MAC_LEN: usize = 32;
+
+pub type Nonce = [u8; NONCE_LEN];
+
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum AuthError {
+    #[error("The MAC does not match the message")]
+    Mac,
+    #[error("The nonce was already used")]
+    Replay,
+    #[error("Expected a key ID and a secret, as ID:SECRET")]
+    Secret,
+}
+
+/// A secret with its key ID, written `ID:SECRET`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyedSecret {
+    pub key_id: String,
+    pub secret: Vec<u8>,
+}
+
+impl FromStr for KeyedSecret {
+    type Err = AuthError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((key_id, secret)) if !key_id.is_empty() && !secret.is_empty() => Ok(Self {
+                key_id: key_id.to_string(),
+                secret: secret.as_bytes().to_vec(),
+            }),
+            _ => Err(AuthError::Secret),
+        }
+    }
+}
+
+impl KeyedSecret {
+    /// The secrets of a file with one per line, ignoring the blank ones and
+    /// those starting with `#`.
+    pub fn parse_lines(text: &str) -> Result<Vec<Self>, AuthError> {
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::parse)
+            .collect()
+    }
+}
+
+/// The MAC of `message` sent with `nonce`.
+pub fn mac(secret: &[u8], nonce: &Nonce, message: &[u8]) -> [u8; MAC_LEN] {
+    keyed(secret, nonce, message).finalize().into_bytes().into()
+}
+
+/// Checks that `tag` is the MAC of `message` sent with `nonce`, and that the
+/// nonce was not seen before, recording it in `nonces`.
+///
+/// The MAC is compared in constant time, so the time taken does not tell how
+/// many of its leading bytes are right. It is checked first, so that forged
+/// messages cannot evict the nonces of genuine ones.
+pub fn verify_mac(
+    secret: &[u8],
+    nonces: &mut Nonces,
+    nonce: &Nonce,
+    message: &[u8],
+    tag: &[u8],
+) -> Result<(), AuthError> {
+    keyed(secret, nonce, message)
+        .verify_slice(tag)
+        .map_err(|_| AuthError::Mac)?;
+
+    nonces.insert(*nonce)
+}
+
+/// Proof of knowing the secret with ID `key_id`: the MAC of the ID and a
+/// challenge, with a fresh nonce. Clients sign the [`crate::Hello::challenge`]
+/// of the server, that changes with every hello, and the server answers with
+/// its own, whose challenge is the nonce of the client, so that neither can be
+/// replayed in another session, even by a restarted server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Auth {
+    pub key_id: String,
+    pub nonce: Nonce,
+    pub mac: [u8; MAC_LEN],
+}
+
+impl Auth {
+    /// Signs `key_id` and `challenge` with `secret` and a random nonce.
+    pub fn sign(key_id: &str, secret: &[u8], challenge: &[u8]) -> Self {
+        // Not a cryptographic generator, but nonces need only be unique
+        let nonce = std::array::from_fn(|_| fastrand::u8(..));
+        let message = [key_id.as_bytes(), challenge].concat();
+
+        Self {
+            key_id: key_id.to_string(),
+            nonce,
+            mac: mac(secret, &nonce, &message),
+        }
+    }
+
+    /// Checks the MAC with [`verify_mac`].
+    pub fn verify(
+        &self,
+        secret: &[u8],
+        nonces: &mut Nonces,
+        challenge: &[u8],
+    ) -> Result<(), AuthError> {
+        let message = [self.key_id.as_bytes(), challenge].concat();
+        verify_mac(secret, nonces, &self.nonce, &message, &self.mac)
+    }
+
+    pub fn encode(&self) -> Result<Box<[u8]>, TlvError> {
+        let id = self.key_id.as_bytes();
+        let length = u8::try_from(id.len())?;
+        let data = [&[length], id, &self.nonce, &self.mac].concat();
+        Ok(Tlv::new(TlvType::Auth, &data)?.encode())
+    }
+}
+
+impl<'a> TryFrom<Tlv<'a>> for Auth {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        let Some((&length, rest)) = tlv.data.split_first() else {
+            return Err(TCPLibError::Generic);
+        };
+        if tlv.tag != TlvType::Auth || rest.len() != usize::from(length) + NONCE_LEN + MAC_LEN {
+            return Err(TCPLibError::Generic);
+        }
+        let (id, rest) = rest.split_at(length.into());
+        let (nonce, mac) = rest.split_at(NONCE_LEN);
+        Ok(Auth {
+            key_id: String::from_utf8(id.to_vec()).map_err(|_| TCPLibError::Generic)?,
+            nonce: nonce.try_into()?,
+            mac: mac.try_into()?,
+        })
+    }
+}
+
+fn keyed(secret: &[u8], nonce: &Nonce, message: &[u8]) -> Hmac<Sha256> {
+    // An HMAC takes keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+    mac.update(nonce);
+    mac.update(message);
+
+    mac
+}
+
+/// The most recent nonces seen, up to a capacity, forgetting the oldest first.
+/// A replay is only caught while its nonce is remembered, so the capacity
+/// bounds how far back a captured message can come from, and must remember at
+/// least one.
+#[derive(Clone, Debug)]
+pub struct Nonces {
+    capacity: NonZeroUsize,
+    seen: HashSet<Nonce>,
+    /// The same nonces as `seen`, the oldest first
+    order: VecDeque<Nonce>,
+}
+
+impl Nonces {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            capacity,
+            seen: HashSet::with_capacity(capacity.get()),
+            order: VecDeque::with_capacity(capacity.get()),
+        }
+    }
+
+    /// Records `nonce`, failing if it is already remembered.
+    pub fn insert(&mut self, nonce: Nonce) -> Result<(), AuthError> {
+        if !self.seen.insert(nonce) {
+            return Err(AuthError::Replay);
+        }
+        self.order.push_back(nonce);
+        if self.order.len() > self.capacity.get() {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use super::{mac, verify_mac, Auth, AuthError, KeyedSecret, Nonces, MAC_LEN};
+    use crate::Tlv;
+
+    #[test]
+    fn secrets_file() {
+        let secrets = KeyedSecret::parse_lines("# rotating\nold:s3cret\n\n  new:0ther \n").unwrap();
+        assert_eq!(
+            secrets,
+            [
+                KeyedSecret {
+                    key_id: "old".to_string(),
+                    secret: b"s3cret".to_vec()
+                },
+                KeyedSecret {
+                    key_id: "new".to_string(),
+                    secret: b"0ther".to_vec()
+                }
+            ]
+        );
+        assert_eq!(
+            KeyedSecret::parse_lines("old:s3cret\nnew"),
+            Err(AuthError::Secret)
+        );
+    }
+
+    #[test]
+    fn verify() {
+        let mut nonces = Nonces::new(NonZeroUsize::new(8).unwrap());
+        let tag = mac(b"secret", &[1; 16], b"3 + 4");
+
+        assert_eq!(
+            verify_mac(b"secret", &mut nonces, &[1; 16], b"3 + 5", &tag),
+            Err(AuthError::Mac)
+        );
+        assert_eq!(
+            verify_mac(b"other", &mut nonces, &[1; 16], b"3 + 4", &tag),
+            Err(AuthError::Mac)
+        );
+        assert_eq!(
+            verify_mac(
+                b"secret",
+                &mut nonces,
+                &[1; 16],
+                b"3 + 4",
+                &tag[..MAC_LEN - 1]
+            ),
+            Err(AuthError::Mac)
+        );
+        assert!(nonces.is_empty());
+
+        assert_eq!(
+            verify_mac(b"secret", &mut nonces, &[1; 16], b"3 + 4", &tag),
+            Ok(())
+        );
+        assert_eq!(
+            verify_mac(b"secret", &mut nonces, &[1; 16], b"3 + 4", &tag),
+            Err(AuthError::Replay)
+        );
+    }
+
+    #[test]
+    fn exchange() {
+        let mut server = Nonces::new(NonZeroUsize::new(8).unwrap());
+        let mut client = Nonces::new(NonZeroUsize::MIN);
+        let request = Auth::sign("2024", b"secret", &[]);
+        let encoded = request.encode().unwrap();
+        assert_eq!(encoded.len(), 2 + 1 + 4 + 16 + 32);
+
+        let received: Auth = Tlv::try_from(&encoded[..]).unwrap().try_into().unwrap();
+        assert_eq!(received, request);
+        assert_eq!(
+            received.verify(b"other", &mut server, &[]),
+            Err(AuthError::Mac)
+        );
+        assert_eq!(received.verify(b"secret", &mut server, &[]), Ok(()));
+        assert_eq!(
+            received.verify(b"secret", &mut server, &[]),
+            Err(AuthError::Replay)
+        );
+
+        let answer = Auth::sign("2024", b"secret", &request.nonce);
+        assert_eq!(
+            answer.verify(b"secret", &mut client, &[]),
+            Err(AuthError::Mac)
+        );
+        assert_eq!(
+            answer.verify(b"secret", &mut client, &request.nonce),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn keyed_secret() {
+        let parsed: KeyedSecret = "2024:s3:cr3t".parse().unwrap();
+        assert_eq!(parsed.key_id, "2024");
+        assert_eq!(parsed.secret, b"s3:cr3t");
+        for wrong in ["secret", ":secret", "2024:"] {
+            assert_eq!(wrong.parse::<KeyedSecret>(), Err(AuthError::Secret));
+        }
+    }
+
+    #[test]
+    fn bounded() {
+        let mut nonces = Nonces::new(NonZeroUsize::new(2).unwrap());
+        for n in 0..3 {
+            assert_eq!(nonces.insert([n; 16]), Ok(()));
+        }
+        assert_eq!(nonces.len(), 2);
+        assert_eq!(nonces.insert([2; 16]), Err(AuthError::Replay));
+        // The oldest was forgotten
+        assert_eq!(nonces.insert([0; 16]), Ok(()));
+        assert_eq!(nonces.insert([1; 16]), Ok(()));
+    }
+}
@@ -0,0 +1,78 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+#[cfg(feature = "tui")]
+use tcp1::cli::proxy;
+use tcp1::cli::{
+    bench, client, conform, dump, generate_if_requested, replay, selftest, server, GenerateArgs,
+};
+
+#[derive(Debug, Parser)]
+#[command(name = "tcp1", about = "Remote TCP calculator")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    #[command(flatten)]
+    generate: GenerateArgs,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run the calculator server (same as tcp1ser)
+    Serve(Box<server::Args>),
+    /// Run the calculator client (same as tcp1cli)
+    Client(Box<client::Args>),
+    /// Relay clients to a server, showing and tampering with their frames (same as tcp1proxy)
+    #[cfg(feature = "tui")]
+    Proxy(proxy::Args),
+    /// Check the client and the server against each other on this computer
+    Selftest(selftest::Args),
+    /// Check that a server running elsewhere follows the protocol
+    Conform(conform::Args),
+    /// Measure how fast a server answers
+    Bench(bench::Args),
+    /// Decode or compare captured sessions (same as tcp1dump)
+    Dump(dump::Args),
+    /// Send the operations of a recorded session again (same as tcp1replay)
+    Replay(replay::Args),
+}
+
+fn main() -> ExitCode {
+    if let Some(code) = generate_if_requested::<Cli>() {
+        return code;
+    }
+
+    match Cli::parse().command {
+        Command::Serve(args) => server::run(*args),
+        Command::Client(args) => client::run(*args),
+        #[cfg(feature = "tui")]
+        Command::Proxy(args) => proxy::run(args),
+        Command::Selftest(args) => selftest::run(args),
+        Command::Conform(args) => conform::run(args),
+        Command::Bench(args) => bench::run(args),
+        Command::Dump(args) => dump::run(args),
+        Command::Replay(args) => replay::run(args),
+    }
+}
@@ -20,46 +20,8 @@
  *
  */
 
-use std::{
-    io::{stdin, Read, Write},
-    net::{IpAddr, SocketAddr, TcpStream},
-};
+use std::process::ExitCode;
 
-use clap::Parser;
-use tcp1::{Answer, Operation, Tlv};
-
-#[derive(Debug, Parser)]
-struct Args {
-    /// Destination IP Address
-    ip: IpAddr,
-    /// Destination port number
-    #[arg(value_parser = clap::value_parser!(u16).range(1..))]
-    dst_port: u16,
-}
-
-fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
-
-    let mut buffer = [0u8; 2048];
-    let mut stream = TcpStream::connect(SocketAddr::from((args.ip, args.dst_port)))?;
-
-    println!("Enter arithmetic expressions using infix notation. For example: 10 * 3 or 5!.");
-
-    for line in stdin().lines() {
-        let iline = line?;
-        if iline.trim() == "QUIT" {
-            break;
-        }
-        match iline.parse::<Operation>() {
-            Ok(operation) => {
-                stream.write_all(&operation.encode())?;
-                let len = stream.read(&mut buffer)?;
-                let Answer(answer) = Tlv::try_from(&buffer[..len])?.try_into()?;
-                println!("Accumulated value = {}", answer);
-            }
-            Err(_) => println!("Could not parse operation. Please, try again."),
-        }
-    }
-
-    Ok(())
+fn main() -> ExitCode {
+    tcp1::cli::client::main()
 }
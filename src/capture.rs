@@ -0,0 +1,246 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Captures of a connection, as saved from one of its directions (for example,
+//! with "Follow TCP Stream" in Wireshark, shown as raw), split into their frames
+//! for `tcp1dump`.
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::{inspect, TlvType};
+
+/// Frames looked ahead for where two captures agree again after they diverge.
+const RESYNC: usize = 16;
+
+/// A frame of a capture, as the bytes it took on the wire.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Captured<'a> {
+    /// Position of the frame in the capture
+    pub offset: usize,
+    pub bytes: &'a [u8],
+}
+
+impl Captured<'_> {
+    pub fn describe(&self) -> String {
+        inspect::describe(self.bytes)
+    }
+}
+
+/// Splits a capture into its frames. The last one is truncated if the capture
+/// ends in the middle of it.
+pub fn frames(capture: &[u8]) -> Vec<Captured<'_>> {
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    while offset < capture.len() {
+        let length = capture
+            .get(offset + 1)
+            .map_or(1, |&length| 2 + usize::from(length));
+        let end = capture.len().min(offset + length);
+        frames.push(Captured {
+            offset,
+            bytes: &capture[offset..end],
+        });
+        offset = end;
+    }
+
+    frames
+}
+
+/// Totals of the frames of a capture.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CaptureStats {
+    pub frames: u64,
+    pub bytes: u64,
+    /// Frames by tag, with those that cannot be decoded as `Invalid`
+    pub tags: BTreeMap<&'static str, u64>,
+}
+
+impl CaptureStats {
+    pub fn add(&mut self, frame: &Captured) {
+        self.frames += 1;
+        self.bytes += frame.bytes.len() as u64;
+        let complete = frame
+            .bytes
+            .get(1)
+            .is_some_and(|&length| frame.bytes.len() == 2 + usize::from(length));
+        let tag = match TlvType::try_from(frame.bytes[0]) {
+            Ok(tag) if complete => tag.name(),
+            _ => "Invalid",
+        };
+        *self.tags.entry(tag).or_default() += 1;
+    }
+}
+
+impl<'a> FromIterator<&'a Captured<'a>> for CaptureStats {
+    fn from_iter<T: IntoIterator<Item = &'a Captured<'a>>>(iter: T) -> Self {
+        let mut stats = CaptureStats::default();
+        for frame in iter {
+            stats.add(frame);
+        }
+
+        stats
+    }
+}
+
+/// The tags from the most frequent, after the totals.
+impl fmt::Display for CaptureStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} frames in {} bytes", self.frames, self.bytes)?;
+        let mut tags: Vec<_> = self.tags.iter().collect();
+        tags.sort_by(|a, b| b.1.cmp(a.1));
+        for (tag, count) in tags {
+            writeln!(f, "{count:>8}  {tag}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// How a frame of the expected capture matches one of the actual capture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alignment<'a> {
+    Same(Captured<'a>, Captured<'a>),
+    Changed(Captured<'a>, Captured<'a>),
+    /// Only in the expected capture
+    Missing(Captured<'a>),
+    /// Only in the actual capture
+    Extra(Captured<'a>),
+}
+
+/// Pairs the frames of two captures in order. Where they diverge, it looks
+/// ahead for the nearest frames where they agree again, so that a frame lost or
+/// added does not make all the following ones differ.
+pub fn align<'a>(expected: &[Captured<'a>], actual: &[Captured<'a>]) -> Vec<Alignment<'a>> {
+    let mut alignment = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() && j < actual.len() {
+        if expected[i].bytes == actual[j].bytes {
+            alignment.push(Alignment::Same(expected[i], actual[j]));
+            (i, j) = (i + 1, j + 1);
+            continue;
+        }
+
+        // The fewest frames skipped in both captures
+        let agree = |(skip_expected, skip_actual): &(usize, usize)| match (
+            expected.get(i + skip_expected),
+            actual.get(j + skip_actual),
+        ) {
+            (Some(a), Some(b)) => a.bytes == b.bytes,
+            _ => false,
+        };
+        let (skip_expected, skip_actual) = (1..=2 * RESYNC)
+            .flat_map(|skipped| (0..=skipped).map(move |skip| (skip, skipped - skip)))
+            .filter(|&(skip_expected, skip_actual)| {
+                skip_expected <= RESYNC && skip_actual <= RESYNC
+            })
+            .find(agree)
+            .unwrap_or((1, 1));
+        let changed = skip_expected.min(skip_actual);
+        for k in 0..changed {
+            alignment.push(Alignment::Changed(expected[i + k], actual[j + k]));
+        }
+        alignment.extend(
+            expected[i + changed..i + skip_expected]
+                .iter()
+                .map(|&frame| Alignment::Missing(frame)),
+        );
+        alignment.extend(
+            actual[j + changed..j + skip_actual]
+                .iter()
+                .map(|&frame| Alignment::Extra(frame)),
+        );
+        (i, j) = (i + skip_expected, j + skip_actual);
+    }
+    alignment.extend(expected[i..].iter().map(|&frame| Alignment::Missing(frame)));
+    alignment.extend(actual[j..].iter().map(|&frame| Alignment::Extra(frame)));
+
+    alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{align, frames, Alignment, CaptureStats};
+    use crate::{Bye, Operation};
+
+    fn capture(operations: &[&str]) -> Vec<u8> {
+        let mut capture: Vec<u8> = operations
+            .iter()
+            .flat_map(|operation| operation.parse::<Operation>().unwrap().encode().into_vec())
+            .collect();
+        capture.extend_from_slice(&Bye.encode());
+        capture
+    }
+
+    #[test]
+    fn split() {
+        let mut bytes = capture(&["1 + 2", "5!"]);
+        bytes.extend_from_slice(&[1, 16, 0]);
+        let frames = frames(&bytes);
+        let described: Vec<_> = frames
+            .iter()
+            .map(|frame| (frame.offset, frame.describe()))
+            .collect();
+        assert_eq!(
+            described,
+            [
+                (0, "Sum 1+2".to_string()),
+                (4, "Fact 5!".to_string()),
+                (7, "Bye".to_string()),
+                (9, "Invalid [01 10 00]".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn stats() {
+        let mut bytes = capture(&["1 + 2", "5!", "3 + 3"]);
+        bytes.extend_from_slice(&[1, 16, 0]);
+        let stats: CaptureStats = frames(&bytes).iter().collect();
+        assert_eq!(
+            stats.to_string(),
+            "5 frames in 16 bytes\n       2  Sum\n       1  Bye\n       1  Fact\n       1  Invalid\n"
+        );
+    }
+
+    #[test]
+    fn alignment() {
+        let expected = capture(&["1 + 2", "3 - 1", "2 × 2", "5!", "7 + 1"]);
+        // One changed, one lost and one added
+        let actual = capture(&["1 + 2", "3 - 2", "5!", "7 + 1", "8 + 8"]);
+        let (expected, actual) = (frames(&expected), frames(&actual));
+        let differences: Vec<_> = align(&expected, &actual)
+            .into_iter()
+            .filter(|alignment| !matches!(alignment, Alignment::Same(..)))
+            .map(|alignment| match alignment {
+                Alignment::Changed(a, b) => format!("{} ~ {}", a.describe(), b.describe()),
+                Alignment::Missing(a) => format!("- {}", a.describe()),
+                Alignment::Extra(b) => format!("+ {}", b.describe()),
+                Alignment::Same(..) => unreachable!(),
+            })
+            .collect();
+        assert_eq!(differences, ["Sub 3-1 ~ Sub 3-2", "- Mul 2×2", "+ Sum 8+8"]);
+
+        assert!(align(&expected, &expected)
+            .iter()
+            .all(|alignment| matches!(alignment, Alignment::Same(..))));
+    }
+}
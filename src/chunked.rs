@@ -0,0 +1,126 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+use std::{
+    io::{self, Write},
+    num::NonZeroUsize,
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{clock, Clock};
+
+/// Splits everything written into writes of at most `chunk_size` bytes,
+/// pausing between them so that the peer receives them in separate segments.
+///
+/// This is a debugging aid to check that peers can reassemble TLVs that
+/// arrive split across several reads.
+pub struct ChunkedWriter<W> {
+    inner: W,
+    chunk_size: NonZeroUsize,
+    pause: Duration,
+    clock: Arc<dyn Clock>,
+    written: bool,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub const DEFAULT_PAUSE: Duration = Duration::from_millis(10);
+
+    pub fn new(inner: W, chunk_size: NonZeroUsize) -> Self {
+        Self {
+            inner,
+            chunk_size,
+            pause: Self::DEFAULT_PAUSE,
+            clock: clock::system(),
+            written: false,
+        }
+    }
+
+    pub fn with_pause(self, pause: Duration) -> Self {
+        Self { pause, ..self }
+    }
+
+    /// Pauses on the `clock` instead of the one of the system.
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.written && !self.pause.is_zero() {
+            self.clock.sleep(self.pause);
+        }
+
+        let len = buf.len().min(self.chunk_size.get());
+        let written = self.inner.write(&buf[..len])?;
+        self.inner.flush()?;
+        self.written = true;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, time::Duration};
+
+    use super::ChunkedWriter;
+
+    struct Recorder(Vec<Vec<u8>>);
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn chunked_writes() {
+        let mut writer = ChunkedWriter::new(Recorder(Vec::new()), 3.try_into().unwrap())
+            .with_pause(Duration::ZERO);
+        writer
+            .write_all(&[16u8, 8, 0, 0, 0, 0, 0, 0, 0, 1])
+            .unwrap();
+
+        assert_eq!(
+            writer.into_inner().0,
+            [vec![16u8, 8, 0], vec![0, 0, 0], vec![0, 0, 0], vec![1]]
+        );
+    }
+}
@@ -0,0 +1,100 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! `tcp1 bench`: measures how fast a server answers, sending it the same
+//! operation many times, one after the other.
+
+use std::{
+    num::NonZeroUsize,
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+
+use super::resolve;
+use crate::{Client, ClientError, Operation};
+
+const ABOUT: &str = "Measure how fast a server of the remote TCP calculator answers";
+
+#[derive(Debug, clap::Args)]
+#[command(about = ABOUT)]
+pub struct Args {
+    /// Address of the server, with the port after a colon, as in localhost:7777
+    #[arg(value_name = "SERVER")]
+    server: String,
+    /// Operations sent, each once the previous one is answered
+    #[arg(long, short = 'n', value_name = "N", default_value = "1000")]
+    operations: NonZeroUsize,
+    /// Operation sent every time
+    #[arg(long, default_value = "0 + 0")]
+    operation: Operation,
+}
+
+/// Exits with 1 if the server could not be reached or stopped answering.
+pub fn run(args: Args) -> ExitCode {
+    match bench(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn bench(args: &Args) -> anyhow::Result<()> {
+    let server = resolve(&args.server)?;
+    let mut client = Client::connect(server, None).context("Could not connect to the server")?;
+    let mut latencies = Vec::with_capacity(args.operations.get());
+    let mut rejections = 0;
+    let started = Instant::now();
+    for _ in 0..args.operations.get() {
+        let sent = Instant::now();
+        match client.compute(args.operation.clone()) {
+            Ok(_) => (),
+            Err(ClientError::Rejected(_)) => rejections += 1,
+            Err(e) => return Err(e).context("Could not get an answer from the server"),
+        }
+        latencies.push(sent.elapsed());
+    }
+    let elapsed = started.elapsed();
+    client
+        .close()
+        .context("Could not say goodbye to the server")?;
+
+    latencies.sort_unstable();
+    let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+    println!(
+        "{} operations in {elapsed:.2?}, {:.0} per second, {rejections} rejected",
+        latencies.len(),
+        latencies.len() as f64 / elapsed.max(Duration::from_micros(1)).as_secs_f64()
+    );
+    println!(
+        "Latency: p50 {:.2?}, p90 {:.2?}, p99 {:.2?}, max {:.2?}",
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        percentile(100)
+    );
+
+    Ok(())
+}
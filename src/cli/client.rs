@@ -0,0 +1,1391 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+use std::{
+    env,
+    io::{self, stdin, stdout, IsTerminal, Write},
+    net::{IpAddr, Ipv6Addr, SocketAddr, ToSocketAddrs},
+    num::{NonZeroU64, NonZeroUsize},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use rustyline::{error::ReadlineError, history::DefaultHistory, Editor};
+
+use super::{
+    generate_if_requested,
+    journal::Journal,
+    recording::Recorder,
+    repl::{self, ReplHelper, PROMPT},
+    ui, GenerateArgs,
+};
+#[cfg(feature = "auth")]
+use crate::auth::KeyedSecret;
+use crate::errors::{Coded, ErrorKind};
+use crate::{
+    format::Radix, operation::MultinomialOperationData, Answer, Capabilities, Client, ClientError,
+    Clock, Operation, OperationError, ParserOptions, Priority, Profile, Progress, Proxy, Rejection,
+    Summary, SystemClock, Tlv, TlvType, UnsolicitedPolicy,
+};
+
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  Success
+  2  Parse error: the command line, some operation or the journal
+  3  Could not connect to the server
+  4  Protocol error: the server closed the connection or sent a malformed answer
+  5  Timeout while waiting for the server
+  6  The server rejected some operation (batch mode only)
+  7  Authentication failed
+  8  Could not write a file, such as the recording";
+
+const ABOUT: &str = "Client of the remote TCP calculator";
+
+#[derive(Debug, clap::Args)]
+#[command(about = ABOUT, after_help = EXIT_CODES)]
+pub struct Args {
+    /// Address of the server: an IP address or a host name, with the port
+    /// after a colon or as the next argument, as in 192.0.2.7:7777,
+    /// localhost 7777 or [::1]:7777 [env: TCP1_SERVER]
+    #[arg(value_name = "SERVER")]
+    server: Option<Destination>,
+    /// Destination port number, unless given with the address of the server
+    /// [env: TCP1_PORT]
+    #[arg(value_parser = clap::value_parser!(u16).range(1..), requires = "server")]
+    dst_port: Option<u16>,
+    /// Experimental: talk to the server over QUIC, each operation on its own stream
+    #[cfg(feature = "quic")]
+    #[arg(long, conflicts_with_all = ["proxy", "chunked_writes", "heartbeat", "trace", "api_key", "capabilities", "answer"])]
+    quic: bool,
+    /// Talk to the server over SCTP, each operation in a message of its own
+    #[cfg(all(feature = "sctp", target_os = "linux"))]
+    #[arg(long, conflicts_with_all = ["proxy", "chunked_writes", "heartbeat", "trace", "api_key", "capabilities", "answer"])]
+    sctp: bool,
+    /// Look for servers announced in the local network and pick one of them
+    #[cfg(feature = "mdns")]
+    #[arg(long, conflicts_with_all = ["server", "dst_port"])]
+    discover: bool,
+    /// Do not connect to any server, but calculate the operations locally, to know the expected answers
+    #[arg(long, conflicts_with_all = ["server", "dst_port", "proxy", "timeout", "chunked_writes", "heartbeat", "trace", "api_key"])]
+    offline: bool,
+    /// Print the encoding of the operation, without connecting to any server
+    #[arg(long, value_name = "OPERATION", conflicts_with_all = ["server", "dst_port", "offline", "decode_only"])]
+    encode_only: Option<String>,
+    /// Print the operation encoded in these hexadecimal bytes, without connecting to any server
+    #[arg(long, value_name = "HEX", conflicts_with_all = ["server", "dst_port", "offline"])]
+    decode_only: Option<String>,
+    /// Reach the server through a proxy (socks5://host:port or http://host:port)
+    #[arg(long)]
+    proxy: Option<Proxy>,
+    /// Seconds to wait for the connection, and for each answer, before giving up
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: Option<u64>,
+    /// Ask the server to give up on every operation it cannot answer within
+    /// this many milliseconds
+    #[arg(long, value_name = "MS", conflicts_with = "offline")]
+    deadline_ms: Option<u64>,
+    /// Ask to be served before the waiting clients of lower priority: bulk,
+    /// normal, interactive or a number up to 255. Only servers that know about
+    /// priorities understand it
+    #[arg(long, value_name = "LEVEL", conflicts_with = "offline")]
+    priority: Option<Priority>,
+    /// In batch mode (standard input is not a terminal), stop at the first operation that cannot be parsed
+    #[arg(long)]
+    fail_fast: bool,
+    /// Debug: split every request into writes of at most N bytes
+    #[arg(long, value_name = "N")]
+    chunked_writes: Option<NonZeroUsize>,
+    /// Send the operations at most at this many bytes per second, as over a slow link
+    #[arg(long, value_name = "BYTES_PER_SEC")]
+    throttle: Option<NonZeroU64>,
+    /// Encoding of the answers: classic (big-endian i64) or le32 (little-endian
+    /// i32), that is asked for in a hello
+    #[arg(long, default_value_t = Profile::Classic, conflicts_with = "offline")]
+    profile: Profile,
+    /// Read the operands and write the answers in this base: bin, oct, dec or
+    /// hex, with the digits of the answers grouped
+    #[arg(long, value_name = "RADIX")]
+    radix: Option<Radix>,
+    /// Send a keep-alive ping to the server every this many seconds (interactive mode only)
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    heartbeat: Option<u64>,
+    /// Attach a trace context to every operation, to find them in the logs of the server
+    #[arg(long)]
+    trace: bool,
+    /// Identify with this API key, when the server has tenants
+    #[arg(long, value_name = "KEY")]
+    api_key: Option<String>,
+    /// Authenticate before the first operation with the secret of this file,
+    /// written as ID:SECRET. Without it, with that of the TCP1_SECRET
+    /// environment variable, if set
+    #[cfg(feature = "auth")]
+    #[arg(long, value_name = "FILE", conflicts_with = "offline")]
+    secret_file: Option<PathBuf>,
+    /// Print what the server supports beyond the basic operations and exit
+    #[arg(long, conflicts_with_all = ["offline", "fail_fast", "heartbeat"])]
+    capabilities: bool,
+    /// Ask for segments of at most this many bytes (TCP_MAXSEG)
+    #[cfg(unix)]
+    #[arg(long, value_name = "BYTES", conflicts_with_all = ["proxy", "offline"])]
+    mss: Option<u32>,
+    /// Send three sums whose frames take a byte less than a segment, a whole
+    /// segment and a byte more, and exit
+    #[arg(long, conflicts_with_all = ["offline", "fail_fast", "heartbeat", "capabilities"])]
+    boundary_frames: bool,
+    /// Answer the operations of a server in quiz mode, typing the result of each
+    /// (or ! if it cannot be calculated)
+    #[arg(long, conflicts_with_all = ["offline", "fail_fast", "heartbeat", "trace", "api_key", "capabilities"])]
+    answer: bool,
+    /// Print what the kernel knows of the TCP connection at the end: its round
+    /// trip time, congestion window and retransmissions
+    #[cfg(all(feature = "tcp-info", target_os = "linux"))]
+    #[arg(long, conflicts_with_all = ["offline", "capabilities", "answer"])]
+    stats: bool,
+
+    /// Write the summary of the session to this file, as TOML, instead of
+    /// printing it at the end
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["offline", "capabilities", "answer"])]
+    report: Option<PathBuf>,
+    /// In batch mode, write the operations to this journal before sending them
+    /// one at a time, and which were answered, so that --resume can send the
+    /// rest if the client stops
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["offline", "capabilities", "answer", "boundary_frames"])]
+    journal: Option<PathBuf>,
+    /// Send the operations of the --journal that were not answered, instead of
+    /// reading them
+    #[arg(long, requires = "journal")]
+    resume: bool,
+    /// At the prompt, write the operations to this file with the time between
+    /// them, to send them again with tcp1replay
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["offline", "capabilities", "answer", "boundary_frames", "journal"])]
+    record: Option<PathBuf>,
+}
+
+/// Address of the server as typed: an IP address or a host name, and maybe the
+/// port after a colon. IPv6 addresses need brackets to take the port.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Destination {
+    host: String,
+    port: Option<u16>,
+}
+
+impl FromStr for Destination {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let port = |port: &str| match port.parse() {
+            Ok(0) | Err(_) => Err(format!(
+                "{port} is not a port. Use a number from 1 to 65535"
+            )),
+            Ok(port) => Ok(Some(port)),
+        };
+
+        if let Some(rest) = s.strip_prefix('[') {
+            let (ip, after) = rest
+                .split_once(']')
+                .ok_or("missing ] after the IPv6 address. Write it as in [::1]:7777")?;
+            let ip: Ipv6Addr = ip
+                .parse()
+                .map_err(|_| format!("{ip} is not an IPv6 address"))?;
+            let port = match after {
+                "" => None,
+                after => port(after.strip_prefix(':').ok_or(format!(
+                    "unexpected {after} after the IPv6 address. Write the port as in [{ip}]:7777"
+                ))?)?,
+            };
+            return Ok(Destination {
+                host: ip.to_string(),
+                port,
+            });
+        }
+        // Including the IPv6 ones, whose colons are not a port
+        if s.parse::<IpAddr>().is_ok() {
+            return Ok(Destination {
+                host: s.to_string(),
+                port: None,
+            });
+        }
+
+        let (host, port) = match s.rsplit_once(':') {
+            Some((host, _)) if host.contains(':') => {
+                return Err(format!(
+                    "{s} is not an address. IPv6 addresses go between brackets to take a port, as in [::1]:7777"
+                ))
+            }
+            Some((host, after)) => (host, port(after)?),
+            None => (s, None),
+        };
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '.';
+        if host.is_empty() || !host.chars().all(valid) {
+            return Err(format!(
+                "{host:?} is not an IP address nor a host name, such as 192.0.2.7 or localhost"
+            ));
+        }
+
+        Ok(Destination {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl Destination {
+    /// Looks the host up, taking its first address.
+    fn resolve(&self, port: u16) -> io::Result<SocketAddr> {
+        (self.host.as_str(), port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+}
+
+/// Picks the server and its port, taking what is missing from the command line
+/// from the `TCP1_SERVER` and `TCP1_PORT` variables, looked up with `var`.
+/// Returns no destination when the server is to be discovered, or an error
+/// message with a tip.
+fn destination(
+    server: Option<&Destination>,
+    dst_port: Option<u16>,
+    discover: bool,
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<Option<(Destination, u16)>, (String, String)> {
+    let destination = match server {
+        Some(destination) => destination.clone(),
+        None if discover => return Ok(None),
+        None => match var("TCP1_SERVER") {
+            Some(value) => value.parse().map_err(|e| {
+                (
+                    format!("invalid TCP1_SERVER: {e}"),
+                    "Fix the variable or unset it".to_string(),
+                )
+            })?,
+            None => {
+                return Err((
+                    "no server to connect to".to_string(),
+                    "Give its address, as in localhost:7777, or set TCP1_SERVER".to_string(),
+                ))
+            }
+        },
+    };
+
+    let port = match (destination.port, dst_port) {
+        (Some(first), Some(second)) => {
+            return Err((
+                format!("the port is given twice, as {first} and {second}"),
+                "Keep only one of them".to_string(),
+            ))
+        }
+        (Some(port), None) | (None, Some(port)) => port,
+        (None, None) => match var("TCP1_PORT") {
+            Some(value) => match value.parse() {
+                Ok(0) | Err(_) => {
+                    return Err((
+                        format!("invalid TCP1_PORT: {value} is not a port"),
+                        "Use a number from 1 to 65535, or unset it".to_string(),
+                    ))
+                }
+                Ok(port) => port,
+            },
+            None => {
+                let host = &destination.host;
+                return Err((
+                    format!("no port for the server {host}"),
+                    format!(
+                        "Add it after the address, as in {host}:7777 or {host} 7777, or set TCP1_PORT"
+                    ),
+                ));
+            }
+        },
+    };
+
+    Ok(Some((destination, port)))
+}
+
+/// Command line of the classic `tcp1cli` binary.
+#[derive(Debug, Parser)]
+#[command(name = "tcp1cli", about = ABOUT, after_help = EXIT_CODES)]
+struct Standalone {
+    #[command(flatten)]
+    args: Args,
+    #[command(flatten)]
+    generate: GenerateArgs,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+enum Status {
+    Success = 0,
+    ParseError = ErrorKind::Parse.exit_code(),
+    ConnectError = ErrorKind::Connection.exit_code(),
+    ProtocolError = ErrorKind::Protocol.exit_code(),
+    Timeout = ErrorKind::Timeout.exit_code(),
+    Rejected = ErrorKind::Rejected.exit_code(),
+    AuthError = ErrorKind::Auth.exit_code(),
+    IoError = ErrorKind::Io.exit_code(),
+}
+
+impl From<ErrorKind> for Status {
+    fn from(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::Parse | ErrorKind::Config => Status::ParseError,
+            ErrorKind::Connection => Status::ConnectError,
+            ErrorKind::Protocol => Status::ProtocolError,
+            ErrorKind::Timeout => Status::Timeout,
+            ErrorKind::Rejected | ErrorKind::Calculation => Status::Rejected,
+            ErrorKind::Auth => Status::AuthError,
+            ErrorKind::Io => Status::IoError,
+        }
+    }
+}
+
+impl From<Status> for ExitCode {
+    fn from(status: Status) -> Self {
+        ExitCode::from(status as u8)
+    }
+}
+
+impl From<&ClientError> for Status {
+    fn from(e: &ClientError) -> Self {
+        match e.kind() {
+            // Once connected, a failing connection is the server misbehaving
+            ErrorKind::Connection => Status::ProtocolError,
+            kind => kind.into(),
+        }
+    }
+}
+
+/// Entry point of the classic `tcp1cli` binary.
+pub fn main() -> ExitCode {
+    if let Some(code) = generate_if_requested::<Standalone>() {
+        return code;
+    }
+
+    run(Standalone::parse().args)
+}
+
+pub fn run(args: Args) -> ExitCode {
+    let batch = !stdin().is_terminal() || args.resume;
+
+    if args.offline {
+        return run_offline(args.radix).into();
+    }
+    if let Some(operation) = &args.encode_only {
+        return encode_only(operation, args.radix).into();
+    }
+    if let Some(hex) = &args.decode_only {
+        return decode_only(hex).into();
+    }
+
+    #[cfg(feature = "mdns")]
+    let discover = args.discover;
+    #[cfg(not(feature = "mdns"))]
+    let discover = false;
+    let destination = match destination(args.server.as_ref(), args.dst_port, discover, |name| {
+        env::var(name).ok()
+    }) {
+        Ok(destination) => destination,
+        Err((message, tip)) => return ui::usage_error(message, tip),
+    };
+    let server = match destination {
+        Some((destination, port)) => match destination.resolve(port) {
+            Ok(server) => server,
+            Err(e) => {
+                ui::report(
+                    format!("Could not find the server {}. {e}", destination.host),
+                    Some("Check the name of the server, or use its IP address"),
+                );
+                return Status::ConnectError.into();
+            }
+        },
+        #[cfg(feature = "mdns")]
+        None => match pick_server() {
+            Some(server) => server,
+            None => return Status::ConnectError.into(),
+        },
+        #[cfg(not(feature = "mdns"))]
+        None => unreachable!("destination() requires the address of the server"),
+    };
+
+    #[cfg(feature = "quic")]
+    if args.quic {
+        return run_quic(server, args.radix).into();
+    }
+    #[cfg(all(feature = "sctp", target_os = "linux"))]
+    if args.sctp {
+        return run_sctp(server, args.radix).into();
+    }
+
+    #[cfg(feature = "auth")]
+    let secret = match secret(args.secret_file.as_deref()) {
+        Ok(secret) => secret,
+        Err(e) => {
+            ui::report(format!("{e:#}"), None);
+            return Status::from(ErrorKind::Config).into();
+        }
+    };
+
+    let timeout = args.timeout.map(Duration::from_secs);
+    let connect = || match timeout {
+        Some(timeout) => Client::connect_timeout(server, args.proxy.as_ref(), timeout),
+        None => Client::connect(server, args.proxy.as_ref()),
+    };
+    #[cfg(unix)]
+    let connected = match args.mss {
+        Some(mss) => Client::connect_with_mss_within(server, mss, timeout),
+        None => connect(),
+    };
+    #[cfg(not(unix))]
+    let connected = connect();
+    let mut client = match connected.and_then(|mut client| {
+        // Sent before the hello, so it cannot wait to see if the server knows it
+        if let Some(priority) = args.priority {
+            client.prioritize(priority)?;
+        }
+        client.set_timeout(timeout)?;
+        client.set_deadline(args.deadline_ms.map(Duration::from_millis));
+        client.set_chunked_writes(args.chunked_writes);
+        client.set_throttle(args.throttle)?;
+        client.set_unsolicited_policy(UnsolicitedPolicy::Skip);
+        if let Some(trace) = client.set_tracing(args.trace) {
+            eprintln!("Tracing as {trace}");
+        }
+        client.set_profile(args.profile)?;
+        if let Some(key) = &args.api_key {
+            client.hello(key)?;
+        }
+        #[cfg(feature = "auth")]
+        if let Some(secret) = &secret {
+            client.authenticate(&secret.key_id, &secret.secret)?;
+        }
+        // The hello asks the server for the profile
+        if args.profile == Profile::LE32 && !client.capabilities()?.contains(Capabilities::LE32) {
+            eprintln!("The server may not answer in the le32 profile");
+        }
+        Ok(client)
+    }) {
+        Ok(client) => client,
+        Err(e) => {
+            let tip = match &e {
+                ClientError::Io(e) => ui::io_tip(e),
+                _ => None,
+            };
+            ui::report(format!("Could not connect to the server. {e}"), tip);
+            return match e.kind() {
+                ErrorKind::Auth | ErrorKind::Timeout => Status::from(e.kind()),
+                _ => Status::ConnectError,
+            }
+            .into();
+        }
+    };
+
+    #[cfg(unix)]
+    if args.mss.is_some() {
+        match client.mss() {
+            Ok(mss) => eprintln!("Connected with segments of {mss} bytes"),
+            Err(e) => eprintln!("Could not get the segment size of the connection. {e}"),
+        }
+    }
+
+    if args.capabilities {
+        return print_capabilities(client).into();
+    } else if args.boundary_frames {
+        return send_boundary_frames(client).into();
+    } else if args.answer {
+        return answer_quiz(client).into();
+    }
+
+    let mut summary = Summary::default();
+    summary.connections = 1;
+    let (status, (bytes_in, bytes_out)) = if batch {
+        let status = match &args.journal {
+            Some(path) => run_journal(&mut client, path, &args, &mut summary),
+            None => run_batch(&mut client, &args, &mut summary),
+        };
+        (status, client.traffic())
+    } else {
+        let client = Arc::new(Mutex::new(client));
+        let status = run_interactive(Arc::clone(&client), &args, &mut summary);
+        let traffic = client.lock().unwrap().traffic();
+        (status, traffic)
+    };
+    summary.bytes_in = bytes_in;
+    summary.bytes_out = bytes_out;
+    if let Err(e) = ui::summary(&summary, args.report.as_deref()) {
+        eprintln!("Could not write the report. {e}");
+    }
+
+    status.into()
+}
+
+/// The secret to authenticate with: the only one of the file at `path`, or
+/// else that of the `TCP1_SECRET` environment variable, if set.
+#[cfg(feature = "auth")]
+fn secret(path: Option<&Path>) -> anyhow::Result<Option<KeyedSecret>> {
+    use anyhow::Context;
+
+    match path {
+        Some(path) => match &super::read_secrets(path)?[..] {
+            [secret] => Ok(Some(secret.clone())),
+            _ => anyhow::bail!("The secrets file {path:?} must have exactly one secret"),
+        },
+        None => env::var("TCP1_SECRET")
+            .ok()
+            .map(|secret| secret.parse().context("Wrong secret in TCP1_SECRET"))
+            .transpose(),
+    }
+}
+
+/// Counts in the summary an error that ended the session.
+fn count_failure(summary: &mut Summary, e: &ClientError) {
+    summary.count_error(match e.is_timeout() {
+        true => "timeouts",
+        false => "connection errors",
+    });
+}
+
+/// Prints whether the server supports each of the [`Capabilities`].
+fn print_capabilities(mut client: Client) -> Status {
+    let capabilities = match client.capabilities() {
+        Ok(capabilities) => capabilities,
+        Err(e) => {
+            eprintln!("Could not get the capabilities of the server. {e}");
+            return Status::from(&e);
+        }
+    };
+    for &(capability, name) in Capabilities::ALL {
+        let supported = match capabilities.contains(capability) {
+            true => "yes",
+            false => "no",
+        };
+        println!("{name:<10} {supported}");
+    }
+
+    match client.close() {
+        Ok(()) => Status::Success,
+        Err(e) => Status::from(&e),
+    }
+}
+
+/// Sends sums whose frames take a byte less than a segment, a whole segment and
+/// a byte more, to watch how they are split with a capture tool.
+fn send_boundary_frames(mut client: Client) -> Status {
+    let mss = match client.mss() {
+        Ok(mss) => mss as usize,
+        Err(e) => {
+            eprintln!("Could not get the segment size of the connection. {e}");
+            return Status::ConnectError;
+        }
+    };
+    // The frame of a sum takes two bytes more than its operands
+    let largest = MultinomialOperationData::MAX_OPERANDS + 2;
+    if !(4..largest).contains(&mss) {
+        ui::report(
+            format!(
+                "No frame can go around segments of {mss} bytes, as they take at most {largest}"
+            ),
+            Some("Ask for smaller segments, as in --mss 200"),
+        );
+        return Status::ConnectError;
+    }
+
+    for len in [mss - 1, mss, mss + 1] {
+        let operands = vec![1; len - 2]
+            .try_into()
+            .expect("the operands were counted to fit");
+        match client.compute(Operation::SumN(operands)) {
+            Ok(answer) => println!("Frame of {len} bytes: accumulated value = {answer}"),
+            Err(e) => {
+                eprintln!("Could not send the frame of {len} bytes. {e}");
+                return Status::from(&e);
+            }
+        }
+    }
+
+    match client.close() {
+        Ok(()) => Status::Success,
+        Err(e) => Status::from(&e),
+    }
+}
+
+/// Asks the user the result of every operation the server sends in a quiz.
+fn answer_quiz(mut client: Client) -> Status {
+    let mut lines = stdin().lines();
+    let result = client.answer_quiz(|operation| loop {
+        print!("{operation} = ");
+        let _ = stdout().flush();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(e)) => {
+                eprintln!("Could not read the answer. {e}");
+                return Err(Rejection::Other);
+            }
+            // Nobody is left to answer
+            None => return Err(Rejection::Other),
+        };
+        match line.trim() {
+            "!" => return Err(Rejection::WrongDomain),
+            result => match result.parse() {
+                Ok(result) => return Ok(result),
+                Err(_) => println!("Type the result, or ! if it cannot be calculated."),
+            },
+        }
+    });
+
+    match result {
+        Ok(report) => {
+            println!("Answered {} operations.", report.answered);
+            if let Some(leaderboard) = report.leaderboard {
+                print!("Leaderboard:\n{leaderboard}");
+            }
+            Status::Success
+        }
+        Err(e) => {
+            eprintln!("Could not finish the quiz. {e}");
+            Status::from(&e)
+        }
+    }
+}
+
+/// Prints the bytes of the operation, and what each of them means.
+fn encode_only(operation: &str, radix: Option<Radix>) -> Status {
+    let operation = match Operation::parse_with(operation, &parser_options(radix)) {
+        Ok(operation) => operation,
+        Err(e) => {
+            eprintln!("Could not parse operation {operation:?}. {e}");
+            return Status::ParseError;
+        }
+    };
+    let hex = operation.to_hex();
+    let bytes: Vec<&str> = hex.split(' ').collect();
+    let encoded = operation.clone().encode();
+    let tlv = Tlv::try_from(&encoded[..]).unwrap();
+    println!("{hex}");
+    println!("tag    {} ({})", bytes[0], tlv.tag.name());
+    println!("length {} ({})", bytes[1], tlv.length);
+    println!("value  {} ({operation})", bytes[2..].join(" "));
+
+    Status::Success
+}
+
+/// Prints the operation encoded in the hexadecimal bytes.
+fn decode_only(hex: &str) -> Status {
+    match Operation::from_hex(hex) {
+        Ok(operation) => {
+            println!("{operation}");
+            Status::Success
+        }
+        Err(e) => {
+            eprintln!("Could not decode {hex:?}. {e}");
+            Status::ParseError
+        }
+    }
+}
+
+/// Lists the servers announced in the local network and asks the user to pick one.
+#[cfg(feature = "mdns")]
+fn pick_server() -> Option<SocketAddr> {
+    println!("Looking for servers…");
+    let servers = match crate::discover(Duration::from_secs(3)) {
+        Ok(servers) => servers,
+        Err(e) => {
+            eprintln!("Could not look for servers. {e}");
+            return None;
+        }
+    };
+    let servers: Vec<_> = servers
+        .into_iter()
+        .filter_map(|server| Some((server.name, *server.addresses.first()?)))
+        .collect();
+    if servers.is_empty() {
+        eprintln!("No servers found");
+        return None;
+    }
+    if !stdin().is_terminal() {
+        // The standard input holds the operations
+        if let [(name, addr)] = &servers[..] {
+            eprintln!("Using {name} ({addr})");
+            return Some(*addr);
+        }
+        eprintln!("Found several servers. Run interactively to pick one of them");
+        return None;
+    }
+    for (n, (name, addr)) in servers.iter().enumerate() {
+        println!("{:>3}) {name} ({addr})", n + 1);
+    }
+
+    loop {
+        print!("Server number: ");
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+        let mut line = String::new();
+        if stdin().read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        match line.trim().parse::<usize>() {
+            Ok(n) if (1..=servers.len()).contains(&n) => return Some(servers[n - 1].1),
+            _ => println!("Please, enter a number between 1 and {}", servers.len()),
+        }
+    }
+}
+
+/// Computes every operation of the standard input over QUIC.
+#[cfg(feature = "quic")]
+fn run_quic(server: SocketAddr, radix: Option<Radix>) -> Status {
+    let mut client = match crate::QuicClient::connect(server) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Could not connect to the server. {e}");
+            return Status::ConnectError;
+        }
+    };
+
+    let status = run_each(
+        |operation| client.compute(operation),
+        |e| match e {
+            crate::QuicError::Rejected(rejection) => Some(*rejection),
+            _ => None,
+        },
+        radix,
+    );
+    client.close();
+
+    status
+}
+
+/// Computes every operation of the standard input over SCTP.
+#[cfg(all(feature = "sctp", target_os = "linux"))]
+fn run_sctp(server: SocketAddr, radix: Option<Radix>) -> Status {
+    let mut client = match crate::SctpClient::connect(server) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Could not connect to the server. {e}");
+            return Status::ConnectError;
+        }
+    };
+
+    run_each(
+        |operation| client.compute(operation),
+        |e| match e {
+            ClientError::Rejected(rejection) => Some(*rejection),
+            _ => None,
+        },
+        radix,
+    )
+}
+
+/// Calculates every operation of the standard input locally, printing the same
+/// answers a server would.
+fn run_offline(radix: Option<Radix>) -> Status {
+    let mut acc = 0;
+    run_each(
+        |operation| {
+            let result = operation.reduce().map_err(|e| Rejection::from(&e))?;
+            let answer = Answer::accumulate(acc, result);
+            acc = answer.value;
+            Ok(answer)
+        },
+        |rejection| Some(*rejection),
+        radix,
+    )
+}
+
+/// How the operands are read, in the base of `--radix`.
+fn parser_options(radix: Option<Radix>) -> ParserOptions {
+    ParserOptions {
+        radix: radix.unwrap_or_default(),
+        ..ParserOptions::lenient()
+    }
+}
+
+/// The answer as printed: with its grouped digits in the base of `--radix`, if
+/// given.
+fn show(answer: Answer, radix: Option<Radix>) -> String {
+    match radix {
+        Some(radix) => answer.format(radix),
+        None => answer.to_string(),
+    }
+}
+
+/// Reads the operations one by one and gets the answer of each with `compute`,
+/// for the transports that carry every operation whole on its own.
+fn run_each<E: std::fmt::Display>(
+    mut compute: impl FnMut(Operation) -> Result<Answer, E>,
+    rejection: impl Fn(&E) -> Option<Rejection>,
+    radix: Option<Radix>,
+) -> Status {
+    let mut status = Status::Success;
+    for line in stdin().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("Could not read the operations. {e}");
+                return Status::ParseError;
+            }
+        };
+        match line.trim() {
+            "" => continue,
+            "QUIT" | ":quit" => break,
+            _ => (),
+        }
+        match Operation::parse_with(&line, &parser_options(radix)) {
+            Ok(operation) => match compute(operation) {
+                Ok(answer) => println!("Accumulated value = {}", show(answer, radix)),
+                Err(e) => match rejection(&e) {
+                    Some(rejection) => {
+                        report_rejection(rejection);
+                        if status == Status::Success {
+                            status = Status::Rejected;
+                        }
+                    }
+                    None => {
+                        eprintln!("Could not get an answer from the server. {e}");
+                        return Status::ProtocolError;
+                    }
+                },
+            },
+            Err(e) => {
+                eprintln!("Could not parse operation {line:?}. {e}");
+                status = Status::ParseError;
+            }
+        }
+    }
+
+    status
+}
+
+/// Explains on the standard error why the server rejected an operation.
+fn report_rejection(rejection: Rejection) {
+    eprintln!(
+        "Operation rejected by the server. {rejection} (code {}). {}.",
+        rejection.code(),
+        rejection.hint()
+    );
+}
+
+/// Parses the operations of the standard input, up to its end or a `QUIT`,
+/// passing each to `send`, that may stop the batch with the status of an error.
+fn read_batch(
+    args: &Args,
+    summary: &mut Summary,
+    mut send: impl FnMut(Operation, &mut Summary) -> Result<(), Status>,
+) -> Result<Status, Status> {
+    let mut status = Status::Success;
+    for line in stdin().lines() {
+        let line = line.map_err(|e| {
+            summary.count_error("read errors");
+            eprintln!("Could not read the operations. {e}");
+            Status::ParseError
+        })?;
+        match line.trim() {
+            "" => continue,
+            "QUIT" | ":quit" => break,
+            command if command.starts_with(':') => {
+                eprintln!("Ignoring {command} in batch mode");
+                continue;
+            }
+            _ => (),
+        }
+        match Operation::parse_with(&line, &parser_options(args.radix)) {
+            Ok(operation) => {
+                summary.count_operation(operation.tag());
+                send(operation, summary)?;
+            }
+            Err(e) => {
+                summary.count_error("parse errors");
+                eprintln!("Could not parse operation {line:?}. {e}");
+                if status == Status::Success {
+                    status = Status::ParseError;
+                }
+                if args.fail_fast {
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(status)
+}
+
+/// Sends every operation without waiting for the answers, then half-closes the
+/// connection and prints the answers as they arrive.
+fn run_batch(client: &mut Client, args: &Args, summary: &mut Summary) -> Status {
+    let sent = read_batch(args, summary, |operation, summary| {
+        client.send_operation(operation).map_err(|e| {
+            count_failure(summary, &e);
+            eprintln!("Could not send the operation to the server. {e}");
+            Status::from(&e)
+        })
+    });
+    let mut status = match sent {
+        Ok(status) => status,
+        Err(status) => return status,
+    };
+
+    let status = match client.finish() {
+        Ok(answers) => {
+            for answer in answers {
+                match answer {
+                    Ok(answer) => println!("Accumulated value = {}", show(answer, args.radix)),
+                    Err(rejection) => {
+                        summary.count_rejection(rejection);
+                        report_rejection(rejection);
+                        if status == Status::Success {
+                            status = Status::Rejected;
+                        }
+                    }
+                }
+            }
+            status
+        }
+        Err(e) => {
+            count_failure(summary, &e);
+            eprintln!("Could not get the answers from the server. {e}");
+            Status::from(&e)
+        }
+    };
+    #[cfg(all(feature = "tcp-info", target_os = "linux"))]
+    if args.stats {
+        print_tcp_info(client);
+    }
+
+    status
+}
+
+/// Like [`run_batch`], but writing the operations to the journal at `path`
+/// first, or taking those left in it with `--resume`, and sending them one at a
+/// time with their idempotency keys, so that the server replays the answers of
+/// those it already calculated instead of applying them again.
+fn run_journal(client: &mut Client, path: &Path, args: &Args, summary: &mut Summary) -> Status {
+    let mut status = Status::Success;
+    let opened = if args.resume {
+        Journal::resume(path)
+    } else {
+        let mut operations = Vec::new();
+        // Not even a journal of part of them
+        status = match read_batch(args, summary, |operation, _| {
+            operations.push(operation);
+            Ok(())
+        }) {
+            Ok(status) => status,
+            Err(status) => return status,
+        };
+        Journal::create(path, operations)
+    };
+    let (mut journal, entries) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            eprintln!("Could not open the journal {path:?}. {e}");
+            return Status::ParseError;
+        }
+    };
+    if args.resume {
+        eprintln!("Resuming {} unanswered operations", entries.len());
+        for (_, operation) in &entries {
+            summary.count_operation(operation.tag());
+        }
+    }
+
+    for (key, operation) in entries {
+        match client.compute_with_key(operation, key) {
+            Ok(answer) => println!("Accumulated value = {}", show(answer, args.radix)),
+            Err(ClientError::Rejected(rejection)) => {
+                summary.count_rejection(rejection);
+                report_rejection(rejection);
+                if status == Status::Success {
+                    status = Status::Rejected;
+                }
+            }
+            Err(e) => {
+                count_failure(summary, &e);
+                eprintln!("Could not get the answer from the server. {e}");
+                eprintln!("Send the rest with --resume --journal {path:?}");
+                return Status::from(&e);
+            }
+        }
+        if let Err(e) = journal.answered(key) {
+            eprintln!("Could not write to the journal {path:?}. {e}");
+            return Status::ParseError;
+        }
+    }
+    if let Err(e) = journal.remove() {
+        eprintln!("Could not remove the journal {path:?}. {e}");
+    }
+
+    status
+}
+
+/// Prints what the kernel knows of the connection to the server.
+#[cfg(all(feature = "tcp-info", target_os = "linux"))]
+fn print_tcp_info(client: &Client) {
+    match client.tcp_info() {
+        Ok(info) => println!("TCP connection: {info}"),
+        Err(e) => eprintln!("Could not get the statistics of the connection. {e}"),
+    }
+}
+
+#[derive(Debug, Default)]
+struct SessionStats {
+    operations: usize,
+    parse_errors: usize,
+    accumulator: Option<i64>,
+}
+
+/// Pings the server every `period`, as told by the `clock`, until it fails.
+fn heartbeat(client: &Mutex<Client>, period: Duration, clock: &dyn Clock) -> ClientError {
+    loop {
+        clock.sleep(period);
+        if let Err(e) = client.lock().unwrap().ping() {
+            return e;
+        }
+    }
+}
+
+fn run_interactive(client: Arc<Mutex<Client>>, args: &Args, summary: &mut Summary) -> Status {
+    {
+        let mut client = client.lock().unwrap();
+        client.set_progress_handler(|Progress(percent)| eprintln!("{percent}% calculated..."));
+        // Servers only tell their progress to the clients that say hello
+        if let Err(e) = client.capabilities() {
+            eprintln!("Could not get the capabilities of the server. {e}");
+            return Status::from(&e);
+        }
+    }
+
+    if let Some(period) = args.heartbeat {
+        let client = Arc::clone(&client);
+        thread::spawn(move || {
+            let e = heartbeat(&client, Duration::from_secs(period), &SystemClock);
+            eprintln!("Heartbeat failed. {e}");
+        });
+    }
+
+    let mut editor = match Editor::<ReplHelper, DefaultHistory>::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("Could not start the interactive editor. {e}");
+            return Status::ProtocolError;
+        }
+    };
+    editor.set_helper(Some(ReplHelper));
+
+    let mut recorder = match &args.record {
+        Some(path) => match Recorder::create(path, &SystemClock) {
+            Ok(recorder) => Some(recorder),
+            Err(e) => {
+                eprintln!("Could not create the recording {path:?}. {e}");
+                return Status::IoError;
+            }
+        },
+        None => None,
+    };
+
+    println!("Enter arithmetic expressions using infix notation. For example: 10 * 3 or 5!.");
+    println!("Use :help to list the commands and operators. Press Tab to complete commands.");
+
+    let mut stats = SessionStats::default();
+    loop {
+        let line = match editor.readline(PROMPT) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("Could not read the input. {e}");
+                break;
+            }
+        };
+        let _ = editor.add_history_entry(line.as_str());
+
+        match line.trim() {
+            "" => continue,
+            "QUIT" | ":quit" => break,
+            ":help" => {
+                print!("{}", repl::help());
+                continue;
+            }
+            ":stats" => {
+                println!(
+                    "Operations: {}. Parse errors: {}. Accumulated value: {}",
+                    stats.operations,
+                    stats.parse_errors,
+                    stats
+                        .accumulator
+                        .map_or("unknown".to_string(), |acc| acc.to_string())
+                );
+                #[cfg(all(feature = "tcp-info", target_os = "linux"))]
+                print_tcp_info(&client.lock().unwrap());
+                continue;
+            }
+            ":ping" => {
+                match client.lock().unwrap().ping() {
+                    Ok(rtt) => println!("Pong received in {rtt:?}"),
+                    Err(e) => {
+                        eprintln!("Could not ping the server. {e}");
+                        return Status::from(&e);
+                    }
+                }
+                continue;
+            }
+            line if matches!(line.split(' ').next(), Some(":store" | ":load")) => {
+                let (command, name) = line.split_once(' ').unwrap_or((line, ""));
+                let &[name] = name.trim().as_bytes() else {
+                    println!("Name the register with a single character, as in {command} x.");
+                    continue;
+                };
+                let mut client = client.lock().unwrap();
+                let start = Instant::now();
+                let (tag, result) = match command {
+                    ":store" => (TlvType::Store, client.store(name)),
+                    _ => (TlvType::Load, client.load(name)),
+                };
+                summary.count_operation(tag);
+                match result {
+                    Ok(answer) => {
+                        summary.add_latency(start.elapsed());
+                        stats.accumulator = Some(answer.value);
+                        println!("Accumulated value = {}", show(answer, args.radix))
+                    }
+                    Err(ClientError::Rejected(rejection)) => {
+                        summary.add_latency(start.elapsed());
+                        summary.count_rejection(rejection);
+                        println!("{rejection}. {}. Please, try again.", rejection.hint())
+                    }
+                    Err(e) => {
+                        count_failure(summary, &e);
+                        eprintln!("Could not get an answer from the server. {e}");
+                        return Status::from(&e);
+                    }
+                }
+                continue;
+            }
+            #[cfg(feature = "audit")]
+            ":audit" => {
+                match client.lock().unwrap().audit() {
+                    Ok(report) => println!(
+                        "Requests {}. Answers {}.",
+                        if report.requests_intact {
+                            "intact"
+                        } else {
+                            "ALTERED"
+                        },
+                        if report.answers_intact {
+                            "intact"
+                        } else {
+                            "ALTERED"
+                        }
+                    ),
+                    Err(e) => {
+                        eprintln!("Could not audit the session. {e}");
+                        return Status::from(&e);
+                    }
+                }
+                continue;
+            }
+            _ => (),
+        }
+        match Operation::parse_with(&line, &parser_options(args.radix)) {
+            Ok(operation) => {
+                summary.count_operation(operation.tag());
+                if let Some(recorder) = &mut recorder {
+                    if let Err(e) = recorder.record(&operation) {
+                        eprintln!("Could not write to the recording. {e}");
+                    }
+                }
+                let start = Instant::now();
+                match client.lock().unwrap().compute(operation) {
+                    Ok(answer) => {
+                        summary.add_latency(start.elapsed());
+                        stats.operations += 1;
+                        stats.accumulator = Some(answer.value);
+                        println!("Accumulated value = {}", show(answer, args.radix))
+                    }
+                    Err(ClientError::Rejected(rejection)) => {
+                        summary.add_latency(start.elapsed());
+                        summary.count_rejection(rejection);
+                        println!("{rejection}. {}. Please, try again.", rejection.hint())
+                    }
+                    Err(e) => {
+                        count_failure(summary, &e);
+                        eprintln!("Could not get an answer from the server. {e}");
+                        return Status::from(&e);
+                    }
+                }
+            }
+            Err(e) => {
+                summary.count_error("parse errors");
+                stats.parse_errors += 1;
+                if let OperationError::Parse { position, .. }
+                | OperationError::Literal { position, .. } = e
+                {
+                    println!("{}^", " ".repeat(PROMPT.len() + position));
+                }
+                println!("{e}. Please, try again.");
+            }
+        }
+    }
+
+    let result = client.lock().unwrap().close();
+    #[cfg(all(feature = "tcp-info", target_os = "linux"))]
+    if args.stats {
+        print_tcp_info(&client.lock().unwrap());
+    }
+    match result {
+        Ok(()) => Status::Success,
+        Err(e) => {
+            eprintln!("The server did not acknowledge the end of the session. {e}");
+            Status::from(&e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::Mutex,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{destination, heartbeat, Destination, Status};
+    use crate::{errors::ErrorKind, Client, Clock, FakeClock, Server, ServerConfig};
+
+    #[test]
+    fn exit_codes() {
+        for kind in [
+            ErrorKind::Parse,
+            ErrorKind::Config,
+            ErrorKind::Calculation,
+            ErrorKind::Connection,
+            ErrorKind::Timeout,
+            ErrorKind::Protocol,
+            ErrorKind::Rejected,
+            ErrorKind::Auth,
+            ErrorKind::Io,
+        ] {
+            assert_eq!(Status::from(kind) as u8, kind.exit_code(), "{kind}");
+        }
+    }
+
+    #[test]
+    fn destinations() {
+        let destination = |host: &str, port| Destination {
+            host: host.to_string(),
+            port,
+        };
+        for (typed, expected) in [
+            ("192.0.2.7", destination("192.0.2.7", None)),
+            ("192.0.2.7:7777", destination("192.0.2.7", Some(7777))),
+            ("localhost:7777", destination("localhost", Some(7777))),
+            ("::1", destination("::1", None)),
+            ("[::1]", destination("::1", None)),
+            ("[::1]:7777", destination("::1", Some(7777))),
+        ] {
+            assert_eq!(typed.parse(), Ok(expected), "{typed}");
+        }
+
+        for typed in [
+            "",
+            "localhost:0",
+            "localhost:port",
+            "[::1",
+            "[::1]7777",
+            "fe80::1:x:7777",
+        ] {
+            assert!(typed.parse::<Destination>().is_err(), "{typed}");
+        }
+        let e = "[192.0.2.7]:7777".parse::<Destination>().unwrap_err();
+        assert_eq!(e, "192.0.2.7 is not an IPv6 address");
+    }
+
+    #[test]
+    fn environment() {
+        let env = |server: &'static str, port: &'static str| {
+            move |name: &str| match name {
+                "TCP1_SERVER" if !server.is_empty() => Some(server.to_string()),
+                "TCP1_PORT" if !port.is_empty() => Some(port.to_string()),
+                _ => None,
+            }
+        };
+        let picked = |server: Option<&str>, port, var| {
+            let server = server.map(|s| s.parse::<Destination>().unwrap());
+            destination(server.as_ref(), port, false, var)
+                .map(|d| d.map(|(d, port)| format!("{} {port}", d.host)))
+                .map_err(|(message, _)| message)
+        };
+        let ok = |s: &str| Ok(Some(s.to_string()));
+
+        // The command line goes before the environment
+        assert_eq!(picked(Some("a:1"), None, env("b:2", "3")), ok("a 1"));
+        assert_eq!(picked(Some("a"), Some(1), env("b:2", "3")), ok("a 1"));
+        assert_eq!(picked(Some("a"), None, env("b:2", "3")), ok("a 3"));
+        // And the port along the server before TCP1_PORT
+        assert_eq!(picked(None, None, env("b:2", "3")), ok("b 2"));
+        assert_eq!(picked(None, None, env("b", "3")), ok("b 3"));
+        assert_eq!(
+            picked(None, Some(1), env("", "")),
+            Err("no server to connect to".into())
+        );
+
+        for (server, port, e) in [
+            ("", "", "no server to connect to"),
+            ("b", "", "no port for the server b"),
+            (
+                "b:x",
+                "3",
+                "invalid TCP1_SERVER: x is not a port. Use a number from 1 to 65535",
+            ),
+            ("b", "0", "invalid TCP1_PORT: 0 is not a port"),
+        ] {
+            assert_eq!(picked(None, None, env(server, port)), Err(e.into()));
+        }
+        assert_eq!(
+            picked(Some("a:1"), Some(1), env("", "")),
+            Err("the port is given twice, as 1 and 1".into())
+        );
+        assert_eq!(destination(None, None, true, env("b:2", "3")), Ok(None));
+    }
+
+    #[test]
+    fn heartbeat_on_fake_clock() {
+        let mut server = Server::bind(ServerConfig::default()).unwrap();
+        let address = SocketAddr::from(([127, 0, 0, 1], server.local_addr().unwrap().port()));
+        thread::spawn(move || server.run());
+
+        let client = Mutex::new(Client::connect(address, None).unwrap());
+        let clock = FakeClock::new();
+        let (start, real_start) = (clock.now(), Instant::now());
+        let period = Duration::from_secs(3600);
+        thread::scope(|scope| {
+            let pings = scope.spawn(|| heartbeat(&client, period, &clock));
+            // Hours of pings, without waiting for them
+            while clock.since(start) < 3 * period {
+                thread::sleep(Duration::from_millis(1));
+            }
+            client.lock().unwrap().close().unwrap();
+            pings.join().unwrap();
+        });
+        assert!(real_start.elapsed() < period);
+    }
+}
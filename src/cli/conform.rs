@@ -0,0 +1,59 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! `tcp1 conform`: goes through the checks of `tcp1 selftest` that leave the
+//! configuration alone against a server running elsewhere, such as one
+//! written by the students.
+
+use std::process::ExitCode;
+
+use super::{
+    resolve,
+    selftest::{self, Bench, CHECKS},
+};
+
+const ABOUT: &str = "Check that a server follows the protocol of the remote TCP calculator";
+
+#[derive(Debug, clap::Args)]
+#[command(about = ABOUT)]
+pub struct Args {
+    /// Address of the server, with the port after a colon, as in localhost:7777
+    #[arg(value_name = "SERVER")]
+    server: String,
+}
+
+pub fn run(args: Args) -> ExitCode {
+    let bench = resolve(&args.server).and_then(Bench::remote);
+    let mut bench = match bench {
+        Ok(bench) => bench,
+        Err(e) => {
+            eprintln!("{e:#}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let checks = CHECKS
+        .iter()
+        .filter(|(_, _, configures)| !configures)
+        .map(|&(name, check, _)| (name, check));
+
+    selftest::check(&mut bench, checks)
+}
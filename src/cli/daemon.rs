@@ -0,0 +1,115 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Running the server unattended: detaching from the terminal, writing a pidfile
+//! and dropping privileges once the socket is bound.
+
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    process,
+};
+
+use nix::unistd::{chdir, dup2, fork, setgid, setsid, setuid, ForkResult, User};
+
+#[derive(Clone, Debug, clap::Args)]
+pub struct DaemonArgs {
+    /// Detach from the terminal and keep running in the background
+    #[arg(long)]
+    daemon: bool,
+    /// Write the process id to FILE
+    #[arg(long, value_name = "FILE")]
+    pidfile: Option<PathBuf>,
+    /// Append the output to FILE instead of discarding it when running in the background
+    #[arg(long, value_name = "FILE", requires = "daemon")]
+    log_file: Option<PathBuf>,
+    /// Switch to this user once the socket is bound
+    #[arg(long)]
+    user: Option<String>,
+}
+
+impl DaemonArgs {
+    /// Detaches, writes the pidfile and drops privileges, as requested. Call it
+    /// after binding the socket, so that privileged ports can still be used.
+    pub fn apply(&self) -> io::Result<()> {
+        let pidfile = match &self.pidfile {
+            Some(path) => Some(env::current_dir()?.join(path)),
+            None => None,
+        };
+
+        if self.daemon {
+            detach(self.log_file.as_deref())?;
+        }
+        if let Some(path) = pidfile {
+            fs::write(path, format!("{}\n", process::id()))?;
+        }
+        if let Some(name) = &self.user {
+            drop_privileges(name)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn detach(log_file: Option<&Path>) -> io::Result<()> {
+    // Open the files before forking, so that errors still reach the terminal
+    let output = match log_file {
+        Some(path) => OpenOptions::new().create(true).append(true).open(path)?,
+        None => OpenOptions::new().write(true).open("/dev/null")?,
+    };
+    let input = File::open("/dev/null")?;
+    io::stdout().flush()?;
+
+    // SAFETY: the server has not started any thread yet
+    if let ForkResult::Parent { .. } = unsafe { fork() }? {
+        process::exit(0);
+    }
+    setsid()?;
+    // Fork again, so that the daemon can never acquire a controlling terminal
+    if let ForkResult::Parent { .. } = unsafe { fork() }? {
+        process::exit(0);
+    }
+
+    chdir("/")?;
+    dup2(input.as_raw_fd(), io::stdin().as_raw_fd())?;
+    dup2(output.as_raw_fd(), io::stdout().as_raw_fd())?;
+    dup2(output.as_raw_fd(), io::stderr().as_raw_fd())?;
+
+    Ok(())
+}
+
+fn drop_privileges(name: &str) -> io::Result<()> {
+    let user = User::from_name(name)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Unknown user {name}")))?;
+
+    // Groups first, as they cannot be changed anymore once the user is not root
+    #[cfg(not(target_vendor = "apple"))]
+    nix::unistd::setgroups(&[user.gid])?;
+    setgid(user.gid)?;
+    setuid(user.uid)?;
+    println!("Running as {name}");
+
+    Ok(())
+}
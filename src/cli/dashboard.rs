@@ -0,0 +1,246 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Live dashboard of `tcp1ser --tui`, fed by the events of the server. The logs
+//! are discarded meanwhile, so that they do not garble the screen.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    net::SocketAddr,
+    os::fd::{AsFd, AsRawFd},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+use nix::unistd::dup2;
+use ratatui::{
+    backend::CrosstermBackend,
+    crossterm::{
+        event::{self, Event, KeyCode, KeyEventKind},
+        execute,
+        terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Layout},
+    text::Line,
+    widgets::{Block, List, Paragraph},
+    Frame, Terminal,
+};
+
+use crate::{Server, ServerEvent};
+
+/// Lines kept in the log and the list of errors.
+const LOG_LINES: usize = 200;
+const ERRORS: usize = 20;
+
+/// Serves from another thread while showing the dashboard, until the user quits.
+pub fn run(mut server: Server) -> io::Result<()> {
+    let (events, received) = mpsc::channel();
+    server.set_observer(move |event| {
+        let _ = events.send(event.clone());
+    });
+    thread::spawn(move || server.run());
+
+    // Keep the terminal, and send the output of the server nowhere
+    let tty = File::from(io::stdout().as_fd().try_clone_to_owned()?);
+    let null = OpenOptions::new().write(true).open("/dev/null")?;
+    dup2(null.as_raw_fd(), io::stdout().as_raw_fd())?;
+    dup2(null.as_raw_fd(), io::stderr().as_raw_fd())?;
+
+    let mut terminal = Terminal::new(CrosstermBackend::new(tty))?;
+    enable_raw_mode()?;
+    execute!(terminal.backend_mut(), EnterAlternateScreen)?;
+    let result = Dashboard::default().run(&mut terminal, &received);
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+
+    result
+}
+
+/// A client, as seen by the dashboard.
+#[derive(Debug, Default)]
+struct Session {
+    connected: bool,
+    operations: u64,
+    accumulator: Option<i64>,
+}
+
+#[derive(Debug, Default)]
+struct Dashboard {
+    sessions: BTreeMap<SocketAddr, Session>,
+    /// When the operations of the last second were answered
+    recent: VecDeque<Instant>,
+    operations: u64,
+    errors: VecDeque<String>,
+    log: VecDeque<String>,
+}
+
+impl Dashboard {
+    fn run<W: Write>(
+        &mut self,
+        terminal: &mut Terminal<CrosstermBackend<W>>,
+        events: &Receiver<ServerEvent>,
+    ) -> io::Result<()> {
+        loop {
+            for event in events.try_iter() {
+                self.record(&event);
+            }
+            let now = Instant::now();
+            while self
+                .recent
+                .front()
+                .is_some_and(|&at| now - at > Duration::from_secs(1))
+            {
+                self.recent.pop_front();
+            }
+
+            terminal.draw(|frame| self.draw(frame))?;
+            if event::poll(Duration::from_millis(250))? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press
+                        && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+                    {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    fn record(&mut self, event: &ServerEvent) {
+        let line = match event {
+            ServerEvent::Connected { peer } => {
+                self.sessions.entry(*peer).or_default().connected = true;
+                format!("{peer} connected")
+            }
+            ServerEvent::Answered {
+                peer,
+                operation,
+                outcome,
+            } => {
+                let session = self.sessions.entry(*peer).or_default();
+                session.operations += 1;
+                self.operations += 1;
+                self.recent.push_back(Instant::now());
+                match outcome {
+                    Ok(answer) => {
+                        session.accumulator = Some(answer.value);
+                        format!("{peer}: {} = {answer}", operation.name())
+                    }
+                    Err(rejection) => {
+                        let error = format!("{peer}: {} rejected. {rejection}", operation.name());
+                        push_bounded(&mut self.errors, error.clone(), ERRORS);
+                        error
+                    }
+                }
+            }
+            ServerEvent::Disconnected { peer, stats } => {
+                self.sessions.entry(*peer).or_default().connected = false;
+                format!("{peer} left: {stats}")
+            }
+            ServerEvent::Refused { peer } => {
+                let error = format!("{peer} refused: too many connections");
+                push_bounded(&mut self.errors, error.clone(), ERRORS);
+                error
+            }
+            ServerEvent::Denied { peer } => {
+                let error = format!("{peer} refused: network not allowed");
+                push_bounded(&mut self.errors, error.clone(), ERRORS);
+                error
+            }
+            ServerEvent::Tarpitted { peer } => {
+                let error = format!("{peer} tarpitted: too many invalid frames");
+                push_bounded(&mut self.errors, error.clone(), ERRORS);
+                error
+            }
+            ServerEvent::Scored {
+                peer,
+                right,
+                questions,
+            } => format!("{peer} scored {right} of {questions}"),
+        };
+        push_bounded(&mut self.log, line, LOG_LINES);
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [summary, middle, log, help] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Percentage(50),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [sessions, errors] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(middle);
+
+        let connected = self.sessions.values().filter(|session| session.connected);
+        frame.render_widget(
+            Paragraph::new(format!(
+                "Connections: {}   Operations/s: {}   Operations: {}",
+                connected.count(),
+                self.recent.len(),
+                self.operations
+            ))
+            .block(Block::bordered().title("tcp1ser")),
+            summary,
+        );
+
+        let lines: Vec<Line> = self
+            .sessions
+            .iter()
+            .map(|(peer, session)| {
+                Line::from(format!(
+                    "{peer:<40} {:<7} {:>6} ops  acc {}",
+                    if session.connected { "online" } else { "gone" },
+                    session.operations,
+                    session
+                        .accumulator
+                        .map_or("-".to_string(), |acc| acc.to_string())
+                ))
+            })
+            .collect();
+        frame.render_widget(
+            List::new(lines).block(Block::bordered().title("Sessions")),
+            sessions,
+        );
+        frame.render_widget(tail(&self.errors, errors.height, "Recent errors"), errors);
+        frame.render_widget(tail(&self.log, log.height, "Log"), log);
+        frame.render_widget(Paragraph::new("q quit"), help);
+    }
+}
+
+fn push_bounded(lines: &mut VecDeque<String>, line: String, max: usize) {
+    if lines.len() == max {
+        lines.pop_front();
+    }
+    lines.push_back(line);
+}
+
+/// The last lines that fit in a bordered box of `height`.
+fn tail<'a>(lines: &'a VecDeque<String>, height: u16, title: &'a str) -> List<'a> {
+    let skip = lines.len().saturating_sub(height.saturating_sub(2).into());
+    List::new(lines.iter().skip(skip).map(String::as_str)).block(Block::bordered().title(title))
+}
@@ -0,0 +1,157 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! `tcp1dump`: decodes the frames of a capture, or compares two captures to
+//! grade a session against the expected one.
+
+use std::{fs, path::PathBuf, process::ExitCode};
+
+use anyhow::Context;
+use clap::Parser;
+
+use super::{generate_if_requested, GenerateArgs};
+use crate::{
+    capture::{self, Alignment, CaptureStats, Captured},
+    filter::Filter,
+};
+
+const ABOUT: &str = "Decoder of captured sessions of the remote TCP calculator";
+
+/// Width of the expected side of a comparison.
+const COLUMN: usize = 40;
+
+#[derive(Debug, clap::Args)]
+#[command(about = ABOUT)]
+pub struct Args {
+    /// Raw bytes of one direction of a connection, as saved with "Follow TCP
+    /// Stream" in Wireshark
+    #[arg(value_name = "CAPTURE", required_unless_present = "compare")]
+    capture: Option<PathBuf>,
+    /// Compare two captures frame by frame instead, exiting with 1 if they differ
+    #[arg(long, num_args = 2, value_names = ["EXPECTED", "ACTUAL"], conflicts_with = "capture")]
+    compare: Option<Vec<PathBuf>>,
+    /// Only take the frames that match EXPR, as in 'tag==Div || length>2'. The
+    /// fields are tag, length and offset
+    #[arg(long, value_name = "EXPR")]
+    filter: Option<Filter>,
+    /// Print the number of frames of each tag instead of the frames
+    #[arg(long, conflicts_with = "compare")]
+    stats_only: bool,
+}
+
+/// Command line of the `tcp1dump` binary.
+#[derive(Debug, Parser)]
+#[command(name = "tcp1dump", about = ABOUT)]
+struct Standalone {
+    #[command(flatten)]
+    args: Args,
+    #[command(flatten)]
+    generate: GenerateArgs,
+}
+
+/// Entry point of the `tcp1dump` binary.
+pub fn main() -> ExitCode {
+    if let Some(code) = generate_if_requested::<Standalone>() {
+        return code;
+    }
+
+    run(Standalone::parse().args)
+}
+
+/// Exits with 0 if the captures are the same, 1 if they differ and 2 if they
+/// could not be read, as `cmp` does.
+pub fn run(args: Args) -> ExitCode {
+    let result = match (&args.compare, &args.capture) {
+        (Some(paths), _) => compare(&paths[0], &paths[1], args.filter.as_ref()),
+        (None, Some(path)) => dump(path, args.filter.as_ref(), args.stats_only).map(|()| true),
+        (None, None) => unreachable!("clap requires a capture"),
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(1),
+        Err(e) => {
+            eprintln!("{e:#}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn read(path: &PathBuf) -> anyhow::Result<Vec<u8>> {
+    fs::read(path).with_context(|| format!("Could not read the capture {path:?}"))
+}
+
+/// The frames of `capture` that pass the `filter`, if any.
+fn frames<'a>(capture: &'a [u8], filter: Option<&Filter>) -> Vec<Captured<'a>> {
+    let mut frames = capture::frames(capture);
+    if let Some(filter) = filter {
+        frames.retain(|frame| filter.matches(frame));
+    }
+
+    frames
+}
+
+fn dump(path: &PathBuf, filter: Option<&Filter>, stats_only: bool) -> anyhow::Result<()> {
+    let capture = read(path)?;
+    let frames = frames(&capture, filter);
+    if stats_only {
+        print!("{}", frames.iter().collect::<CaptureStats>());
+        return Ok(());
+    }
+    for frame in frames {
+        println!("{:>8}  {}", frame.offset, frame.describe());
+    }
+
+    Ok(())
+}
+
+/// Prints the frames that differ side by side, returning whether there were
+/// none. Those that do not pass the `filter` are left out of the comparison.
+fn compare(expected: &PathBuf, actual: &PathBuf, filter: Option<&Filter>) -> anyhow::Result<bool> {
+    let (expected, actual) = (read(expected)?, read(actual)?);
+    let (expected, actual) = (frames(&expected, filter), frames(&actual, filter));
+    let alignment = capture::align(&expected, &actual);
+
+    let side = |frame: Option<&Captured>| match frame {
+        Some(frame) => format!("@{} {}", frame.offset, frame.describe()),
+        None => String::new(),
+    };
+    let mut differences = 0;
+    for step in &alignment {
+        let (marker, left, right) = match step {
+            Alignment::Same(..) => continue,
+            Alignment::Changed(a, b) => ('~', Some(a), Some(b)),
+            Alignment::Missing(a) => ('-', Some(a), None),
+            Alignment::Extra(b) => ('+', None, Some(b)),
+        };
+        if differences == 0 {
+            println!("  {:COLUMN$} actual", "expected");
+        }
+        differences += 1;
+        println!("{marker} {:COLUMN$} {}", side(left), side(right));
+    }
+    match differences {
+        0 => println!("The {} frames are the same", alignment.len()),
+        _ => println!("{differences} of {} frames differ", alignment.len()),
+    }
+
+    Ok(differences == 0)
+}
@@ -0,0 +1,154 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Journal of the operations of a batch, so that `tcp1cli --resume` can send
+//! those left unanswered when the client stopped.
+//!
+//! Every line is appended as it happens: `op KEY COMMAND` for each operation,
+//! with its idempotency key in hexadecimal and its command form, and
+//! `done KEY` once the server answers it.
+
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{IdempotencyKey, Operation};
+
+/// An operation of the journal, with the key it is always sent with.
+pub(super) type Entry = (IdempotencyKey, Operation);
+
+#[derive(Debug)]
+pub(super) struct Journal {
+    path: PathBuf,
+    file: File,
+}
+
+impl Journal {
+    /// Writes the `operations` to a new journal at `path`, each with a key of
+    /// its own, returning them.
+    pub fn create(path: &Path, operations: Vec<Operation>) -> io::Result<(Self, Vec<Entry>)> {
+        let mut file = File::create(path)?;
+        let first = fastrand::u64(..);
+        let mut entries = Vec::with_capacity(operations.len());
+        let mut text = String::new();
+        for (key, operation) in (0..).map(|n| first.wrapping_add(n)).zip(operations) {
+            text += &format!("op {key:016x} {}\n", operation.to_command());
+            entries.push((IdempotencyKey(key), operation));
+        }
+        file.write_all(text.as_bytes())?;
+        file.sync_data()?;
+
+        let journal = Self {
+            path: path.to_path_buf(),
+            file,
+        };
+        Ok((journal, entries))
+    }
+
+    /// Opens the journal at `path`, returning the operations not answered yet.
+    pub fn resume(path: &Path) -> io::Result<(Self, Vec<Entry>)> {
+        let mut entries = Vec::new();
+        let mut answered = HashSet::new();
+        for (number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            let invalid = |reason: String| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Line {} of the journal: {reason}", number + 1),
+                )
+            };
+            let (kind, rest) = line.split_once(' ').unwrap_or((&line, ""));
+            let (key, command) = rest.split_once(' ').unwrap_or((rest, ""));
+            let key = u64::from_str_radix(key, 16)
+                .map_err(|_| invalid(format!("{key:?} is not a key")))?;
+            match kind {
+                "op" => {
+                    let operation =
+                        Operation::from_command(command).map_err(|e| invalid(e.to_string()))?;
+                    entries.push((IdempotencyKey(key), operation));
+                }
+                "done" => {
+                    answered.insert(key);
+                }
+                _ => return Err(invalid(format!("unknown entry {kind:?}"))),
+            }
+        }
+        entries.retain(|(key, _)| !answered.contains(&key.0));
+
+        let file = OpenOptions::new().append(true).open(path)?;
+        let journal = Self {
+            path: path.to_path_buf(),
+            file,
+        };
+        Ok((journal, entries))
+    }
+
+    /// Records that the server answered the operation with `key`.
+    pub fn answered(&mut self, key: IdempotencyKey) -> io::Result<()> {
+        writeln!(self.file, "done {:016x}", key.0)?;
+        self.file.sync_data()
+    }
+
+    /// Removes the journal, once every operation is answered.
+    pub fn remove(self) -> io::Result<()> {
+        fs::remove_file(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::Journal;
+
+    #[test]
+    fn resume() {
+        let path = env::temp_dir().join(format!("tcp1-journal-{}", process::id()));
+        let operations = ["3 + 4", "5!", "-2 * 7"].map(|operation| operation.parse().unwrap());
+        let (mut journal, entries) = Journal::create(&path, operations.to_vec()).unwrap();
+        assert_eq!(entries.len(), 3);
+        journal.answered(entries[0].0).unwrap();
+        drop(journal);
+
+        let (mut journal, pending) = Journal::resume(&path).unwrap();
+        assert_eq!(pending, entries[1..]);
+        journal.answered(entries[2].0).unwrap();
+        drop(journal);
+
+        let (journal, pending) = Journal::resume(&path).unwrap();
+        assert_eq!(pending, entries[1..2]);
+        journal.remove().unwrap();
+        assert!(Journal::resume(&path).is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        let path = env::temp_dir().join(format!("tcp1-journal-invalid-{}", process::id()));
+        std::fs::write(&path, "op 07 SUM 3 4\nop zz SUM 1 1\n").unwrap();
+        let error = Journal::resume(&path).unwrap_err();
+        assert!(error.to_string().starts_with("Line 2 of the journal"));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,185 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Command line interface of the programs. Both the `tcp1` multiplexed binary and
+//! the classic `tcp1cli` and `tcp1ser` ones are thin wrappers around this module.
+
+use std::{
+    env,
+    ffi::OsString,
+    io::{self, Write},
+    net::{SocketAddr, ToSocketAddrs},
+    process::ExitCode,
+};
+
+use anyhow::{anyhow, Context};
+use clap::{Args, CommandFactory, FromArgMatches};
+use clap_complete::Shell;
+
+pub mod bench;
+pub mod client;
+pub mod conform;
+#[cfg(unix)]
+mod daemon;
+#[cfg(all(feature = "tui", unix))]
+mod dashboard;
+pub mod dump;
+mod journal;
+#[cfg(feature = "tui")]
+pub mod proxy;
+mod recording;
+mod repl;
+pub mod replay;
+pub mod selftest;
+pub mod server;
+#[cfg(all(windows, feature = "windows-service"))]
+mod service;
+mod ui;
+
+/// Options to generate installation artifacts instead of running the program.
+#[derive(Debug, Args)]
+pub struct GenerateArgs {
+    /// Print the completion script for SHELL and exit
+    #[arg(long, value_name = "SHELL", exclusive = true)]
+    pub generate_completion: Option<Shell>,
+    /// Print the manual page in roff format and exit
+    #[arg(long, exclusive = true)]
+    pub generate_man: bool,
+}
+
+impl GenerateArgs {
+    /// Extracts the generation options from the arguments of the command `C`, which
+    /// must flatten a `GenerateArgs`, even if its mandatory arguments are missing.
+    pub fn from_command_line<C, I, T>(args: I) -> Self
+    where
+        C: CommandFactory,
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        C::command()
+            .ignore_errors(true)
+            .try_get_matches_from(args)
+            .ok()
+            .and_then(|matches| Self::from_arg_matches(&matches).ok())
+            .unwrap_or(Self {
+                generate_completion: None,
+                generate_man: false,
+            })
+    }
+
+    /// Writes the requested artifact for the command `C` to `out`.
+    ///
+    /// Returns `false` if nothing was requested, so the program should go on.
+    pub fn generate<C: CommandFactory>(&self, out: &mut dyn Write) -> io::Result<bool> {
+        let mut command = C::command();
+        let name = command.get_name().to_string();
+
+        if let Some(shell) = self.generate_completion {
+            clap_complete::generate(shell, &mut command, name, out);
+        } else if self.generate_man {
+            clap_mangen::Man::new(command).render(out)?;
+        } else {
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+}
+
+/// Generates the artifact requested in the command line of `C`, if any, and
+/// returns the code the program should exit with.
+pub fn generate_if_requested<C: CommandFactory>() -> Option<ExitCode> {
+    match GenerateArgs::from_command_line::<C, _, _>(env::args_os())
+        .generate::<C>(&mut io::stdout())
+    {
+        Ok(true) => Some(ExitCode::SUCCESS),
+        Ok(false) => None,
+        Err(e) => {
+            eprintln!("Could not generate the requested file. {e}");
+            Some(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// The secrets in the file at `path`, one per line as ID:SECRET.
+#[cfg(feature = "auth")]
+fn read_secrets(path: &std::path::Path) -> anyhow::Result<Vec<crate::auth::KeyedSecret>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Could not read {path:?}"))?;
+    crate::auth::KeyedSecret::parse_lines(&text)
+        .with_context(|| format!("Wrong secrets file {path:?}"))
+}
+
+/// The first address of the `server`, written as `host:port`.
+fn resolve(server: &str) -> anyhow::Result<SocketAddr> {
+    server
+        .to_socket_addrs()
+        .with_context(|| format!("Could not find the server {server}"))?
+        .next()
+        .ok_or_else(|| anyhow!("The server {server} has no address"))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use clap_complete::Shell;
+
+    use super::GenerateArgs;
+
+    #[derive(Debug, Parser)]
+    #[command(name = "tcp1test")]
+    struct Args {
+        port: u16,
+        #[command(flatten)]
+        generate: GenerateArgs,
+    }
+
+    #[test]
+    fn generate_without_positionals() {
+        let generate = GenerateArgs::from_command_line::<Args, _, _>([
+            "tcp1test",
+            "--generate-completion",
+            "bash",
+        ]);
+        assert_eq!(generate.generate_completion, Some(Shell::Bash));
+
+        let mut out = Vec::new();
+        assert!(generate.generate::<Args>(&mut out).unwrap());
+        assert!(String::from_utf8(out).unwrap().contains("tcp1test"));
+    }
+
+    #[test]
+    fn generate_man() {
+        let generate =
+            GenerateArgs::from_command_line::<Args, _, _>(["tcp1test", "--generate-man"]);
+        let mut out = Vec::new();
+        assert!(generate.generate::<Args>(&mut out).unwrap());
+        assert!(String::from_utf8(out).unwrap().contains(".TH tcp1test"));
+    }
+
+    #[test]
+    fn nothing_to_generate() {
+        let generate = GenerateArgs::from_command_line::<Args, _, _>(["tcp1test", "7777"]);
+        assert!(!generate.generate::<Args>(&mut Vec::new()).unwrap());
+        assert!(Args::try_parse_from(["tcp1test", "7777"]).is_ok());
+        assert!(Args::try_parse_from(["tcp1test", "7777", "--generate-man"]).is_err());
+    }
+}
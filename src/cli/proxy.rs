@@ -0,0 +1,259 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Terminal user interface of `tcp1proxy`, to watch the frames between the
+//! clients and a server and to tamper with them.
+
+use std::{
+    fs, io,
+    net::{IpAddr, Ipv6Addr, SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    thread,
+    time::Duration,
+};
+
+use clap::Parser;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, List, ListState, Paragraph},
+    DefaultTerminal, Frame,
+};
+
+use super::{generate_if_requested, GenerateArgs};
+use crate::inspect::{Direction, Inspector, Schedule, Tamper};
+
+const ABOUT: &str = "Proxy that shows and tampers with the frames of the remote TCP calculator";
+
+const HELP: &str = "↑↓ connection  Tab direction  d drop  w delay  c corrupt  n none  q quit";
+
+/// How long a delayed frame waits.
+const DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Debug, clap::Args)]
+#[command(about = ABOUT)]
+pub struct Args {
+    /// Port number to listen on
+    #[arg(value_parser = clap::value_parser!(u16).range(1..))]
+    port: u16,
+    /// IP address of the server
+    server_ip: IpAddr,
+    /// Port number of the server
+    #[arg(value_parser = clap::value_parser!(u16).range(1..))]
+    server_port: u16,
+    /// Also inject the faults of this TOML schedule, at the frames it gives
+    #[arg(long, value_name = "FILE")]
+    schedule: Option<PathBuf>,
+}
+
+/// Command line of the `tcp1proxy` binary.
+#[derive(Debug, Parser)]
+#[command(name = "tcp1proxy", about = ABOUT)]
+struct Standalone {
+    #[command(flatten)]
+    args: Args,
+    #[command(flatten)]
+    generate: GenerateArgs,
+}
+
+/// Entry point of the `tcp1proxy` binary.
+pub fn main() -> ExitCode {
+    if let Some(code) = generate_if_requested::<Standalone>() {
+        return code;
+    }
+
+    run(Standalone::parse().args)
+}
+
+pub fn run(args: Args) -> ExitCode {
+    let listener = match TcpListener::bind((Ipv6Addr::UNSPECIFIED, args.port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Could not listen on port {}. {e}", args.port);
+            return ExitCode::FAILURE;
+        }
+    };
+    let schedule = match args.schedule.as_deref().map(load_schedule).transpose() {
+        Ok(schedule) => schedule.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("Could not load the schedule. {e:#}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let server = SocketAddr::from((args.server_ip, args.server_port));
+    let inspector = Inspector::with_schedule(schedule);
+    let relaying = inspector.clone();
+    thread::spawn(move || relaying.run(listener, server));
+
+    let mut terminal = ratatui::init();
+    let result = Ui::new(inspector).run(&mut terminal);
+    ratatui::restore();
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Could not draw the interface. {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn load_schedule(path: &Path) -> anyhow::Result<Schedule> {
+    Ok(fs::read_to_string(path)?.parse()?)
+}
+
+struct Ui {
+    inspector: Inspector,
+    selected: ListState,
+    direction: Direction,
+}
+
+impl Ui {
+    fn new(inspector: Inspector) -> Self {
+        Self {
+            inspector,
+            selected: ListState::default(),
+            direction: Direction::Request,
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if !event::poll(Duration::from_millis(200))? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            let count = self.inspector.connections().len();
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Up => self.selected.select_previous(),
+                KeyCode::Down if count > 0 => {
+                    let next = self.selected.selected().map_or(0, |n| n + 1);
+                    self.selected.select(Some(next.min(count - 1)));
+                }
+                KeyCode::Tab => {
+                    self.direction = match self.direction {
+                        Direction::Request => Direction::Answer,
+                        Direction::Answer => Direction::Request,
+                    }
+                }
+                KeyCode::Char('d') => self.tamper(Some(Tamper::Drop)),
+                KeyCode::Char('w') => self.tamper(Some(Tamper::Delay(DELAY))),
+                KeyCode::Char('c') => self.tamper(Some(Tamper::Corrupt)),
+                KeyCode::Char('n') => self.tamper(None),
+                _ => (),
+            }
+        }
+    }
+
+    fn tamper(&self, tamper: Option<Tamper>) {
+        if let Some(id) = self.selected.selected() {
+            self.inspector.tamper_next(id, self.direction, tamper);
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, help] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)])
+                .areas(main);
+
+        let connections = self.inspector.connections();
+        if self.selected.selected().is_none() && !connections.is_empty() {
+            self.selected.select_first();
+        }
+        let items: Vec<Line> = connections
+            .iter()
+            .map(|connection| {
+                let mut line = format!(
+                    "{} {}",
+                    connection.peer,
+                    if connection.is_open() {
+                        "open"
+                    } else {
+                        "closed"
+                    }
+                );
+                if let Some(tamper) = connection.next_tamper(self.direction) {
+                    line += &format!(" (next {tamper})");
+                }
+                Line::from(line)
+            })
+            .collect();
+        let title = match self.direction {
+            Direction::Request => "Connections [tampering with requests]",
+            Direction::Answer => "Connections [tampering with answers]",
+        };
+        frame.render_stateful_widget(
+            List::new(items)
+                .block(Block::bordered().title(title))
+                .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+            left,
+            &mut self.selected,
+        );
+
+        let frames: Vec<Line> = self
+            .selected
+            .selected()
+            .and_then(|id| connections.get(id))
+            .map(|connection| {
+                // The most recent ones that fit
+                let skip = connection
+                    .frames
+                    .len()
+                    .saturating_sub(right.height.saturating_sub(2).into());
+                connection
+                    .frames
+                    .iter()
+                    .skip(skip)
+                    .map(|relayed| {
+                        let arrow = match relayed.direction {
+                            Direction::Request => "→",
+                            Direction::Answer => "←",
+                        };
+                        let tamper = relayed
+                            .tamper
+                            .map(|tamper| format!(" ({tamper})"))
+                            .unwrap_or_default();
+                        Line::from(format!("{arrow} {}{tamper}", relayed.description))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        frame.render_widget(
+            List::new(frames).block(Block::bordered().title("Frames")),
+            right,
+        );
+        frame.render_widget(Paragraph::new(HELP), help);
+    }
+}
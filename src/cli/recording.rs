@@ -0,0 +1,222 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Sessions recorded with `tcp1cli --record`, for `tcp1replay` to send their
+//! operations again with the same pauses between them.
+//!
+//! The first line, `at SECS`, anchors the recording to the wall clock, in
+//! seconds since the Unix epoch. Every other line is `+MICROS COMMAND`: the time
+//! since the previous operation, or since the start for the first one, as told
+//! by a monotonic clock, and the command form of the operation. Replays only
+//! follow the pauses, so the clocks of the machines that record and replay need
+//! not agree, and adjusting the wall clock while recording does not distort them.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{Clock, Operation};
+
+/// Writes the operations of a session as they are sent.
+#[derive(Debug)]
+pub(super) struct Recorder<'a> {
+    file: File,
+    clock: &'a dyn Clock,
+    /// When the last operation was sent
+    last: Instant,
+}
+
+impl<'a> Recorder<'a> {
+    pub fn create(path: &Path, clock: &'a dyn Clock) -> io::Result<Self> {
+        let mut file = File::create(path)?;
+        // A system clock set before 1970 only spoils the anchor
+        let anchor = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        writeln!(file, "at {}", anchor.as_secs())?;
+
+        Ok(Self {
+            file,
+            clock,
+            last: clock.now(),
+        })
+    }
+
+    pub fn record(&mut self, operation: &Operation) -> io::Result<()> {
+        let now = self.clock.now();
+        let pause = now.saturating_duration_since(self.last);
+        self.last = now;
+        writeln!(
+            self.file,
+            "+{} {}",
+            pause.as_micros(),
+            operation.to_command()
+        )
+    }
+}
+
+/// A recorded session.
+#[derive(Debug, PartialEq)]
+pub(super) struct Recording {
+    /// When it started, by the clock of the machine that recorded it
+    pub anchor: SystemTime,
+    /// The operations, each with the pause before it
+    pub steps: Vec<(Duration, Operation)>,
+}
+
+impl Recording {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let invalid = |number: usize, reason: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Line {number} of the recording: {reason}"),
+            )
+        };
+
+        let first = lines.next().transpose()?.unwrap_or_default();
+        let anchor = first
+            .strip_prefix("at ")
+            .and_then(|secs| secs.parse().ok())
+            .ok_or_else(|| {
+                invalid(
+                    1,
+                    format!("{first:?} is not an anchor, as in at 1700000000"),
+                )
+            })?;
+        let mut steps = Vec::new();
+        for (number, line) in (2..).zip(lines) {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let (pause, command) = line.split_once(' ').unwrap_or((&line, ""));
+            let pause = pause
+                .strip_prefix('+')
+                .and_then(|micros| micros.parse().ok())
+                .ok_or_else(|| invalid(number, format!("{pause:?} is not a pause, as in +1500")))?;
+            let operation =
+                Operation::from_command(command).map_err(|e| invalid(number, e.to_string()))?;
+            steps.push((Duration::from_micros(pause), operation));
+        }
+
+        Ok(Self {
+            anchor: UNIX_EPOCH + Duration::from_secs(anchor),
+            steps,
+        })
+    }
+
+    /// How long ago it was recorded, or `None` if the clock of this machine
+    /// says it has not happened yet.
+    pub fn age(&self) -> Option<Duration> {
+        SystemTime::now().duration_since(self.anchor).ok()
+    }
+}
+
+/// Passes the operations of the `recording` to `send`, with the pauses they
+/// had when recorded shortened `speed` times, as told by the `clock`. The
+/// pauses count from the start of the replay, so the time `send` takes does
+/// not delay the operations after it. Stops at the first error of `send`.
+pub(super) fn replay<E>(
+    recording: &Recording,
+    speed: f64,
+    clock: &dyn Clock,
+    mut send: impl FnMut(&Operation) -> Result<(), E>,
+) -> Result<(), E> {
+    let start = clock.now();
+    let mut due = Duration::ZERO;
+    for (pause, operation) in &recording.steps {
+        // Saturating, as a recording may hold pauses as long as it wants
+        due = due.saturating_add(pause.div_f64(speed));
+        clock.sleep(due.saturating_sub(clock.since(start)));
+        send(operation)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, env, fs, process, time::Duration};
+
+    use super::{replay, Recorder, Recording};
+    use crate::{Clock, FakeClock};
+
+    #[test]
+    fn record_and_replay() {
+        let path = env::temp_dir().join(format!("tcp1-recording-{}", process::id()));
+        let clock = FakeClock::new();
+        let mut recorder = Recorder::create(&path, &clock).unwrap();
+        for (pause, operation) in [(2000, "3 + 4"), (500, "5!"), (1500, "-2 * 7")] {
+            clock.advance(Duration::from_millis(pause));
+            recorder.record(&operation.parse().unwrap()).unwrap();
+        }
+        drop(recorder);
+
+        let recording = Recording::read(&path).unwrap();
+        assert!(recording.age().unwrap() < Duration::from_secs(60));
+        let pauses: Vec<_> = recording
+            .steps
+            .iter()
+            .map(|(pause, _)| pause.as_millis())
+            .collect();
+        assert_eq!(pauses, [2000, 500, 1500]);
+
+        // Twice as fast, on a clock of its own
+        let clock = FakeClock::new();
+        let start = clock.now();
+        let mut sent = Vec::new();
+        replay(&recording, 2.0, &clock, |operation| {
+            sent.push((clock.since(start).as_millis(), operation.clone()));
+            // Taking its time, without delaying the next one
+            clock.advance(Duration::from_millis(100));
+            Ok::<_, Infallible>(())
+        })
+        .unwrap();
+        let at: Vec<_> = sent.iter().map(|(at, _)| *at).collect();
+        assert_eq!(at, [1000, 1250, 2000]);
+        let operations = recording.steps.into_iter().map(|(_, operation)| operation);
+        assert!(sent
+            .into_iter()
+            .map(|(_, operation)| operation)
+            .eq(operations));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn skewed_anchor() {
+        let path = env::temp_dir().join(format!("tcp1-recording-skewed-{}", process::id()));
+        // Recorded by a machine whose clock is far ahead
+        fs::write(&path, "at 99999999999\n+1000 SUM 3 4\n").unwrap();
+        let recording = Recording::read(&path).unwrap();
+        assert_eq!(recording.age(), None);
+        assert_eq!(recording.steps.len(), 1);
+
+        fs::write(&path, "at 0\n1000 SUM 3 4\n").unwrap();
+        let error = Recording::read(&path).unwrap_err();
+        assert!(error.to_string().starts_with("Line 2 of the recording"));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+use crate::Operation;
+use rustyline::{
+    completion::{Completer, Pair},
+    highlight::Highlighter,
+    hint::{Hint, Hinter},
+    validate::Validator,
+    Context, Helper,
+};
+
+pub const PROMPT: &str = "> ";
+
+pub const COMMANDS: &[(&str, &str)] = &[
+    (":help", "Show this help"),
+    (":ping", "Measure the round-trip time to the server"),
+    (":stats", "Show some statistics about this session"),
+    (
+        ":store",
+        "Save the accumulator in a register, as in :store x",
+    ),
+    (":load", "Set the accumulator to a register, as in :load x"),
+    #[cfg(feature = "audit")]
+    (
+        ":audit",
+        "Check that nothing altered the bytes exchanged with the server",
+    ),
+    (":quit", "End the session (QUIT works too)"),
+];
+
+/// Help text built from the commands and the operators known by the parser.
+pub fn help() -> String {
+    let mut help = String::from("Commands:\n");
+    for (command, description) in COMMANDS {
+        help += &format!("  {command:<8} {description}\n");
+    }
+
+    help += "Operations:\n";
+    for info in Operation::OPERATORS {
+        help += &format!(
+            "  {:<20} {:<8} e.g. {}\n",
+            info.name,
+            info.symbols.join(" "),
+            info.example
+        );
+    }
+
+    help
+}
+
+pub struct ReplHint {
+    display: String,
+    completion: Option<String>,
+}
+
+impl Hint for ReplHint {
+    fn display(&self) -> &str {
+        &self.display
+    }
+
+    fn completion(&self) -> Option<&str> {
+        self.completion.as_deref()
+    }
+}
+
+/// Completes command names and hints the available operators after the first operand.
+pub struct ReplHelper;
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let prefix = &line[..pos];
+        if !prefix.starts_with(':') {
+            return Ok((pos, Vec::new()));
+        }
+
+        let candidates = COMMANDS
+            .iter()
+            .filter(|(command, _)| command.starts_with(prefix))
+            .map(|(command, _)| Pair {
+                display: command.to_string(),
+                replacement: command.to_string(),
+            })
+            .collect();
+
+        Ok((0, candidates))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = ReplHint;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<ReplHint> {
+        if pos < line.len() || line.is_empty() {
+            return None;
+        }
+
+        if line.starts_with(':') {
+            let mut matches = COMMANDS
+                .iter()
+                .filter(|(command, _)| command.starts_with(line));
+            return match (matches.next(), matches.next()) {
+                (Some((command, _)), None) if command.len() > line.len() => Some(ReplHint {
+                    display: command[line.len()..].to_string(),
+                    completion: Some(command[line.len()..].to_string()),
+                }),
+                _ => None,
+            };
+        }
+
+        // Only the first operand so far: show the operators that may follow it
+        line.trim().parse::<i8>().ok().map(|_| {
+            let symbols: Vec<&str> = Operation::OPERATORS
+                .iter()
+                .filter(|info| !info.prefix)
+                .map(|info| info.symbols[0])
+                .collect();
+            ReplHint {
+                display: format!("  [{}]", symbols.join(" ")),
+                completion: None,
+            }
+        })
+    }
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
@@ -0,0 +1,145 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! `tcp1replay`: sends the operations of a session recorded with `tcp1cli
+//! --record` to a server again, with the same pauses between them, or scaled.
+
+use std::{ops::RangeInclusive, path::PathBuf, process::ExitCode};
+
+use anyhow::Context;
+use clap::Parser;
+
+use super::{
+    generate_if_requested,
+    recording::{self, Recording},
+    resolve, GenerateArgs,
+};
+use crate::{Client, ClientError, SystemClock};
+
+const ABOUT: &str = "Replayer of recorded sessions of the remote TCP calculator";
+
+#[derive(Debug, clap::Args)]
+#[command(about = ABOUT)]
+pub struct Args {
+    /// Address of the server, with the port after a colon, as in localhost:7777
+    #[arg(value_name = "SERVER")]
+    server: String,
+    /// Session recorded with tcp1cli --record
+    #[arg(value_name = "RECORDING")]
+    recording: PathBuf,
+    /// Play the pauses between the operations this many times faster, as in
+    /// 2.0, or slower, as in 0.5, from 0.01 to 100
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0, value_parser = speed)]
+    speed: f64,
+}
+
+/// Bounds of `--speed`, so that the scaled pauses always fit in a `Duration`.
+const SPEEDS: RangeInclusive<f64> = 0.01..=100.0;
+
+fn speed(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(speed) if SPEEDS.contains(&speed) => Ok(speed),
+        _ => Err(format!(
+            "{s} is not a factor from {} to {}, such as 2.0",
+            SPEEDS.start(),
+            SPEEDS.end()
+        )),
+    }
+}
+
+/// Command line of the `tcp1replay` binary.
+#[derive(Debug, Parser)]
+#[command(name = "tcp1replay", about = ABOUT)]
+struct Standalone {
+    #[command(flatten)]
+    args: Args,
+    #[command(flatten)]
+    generate: GenerateArgs,
+}
+
+/// Entry point of the `tcp1replay` binary.
+pub fn main() -> ExitCode {
+    if let Some(code) = generate_if_requested::<Standalone>() {
+        return code;
+    }
+
+    run(Standalone::parse().args)
+}
+
+/// Exits with 0 once every operation is answered, even if rejected, and with 1
+/// if the recording cannot be read or the server fails.
+pub fn run(args: Args) -> ExitCode {
+    match replay(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn replay(args: &Args) -> anyhow::Result<()> {
+    let path = &args.recording;
+    let recording =
+        Recording::read(path).with_context(|| format!("Could not read the recording {path:?}"))?;
+    let operations = recording.steps.len();
+    match recording.age() {
+        Some(age) => println!(
+            "Replaying {operations} operations recorded {} seconds ago",
+            age.as_secs()
+        ),
+        // Only the pauses matter, so a skewed clock is no reason to stop
+        None => println!(
+            "Replaying {operations} operations recorded by a clock ahead of the one of this computer"
+        ),
+    }
+
+    let server = resolve(&args.server)?;
+    let mut client = Client::connect(server, None).context("Could not connect to the server")?;
+    recording::replay(&recording, args.speed, &SystemClock, |operation| {
+        match client.compute(operation.clone()) {
+            Ok(answer) => println!("{operation}: accumulated value = {}", answer.value),
+            Err(ClientError::Rejected(rejection)) => println!("{operation}: {rejection}"),
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    })
+    .context("Could not get an answer from the server")?;
+
+    client
+        .close()
+        .context("Could not say goodbye to the server")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::speed;
+
+    #[test]
+    fn speeds() {
+        assert_eq!(speed("2.0"), Ok(2.0));
+        assert_eq!(speed("0.01"), Ok(0.01));
+        for wrong in ["0", "-1", "1e-300", "1e300", "inf", "NaN", "fast"] {
+            assert!(speed(wrong).is_err(), "{wrong}");
+        }
+    }
+}
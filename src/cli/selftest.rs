@@ -0,0 +1,291 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+//! `tcp1 selftest`: runs a server in the same process and goes through the
+//! protocol with it, to check that everything was built right.
+
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    num::NonZeroUsize,
+    process::ExitCode,
+    thread,
+    time::Duration,
+};
+
+use anyhow::{ensure, Context};
+
+use crate::{
+    tlv::Decoder, Answer, Client, ClientError, ConfigHandle, Operation, Rejection, Server,
+    ServerConfig,
+};
+
+const ABOUT: &str = "Check the client and the server against each other locally";
+
+#[derive(Debug, clap::Args)]
+#[command(about = ABOUT)]
+pub struct Args {}
+
+/// Server under test, and the accumulator it should have, as it is shared by
+/// all the connections.
+pub(super) struct Bench {
+    server: SocketAddr,
+    /// Of the server, when it runs in this process
+    config: Option<ConfigHandle>,
+    acc: i64,
+}
+
+impl Bench {
+    /// Starts a server on a free port, in a thread of its own.
+    fn start() -> io::Result<Self> {
+        let mut server = Server::bind(ServerConfig::default())?;
+        let bench = Bench {
+            server: SocketAddr::from(([127, 0, 0, 1], server.local_addr()?.port())),
+            config: Some(server.config_handle()),
+            acc: 0,
+        };
+        thread::spawn(move || server.run());
+        Ok(bench)
+    }
+
+    /// Checks the server at `server`, running elsewhere, asking it first for
+    /// its accumulator.
+    pub(super) fn remote(server: SocketAddr) -> anyhow::Result<Self> {
+        let mut bench = Bench {
+            server,
+            config: None,
+            acc: 0,
+        };
+        let mut client = bench.connect()?;
+        bench.acc = client
+            .compute("0 + 0".parse()?)
+            .context("Could not get the accumulator of the server")?
+            .value;
+        client.close()?;
+        Ok(bench)
+    }
+
+    /// The configuration of the server, if it runs in this process.
+    fn config(&self) -> anyhow::Result<&ConfigHandle> {
+        self.config
+            .as_ref()
+            .context("the configuration of the server cannot be changed from here")
+    }
+
+    fn connect(&self) -> anyhow::Result<Client> {
+        Client::connect(self.server, None).context("Could not connect to the server")
+    }
+
+    /// Computes `operation`, checking that the server adds it to the accumulator.
+    fn compute(&mut self, client: &mut Client, operation: &str) -> anyhow::Result<()> {
+        let operation: Operation = operation.parse()?;
+        let expected = self.acc + operation.reduce()?;
+        let answer = client.compute(operation.clone())?;
+        ensure!(
+            answer.value == expected,
+            "{operation} gave {}, not {expected}",
+            answer.value
+        );
+        self.acc = expected;
+        Ok(())
+    }
+}
+
+/// Part of the protocol checked against the server.
+type Check = fn(&mut Bench) -> anyhow::Result<()>;
+
+/// The checks, and whether they change the configuration of the server, so
+/// that they only run against one in this process.
+pub(super) const CHECKS: &[(&str, Check, bool)] = &[
+    ("every operation", operations, false),
+    ("registers and ping", registers, false),
+    ("malformed frames", malformed_frames, false),
+    ("split writes", split_writes, true),
+    ("timeouts", timeouts, true),
+];
+
+fn operations(bench: &mut Bench) -> anyhow::Result<()> {
+    let mut client = bench.connect()?;
+    for operator in Operation::OPERATORS {
+        bench.compute(&mut client, operator.example)?;
+    }
+    Ok(client.close()?)
+}
+
+fn registers(bench: &mut Bench) -> anyhow::Result<()> {
+    let mut client = bench.connect()?;
+    let stored = client.store(b'r')?.value;
+    ensure!(stored == bench.acc, "stored {stored}, not {}", bench.acc);
+    bench.compute(&mut client, "1 + 1")?;
+    let loaded = client.load(b'r')?.value;
+    ensure!(loaded == stored, "loaded {loaded}, not {stored}");
+    bench.acc = loaded;
+    client.ping()?;
+    Ok(client.close()?)
+}
+
+/// Sends frames the server must ignore, and checks it still answers.
+fn malformed_frames(bench: &mut Bench) -> anyhow::Result<()> {
+    let mut stream = TcpStream::connect(bench.server)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    // An unknown tag, and an answer, which only the server sends
+    stream.write_all(&[0xee, 1, 0])?;
+    stream.write_all(&Answer::from(7).encode())?;
+    stream.write_all(&"0 + 0".parse::<Operation>()?.encode())?;
+    stream.shutdown(Shutdown::Write)?;
+
+    let mut decoder = Decoder::new();
+    let mut buffer = [0; 64];
+    let frame = loop {
+        if let Some(frame) = decoder.next_frame()? {
+            break frame;
+        }
+        match stream.read(&mut buffer)? {
+            0 => anyhow::bail!("the server closed the connection without answering"),
+            len => decoder.extend(&buffer[..len]),
+        }
+    };
+    let answer = Answer::try_from(frame.as_tlv())?;
+    ensure!(
+        answer.value == bench.acc,
+        "answered {}, not {}",
+        answer.value,
+        bench.acc
+    );
+    Ok(())
+}
+
+/// Sends the operations a byte at a time, and gets the answers the same way.
+fn split_writes(bench: &mut Bench) -> anyhow::Result<()> {
+    let mut client = bench.connect()?;
+    client.set_chunked_writes(NonZeroUsize::new(1));
+    bench.compute(&mut client, "0 + 1")?;
+    client.set_chunked_writes(None);
+    let handle = bench.config()?.clone();
+    handle.update(|config| config.chunked_writes = NonZeroUsize::new(1));
+    let result = bench.compute(&mut client, "0 - 1");
+    handle.update(|config| config.chunked_writes = None);
+    result?;
+    Ok(client.close()?)
+}
+
+/// Delays the answers, first past the patience of the client and then past
+/// the time the server allows for an operation.
+fn timeouts(bench: &mut Bench) -> anyhow::Result<()> {
+    let handle = bench.config()?.clone();
+    let result = (|| {
+        handle.update(|config| config.delay = Duration::from_millis(300));
+        let mut client = bench.connect()?;
+        client.set_timeout(Some(Duration::from_millis(50)))?;
+        match client.compute("0 + 0".parse()?) {
+            Err(e) if e.is_timeout() => (),
+            other => anyhow::bail!("the client did not time out, but got {other:?}"),
+        }
+        drop(client);
+
+        handle.update(|config| config.op_timeout = Some(Duration::from_millis(50)));
+        let mut client = bench.connect()?;
+        match client.compute("0 + 0".parse()?) {
+            Err(ClientError::Rejected(Rejection::Timeout)) => (),
+            other => anyhow::bail!("the server did not time out, but answered {other:?}"),
+        }
+        Ok(client.close()?)
+    })();
+    handle.update(|config| {
+        config.delay = Duration::ZERO;
+        config.op_timeout = None;
+    });
+    result
+}
+
+pub fn run(_args: Args) -> ExitCode {
+    let mut bench = match Bench::start() {
+        Ok(bench) => bench,
+        Err(e) => {
+            eprintln!("Could not start the server. {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    check(
+        &mut bench,
+        CHECKS.iter().map(|&(name, check, _)| (name, check)),
+    )
+}
+
+/// Runs the `checks` against the server of the `bench`, printing a PASS or FAIL
+/// line for each, and fails unless they all pass.
+pub(super) fn check<'a>(
+    bench: &mut Bench,
+    checks: impl Iterator<Item = (&'a str, Check)>,
+) -> ExitCode {
+    let results: Vec<_> = checks.map(|(name, check)| (name, check(bench))).collect();
+
+    println!();
+    let mut passed = 0;
+    for (name, result) in &results {
+        match result {
+            Ok(()) => {
+                passed += 1;
+                println!("PASS {name}");
+            }
+            Err(e) => println!("FAIL {name}: {e:#}"),
+        }
+    }
+    println!("{passed} of {} checks passed", results.len());
+
+    if passed == results.len() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bench, CHECKS};
+
+    #[test]
+    fn checks_pass() {
+        let mut bench = Bench::start().unwrap();
+        for (name, check, _) in CHECKS {
+            if let Err(e) = check(&mut bench) {
+                panic!("{name}: {e:#}");
+            }
+        }
+    }
+
+    #[test]
+    fn remote_checks_pass() {
+        let local = Bench::start().unwrap();
+        let mut client = local.connect().unwrap();
+        client.compute("3 + 4".parse().unwrap()).unwrap();
+        client.close().unwrap();
+
+        let mut bench = Bench::remote(local.server).unwrap();
+        assert_eq!(bench.acc, 7);
+        for (name, check, _) in CHECKS.iter().filter(|(_, _, configures)| !configures) {
+            if let Err(e) = check(&mut bench) {
+                panic!("{name}: {e:#}");
+            }
+        }
+    }
+}
@@ -0,0 +1,661 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    net::{Ipv4Addr, SocketAddr},
+    num::{NonZeroU32, NonZeroU64, NonZeroUsize},
+    path::{self, Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+
+use anyhow::{bail, ensure, Context};
+use clap::Parser;
+use serde::Deserialize;
+
+#[cfg(unix)]
+use super::daemon::DaemonArgs;
+#[cfg(all(windows, feature = "windows-service"))]
+use super::service::ServiceArgs;
+use super::{generate_if_requested, ui, GenerateArgs};
+use crate::{
+    net::Cidr, Client, Leaderboard, Operation, Profile, Server, ServerConfig, Tenant, TlvType,
+    Tournament,
+};
+
+const ABOUT: &str = "Server of the remote TCP calculator";
+
+#[derive(Clone, Debug, clap::Args)]
+#[command(about = ABOUT)]
+pub struct Args {
+    /// Port number, or 0 to take any free one
+    #[arg(env = "TCP1_LISTEN")]
+    port: u16,
+    /// Listen on a port below 1024, which usually needs root
+    #[arg(long)]
+    allow_privileged: bool,
+    /// Expect a PROXY protocol (v1 or v2) header at the start of every connection
+    #[arg(long)]
+    proxy_protocol: bool,
+    /// Delay every answer by this many milliseconds
+    #[arg(long, default_value_t = 0)]
+    delay_ms: u64,
+    /// Randomly vary the delay of every answer by up to this many milliseconds
+    #[arg(long, default_value_t = 0)]
+    jitter_ms: u64,
+    /// While delaying an answer, tell the clients that support it how far it
+    /// went every MS milliseconds
+    #[arg(long, value_name = "MS")]
+    progress_ms: Option<u64>,
+    /// Abandon the operations that would take longer than MS milliseconds,
+    /// answering them with a timeout once the time runs out
+    #[arg(long, value_name = "MS")]
+    op_timeout_ms: Option<u64>,
+    /// Debug: split every answer into writes of at most N bytes
+    #[arg(long, value_name = "N")]
+    chunked_writes: Option<NonZeroUsize>,
+    /// Send the answers at most at this many bytes per second, as over a slow link
+    #[arg(long, value_name = "BYTES_PER_SEC")]
+    throttle: Option<NonZeroU64>,
+    /// Ask the clients for segments of at most this many bytes (TCP_MAXSEG)
+    #[arg(long, value_name = "BYTES")]
+    mss: Option<u32>,
+    /// Encoding of the answers: classic (big-endian i64) or le32 (little-endian
+    /// i32). Clients may still ask for le32 in their hello
+    #[arg(long, default_value_t = Profile::Classic)]
+    profile: Profile,
+    /// Reject factorials of numbers above N
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(i8).range(0..=Operation::MAX_FACTORIAL as i64))]
+    max_factorial: Option<i8>,
+    /// Maximum number of connections waiting to be accepted
+    #[arg(long, default_value_t = 128, value_parser = clap::value_parser!(i32).range(1..))]
+    backlog: i32,
+    /// Do not set SO_REUSEADDR on the listening socket
+    #[arg(long)]
+    no_reuseaddr: bool,
+    /// Set SO_REUSEPORT, so that a new server can start while this one drains (Unix only)
+    #[arg(long)]
+    reuse_port: bool,
+    /// On SIGTERM or SIGINT, stop accepting connections and give the connected
+    /// client up to SECS seconds to leave before exiting, instead of closing it
+    /// at once. A second signal exits without waiting (Unix only, or when
+    /// stopped as a Windows service)
+    #[arg(long, value_name = "SECS")]
+    drain: Option<u64>,
+    /// Write the summary of the run to FILE when exiting, instead of printing it
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+    /// Write the value of the accumulator after every operation to FILE when
+    /// exiting, as CSV, to plot how it evolved
+    #[arg(long, value_name = "FILE")]
+    dump_series: Option<PathBuf>,
+    /// Also accept operations typed as lines of text (e.g. with nc or telnet),
+    /// answering them in decimal
+    #[arg(long)]
+    ascii_compat: bool,
+    /// Refuse connections from addresses that already have N, counting those
+    /// waiting for their turn
+    #[arg(long, value_name = "N")]
+    max_conns_per_ip: Option<NonZeroUsize>,
+    /// Only serve clients in this network, as in 10.1.2.0/24. Repeat it to
+    /// allow several networks
+    #[arg(long, value_name = "CIDR")]
+    allow_cidr: Vec<Cidr>,
+    /// Refuse clients in this network, even if allowed. Repeat it to deny
+    /// several networks
+    #[arg(long, value_name = "CIDR")]
+    deny_cidr: Vec<Cidr>,
+    /// Serve slowly, a byte at a time, the clients whose address already sent
+    /// N invalid frames
+    #[arg(long, value_name = "N")]
+    tarpit_after: Option<NonZeroU64>,
+    /// Pause before every byte read from or written to a tarpitted client
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 1000,
+        requires = "tarpit_after"
+    )]
+    tarpit_delay: u64,
+    /// Close a tarpitted connection once its pauses took SECS seconds, as
+    /// nobody else is served meanwhile
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 10,
+        requires = "tarpit_after"
+    )]
+    tarpit_budget: u64,
+    /// Coalesce the answers into a single write once the oldest one waited MS
+    /// milliseconds (0: after every read from the client), instead of one write
+    /// per answer
+    #[arg(long, value_name = "MS")]
+    flush_interval_ms: Option<u64>,
+    /// Ask every client N random operations and score its answers, instead of
+    /// answering those of the client (use tcp1cli --answer)
+    #[arg(long, value_name = "N", conflicts_with = "ascii_compat")]
+    quiz: Option<NonZeroUsize>,
+    /// Size in bytes of the socket receive buffer
+    #[arg(long, value_name = "BYTES")]
+    recv_buffer: Option<usize>,
+    /// Size in bytes of the socket send buffer
+    #[arg(long, value_name = "BYTES")]
+    send_buffer: Option<usize>,
+    /// Read the delays, limits and disabled operations from this TOML file.
+    /// On Unix, send SIGHUP to the server to reload it.
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Only serve the tenants of this TOML file, each with its own accumulator.
+    /// Clients identify themselves with the API key of their tenant
+    #[arg(long, value_name = "FILE")]
+    keys_file: Option<PathBuf>,
+    /// Keep the accumulators in this file, or in the Redis server of a
+    /// redis:// URL, so that they outlive the server
+    #[arg(long, value_name = "LOCATION")]
+    store: Option<String>,
+    /// Add the results to the accumulators in the store in a single step, so
+    /// that the servers sharing it agree on the totals
+    #[arg(long, requires = "store")]
+    shared_accumulator: bool,
+    /// Serve the waiting clients by the priority they send, such as that of
+    /// tcp1cli at its prompt, before the bulk ones
+    #[arg(long)]
+    priorities: bool,
+    /// Only calculate the operations of clients that authenticate with one of
+    /// the secrets of this file, one per line as ID:SECRET, such as the old and
+    /// the new one while rotating them
+    #[cfg(feature = "auth")]
+    #[arg(long, value_name = "FILE")]
+    secrets_file: Option<PathBuf>,
+    /// Announce the server in the local network with mDNS, as NAME (by default,
+    /// the host name and the port)
+    #[cfg(feature = "mdns")]
+    #[arg(long, value_name = "NAME")]
+    announce: Option<Option<String>>,
+    /// Experimental: serve over QUIC, on the same UDP port, instead of TCP.
+    /// Most of the other options do not apply
+    #[cfg(feature = "quic")]
+    #[arg(long)]
+    quic: bool,
+    /// Serve over SCTP instead of TCP. Most of the other options do not apply
+    #[cfg(all(feature = "sctp", target_os = "linux"))]
+    #[arg(long)]
+    sctp: bool,
+    /// Experimental: serve all the clients at once from an io_uring event loop.
+    /// Most of the other options do not apply
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    #[arg(long)]
+    io_uring: bool,
+    /// Let this Rhai script decide the answers, to simulate a misbehaving server
+    #[cfg(feature = "script")]
+    #[arg(long, value_name = "FILE")]
+    handler_script: Option<PathBuf>,
+    /// Show a live dashboard of the connections and operations instead of the logs
+    #[cfg(all(feature = "tui", unix))]
+    #[arg(long, conflicts_with = "daemon")]
+    tui: bool,
+    #[cfg(unix)]
+    #[command(flatten)]
+    daemon: DaemonArgs,
+    #[cfg(all(windows, feature = "windows-service"))]
+    #[command(flatten)]
+    service: ServiceArgs,
+}
+
+/// Command line of the classic `tcp1ser` binary.
+#[derive(Debug, Parser)]
+#[command(name = "tcp1ser", about = ABOUT)]
+struct Standalone {
+    #[command(flatten)]
+    args: Args,
+    #[command(flatten)]
+    generate: GenerateArgs,
+}
+
+impl From<Args> for ServerConfig {
+    fn from(args: Args) -> Self {
+        Self {
+            port: args.port,
+            proxy_protocol: args.proxy_protocol,
+            delay: Duration::from_millis(args.delay_ms),
+            jitter: Duration::from_millis(args.jitter_ms),
+            chunked_writes: args.chunked_writes,
+            max_factorial: args.max_factorial,
+            backlog: args.backlog,
+            reuse_address: !args.no_reuseaddr,
+            reuse_port: args.reuse_port,
+            recv_buffer: args.recv_buffer,
+            send_buffer: args.send_buffer,
+            disabled_operations: Vec::new(),
+            tenants: HashMap::new(),
+            ascii_compat: args.ascii_compat,
+            max_conns_per_ip: args.max_conns_per_ip,
+            allowed_networks: args.allow_cidr.into_iter().collect(),
+            denied_networks: args.deny_cidr.into_iter().collect(),
+            tarpit_after: args.tarpit_after,
+            tarpit_delay: Duration::from_millis(args.tarpit_delay),
+            tarpit_budget: Duration::from_secs(args.tarpit_budget),
+            flush_interval: args.flush_interval_ms.map(Duration::from_millis),
+            quiz: args.quiz,
+            progress_interval: args.progress_ms.map(Duration::from_millis),
+            op_timeout: args.op_timeout_ms.map(Duration::from_millis),
+            throttle: args.throttle,
+            mss: args.mss,
+            profile: args.profile,
+            #[cfg(feature = "script")]
+            handler: None,
+            #[cfg(feature = "auth")]
+            secrets: HashMap::new(),
+            store: None,
+            shared_accumulator: args.shared_accumulator,
+            priorities: args.priorities,
+        }
+    }
+}
+
+/// Settings of the `--config` file. Those missing keep their previous value.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    delay_ms: Option<u64>,
+    jitter_ms: Option<u64>,
+    max_factorial: Option<i8>,
+    /// Tag names, as in `["Fact", "Mul"]`
+    disabled_operations: Option<Vec<String>>,
+}
+
+impl ConfigFile {
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let file: Self = toml::from_str(
+            &fs::read_to_string(path).with_context(|| format!("Could not read {path:?}"))?,
+        )
+        .with_context(|| format!("Wrong configuration in {path:?}"))?;
+
+        if let Some(max) = file.max_factorial {
+            ensure!(
+                (0..=Operation::MAX_FACTORIAL).contains(&max),
+                "max_factorial must be between 0 and {}",
+                Operation::MAX_FACTORIAL
+            );
+        }
+        for name in file.disabled_operations.iter().flatten() {
+            TlvType::try_from(name.as_str())?;
+        }
+
+        Ok(file)
+    }
+
+    fn apply(&self, config: &mut ServerConfig) {
+        if let Some(delay) = self.delay_ms {
+            config.delay = Duration::from_millis(delay);
+        }
+        if let Some(jitter) = self.jitter_ms {
+            config.jitter = Duration::from_millis(jitter);
+        }
+        if self.max_factorial.is_some() {
+            config.max_factorial = self.max_factorial;
+        }
+        if let Some(names) = &self.disabled_operations {
+            config.disabled_operations = names
+                .iter()
+                .filter_map(|name| TlvType::try_from(name.as_str()).ok())
+                .collect();
+        }
+    }
+}
+
+/// Contents of the `--keys-file`: a `[[tenant]]` table for every tenant.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeysFile {
+    #[serde(default)]
+    tenant: Vec<TenantEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TenantEntry {
+    name: String,
+    key: String,
+    /// Operations per minute
+    rate_limit: Option<NonZeroU32>,
+    /// Share of the server when other tenants are waiting too. Defaults to 1
+    weight: Option<NonZeroU32>,
+}
+
+impl KeysFile {
+    /// Loads the tenants of the file, by API key.
+    fn load(path: &Path) -> anyhow::Result<HashMap<String, Tenant>> {
+        let file: Self = toml::from_str(
+            &fs::read_to_string(path).with_context(|| format!("Could not read {path:?}"))?,
+        )
+        .with_context(|| format!("Wrong keys file {path:?}"))?;
+
+        let mut tenants = HashMap::new();
+        for TenantEntry {
+            name,
+            key,
+            rate_limit,
+            weight,
+        } in file.tenant
+        {
+            ensure!(
+                !key.is_empty() && key.len() <= u8::MAX.into(),
+                "The key of tenant {name} must have between 1 and 255 bytes"
+            );
+            let tenant = Tenant::new(name)
+                .with_rate_limit(rate_limit)
+                .with_weight(weight.unwrap_or(NonZeroU32::MIN));
+            if let Some(other) = tenants.insert(key, tenant) {
+                bail!("Tenant {} shares its key with another tenant", other.name);
+            }
+        }
+        ensure!(!tenants.is_empty(), "No tenants in {path:?}");
+
+        Ok(tenants)
+    }
+}
+
+/// Entry point of the classic `tcp1ser` binary.
+pub fn main() -> ExitCode {
+    if let Some(code) = generate_if_requested::<Standalone>() {
+        return code;
+    }
+
+    run(Standalone::parse().args)
+}
+
+pub fn run(args: Args) -> ExitCode {
+    #[cfg(all(windows, feature = "windows-service"))]
+    if let Some(result) = args.service.apply(&args) {
+        return match result {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                ui::report(format!("Service error. {e:#}"), None);
+                ExitCode::FAILURE
+            }
+        };
+    }
+    if (1..1024).contains(&args.port) && !args.allow_privileged {
+        return ui::usage_error(
+            format!("port {} is privileged", args.port),
+            "Pick a port from 1024 up, such as 7777, or pass --allow-privileged if you mean it",
+        );
+    }
+
+    let port = args.port;
+    match start(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let cause = e
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<io::Error>());
+            let tip = match cause {
+                Some(cause) if cause.kind() == io::ErrorKind::AddrInUse && serving(port) => Some(
+                    "A calculator server is already running on this port. Stop it, or pick another port, or 0 for any free one",
+                ),
+                Some(cause) => ui::io_tip(cause),
+                None => None,
+            };
+            ui::report(format!("Server error. {e:#}"), tip);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Whether a calculator server answers pings in `port` of this computer.
+fn serving(port: u16) -> bool {
+    let server = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    Client::connect(server, None)
+        .and_then(|mut client| {
+            client.set_timeout(Some(Duration::from_millis(500)))?;
+            client.ping()
+        })
+        .is_ok()
+}
+
+pub(super) fn start(args: Args) -> anyhow::Result<()> {
+    #[cfg(feature = "quic")]
+    if args.quic {
+        let server = crate::QuicServer::bind(args.port)?;
+        println!("Listening over QUIC on {}", server.local_addr()?);
+        return Ok(server.run()?);
+    }
+    #[cfg(all(feature = "sctp", target_os = "linux"))]
+    if args.sctp {
+        let server = crate::SctpServer::bind(args.port)?;
+        println!("Listening over SCTP on {}", server.local_addr()?);
+        return Ok(server.run()?);
+    }
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    let io_uring = args.io_uring;
+
+    #[cfg(unix)]
+    let daemon = args.daemon.clone();
+    // Absolute, as the daemon changes its working directory
+    let config_file = args.config.as_deref().map(path::absolute).transpose()?;
+    let report = args.report.as_deref().map(path::absolute).transpose()?;
+    let series = args
+        .dump_series
+        .as_deref()
+        .map(path::absolute)
+        .transpose()?;
+
+    #[cfg(any(unix, all(windows, feature = "windows-service")))]
+    let drain = args.drain;
+    #[cfg(not(any(unix, all(windows, feature = "windows-service"))))]
+    if args.drain.is_some() {
+        eprintln!("Draining is not supported in this system");
+    }
+
+    let keys_file = args.keys_file.clone();
+    #[cfg(feature = "auth")]
+    let secrets_file = args.secrets_file.clone();
+    let store = args.store.clone();
+    #[cfg(feature = "script")]
+    let handler_script = args.handler_script.clone();
+    #[cfg(all(feature = "tui", unix))]
+    let tui = args.tui;
+    #[cfg(feature = "mdns")]
+    let announce = args.announce.clone();
+    let quiz = args.quiz.is_some();
+    let mut config = ServerConfig::from(args);
+    if let Some(path) = &config_file {
+        ConfigFile::load(path)?.apply(&mut config);
+    }
+    if let Some(path) = &keys_file {
+        config.tenants = KeysFile::load(path)?;
+        println!("Serving {} tenants", config.tenants.len());
+    }
+    if let Some(location) = &store {
+        let opened = crate::store::open(location)
+            .with_context(|| format!("Could not open the store {location}"))?;
+        config.store = Some(opened);
+        println!("Keeping the accumulators in {location}");
+    }
+    #[cfg(feature = "auth")]
+    if let Some(path) = &secrets_file {
+        config.secrets = super::read_secrets(path)?
+            .into_iter()
+            .map(|keyed| (keyed.key_id, keyed.secret))
+            .collect();
+    }
+    #[cfg(feature = "auth")]
+    if !config.secrets.is_empty() {
+        let mut ids: Vec<&str> = config.secrets.keys().map(String::as_str).collect();
+        ids.sort_unstable();
+        println!("Accepting the secrets with key IDs {}", ids.join(", "));
+    }
+    #[cfg(feature = "script")]
+    if let Some(path) = &handler_script {
+        let handler = crate::ScriptHandler::load(path)
+            .with_context(|| format!("Could not load the handler script {path:?}"))?;
+        config.handler = Some(std::sync::Arc::new(handler));
+        println!("Answering with the script {path:?}");
+    }
+    // With the middlewares configured, as it answers through them
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    if io_uring {
+        let mut server = crate::UringServer::bind(config)?;
+        println!("Listening with io_uring on {}", server.local_addr()?);
+        return Ok(server.run()?);
+    }
+    let port = config.port;
+    let mut server =
+        Server::bind(config).with_context(|| format!("Could not listen on port {port}"))?;
+    if series.is_some() {
+        server.keep_series();
+    }
+    #[cfg(unix)]
+    {
+        daemon.apply()?;
+        // After detaching, as forking only keeps the calling thread
+        if let Some(path) = config_file {
+            reload_on_sighup(path, server.config_handle())?;
+        }
+        // Even without draining, to report the summary before exiting
+        let timeout = Duration::from_secs(drain.unwrap_or_default());
+        drain_on_termination(timeout, server.drain_handle()?)?;
+    }
+    #[cfg(all(windows, feature = "windows-service"))]
+    {
+        let timeout = Duration::from_secs(drain.unwrap_or_default());
+        super::service::stop_with(server.drain_handle()?, timeout);
+    }
+    // After detaching too, as it runs in its own thread
+    #[cfg(feature = "mdns")]
+    let _announcement = match announce {
+        Some(name) => {
+            let port = server.local_addr()?.port();
+            Some(crate::Announcement::new(name.as_deref(), port)?)
+        }
+        None => None,
+    };
+
+    #[cfg(all(feature = "tui", unix))]
+    if tui {
+        return Ok(super::dashboard::run(server)?);
+    }
+
+    let tournament = server.tournament();
+    #[cfg(unix)]
+    if quiz {
+        print_standings_on_sigusr1(tournament.clone())?;
+    }
+    server.run()?;
+    if quiz {
+        print_standings(&tournament);
+    }
+
+    if let Some(path) = series {
+        fs::File::create(&path)
+            .and_then(|file| server.summary().write_series(io::BufWriter::new(file)))
+            .with_context(|| format!("Could not write the series to {path:?}"))?;
+    }
+    ui::summary(server.summary(), report.as_deref()).context("Could not write the report")
+}
+
+fn print_standings(tournament: &Tournament) {
+    print!("Standings:\n{}", Leaderboard(tournament.standings()));
+}
+
+#[cfg(unix)]
+fn print_standings_on_sigusr1(tournament: Tournament) -> anyhow::Result<()> {
+    use signal_hook::{consts::SIGUSR1, iterator::Signals};
+
+    let mut signals = Signals::new([SIGUSR1])?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            print_standings(&tournament);
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn reload_on_sighup(path: PathBuf, config: crate::ConfigHandle) -> anyhow::Result<()> {
+    use signal_hook::{consts::SIGHUP, iterator::Signals};
+
+    let mut signals = Signals::new([SIGHUP])?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            match ConfigFile::load(&path) {
+                Ok(file) => {
+                    config.update(|config| file.apply(config));
+                    println!("Configuration reloaded from {path:?}");
+                }
+                Err(e) => eprintln!("Keeping the previous configuration. {e:#}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn drain_on_termination(timeout: Duration, drain: crate::DrainHandle) -> anyhow::Result<()> {
+    use signal_hook::{
+        consts::{SIGINT, SIGTERM},
+        iterator::Signals,
+    };
+
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    std::thread::spawn(move || {
+        let mut signals = signals.forever();
+        if signals.next().is_some() {
+            if !timeout.is_zero() {
+                println!("Draining for {timeout:?}");
+            }
+            drain.drain(timeout);
+        }
+        // Whoever insists does not want to wait for the drain
+        if let Some(signal) = signals.next() {
+            eprintln!("Exiting without draining");
+            std::process::exit(128 + signal);
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use super::serving;
+    use crate::{Server, ServerConfig};
+
+    #[test]
+    fn detect_running_server() {
+        let mut server = Server::bind(ServerConfig::default()).unwrap();
+        let port = server.local_addr().unwrap().port();
+        thread::spawn(move || server.run());
+        assert!(serving(port));
+
+        // Accepting connections, but not answering pings
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        assert!(!serving(listener.local_addr().unwrap().port()));
+    }
+}
@@ -0,0 +1,406 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Running the server unattended on Windows: registering it as a service, and
+//! serving under the service control manager, that stops it with a drain. It
+//! calls the service functions of `advapi32`, that the standard library
+//! already links.
+
+use std::{
+    env,
+    ffi::{c_void, OsStr, OsString},
+    io,
+    os::windows::ffi::OsStrExt,
+    ptr,
+    sync::{
+        atomic::{AtomicPtr, Ordering},
+        Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+
+use super::server::Args;
+use crate::DrainHandle;
+
+/// Flag that the service control manager starts the server with.
+const RUN_AS_SERVICE: &str = "--run-as-service";
+
+#[derive(Clone, Debug, clap::Args)]
+pub struct ServiceArgs {
+    /// Register the server, with the rest of this command line, as the Windows
+    /// service NAME that starts with the system, and exit
+    #[arg(long, value_name = "NAME", conflicts_with = "uninstall_service")]
+    install_service: Option<String>,
+    /// Remove the Windows service NAME, and exit
+    #[arg(long, value_name = "NAME")]
+    uninstall_service: Option<String>,
+    /// Serve under the service control manager, as the service NAME
+    #[arg(long = "run-as-service", value_name = "NAME", hide = true)]
+    run_as_service: Option<String>,
+}
+
+/// Name of the service and server waiting to be started by the service control
+/// manager, and the drain of the server once it is running, with its timeout.
+static SERVE: Mutex<Option<(String, Args)>> = Mutex::new(None);
+static DRAIN: OnceLock<(DrainHandle, Duration)> = OnceLock::new();
+/// Where the service reports its state.
+static STATUS: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+
+impl ServiceArgs {
+    /// Installs or uninstalls the service, or serves as one, as requested.
+    /// Returns `None` if the server must run as usual.
+    pub fn apply(&self, args: &Args) -> Option<anyhow::Result<()>> {
+        if let Some(name) = &self.install_service {
+            return Some(install(name));
+        }
+        if let Some(name) = &self.uninstall_service {
+            return Some(uninstall(name));
+        }
+        let name = self.run_as_service.as_ref()?;
+        *SERVE.lock().unwrap() = Some((name.clone(), args.clone()));
+        let mut name = wide(OsStr::new(name));
+        let table = [
+            ServiceTableEntry {
+                name: name.as_mut_ptr(),
+                main: Some(service_main),
+            },
+            // The end of the table
+            ServiceTableEntry {
+                name: ptr::null_mut(),
+                main: None,
+            },
+        ];
+        // SAFETY: the table ends with a null entry, and outlives the dispatcher,
+        // that returns once the service stops
+        let dispatched = unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } != 0;
+        Some(
+            dispatched
+                .then_some(())
+                .ok_or_else(io::Error::last_os_error)
+                .context("Could not run as a service"),
+        )
+    }
+}
+
+/// Lets the service control manager stop the server with `drain`, waiting
+/// for the clients up to `timeout`.
+pub fn stop_with(drain: DrainHandle, timeout: Duration) {
+    let _ = DRAIN.set((drain, timeout));
+}
+
+fn install(name: &str) -> anyhow::Result<()> {
+    let manager = ScHandle::manager(SC_MANAGER_CREATE_SERVICE)?;
+    let mut command_line = vec![env::current_exe()?.into_os_string()];
+    command_line.extend(launch_arguments(env::args_os().skip(1)));
+    command_line.extend([RUN_AS_SERVICE.into(), name.into()]);
+    let display_name = wide(OsStr::new(&format!("TCP calculator server ({name})")));
+    let binary_path = wide(&quote(&command_line));
+    let wide_name = wide(OsStr::new(name));
+    // SAFETY: the strings end with a null, and the optional arguments are null
+    let service = unsafe {
+        CreateServiceW(
+            manager.0,
+            wide_name.as_ptr(),
+            display_name.as_ptr(),
+            SERVICE_QUERY_STATUS,
+            SERVICE_WIN32_OWN_PROCESS,
+            SERVICE_AUTO_START,
+            SERVICE_ERROR_NORMAL,
+            binary_path.as_ptr(),
+            ptr::null(),
+            ptr::null_mut(),
+            ptr::null(),
+            ptr::null(),
+            ptr::null(),
+        )
+    };
+    ScHandle::new(service).with_context(|| format!("Could not install the service {name}"))?;
+    println!("Installed the service {name}");
+
+    Ok(())
+}
+
+fn uninstall(name: &str) -> anyhow::Result<()> {
+    let manager = ScHandle::manager(SC_MANAGER_CONNECT)?;
+    let wide_name = wide(OsStr::new(name));
+    // SAFETY: the name ends with a null
+    let service = unsafe { OpenServiceW(manager.0, wide_name.as_ptr(), DELETE) };
+    let service =
+        ScHandle::new(service).with_context(|| format!("Could not open the service {name}"))?;
+    // SAFETY: the handle was opened with the access to delete the service
+    if unsafe { DeleteService(service.0) } == 0 {
+        return Err(io::Error::last_os_error())
+            .with_context(|| format!("Could not uninstall the service {name}"));
+    }
+    println!("Uninstalled the service {name}, removed once stopped");
+
+    Ok(())
+}
+
+/// The command line of the service, that of the installation without the
+/// `--install-service` flag and its name.
+fn launch_arguments(args: impl IntoIterator<Item = OsString>) -> Vec<OsString> {
+    let mut args = args.into_iter();
+    let mut kept = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == OsStr::new("--install-service") {
+            args.next();
+        } else if !arg
+            .to_str()
+            .is_some_and(|arg| arg.starts_with("--install-service="))
+        {
+            kept.push(arg);
+        }
+    }
+
+    kept
+}
+
+/// Joins `args` into a command line that Windows programs split back into
+/// them, quoting those that are empty or have spaces or quotes.
+fn quote(args: &[OsString]) -> OsString {
+    let mut line = String::new();
+    for arg in args {
+        let arg = arg.to_string_lossy();
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+            line.push_str(&arg);
+            continue;
+        }
+        line.push('"');
+        let mut backslashes = 0;
+        for c in arg.chars() {
+            match c {
+                '\\' => {
+                    backslashes += 1;
+                    continue;
+                }
+                // Those before a quote escape each other, and then the quote
+                '"' => line.extend(std::iter::repeat_n('\\', 2 * backslashes + 1)),
+                _ => line.extend(std::iter::repeat_n('\\', backslashes)),
+            }
+            backslashes = 0;
+            line.push(c);
+        }
+        // Those before the closing quote escape each other
+        line.extend(std::iter::repeat_n('\\', 2 * backslashes));
+        line.push('"');
+    }
+
+    line.into()
+}
+
+/// `s` as the null-terminated UTF-16 of the Windows API.
+fn wide(s: &OsStr) -> Vec<u16> {
+    s.encode_wide().chain([0]).collect()
+}
+
+/// Tells the service control manager the `state` of the service, and the code
+/// it exits with if it failed.
+fn report(state: u32, exit_code: Option<u32>) {
+    let status = ServiceStatus {
+        service_type: SERVICE_WIN32_OWN_PROCESS,
+        current_state: state,
+        controls_accepted: match state {
+            SERVICE_RUNNING => SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN,
+            _ => 0,
+        },
+        win32_exit_code: match exit_code {
+            Some(_) => ERROR_SERVICE_SPECIFIC_ERROR,
+            None => NO_ERROR,
+        },
+        service_specific_exit_code: exit_code.unwrap_or_default(),
+        check_point: 0,
+        wait_hint: 0,
+    };
+    // SAFETY: the handle is that of the registration, that is never closed
+    unsafe { SetServiceStatus(STATUS.load(Ordering::Acquire), &status) };
+}
+
+unsafe extern "system" fn service_main(_argc: u32, _argv: *mut *mut u16) {
+    let Some((name, args)) = SERVE.lock().unwrap().take() else {
+        return;
+    };
+    let name = wide(OsStr::new(&name));
+    // SAFETY: the name ends with a null, and the handler is a plain function
+    let status = unsafe { RegisterServiceCtrlHandlerExW(name.as_ptr(), handler, ptr::null_mut()) };
+    if status.is_null() {
+        return;
+    }
+    STATUS.store(status, Ordering::Release);
+    report(SERVICE_RUNNING, None);
+
+    let exit_code = super::server::start(args).err().map(|_| 1);
+    report(SERVICE_STOPPED, exit_code);
+}
+
+unsafe extern "system" fn handler(
+    control: u32,
+    _event_type: u32,
+    _event_data: *mut c_void,
+    _context: *mut c_void,
+) -> u32 {
+    match control {
+        SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+            // Before the server runs there is nothing to drain yet
+            if let Some((drain, timeout)) = DRAIN.get() {
+                report(SERVICE_STOP_PENDING, None);
+                drain.drain(*timeout);
+            }
+            NO_ERROR
+        }
+        SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+        _ => ERROR_CALL_NOT_IMPLEMENTED,
+    }
+}
+
+/// Handle of the service control manager or of a service, closed when dropped.
+struct ScHandle(*mut c_void);
+
+impl ScHandle {
+    fn new(handle: *mut c_void) -> io::Result<Self> {
+        match handle.is_null() {
+            true => Err(io::Error::last_os_error()),
+            false => Ok(Self(handle)),
+        }
+    }
+
+    fn manager(access: u32) -> anyhow::Result<Self> {
+        // SAFETY: null names are those of the local computer and its database
+        let manager = unsafe { OpenSCManagerW(ptr::null(), ptr::null(), access) };
+        Self::new(manager).context("Could not open the service control manager")
+    }
+}
+
+impl Drop for ScHandle {
+    fn drop(&mut self) {
+        // SAFETY: the handle is open, and only closed here
+        unsafe { CloseServiceHandle(self.0) };
+    }
+}
+
+const SC_MANAGER_CONNECT: u32 = 0x0001;
+const SC_MANAGER_CREATE_SERVICE: u32 = 0x0002;
+const SERVICE_QUERY_STATUS: u32 = 0x0004;
+const DELETE: u32 = 0x0001_0000;
+const SERVICE_WIN32_OWN_PROCESS: u32 = 0x10;
+const SERVICE_AUTO_START: u32 = 2;
+const SERVICE_ERROR_NORMAL: u32 = 1;
+const SERVICE_STOPPED: u32 = 1;
+const SERVICE_STOP_PENDING: u32 = 3;
+const SERVICE_RUNNING: u32 = 4;
+const SERVICE_ACCEPT_STOP: u32 = 0x1;
+const SERVICE_ACCEPT_SHUTDOWN: u32 = 0x4;
+const SERVICE_CONTROL_STOP: u32 = 1;
+const SERVICE_CONTROL_INTERROGATE: u32 = 4;
+const SERVICE_CONTROL_SHUTDOWN: u32 = 5;
+const NO_ERROR: u32 = 0;
+const ERROR_CALL_NOT_IMPLEMENTED: u32 = 120;
+const ERROR_SERVICE_SPECIFIC_ERROR: u32 = 1066;
+
+#[repr(C)]
+struct ServiceStatus {
+    service_type: u32,
+    current_state: u32,
+    controls_accepted: u32,
+    win32_exit_code: u32,
+    service_specific_exit_code: u32,
+    check_point: u32,
+    wait_hint: u32,
+}
+
+#[repr(C)]
+struct ServiceTableEntry {
+    name: *mut u16,
+    main: Option<unsafe extern "system" fn(u32, *mut *mut u16)>,
+}
+
+type Handler = unsafe extern "system" fn(u32, u32, *mut c_void, *mut c_void) -> u32;
+
+#[link(name = "advapi32")]
+extern "system" {
+    fn OpenSCManagerW(machine: *const u16, database: *const u16, access: u32) -> *mut c_void;
+    fn CreateServiceW(
+        manager: *mut c_void,
+        name: *const u16,
+        display_name: *const u16,
+        access: u32,
+        service_type: u32,
+        start_type: u32,
+        error_control: u32,
+        binary_path: *const u16,
+        load_order_group: *const u16,
+        tag_id: *mut u32,
+        dependencies: *const u16,
+        account: *const u16,
+        password: *const u16,
+    ) -> *mut c_void;
+    fn OpenServiceW(manager: *mut c_void, name: *const u16, access: u32) -> *mut c_void;
+    fn DeleteService(service: *mut c_void) -> i32;
+    fn CloseServiceHandle(handle: *mut c_void) -> i32;
+    fn StartServiceCtrlDispatcherW(table: *const ServiceTableEntry) -> i32;
+    fn RegisterServiceCtrlHandlerExW(
+        name: *const u16,
+        handler: Handler,
+        context: *mut c_void,
+    ) -> *mut c_void;
+    fn SetServiceStatus(status: *mut c_void, service_status: *const ServiceStatus) -> i32;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+
+    use super::{launch_arguments, quote};
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn without_the_installation() {
+        assert_eq!(
+            launch_arguments(args(&["7777", "--install-service", "tcp1", "--drain", "5"])),
+            args(&["7777", "--drain", "5"])
+        );
+        assert_eq!(
+            launch_arguments(args(&["--install-service=tcp1", "7777"])),
+            args(&["7777"])
+        );
+    }
+
+    #[test]
+    fn quoted() {
+        assert_eq!(
+            quote(&args(&[r"C:\Program Files\tcp1ser.exe", "7777", ""])),
+            OsString::from(r#""C:\Program Files\tcp1ser.exe" 7777 """#)
+        );
+        assert_eq!(
+            quote(&args(&[r#"a "b""#, r"c d\"])),
+            OsString::from(r#""a \"b\"" "c d\\""#)
+        );
+    }
+}
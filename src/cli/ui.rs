@@ -0,0 +1,78 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Messages of the command line tools for their users: what went wrong and
+//! what they can do about it.
+
+use std::{fmt::Display, fs, io, path::Path, process::ExitCode};
+
+use crate::{errors::ErrorKind, Summary};
+
+/// Exit code of the mistakes in the command line, the same that clap uses.
+const USAGE: u8 = ErrorKind::Config.exit_code();
+
+/// Reports a mistake in the command line in the style of clap, with a tip on
+/// how to fix it, returning the exit code for it.
+pub fn usage_error(message: impl Display, tip: impl Display) -> ExitCode {
+    eprintln!("error: {message}\n\n  tip: {tip}\n\nFor more information, try '--help'.");
+    ExitCode::from(USAGE)
+}
+
+/// Reports an error, followed by a tip on how to fix it if there is one.
+pub fn report(message: impl Display, tip: Option<&str>) {
+    eprintln!("{message}");
+    if let Some(tip) = tip {
+        eprintln!("  tip: {tip}");
+    }
+}
+
+/// What usually fixes the network errors that users run into.
+pub fn io_tip(e: &io::Error) -> Option<&'static str> {
+    match e.kind() {
+        io::ErrorKind::AddrInUse => {
+            Some("Another program is using the port. Stop it, or pick another port, or 0 for any free one")
+        }
+        io::ErrorKind::PermissionDenied => {
+            Some("Ports below 1024 are privileged. Pick one from 1024 up, such as 7777")
+        }
+        io::ErrorKind::ConnectionRefused => {
+            Some("Check that the server is running, and that the address and port are right")
+        }
+        io::ErrorKind::TimedOut
+        | io::ErrorKind::HostUnreachable
+        | io::ErrorKind::NetworkUnreachable => {
+            Some("Check that the server is reachable, for instance with ping")
+        }
+        _ => None,
+    }
+}
+
+/// Writes the summary of a run to `path`, or prints it if there is none.
+pub fn summary(summary: &Summary, path: Option<&Path>) -> io::Result<()> {
+    match path {
+        Some(path) => fs::write(path, summary.to_string()),
+        None => {
+            eprint!("Summary:\n{summary}");
+            Ok(())
+        }
+    }
+}
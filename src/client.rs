@@ -0,0 +1,2096 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+use std::{
+    io::{self, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    num::{NonZeroU64, NonZeroUsize},
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use socket2::{Domain, Socket, Type};
+use thiserror::Error;
+
+#[cfg(feature = "auth")]
+use crate::auth::{Auth, AuthError, Nonces};
+use crate::{
+    audit::Transcript, net::Stream, proxy::ProxyError, tlv::TlvError, Answer, AsyncClient, Batch,
+    Bye, Capabilities, ChannelFrame, ChunkedWriter, Deadline, Decoder, Frame, GoAway, Hello,
+    IdempotencyKey, Leaderboard, Load, Operation, Peer, Ping, Pong, Priority, Profile, Progress,
+    Proxy, Rejection, Session, SessionError, Store, TCPLibError, ThrottledStream, Tlv, TlvIterator,
+    TlvType, TraceContext,
+};
+#[cfg(feature = "audit")]
+use crate::{AuditDigest, AuditQuery};
+
+/// What the server sends in a quiz: the operations, and the leaderboard and a
+/// GoAway at the end.
+const QUIZ_FRAMES: &[TlvType] = &[
+    TlvType::Sum,
+    TlvType::Sub,
+    TlvType::Mul,
+    TlvType::Div,
+    TlvType::Rem,
+    TlvType::Fact,
+    TlvType::DivEuclid,
+    TlvType::RemEuclid,
+    TlvType::SumN,
+    TlvType::Leaderboard,
+    TlvType::GoAway,
+];
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("Connection error")]
+    Io(#[from] io::Error),
+    #[error("Proxy error")]
+    Proxy(#[from] ProxyError),
+    #[error("Malformed answer. {0}")]
+    Tlv(#[from] TlvError),
+    #[error("Invalid answer")]
+    Answer(#[from] TCPLibError),
+    #[error("Unexpected message from the server")]
+    Unexpected,
+    #[error("Operation rejected by the server: {0}")]
+    Rejected(Rejection),
+    #[error("The server is shutting down. Try again in {0:?}")]
+    GoingAway(Duration),
+    #[error(transparent)]
+    Session(#[from] SessionError),
+    #[cfg(feature = "auth")]
+    #[error("The server did not prove it knows the secret. {0}")]
+    Auth(#[from] AuthError),
+}
+
+impl ClientError {
+    /// Whether the error was caused by the server not answering in time.
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            ClientError::Io(e) => {
+                matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                )
+            }
+            _ => false,
+        }
+    }
+}
+
+/// What to do with frames the server sends when the client is waiting for something else.
+///
+/// Without correlation identifiers an extra answer cannot be told apart from the
+/// next expected one, so only frames of a different kind are detected.
+pub enum UnsolicitedPolicy {
+    /// Log the frame to the standard error and keep waiting
+    Skip,
+    /// Fail with [`ClientError::Unexpected`]
+    Error,
+    /// Deliver the frame to the callback and keep waiting
+    Handler(Box<dyn FnMut(Frame) + Send>),
+}
+
+pub struct Client {
+    stream: TcpStream,
+    unsolicited: UnsolicitedPolicy,
+    decoder: Decoder,
+    timeout: Option<Duration>,
+    chunk_size: Option<NonZeroUsize>,
+    ping_sequence: u64,
+    /// Key for the next request, when idempotency keys are enabled
+    next_key: Option<u64>,
+    /// Time the server has to answer every request
+    deadline: Option<Duration>,
+    /// Trace of the session, when trace contexts are enabled
+    trace: Option<TraceContext>,
+    /// Advertised by the server in its [`Hello`]
+    capabilities: Option<Capabilities>,
+    /// Of the last [`Hello`] of the server, for the next [`Auth`]
+    challenge: Option<[u8; Hello::CHALLENGE_LEN]>,
+    session: Session,
+    transcript: Transcript,
+    /// Channels opened so far, numbered from zero
+    channels: u16,
+    /// Called with every [`Progress`] the server sends
+    progress: Option<Box<dyn FnMut(Progress) + Send>>,
+    /// Operations sent one by one so far, that numbers them for a [`Cancel`]
+    requests: u64,
+    /// Another handle of the stream, to write the requests slowly through
+    throttled: Option<ThrottledStream<TcpStream>>,
+    /// Bytes sent so far
+    sent: u64,
+    /// Encoding of the answers
+    profile: Profile,
+}
+
+/// Handle of a logical session opened with [`Client::open_channel`]. Its
+/// operations go in [`ChannelFrame`]s and update an accumulator of its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Channel(u8);
+
+impl Channel {
+    pub fn id(self) -> u8 {
+        self.0
+    }
+}
+
+/// Outcome of [`Client::audit`].
+#[cfg(feature = "audit")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AuditReport {
+    /// The server received the frames exactly as the client sent them
+    pub requests_intact: bool,
+    /// The client received the frames exactly as the server sent them
+    pub answers_intact: bool,
+}
+
+/// Outcome of [`Client::answer_quiz`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QuizReport {
+    pub answered: usize,
+    /// Sent by the server at the end of the quiz
+    pub leaderboard: Option<Leaderboard>,
+}
+
+impl Client {
+    /// Connects to the server, optionally through a SOCKS5 or HTTP CONNECT proxy.
+    pub fn connect(server: SocketAddr, proxy: Option<&Proxy>) -> Result<Self, ClientError> {
+        let stream = match proxy {
+            Some(proxy) => proxy.connect(server)?,
+            None => TcpStream::connect(server)?,
+        };
+
+        Ok(Self::new(stream))
+    }
+
+    /// Like [`Client::connect`], giving up if the server, or the proxy, does not
+    /// accept the connection within `timeout`.
+    pub fn connect_timeout(
+        server: SocketAddr,
+        proxy: Option<&Proxy>,
+        timeout: Duration,
+    ) -> Result<Self, ClientError> {
+        let stream = match proxy {
+            Some(proxy) => proxy.connect_timeout(server, timeout)?,
+            None => TcpStream::connect_timeout(&server, timeout)?,
+        };
+
+        Ok(Self::new(stream))
+    }
+
+    /// Connects to the server, asking for segments of at most `mss` bytes in
+    /// both directions (`TCP_MAXSEG`).
+    #[cfg(unix)]
+    pub fn connect_with_mss(server: SocketAddr, mss: u32) -> Result<Self, ClientError> {
+        Self::connect_with_mss_within(server, mss, None)
+    }
+
+    /// Like [`Client::connect_with_mss`], giving up after the `timeout`, if any.
+    #[cfg(unix)]
+    pub(crate) fn connect_with_mss_within(
+        server: SocketAddr,
+        mss: u32,
+        timeout: Option<Duration>,
+    ) -> Result<Self, ClientError> {
+        let socket = Socket::new(Domain::for_address(server), Type::STREAM, None)?;
+        socket.set_mss(mss)?;
+        match timeout {
+            Some(timeout) => socket.connect_timeout(&server.into(), timeout)?,
+            None => socket.connect(&server.into())?,
+        }
+
+        Ok(Self::new(socket.into()))
+    }
+
+    fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            decoder: Decoder::new(),
+            unsolicited: UnsolicitedPolicy::Error,
+            timeout: None,
+            chunk_size: None,
+            ping_sequence: 0,
+            next_key: None,
+            deadline: None,
+            trace: None,
+            capabilities: None,
+            challenge: None,
+            session: Session::new(),
+            transcript: Transcript::default(),
+            channels: 0,
+            progress: None,
+            requests: 0,
+            throttled: None,
+            sent: 0,
+            profile: Profile::Classic,
+        }
+    }
+
+    /// Bytes received from and sent to the server so far.
+    pub fn traffic(&self) -> (u64, u64) {
+        let received = self.decoder.offset() + self.decoder.pending();
+        (received as u64, self.sent)
+    }
+
+    /// Largest segment of the connection, as negotiated with the server.
+    pub fn mss(&self) -> io::Result<u32> {
+        crate::net::mss(&self.stream)
+    }
+
+    /// Sets how long to wait for a complete answer. `None` waits forever.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<(), ClientError> {
+        if timeout == Some(Duration::ZERO) {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput).into());
+        }
+        self.timeout = timeout;
+
+        Ok(())
+    }
+
+    /// Sends the requests at most at `rate` bytes per second, or as fast as
+    /// possible with `None`. See [`ThrottledStream`].
+    pub fn set_throttle(&mut self, rate: Option<NonZeroU64>) -> Result<(), ClientError> {
+        self.throttled = match rate {
+            Some(rate) => Some(ThrottledStream::new(self.stream.try_clone()?, rate)),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Decodes the answers as in `profile`. The server uses [`Profile::LE32`]
+    /// when it was started with it, or once the client says [`Client::hello`]
+    /// with this profile, so this says it again if the client already did, or
+    /// for the first time for [`Profile::LE32`].
+    pub fn set_profile(&mut self, profile: Profile) -> Result<(), ClientError> {
+        self.profile = profile;
+        if self.capabilities.is_some() || profile == Profile::LE32 {
+            self.hello("")?;
+        }
+        Ok(())
+    }
+
+    /// Splits every request into writes of at most `chunk_size` bytes. See [`ChunkedWriter`].
+    pub fn set_chunked_writes(&mut self, chunk_size: Option<NonZeroUsize>) {
+        self.chunk_size = chunk_size;
+    }
+
+    pub fn set_unsolicited_policy(&mut self, policy: UnsolicitedPolicy) {
+        self.unsolicited = policy;
+    }
+
+    /// Delivers every unsolicited frame to `handler`. See [`UnsolicitedPolicy`].
+    pub fn set_unsolicited_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(Frame) + Send + 'static,
+    {
+        self.unsolicited = UnsolicitedPolicy::Handler(Box::new(handler));
+    }
+
+    /// Calls `handler` with every [`Progress`] that the server sends while
+    /// calculating slow operations. Servers only send them after a [`Hello`]
+    /// with [`Capabilities::PROGRESS`], as [`Client::hello`] sends.
+    pub fn set_progress_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(Progress) + Send + 'static,
+    {
+        self.progress = Some(Box::new(handler));
+    }
+
+    /// Attaches a fresh [`IdempotencyKey`] to every operation sent, so that the
+    /// server does not apply it twice if it is sent again with the same key.
+    pub fn set_idempotency_keys(&mut self, enabled: bool) {
+        self.next_key = enabled.then(|| fastrand::u64(..));
+    }
+
+    /// Attaches a [`Deadline`] to every operation sent, so that the server
+    /// gives up on those it cannot answer within `deadline`, rejecting them
+    /// with [`Rejection::DeadlineExceeded`].
+    pub fn set_deadline(&mut self, deadline: Option<Duration>) {
+        self.deadline = deadline;
+    }
+
+    /// Tells the server the [`Priority`] of the operations of this connection.
+    /// Send it before anything else, as servers with [`crate::ServerConfig::priorities`]
+    /// only look for it while the connection waits for its turn.
+    pub fn prioritize(&mut self, priority: Priority) -> Result<(), ClientError> {
+        self.send(&priority.encode())
+    }
+
+    /// Attaches a [`TraceContext`] to every operation sent, all in the same trace,
+    /// so that they can be found in the logs of the server. Returns the trace.
+    pub fn set_tracing(&mut self, enabled: bool) -> Option<TraceContext> {
+        self.trace = enabled.then(TraceContext::new);
+        self.trace
+    }
+
+    /// Identifies the client with the API key of its tenant. Call it before
+    /// sending any operation. The server answers with its [`Capabilities`], but
+    /// rejects the operations with [`Rejection::Unauthorized`] if the key is
+    /// not known.
+    pub fn hello(&mut self, api_key: &str) -> Result<Capabilities, ClientError> {
+        let mut capabilities = Capabilities::SUPPORTED;
+        if self.profile != Profile::LE32 {
+            capabilities.remove(Capabilities::LE32);
+        }
+        let hello = Hello {
+            capabilities,
+            challenge: None,
+            api_key: api_key.to_string(),
+        };
+        self.send(&hello.encode()?)?;
+
+        let Hello {
+            capabilities,
+            challenge,
+            ..
+        } = self.receive(&[TlvType::Hello])?.as_tlv().try_into()?;
+        self.capabilities = Some(capabilities);
+        self.challenge = challenge;
+        Ok(capabilities)
+    }
+
+    /// Proves that the client knows the secret with ID `key_id`, and checks that
+    /// the server knows it too. Call it before sending any operation to a server
+    /// with secrets, that rejects them with [`Rejection::Unauthorized`] otherwise.
+    /// It signs the challenge of the last [`Hello`] of the server, saying one
+    /// first if needed.
+    #[cfg(feature = "auth")]
+    pub fn authenticate(&mut self, key_id: &str, secret: &[u8]) -> Result<(), ClientError> {
+        let challenge = match self.challenge.take() {
+            Some(challenge) => challenge,
+            None => {
+                self.hello("")?;
+                // A server that sends none rejects the Auth, and tells why
+                self.challenge.take().unwrap_or_default()
+            }
+        };
+        let request = Auth::sign(key_id, secret, &challenge);
+        self.send(&request.encode()?)?;
+
+        let frame = self.receive(&[TlvType::Auth, TlvType::Rejection])?;
+        match frame.tag {
+            TlvType::Auth => {
+                let answer = Auth::try_from(frame.as_tlv())?;
+                // The challenge already ties the answer to this request
+                Ok(answer.verify(secret, &mut Nonces::new(NonZeroUsize::MIN), &request.nonce)?)
+            }
+            _ => Err(ClientError::Rejected(frame.as_tlv().try_into()?)),
+        }
+    }
+
+    /// What the server supports beyond the basic operations, asking for it
+    /// with a [`Hello`] without key the first time.
+    pub fn capabilities(&mut self) -> Result<Capabilities, ClientError> {
+        match self.capabilities {
+            Some(capabilities) => Ok(capabilities),
+            None => self.hello(""),
+        }
+    }
+
+    /// Sends the operation and waits for the updated accumulator.
+    pub fn compute(&mut self, operation: Operation) -> Result<Answer, ClientError> {
+        self.send_operation(operation)?;
+        self.recv_answer()
+    }
+
+    /// Like [`Client::compute`], but with the given key. Use it to retry a request
+    /// whose answer was lost.
+    pub fn compute_with_key(
+        &mut self,
+        operation: Operation,
+        key: IdempotencyKey,
+    ) -> Result<Answer, ClientError> {
+        let deadline = self.deadline.map(|left| Deadline::after(left).encode());
+        self.send(
+            &[
+                deadline.unwrap_or_default(),
+                key.encode(),
+                operation.encode(),
+            ]
+            .concat(),
+        )?;
+        self.requests += 1;
+        self.recv_answer()
+    }
+
+    /// Saves the accumulator in the register `name` of the session, returning it.
+    pub fn store(&mut self, name: u8) -> Result<Answer, ClientError> {
+        self.send(&Store(name).encode())?;
+        self.requests += 1;
+        self.recv_answer()
+    }
+
+    /// Sets the accumulator to the value saved in the register `name`.
+    pub fn load(&mut self, name: u8) -> Result<Answer, ClientError> {
+        self.send(&Load(name).encode())?;
+        self.requests += 1;
+        self.recv_answer()
+    }
+
+    /// Waits for the next answer from the server.
+    pub fn recv_answer(&mut self) -> Result<Answer, ClientError> {
+        let frame = self.receive(&[TlvType::Numi64, TlvType::Rejection])?;
+        match frame.tag {
+            TlvType::Rejection => Err(ClientError::Rejected(frame.as_tlv().try_into()?)),
+            _ => Ok(self.profile.decode_answer(frame.as_tlv())?),
+        }
+    }
+
+    /// Sends all the operations in a single frame and waits for their results,
+    /// which the server also packs together. It saves a frame, and a write, per
+    /// operation, but fails with more operations than fit in a frame.
+    pub fn send_batch(
+        &mut self,
+        operations: &[Operation],
+    ) -> Result<Vec<Result<Answer, Rejection>>, ClientError> {
+        self.send(&Batch(operations.to_vec()).encode()?)?;
+
+        let mut results = Vec::with_capacity(operations.len());
+        loop {
+            let frame = self.receive(&[TlvType::AnswerBatch])?;
+            results.extend(self.profile.decode_batch(frame.as_tlv())?);
+            if results.len() >= operations.len() {
+                return Ok(results);
+            }
+        }
+    }
+
+    /// Sends the operation without waiting for its answer. Collect the answers with [`Client::finish`].
+    pub fn send_operation(&mut self, operation: Operation) -> Result<(), ClientError> {
+        let mut request = Vec::new();
+        if let Some(trace) = self.trace {
+            let trace = trace.next();
+            request.extend_from_slice(&trace.encode());
+            self.trace = Some(trace);
+        }
+        if let Some(left) = self.deadline {
+            request.extend_from_slice(&Deadline::after(left).encode());
+        }
+        if let Some(key) = self.next_key {
+            request.extend_from_slice(&IdempotencyKey(key).encode());
+            self.next_key = Some(key.wrapping_add(1));
+        }
+        request.extend_from_slice(&operation.encode());
+
+        self.send(&request)?;
+        self.requests += 1;
+        Ok(())
+    }
+
+    /// Opens a new channel in the connection, or `None` once the 256 of them are open.
+    pub fn open_channel(&mut self) -> Option<Channel> {
+        let channel = u8::try_from(self.channels).ok()?;
+        self.channels += 1;
+        Some(Channel(channel))
+    }
+
+    /// Like [`Client::compute`], but in the session of the `channel`, with its
+    /// own accumulator.
+    pub fn compute_in(
+        &mut self,
+        channel: Channel,
+        operation: Operation,
+    ) -> Result<Answer, ClientError> {
+        let request = operation.encode();
+        let request = ChannelFrame {
+            channel: channel.0,
+            frame: Tlv::whole(&request)?,
+        };
+        self.send(&request.encode()?)?;
+
+        let reply = self.receive(&[TlvType::Channel])?;
+        let ChannelFrame { channel: id, frame } = reply.as_tlv().try_into()?;
+        match (id == channel.0, frame.tag) {
+            (false, _) => Err(ClientError::Unexpected),
+            (true, TlvType::Rejection) => Err(ClientError::Rejected(frame.try_into()?)),
+            (true, _) => Ok(self.profile.decode_answer(frame)?),
+        }
+    }
+
+    /// Says goodbye and half-closes the connection, so the server sees the end of the
+    /// stream, and then collects the answers still pending until the server acknowledges.
+    /// Operations rejected by the server get their [`Rejection`] in place of the answer.
+    pub fn finish(&mut self) -> Result<Vec<Result<Answer, Rejection>>, ClientError> {
+        self.send(&Bye.encode())?;
+        self.stream.shutdown(Shutdown::Write)?;
+
+        let mut answers = Vec::new();
+        loop {
+            let frame = self.receive(&[
+                TlvType::Numi64,
+                TlvType::Rejection,
+                TlvType::GoAway,
+                TlvType::Bye,
+            ])?;
+            match frame.tag {
+                TlvType::Bye => return Ok(answers),
+                // We are already leaving
+                TlvType::GoAway => (),
+                TlvType::Rejection => answers.push(Err(frame.as_tlv().try_into()?)),
+                _ => answers.push(Ok(self.profile.decode_answer(frame.as_tlv())?)),
+            }
+        }
+    }
+
+    /// Sends a keep-alive Ping and waits for its Pong, returning the round-trip time.
+    pub fn ping(&mut self) -> Result<Duration, ClientError> {
+        self.ping_sequence = self.ping_sequence.wrapping_add(1);
+        let ping = Ping(self.ping_sequence.to_be_bytes());
+
+        let start = Instant::now();
+        self.send(&ping.encode())?;
+        let pong: Pong = self.receive(&[TlvType::Pong])?.as_tlv().try_into()?;
+        if pong != ping.into() {
+            return Err(ClientError::Unexpected);
+        }
+
+        Ok(start.elapsed())
+    }
+
+    /// Asks the server for the digests of the frames exchanged so far and compares
+    /// them with our own, to detect anything in between altering the stream.
+    #[cfg(feature = "audit")]
+    pub fn audit(&mut self) -> Result<AuditReport, ClientError> {
+        self.send(&AuditQuery.encode())?;
+        let sent = self.transcript.digest().sent;
+        let frame = self.receive(&[TlvType::AuditDigest, TlvType::Rejection])?;
+        if frame.tag == TlvType::Rejection {
+            return Err(ClientError::Rejected(frame.as_tlv().try_into()?));
+        }
+        let server: AuditDigest = frame.as_tlv().try_into()?;
+
+        Ok(AuditReport {
+            requests_intact: server.received == sent,
+            answers_intact: server.sent == self.transcript.digest().received,
+        })
+    }
+
+    /// Answers the operations that a server in quiz mode asks, one at a time,
+    /// with the outcome of `solve` for each, until the server ends the quiz.
+    /// Call it right after connecting.
+    pub fn answer_quiz<F>(&mut self, mut solve: F) -> Result<QuizReport, ClientError>
+    where
+        F: FnMut(&Operation) -> Result<i64, Rejection>,
+    {
+        self.session = Session::reversed();
+        let mut report = QuizReport::default();
+        loop {
+            let frame = self.receive(QUIZ_FRAMES)?;
+            match frame.tag {
+                TlvType::GoAway => {
+                    self.close()?;
+                    return Ok(report);
+                }
+                TlvType::Leaderboard => {
+                    report.leaderboard = Some(frame.as_tlv().try_into()?);
+                    continue;
+                }
+                _ => (),
+            }
+
+            let operation = Operation::try_from(frame.as_tlv()).map_err(TCPLibError::from)?;
+            match solve(&operation) {
+                Ok(value) => self.send(&Answer::from(value).encode())?,
+                Err(rejection) => self.send(&rejection.encode())?,
+            }
+            report.answered += 1;
+        }
+    }
+
+    /// What the kernel knows of the connection to the server.
+    #[cfg(all(feature = "tcp-info", target_os = "linux"))]
+    pub fn tcp_info(&self) -> io::Result<crate::net::TcpInfo> {
+        crate::net::tcp_info(&self.stream)
+    }
+
+    /// Says goodbye to the server and waits for it to acknowledge the end of the session.
+    pub fn close(&mut self) -> Result<(), ClientError> {
+        self.send(&Bye.encode())?;
+        let Bye = self.receive(&[TlvType::Bye])?.as_tlv().try_into()?;
+
+        Ok(())
+    }
+
+    /// Hands the connection to a background reader, so that several requests
+    /// can be waiting for their answers at once. See [`AsyncClient`].
+    pub fn into_async(self) -> Result<AsyncClient, ClientError> {
+        Ok(AsyncClient::new(
+            self.stream,
+            self.decoder,
+            self.requests,
+            self.profile,
+        )?)
+    }
+
+    fn send(&mut self, bytes: &[u8]) -> Result<(), ClientError> {
+        for tlv in TlvIterator::process(bytes) {
+            self.session.advance(Peer::Client, tlv.tag)?;
+        }
+
+        let stream: &mut dyn Write = match &mut self.throttled {
+            Some(throttled) => throttled,
+            None => &mut self.stream,
+        };
+        write_request(stream, self.chunk_size, bytes)?;
+        self.transcript.sent(bytes);
+        self.sent += bytes.len() as u64;
+
+        Ok(())
+    }
+
+    /// Waits for a frame with one of the `expected` tags, dealing with any other
+    /// according to the [`UnsolicitedPolicy`].
+    fn receive(&mut self, expected: &[TlvType]) -> Result<Frame, ClientError> {
+        loop {
+            let frame = self.receive_frame()?;
+            self.session.advance(Peer::Server, frame.tag)?;
+            self.transcript.received(frame.as_tlv());
+            #[cfg(feature = "audit")]
+            if frame.tag == TlvType::AuditQuery {
+                self.send(&self.transcript.digest().encode())?;
+                continue;
+            }
+            if frame.tag == TlvType::Progress {
+                let progress = frame.as_tlv().try_into()?;
+                if let Some(handler) = &mut self.progress {
+                    handler(progress);
+                }
+                continue;
+            }
+            if expected.contains(&frame.tag) {
+                return Ok(frame);
+            }
+            if frame.tag == TlvType::GoAway {
+                let GoAway { retry_after } = frame.as_tlv().try_into()?;
+                return Err(ClientError::GoingAway(retry_after));
+            }
+
+            match &mut self.unsolicited {
+                UnsolicitedPolicy::Skip => {
+                    eprintln!("Ignoring unsolicited {:?} frame from the server", frame.tag)
+                }
+                UnsolicitedPolicy::Error => return Err(ClientError::Unexpected),
+                UnsolicitedPolicy::Handler(handler) => handler(frame),
+            }
+        }
+    }
+
+    fn receive_frame(&mut self) -> Result<Frame, ClientError> {
+        read_frame(&mut self.stream, &mut self.decoder, self.timeout)
+    }
+}
+
+/// Writes a request, in writes of at most `chunk_size` bytes if given.
+fn write_request(
+    stream: &mut (impl Write + ?Sized),
+    chunk_size: Option<NonZeroUsize>,
+    bytes: &[u8],
+) -> io::Result<()> {
+    match chunk_size {
+        Some(chunk_size) => ChunkedWriter::new(stream, chunk_size).write_all(bytes),
+        None => stream.write_all(bytes),
+    }
+}
+
+/// Reads until a complete TLV arrives, however the server splits it, or the
+/// `timeout` fires.
+fn read_frame(
+    stream: &mut impl Stream,
+    decoder: &mut Decoder,
+    timeout: Option<Duration>,
+) -> Result<Frame, ClientError> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut buffer = [0u8; 2048];
+
+    loop {
+        if let Some(frame) = decoder.next_frame()? {
+            return Ok(frame);
+        }
+
+        if let Some(deadline) = deadline {
+            match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) if !remaining.is_zero() => {
+                    stream.set_read_timeout(Some(remaining))?
+                }
+                _ => return Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+            }
+        }
+
+        match stream.read(&mut buffer) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Ok(len) => decoder.extend(&buffer[..len]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, ErrorKind, Read, Write},
+        mem,
+        net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+        num::{NonZeroU64, NonZeroUsize},
+        sync::{mpsc, Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{read_frame, write_request};
+    use crate::{
+        store::{MemoryStore, SharedStore},
+        testing::TestStream,
+        Answer, Cancel, Capabilities, Client, ClientError, Clock, Decoder, FakeClock, Hello,
+        IdempotencyKey, Operation, Pong, Priority, Profile, Progress, Rejection, Server,
+        ServerConfig, ServerEvent, Tenant, TlvType, UnsolicitedPolicy,
+    };
+
+    fn spawn_server() -> SocketAddr {
+        spawn_server_with(ServerConfig::default())
+    }
+
+    fn spawn_server_with(config: ServerConfig) -> SocketAddr {
+        spawn_server_on(config, None)
+    }
+
+    /// Like `spawn_server_with`, telling the time with the `clock`, if any.
+    fn spawn_server_on(config: ServerConfig, clock: Option<FakeClock>) -> SocketAddr {
+        let mut server = Server::bind(config).unwrap();
+        if let Some(clock) = clock {
+            server.set_clock(clock);
+        }
+        let port = server.local_addr().unwrap().port();
+        thread::spawn(move || server.run());
+
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn scripted_stream() {
+        let answer = Answer::from(7).encode();
+        let mut stream = TestStream::new()
+            .reading(&answer[..3])
+            .failing_read(ErrorKind::Interrupted)
+            .reading(&answer[3..]);
+        let frame = read_frame(&mut stream, &mut Decoder::new(), None).unwrap();
+        assert_eq!(Answer::try_from(frame.as_tlv()).unwrap(), Answer::from(7));
+        // The end of the stream in the middle of a frame
+        let mut stream = TestStream::new().reading(&answer[..3]);
+        match read_frame(&mut stream, &mut Decoder::new(), None) {
+            Err(ClientError::Io(e)) => assert_eq!(e.kind(), ErrorKind::UnexpectedEof),
+            other => panic!("{other:?}"),
+        }
+        let mut stream = TestStream::new().failing_read(ErrorKind::WouldBlock);
+        let timeout = Duration::from_secs(5);
+        let e = read_frame(&mut stream, &mut Decoder::new(), Some(timeout)).unwrap_err();
+        assert!(e.is_timeout());
+        assert!(stream.read_timeout().is_some_and(|set| set <= timeout));
+
+        let request = "3 + 4".parse::<Operation>().unwrap().encode();
+        let mut stream = TestStream::new()
+            .writing(1)
+            .failing_write(ErrorKind::Interrupted)
+            .writing(2);
+        write_request(&mut stream, None, &request).unwrap();
+        assert_eq!(stream.written(), &request[..]);
+        let mut stream = TestStream::new().failing_write(ErrorKind::WouldBlock);
+        let chunk_size = NonZeroUsize::new(4);
+        let e = write_request(&mut stream, chunk_size, &request).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn compute_ping_and_close() {
+        let mut client = Client::connect(spawn_server(), None).unwrap();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        assert!(client.ping().is_ok());
+        assert_eq!(client.compute("2 * 3".parse().unwrap()).unwrap().value, 13);
+        assert!(client.close().is_ok());
+    }
+
+    #[test]
+    fn connect_timeout() {
+        let timeout = Duration::from_secs(5);
+        let mut client = Client::connect_timeout(spawn_server(), None, timeout).unwrap();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        assert!(client.close().is_ok());
+    }
+
+    #[cfg(feature = "audit")]
+    #[test]
+    fn audit() {
+        let mut client = Client::connect(spawn_server(), None).unwrap();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        assert!(client.ping().is_ok());
+        let report = client.audit().unwrap();
+        assert!(report.requests_intact && report.answers_intact);
+        client.set_tracing(true);
+        assert_eq!(client.compute("2 * 3".parse().unwrap()).unwrap().value, 13);
+        let report = client.audit().unwrap();
+        assert!(report.requests_intact && report.answers_intact);
+    }
+
+    #[test]
+    fn pipeline_and_finish() {
+        let mut client = Client::connect(spawn_server(), None).unwrap();
+        for operation in ["1 + 1", "5!", "10 / 0", "3 - 4"] {
+            if let Ok(operation) = operation.parse() {
+                client.send_operation(operation).unwrap();
+            }
+        }
+        assert_eq!(
+            client
+                .finish()
+                .unwrap()
+                .into_iter()
+                .map(|answer| answer.unwrap().value)
+                .collect::<Vec<_>>(),
+            [2, 122, 121]
+        );
+    }
+
+    #[test]
+    fn send_batch() {
+        let mut client = Client::connect(spawn_server(), None).unwrap();
+        // The parser already refuses 21!
+        let operations = [
+            "3 + 4".parse().unwrap(),
+            "5!".parse().unwrap(),
+            Operation::Fact(21.into()),
+        ];
+        assert_eq!(
+            client.send_batch(&operations).unwrap(),
+            [
+                Ok(Answer::from(7)),
+                Ok(Answer::from(127)),
+                Err(Rejection::WrongDomain)
+            ]
+        );
+
+        // More results than fit in a frame
+        let operations = vec!["0 + 1".parse().unwrap(); 40];
+        let results = client.send_batch(&operations).unwrap();
+        assert_eq!(results.len(), 40);
+        assert_eq!(results[39], Ok(Answer::from(167)));
+
+        assert!(client.send_batch(&[]).unwrap().is_empty());
+        assert_eq!(client.compute("1 - 1".parse().unwrap()).unwrap().value, 167);
+    }
+
+    #[test]
+    fn capabilities() {
+        let server = spawn_server_with(ServerConfig {
+            disabled_operations: vec![TlvType::Store],
+            ..Default::default()
+        });
+        let mut client = Client::connect(server, None).unwrap();
+        let capabilities = client.capabilities().unwrap();
+        assert!(capabilities.contains(Capabilities::BATCH | Capabilities::SUM_N));
+        assert!(!capabilities.contains(Capabilities::REGISTERS));
+        assert!(!capabilities.contains(Capabilities::TEXT));
+        // Asked only once
+        assert_eq!(client.capabilities().unwrap(), capabilities);
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        client.close().unwrap();
+
+        let mut server = Server::bind(ServerConfig::default()).unwrap();
+        server.advertise(Capabilities::default());
+        let addr = SocketAddr::from(([127, 0, 0, 1], server.local_addr().unwrap().port()));
+        thread::spawn(move || server.run());
+        let mut client = Client::connect(addr, None).unwrap();
+        assert_eq!(client.capabilities().unwrap(), Capabilities::default());
+    }
+
+    #[test]
+    fn registers() {
+        let server = spawn_server();
+        let mut client = Client::connect(server, None).unwrap();
+        client.compute("3 + 4".parse().unwrap()).unwrap();
+        assert_eq!(client.store(b'x').unwrap().value, 7);
+        client.compute("1 + 1".parse().unwrap()).unwrap();
+        assert_eq!(client.load(b'x').unwrap().value, 7);
+        assert!(matches!(
+            client.load(b'y'),
+            Err(ClientError::Rejected(Rejection::UnknownRegister))
+        ));
+
+        // Along with x, the 16 registers of the session
+        for name in 0..15 {
+            client.store(name).unwrap();
+        }
+        assert!(matches!(
+            client.store(15),
+            Err(ClientError::Rejected(Rejection::TooManyRegisters))
+        ));
+        // Overwriting one still works
+        assert_eq!(client.store(b'x').unwrap().value, 7);
+
+        // The registers belong to the session
+        client.close().unwrap();
+        let mut other = Client::connect(server, None).unwrap();
+        assert!(matches!(
+            other.load(b'x'),
+            Err(ClientError::Rejected(Rejection::UnknownRegister))
+        ));
+    }
+
+    #[test]
+    fn sum_n() {
+        let mut client = Client::connect(spawn_server(), None).unwrap();
+        assert_eq!(
+            client
+                .compute("sum 1 2 3 4".parse().unwrap())
+                .unwrap()
+                .value,
+            10
+        );
+        // A frame with the longest length byte
+        let operation = format!("sum{}", " 127".repeat(255)).parse().unwrap();
+        assert_eq!(client.compute(operation).unwrap().value, 10 + 255 * 127);
+    }
+
+    #[cfg(feature = "script")]
+    #[test]
+    fn handler_script() {
+        let handler = crate::ScriptHandler::compile(
+            r#"fn answer(command, acc) { if command == "SUM 3 4" { acc + 8 } else if command.starts_with("MUL") { "Disabled" } }"#,
+        )
+        .unwrap();
+        let mut client = Client::connect(
+            spawn_server_with(ServerConfig {
+                handler: Some(Arc::new(handler)),
+                ..Default::default()
+            }),
+            None,
+        )
+        .unwrap();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 8);
+        assert!(matches!(
+            client.compute("2 * 3".parse().unwrap()),
+            Err(ClientError::Rejected(Rejection::Disabled))
+        ));
+        assert_eq!(client.compute("2 - 3".parse().unwrap()).unwrap().value, 7);
+    }
+
+    #[test]
+    fn tenants() {
+        let tenant = |name: &str, rate_limit: u32| {
+            Tenant::new(name).with_rate_limit(rate_limit.try_into().ok())
+        };
+        let server = spawn_server_with(ServerConfig {
+            tenants: [
+                ("ka".to_string(), tenant("a", 0)),
+                ("kb".to_string(), tenant("b", 2)),
+            ]
+            .into(),
+            ..Default::default()
+        });
+        let connect = |key: Option<&str>| {
+            let mut client = Client::connect(server, None).unwrap();
+            if let Some(key) = key {
+                client.hello(key).unwrap();
+            }
+            client
+        };
+
+        for key in [None, Some("kc")] {
+            let mut client = connect(key);
+            assert!(matches!(
+                client.compute("3 + 4".parse().unwrap()),
+                Err(ClientError::Rejected(Rejection::Unauthorized))
+            ));
+            client.close().unwrap();
+        }
+
+        let mut client = connect(Some("ka"));
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        client.close().unwrap();
+
+        let mut client = connect(Some("kb"));
+        assert_eq!(client.compute("1 + 1".parse().unwrap()).unwrap().value, 2);
+        assert_eq!(client.compute("1 + 1".parse().unwrap()).unwrap().value, 4);
+        assert!(matches!(
+            client.compute("1 + 1".parse().unwrap()),
+            Err(ClientError::Rejected(Rejection::RateLimited))
+        ));
+        client.close().unwrap();
+
+        let mut client = connect(Some("ka"));
+        assert_eq!(client.compute("1 + 1".parse().unwrap()).unwrap().value, 9);
+        client.close().unwrap();
+    }
+
+    #[cfg(feature = "auth")]
+    #[test]
+    fn secrets() {
+        use crate::auth::Auth;
+
+        let server = spawn_server_with(ServerConfig {
+            secrets: [
+                ("old".to_string(), b"first".to_vec()),
+                ("new".to_string(), b"second".to_vec()),
+            ]
+            .into(),
+            ..Default::default()
+        });
+
+        let mut client = Client::connect(server, None).unwrap();
+        assert!(matches!(
+            client.compute("3 + 4".parse().unwrap()),
+            Err(ClientError::Rejected(Rejection::Unauthorized))
+        ));
+        for (key_id, secret) in [("old", &b"second"[..]), ("other", b"first")] {
+            assert!(matches!(
+                client.authenticate(key_id, secret),
+                Err(ClientError::Rejected(Rejection::Unauthorized))
+            ));
+        }
+        client.authenticate("old", b"first").unwrap();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        client.close().unwrap();
+
+        let mut client = Client::connect(server, None).unwrap();
+        client.authenticate("new", b"second").unwrap();
+        assert_eq!(client.compute("1 + 1".parse().unwrap()).unwrap().value, 9);
+        client.close().unwrap();
+
+        // Captured from another session, even of a server since restarted
+        let mut client = Client::connect(server, None).unwrap();
+        client.hello("").unwrap();
+        let challenge = client.challenge.unwrap();
+        let captured = Auth::sign("new", b"second", &challenge).encode().unwrap();
+        client.send(&captured).unwrap();
+        let auth = [TlvType::Auth, TlvType::Rejection];
+        assert_eq!(client.receive(&auth).unwrap().tag, TlvType::Auth);
+        client.close().unwrap();
+        let restarted = spawn_server_with(ServerConfig {
+            secrets: [("new".to_string(), b"second".to_vec())].into(),
+            ..Default::default()
+        });
+        for server in [server, restarted] {
+            let mut client = Client::connect(server, None).unwrap();
+            client.hello("").unwrap();
+            client.send(&captured).unwrap();
+            assert_eq!(client.receive(&auth).unwrap().tag, TlvType::Rejection);
+            client.close().unwrap();
+        }
+        // Nor without a challenge
+        let mut client = Client::connect(server, None).unwrap();
+        let unchallenged = Auth::sign("new", b"second", &challenge).encode().unwrap();
+        client.send(&unchallenged).unwrap();
+        assert_eq!(client.receive(&auth).unwrap().tag, TlvType::Rejection);
+        client.close().unwrap();
+    }
+
+    #[cfg(feature = "auth")]
+    #[test]
+    fn secrets_with_ascii_compat() {
+        let server = spawn_server_with(ServerConfig {
+            secrets: [("key".to_string(), b"s3cret".to_vec())].into(),
+            ascii_compat: true,
+            ..Default::default()
+        });
+        let mut client = Client::connect(server, None).unwrap();
+        client.set_timeout(Some(Duration::from_secs(5))).unwrap();
+        // The Auth, that starts with a printable tag, goes first
+        client.challenge = Some([0; Hello::CHALLENGE_LEN]);
+        assert!(matches!(
+            client.authenticate("key", b"s3cret"),
+            Err(ClientError::Rejected(Rejection::Unauthorized))
+        ));
+        client.authenticate("key", b"s3cret").unwrap();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        client.close().unwrap();
+    }
+
+    #[test]
+    fn ascii_compat() {
+        let server = spawn_server_with(ServerConfig {
+            ascii_compat: true,
+            ..Default::default()
+        });
+
+        let mut stream = TcpStream::connect(server).unwrap();
+        stream.write_all(b"3 + 4\n5 / 0\r\n\n2 *").unwrap();
+        stream.write_all(b" 3\nSUB 20 1\nfact 2 3\nQUIT\n").unwrap();
+        let mut lines = BufReader::new(stream).lines().map(Result::unwrap);
+        assert_eq!(lines.next().unwrap(), "7");
+        assert!(lines.next().unwrap().starts_with("ERROR: "));
+        assert_eq!(lines.next().unwrap(), "13");
+        assert_eq!(lines.next().unwrap(), "32");
+        assert!(lines.next().unwrap().starts_with("ERROR: "));
+        assert_eq!(lines.next().unwrap(), "BYE");
+
+        // TLVs still work, with the same accumulator
+        let mut client = Client::connect(server, None).unwrap();
+        assert_eq!(client.compute("1 + 1".parse().unwrap()).unwrap().value, 34);
+    }
+
+    #[test]
+    fn connection_stats() {
+        let mut server = Server::bind(ServerConfig {
+            max_factorial: Some(3),
+            ..Default::default()
+        })
+        .unwrap();
+        let port = server.local_addr().unwrap().port();
+        let (events, received) = mpsc::channel();
+        server.set_observer(move |event| events.send(event.clone()).unwrap());
+        thread::spawn(move || server.run());
+
+        let mut client = Client::connect(SocketAddr::from(([127, 0, 0, 1], port)), None).unwrap();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        assert!(client.compute("5!".parse().unwrap()).is_err());
+        client.ping().unwrap();
+        client.close().unwrap();
+
+        let next = || received.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(next(), ServerEvent::Connected { .. }));
+        assert!(matches!(
+            next(),
+            ServerEvent::Answered {
+                operation: TlvType::Sum,
+                outcome: Ok(Answer { value: 7, .. }),
+                ..
+            }
+        ));
+        assert!(matches!(
+            next(),
+            ServerEvent::Answered {
+                operation: TlvType::Fact,
+                outcome: Err(Rejection::WrongDomain),
+                ..
+            }
+        ));
+        let ServerEvent::Disconnected { stats, .. } = next() else {
+            panic!("the server did not report the disconnection");
+        };
+        assert_eq!(stats.bytes_in, 4 + 3 + 10 + 2);
+        assert_eq!(stats.bytes_out, 10 + 3 + 10 + 2);
+        assert_eq!(stats.operations[&TlvType::Sum], 1);
+        assert_eq!(stats.operations[&TlvType::Fact], 1);
+        assert_eq!(stats.rejections, 1);
+        assert_eq!(stats.invalid_frames, 0);
+    }
+
+    #[test]
+    fn connection_hooks() {
+        let mut server = Server::bind(ServerConfig::default()).unwrap();
+        let port = server.local_addr().unwrap().port();
+        let (events, received) = mpsc::channel();
+        let connects = events.clone();
+        server.on_connect(move |_| connects.send("connect".to_string()).unwrap());
+        let errors = events.clone();
+        server.on_error(move |_, e, _| errors.send(format!("error {:?}", e.kind())).unwrap());
+        server.on_disconnect(move |_, stats| {
+            let operations: u64 = stats.operations.values().sum();
+            events.send(format!("disconnect {operations}")).unwrap()
+        });
+        thread::spawn(move || server.run());
+        let server = SocketAddr::from(([127, 0, 0, 1], port));
+
+        let mut client = Client::connect(server, None).unwrap();
+        client.compute("3 + 4".parse().unwrap()).unwrap();
+        client.close().unwrap();
+        let next = || received.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(next(), "connect");
+        assert_eq!(next(), "disconnect 1");
+
+        // Resetting the connection, with a frame half sent
+        let mut stream = TcpStream::connect(server).unwrap();
+        stream.write_all(&[1, 2, 3]).unwrap();
+        socket2::SockRef::from(&stream)
+            .set_linger(Some(Duration::ZERO))
+            .unwrap();
+        assert_eq!(next(), "connect");
+        drop(stream);
+        assert_eq!(next(), "error ConnectionReset");
+        assert_eq!(next(), "disconnect 0");
+    }
+
+    #[test]
+    fn cancelled_accounting() {
+        let mut server = Server::bind(ServerConfig {
+            delay: Duration::from_millis(200),
+            ..Default::default()
+        })
+        .unwrap();
+        let port = server.local_addr().unwrap().port();
+        let (events, received) = mpsc::channel();
+        server.on_disconnect(move |_, stats| events.send(stats.clone()).unwrap());
+        thread::spawn(move || server.run());
+
+        let mut client = Client::connect(SocketAddr::from(([127, 0, 0, 1], port)), None).unwrap();
+        let operation = "1 + 1".parse::<Operation>().unwrap();
+        client
+            .send(&[operation.encode(), Cancel(0).encode()].concat())
+            .unwrap();
+        client.requests += 1;
+        assert!(matches!(
+            client.recv_answer(),
+            Err(ClientError::Rejected(Rejection::Cancelled))
+        ));
+        client.close().unwrap();
+        // Counted once, as rejected
+        let stats = received.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(stats.operations.values().sum::<u64>(), 1);
+        assert_eq!(stats.rejections, 1);
+    }
+
+    #[test]
+    fn quiz() {
+        let mut server = Server::bind(ServerConfig {
+            quiz: NonZeroUsize::new(5),
+            ..Default::default()
+        })
+        .unwrap();
+        let port = server.local_addr().unwrap().port();
+        let (events, received) = mpsc::channel();
+        server.set_observer(move |event| events.send(event.clone()).unwrap());
+        thread::spawn(move || server.run());
+        let server = SocketAddr::from(([127, 0, 0, 1], port));
+
+        let mut client = Client::connect(server, None).unwrap();
+        let solve = |operation: &Operation| operation.reduce().map_err(|e| Rejection::from(&e));
+        let report = client.answer_quiz(solve).unwrap();
+        assert_eq!(report.answered, 5);
+        let leaderboard = report.leaderboard.unwrap();
+        assert_eq!(leaderboard.0.len(), 1);
+        assert_eq!((leaderboard.0[0].right, leaderboard.0[0].asked), (5, 5));
+
+        // Get one wrong on purpose
+        let mut client = Client::connect(server, None).unwrap();
+        let mut first = true;
+        let answered = client.answer_quiz(|operation| match mem::take(&mut first) {
+            true => Err(Rejection::Other),
+            false => solve(operation),
+        });
+        let leaderboard = answered.unwrap().leaderboard.unwrap();
+        assert_eq!(leaderboard.0[0].player, "127.0.0.1");
+        assert_eq!(
+            (
+                leaderboard.0[0].rounds,
+                leaderboard.0[0].right,
+                leaderboard.0[0].asked
+            ),
+            (2, 9, 10)
+        );
+
+        let scores: Vec<_> = received
+            .iter()
+            .filter_map(|event| match event {
+                ServerEvent::Scored {
+                    right, questions, ..
+                } => Some((right, questions)),
+                _ => None,
+            })
+            .take(2)
+            .collect();
+        assert_eq!(scores, [(5, 5), (4, 5)]);
+    }
+
+    #[test]
+    fn max_conns_per_ip() {
+        let server = spawn_server_with(ServerConfig {
+            max_conns_per_ip: 1.try_into().ok(),
+            ..Default::default()
+        });
+
+        let mut served = Client::connect(server, None).unwrap();
+        assert_eq!(served.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        // Both wait in the backlog until the first one leaves
+        let mut waiting = Client::connect(server, None).unwrap();
+        let mut excess = TcpStream::connect(server).unwrap();
+        served.close().unwrap();
+
+        let mut refused = Vec::new();
+        excess.read_to_end(&mut refused).unwrap();
+        assert_eq!(refused, &Rejection::TooManyConnections.encode()[..]);
+        assert_eq!(waiting.compute("1 + 1".parse().unwrap()).unwrap().value, 9);
+    }
+
+    #[test]
+    fn proxy_header_timeout() {
+        let server = spawn_server_with(ServerConfig {
+            proxy_protocol: true,
+            ..Default::default()
+        });
+        // Dropped once the header is late, without holding the next one
+        let mut silent = TcpStream::connect(server).unwrap();
+        let mut stream = TcpStream::connect(server).unwrap();
+        stream
+            .write_all(b"PROXY TCP4 192.0.2.1 127.0.0.1 5000 6000\r\n")
+            .unwrap();
+        stream
+            .write_all(&"3 + 4".parse::<Operation>().unwrap().encode())
+            .unwrap();
+        let mut answer = [0; 10];
+        stream.read_exact(&mut answer).unwrap();
+        assert_eq!(Answer::try_from(&answer[..]).unwrap().value, 7);
+        let mut nothing = Vec::new();
+        silent.read_to_end(&mut nothing).unwrap();
+        assert!(nothing.is_empty());
+    }
+
+    #[test]
+    fn proxied_networks() {
+        let server = spawn_server_with(ServerConfig {
+            proxy_protocol: true,
+            allowed_networks: ["192.0.2.0/24".parse().unwrap()].into_iter().collect(),
+            ..Default::default()
+        });
+        let connect = |source: &str| {
+            let mut stream = TcpStream::connect(server)?;
+            let header = format!("PROXY TCP4 {source} 127.0.0.1 5000 6000\r\n");
+            stream.write_all(header.as_bytes())?;
+            stream.write_all(&"3 + 4".parse::<Operation>().unwrap().encode())?;
+            stream.shutdown(Shutdown::Write)?;
+            let mut answer = Vec::new();
+            stream.read_to_end(&mut answer).map(|_| answer)
+        };
+        // Closed without a word, maybe resetting what it did not read
+        assert!(!matches!(connect("198.51.100.1"), Ok(answer) if !answer.is_empty()));
+        let answer = connect("192.0.2.1").unwrap();
+        assert_eq!(Answer::try_from(&answer[..]).unwrap().value, 7);
+    }
+
+    #[test]
+    fn drain_with_loopback_denied() {
+        let mut server = Server::bind(ServerConfig {
+            denied_networks: ["127.0.0.0/8".parse().unwrap()].into_iter().collect(),
+            ..Default::default()
+        })
+        .unwrap();
+        let drain = server.drain_handle().unwrap();
+        let server = thread::spawn(move || server.run());
+        // Waiting for a connection when the drain starts
+        thread::sleep(Duration::from_millis(50));
+        drain.drain(Duration::from_secs(5));
+        assert!(server.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn series_only_if_kept() {
+        for keep in [false, true] {
+            let mut server = Server::bind(ServerConfig::default()).unwrap();
+            if keep {
+                server.keep_series();
+            }
+            let port = server.local_addr().unwrap().port();
+            let drain = server.drain_handle().unwrap();
+            let server = thread::spawn(move || server.run().map(|()| server));
+            let mut client =
+                Client::connect(SocketAddr::from(([127, 0, 0, 1], port)), None).unwrap();
+            assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+            client.close().unwrap();
+            drain.drain(Duration::ZERO);
+            let server = server.join().unwrap().unwrap();
+            assert_eq!(server.summary().series.len(), usize::from(keep));
+        }
+    }
+
+    #[test]
+    fn allowed_networks() {
+        let networks = |cidrs: &[&str]| cidrs.iter().map(|cidr| cidr.parse().unwrap()).collect();
+        let server = spawn_server_with(ServerConfig {
+            allowed_networks: networks(&["10.0.0.0/8", "127.0.0.0/8"]),
+            denied_networks: networks(&["127.0.0.2"]),
+            ..Default::default()
+        });
+        let mut client = Client::connect(server, None).unwrap();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        client.close().unwrap();
+
+        for config in [
+            ServerConfig {
+                allowed_networks: networks(&["10.0.0.0/8"]),
+                ..Default::default()
+            },
+            ServerConfig {
+                denied_networks: networks(&["127.0.0.0/8"]),
+                ..Default::default()
+            },
+        ] {
+            // Closed right away, without a word
+            let mut refused = Vec::new();
+            let mut stream = TcpStream::connect(spawn_server_with(config)).unwrap();
+            stream.read_to_end(&mut refused).unwrap();
+            assert!(refused.is_empty());
+        }
+    }
+
+    #[test]
+    fn channels() {
+        let server = spawn_server_with(ServerConfig {
+            max_factorial: Some(3),
+            ..Default::default()
+        });
+        let mut client = Client::connect(server, None).unwrap();
+        let first = client.open_channel().unwrap();
+        let second = client.open_channel().unwrap();
+        assert_ne!(first, second);
+
+        let mut compute_in =
+            |channel, operation: &str| client.compute_in(channel, operation.parse().unwrap());
+        assert_eq!(compute_in(first, "3 + 4").unwrap().value, 7);
+        assert_eq!(compute_in(second, "2 * 3").unwrap().value, 6);
+        assert_eq!(compute_in(first, "1 + 1").unwrap().value, 9);
+        assert!(matches!(
+            compute_in(second, "5!"),
+            Err(ClientError::Rejected(Rejection::WrongDomain))
+        ));
+        // The connection keeps its own accumulator too
+        assert_eq!(client.compute("1 + 1".parse().unwrap()).unwrap().value, 2);
+        assert_eq!(
+            client
+                .compute_in(second, "0 + 0".parse().unwrap())
+                .unwrap()
+                .value,
+            6
+        );
+
+        while client.open_channel().is_some() {}
+        assert_eq!(client.channels, 256);
+        client.close().unwrap();
+    }
+
+    #[test]
+    fn progress() {
+        let server = spawn_server_with(ServerConfig {
+            delay: Duration::from_millis(100),
+            progress_interval: Some(Duration::from_millis(20)),
+            ..Default::default()
+        });
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let mut client = Client::connect(server, None).unwrap();
+        client.set_progress_handler({
+            let reported = Arc::clone(&reported);
+            move |Progress(percent)| reported.lock().unwrap().push(percent)
+        });
+        // Not without saying hello
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        assert!(reported.lock().unwrap().is_empty());
+
+        assert!(client.hello("").unwrap().contains(Capabilities::PROGRESS));
+        assert_eq!(client.compute("1 + 1".parse().unwrap()).unwrap().value, 9);
+        let reported = reported.lock().unwrap();
+        assert!(reported.len() >= 2);
+        assert!(reported.windows(2).all(|pair| pair[0] < pair[1]));
+        client.close().unwrap();
+
+        // Even shorter than a millisecond, on a clock that is never late
+        let config = ServerConfig {
+            delay: Duration::from_micros(500),
+            progress_interval: Some(Duration::from_micros(100)),
+            ..Default::default()
+        };
+        let server = spawn_server_on(config, Some(FakeClock::new()));
+        let mut client = Client::connect(server, None).unwrap();
+        client.set_progress_handler(|_| ());
+        client.hello("").unwrap();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        client.close().unwrap();
+    }
+
+    #[test]
+    fn tarpit() {
+        let clock = FakeClock::new();
+        let delay = Duration::from_secs(3600);
+        let config = ServerConfig {
+            tarpit_after: NonZeroU64::new(1),
+            tarpit_delay: delay,
+            tarpit_budget: 20 * delay,
+            ..Default::default()
+        };
+        let server = spawn_server_on(config, Some(clock.clone()));
+        let offend = || {
+            let mut stream = TcpStream::connect(server).unwrap();
+            // Only the server sends answers
+            stream.write_all(&Answer::from(7).encode()).unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+            stream.read_to_end(&mut Vec::new()).unwrap();
+        };
+        offend();
+
+        let mut client = Client::connect(server, None).unwrap();
+        let (start, real_start) = (clock.now(), Instant::now());
+        assert!(client.compute("3 + 4".parse().unwrap()).is_ok());
+        // The answer takes ten bytes, written one at a time, but the hours of
+        // pauses pass only on the clock of the server
+        assert!(clock.since(start) >= 10 * delay);
+        assert!(real_start.elapsed() < delay);
+        // Closed before its pauses take longer than allowed
+        assert!(client.compute("1 + 1".parse().unwrap()).is_err());
+
+        // Until the offence is forgotten
+        clock.advance(Duration::from_secs(600));
+        let mut client = Client::connect(server, None).unwrap();
+        let start = clock.now();
+        assert!(client.compute("0 + 0".parse().unwrap()).is_ok());
+        assert!(clock.since(start) < delay);
+        client.close().unwrap();
+    }
+
+    /// Lowering the limit of descriptors would break the other tests, so it runs
+    /// in a process of its own.
+    #[cfg(unix)]
+    #[test]
+    fn fd_exhaustion() {
+        use std::{env, fs::File, process::Command};
+
+        use nix::sys::resource::{getrlimit, setrlimit, Resource};
+
+        const CHILD: &str = "TCP1_FD_EXHAUSTION_TEST";
+        if env::var_os(CHILD).is_none() {
+            let status = Command::new(env::current_exe().unwrap())
+                .args(["--exact", "client::tests::fd_exhaustion", "--nocapture"])
+                .env(CHILD, "1")
+                .status()
+                .unwrap();
+            assert!(status.success());
+            return;
+        }
+
+        let (_, hard) = getrlimit(Resource::RLIMIT_NOFILE).unwrap();
+        setrlimit(Resource::RLIMIT_NOFILE, 64, hard).unwrap();
+        let server = spawn_server();
+
+        let mut hogs = Vec::new();
+        while let Ok(file) = File::open("/dev/null") {
+            hogs.push(file);
+        }
+        hogs.pop();
+        // It takes the last descriptor, so the server cannot accept it
+        let mut shed = TcpStream::connect(server).unwrap();
+        shed.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(shed.read(&mut [0; 1]).unwrap(), 0);
+
+        drop(hogs);
+        let mut client = Client::connect(server, None).unwrap();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+    }
+
+    #[test]
+    fn rejected_factorial() {
+        let mut client = Client::connect(
+            spawn_server_with(ServerConfig {
+                max_factorial: Some(5),
+                ..Default::default()
+            }),
+            None,
+        )
+        .unwrap();
+        assert!(matches!(
+            client.compute("6!".parse().unwrap()),
+            Err(ClientError::Rejected(Rejection::WrongDomain))
+        ));
+        assert_eq!(client.compute("5!".parse().unwrap()).unwrap().value, 120);
+    }
+
+    #[test]
+    fn reassemble_split_answers() {
+        let mut client = Client::connect(
+            spawn_server_with(ServerConfig {
+                chunked_writes: 3.try_into().ok(),
+                ..Default::default()
+            }),
+            None,
+        )
+        .unwrap();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        assert_eq!(
+            client.compute("-1 * 100".parse().unwrap()).unwrap().value,
+            -93
+        );
+    }
+
+    #[test]
+    fn timeout() {
+        let mut client = Client::connect(
+            spawn_server_with(ServerConfig {
+                delay: Duration::from_millis(500),
+                ..Default::default()
+            }),
+            None,
+        )
+        .unwrap();
+        client.set_timeout(Some(Duration::from_millis(50))).unwrap();
+        assert!(client
+            .compute("3 + 4".parse().unwrap())
+            .unwrap_err()
+            .is_timeout());
+    }
+
+    #[test]
+    fn saturated_accumulator() {
+        let mut client = Client::connect(spawn_server(), None).unwrap();
+        let answer = client.compute("100 * 100".parse().unwrap()).unwrap();
+        assert!(!answer.overflow);
+        for _ in 0..8 {
+            client.compute("20!".parse().unwrap()).unwrap();
+        }
+        let answer = client.compute("20!".parse().unwrap()).unwrap();
+        assert_eq!(answer, Answer::saturated(i64::MAX));
+    }
+
+    #[test]
+    fn shared_store() {
+        let store: SharedStore = Arc::new(Mutex::new(MemoryStore::new()));
+        let config = || ServerConfig {
+            store: Some(store.clone()),
+            ..Default::default()
+        };
+        let (first, second) = (spawn_server_with(config()), spawn_server_with(config()));
+
+        let mut client = Client::connect(first, None).unwrap();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        let mut other = Client::connect(second, None).unwrap();
+        assert_eq!(other.compute("2 * 3".parse().unwrap()).unwrap().value, 13);
+        assert_eq!(client.compute("1 - 1".parse().unwrap()).unwrap().value, 13);
+        assert_eq!(store.lock().unwrap().get("acc:").unwrap(), Some(13));
+    }
+
+    #[test]
+    fn shared_accumulator() {
+        let store: SharedStore = Arc::new(Mutex::new(MemoryStore::new()));
+        let clients: Vec<_> = (0..2)
+            .map(|_| {
+                let server = spawn_server_with(ServerConfig {
+                    store: Some(store.clone()),
+                    shared_accumulator: true,
+                    ..Default::default()
+                });
+                thread::spawn(move || {
+                    let mut client = Client::connect(server, None).unwrap();
+                    for _ in 0..50 {
+                        client.compute("1 + 0".parse().unwrap()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for client in clients {
+            client.join().unwrap();
+        }
+        // None of the additions of the other server is lost
+        assert_eq!(store.lock().unwrap().get("acc:").unwrap(), Some(100));
+    }
+
+    #[test]
+    fn idempotency_keys() {
+        let server = spawn_server();
+        let mut client = Client::connect(server, None).unwrap();
+        let key = IdempotencyKey(7);
+        assert_eq!(
+            client
+                .compute_with_key("3 + 4".parse().unwrap(), key)
+                .unwrap()
+                .value,
+            7
+        );
+        assert_eq!(
+            client
+                .compute_with_key("3 + 4".parse().unwrap(), key)
+                .unwrap()
+                .value,
+            7
+        );
+
+        client.set_idempotency_keys(true);
+        assert!(client.set_tracing(true).is_some());
+        assert_eq!(client.compute("1 + 1".parse().unwrap()).unwrap().value, 9);
+        assert_eq!(client.compute("1 + 1".parse().unwrap()).unwrap().value, 11);
+
+        // The answers outlive the connection
+        client.close().unwrap();
+        let mut client = Client::connect(server, None).unwrap();
+        assert_eq!(
+            client
+                .compute_with_key("3 + 4".parse().unwrap(), key)
+                .unwrap()
+                .value,
+            7
+        );
+    }
+
+    #[test]
+    fn coalesced_writes() {
+        let server = spawn_server_with(ServerConfig {
+            flush_interval: Some(Duration::ZERO),
+            ..Default::default()
+        });
+        let mut stream = TcpStream::connect(server).unwrap();
+        let requests: Vec<u8> = ["3 + 4", "2 * 3", "1 - 1"]
+            .iter()
+            .flat_map(|op| op.parse::<Operation>().unwrap().encode().into_vec())
+            .collect();
+        stream.write_all(&requests).unwrap();
+        // The three answers, written at once after the read
+        let mut answers = [0u8; 64];
+        assert_eq!(stream.read(&mut answers).unwrap(), 30);
+
+        let server = spawn_server_with(ServerConfig {
+            flush_interval: Some(Duration::from_millis(150)),
+            ..Default::default()
+        });
+        let mut client = Client::connect(server, None).unwrap();
+        let start = Instant::now();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        assert!(client.close().is_ok());
+    }
+
+    #[test]
+    fn throttle() {
+        let server = spawn_server_with(ServerConfig {
+            throttle: NonZeroU64::new(200),
+            ..Default::default()
+        });
+        let mut client = Client::connect(server, None).unwrap();
+        client.set_throttle(NonZeroU64::new(200)).unwrap();
+        let start = Instant::now();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        // Two bytes go at once, and then one every 5ms: two more of the
+        // request and eight of the answer
+        assert!(start.elapsed() >= Duration::from_millis(45));
+        client.close().unwrap();
+    }
+
+    #[test]
+    fn profile() {
+        // Asked for in the hello
+        let server = spawn_server();
+        let mut client = Client::connect(server, None).unwrap();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        client.set_profile(Profile::LE32).unwrap();
+        assert!(client.capabilities().unwrap().contains(Capabilities::LE32));
+        assert_eq!(client.compute("3 - 13".parse().unwrap()).unwrap().value, -3);
+        let answer = client.compute("20!".parse().unwrap()).unwrap();
+        assert_eq!(answer, Answer::saturated(i32::MAX.into()));
+        // Also the answers of batches and channels
+        let results = client
+            .send_batch(&["0 - 1".parse().unwrap(), "0 + 0".parse().unwrap()])
+            .unwrap();
+        assert_eq!(results, [Ok(Answer::saturated(i32::MAX.into())); 2]);
+        let channel = client.open_channel().unwrap();
+        assert_eq!(
+            client
+                .compute_in(channel, "0 - 5".parse().unwrap())
+                .unwrap(),
+            Answer::from(-5)
+        );
+        // And back to the classic profile
+        client.set_profile(Profile::Classic).unwrap();
+        let answer = client.compute("0 + 0".parse().unwrap()).unwrap();
+        assert!(answer.value > i64::from(i32::MAX));
+        client.close().unwrap();
+
+        // Imposed by the server
+        let server = spawn_server_with(ServerConfig {
+            profile: Profile::LE32,
+            ..Default::default()
+        });
+        let mut classic = Client::connect(server, None).unwrap();
+        assert!(classic.compute("3 + 4".parse().unwrap()).is_err());
+        drop(classic);
+        let mut client = Client::connect(server, None).unwrap();
+        client.set_profile(Profile::LE32).unwrap();
+        // The accumulator is shared with the first client
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 14);
+        client.close().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mss() {
+        let server = spawn_server_with(ServerConfig {
+            mss: Some(200),
+            ..Default::default()
+        });
+        let mut client = Client::connect_with_mss(server, 120).unwrap();
+        let mss = client.mss().unwrap();
+        assert!(mss <= 120, "{mss}");
+        // A frame that fills the segment, and another that does not fit in it
+        for operands in [mss - 2, mss - 1] {
+            let sum = Operation::SumN(vec![1; operands as usize].try_into().unwrap());
+            assert!(client.compute(sum).is_ok());
+        }
+        client.close().unwrap();
+    }
+
+    #[test]
+    fn op_timeout() {
+        let mut server = Server::bind(ServerConfig {
+            delay: Duration::from_millis(200),
+            op_timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        })
+        .unwrap();
+        let address = SocketAddr::from(([127, 0, 0, 1], server.local_addr().unwrap().port()));
+        let config = server.config_handle();
+        thread::spawn(move || server.run());
+
+        let mut client = Client::connect(address, None).unwrap();
+        let start = Instant::now();
+        assert!(matches!(
+            client.compute("3 + 4".parse().unwrap()),
+            Err(ClientError::Rejected(Rejection::Timeout))
+        ));
+        assert!((50..200).contains(&start.elapsed().as_millis()));
+        config.update(|config| config.op_timeout = None);
+        assert_eq!(client.compute("1 + 1".parse().unwrap()).unwrap().value, 2);
+        client.close().unwrap();
+    }
+
+    #[test]
+    fn deadline() {
+        let clock = FakeClock::new();
+        let config = ServerConfig {
+            delay: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let server = spawn_server_on(config, Some(clock.clone()));
+        let mut client = Client::connect(server, None).unwrap();
+        client.set_deadline(Some(Duration::from_millis(50)));
+        let start = clock.now();
+        assert!(matches!(
+            client.compute("3 + 4".parse().unwrap()),
+            Err(ClientError::Rejected(Rejection::DeadlineExceeded))
+        ));
+        // Abandoned when the deadline passed
+        assert_eq!(clock.since(start), Duration::from_millis(50));
+
+        // Those behind it in a batch are skipped, without waiting
+        let start = clock.now();
+        let sum = || "1 + 1".parse().unwrap();
+        client.send_operation(sum()).unwrap();
+        client.send_operation(sum()).unwrap();
+        assert!(matches!(
+            client.recv_answer(),
+            Err(ClientError::Rejected(Rejection::DeadlineExceeded))
+        ));
+        assert!(matches!(
+            client.recv_answer(),
+            Err(ClientError::Rejected(Rejection::DeadlineExceeded))
+        ));
+        assert!(clock.since(start) < Duration::from_millis(200));
+
+        client.set_deadline(Some(Duration::from_secs(5)));
+        assert_eq!(client.compute("1 + 1".parse().unwrap()).unwrap().value, 2);
+        client.close().unwrap();
+    }
+
+    #[test]
+    fn priorities() {
+        let server = spawn_server_with(ServerConfig {
+            priorities: true,
+            ..Default::default()
+        });
+        let mut first = Client::connect(server, None).unwrap();
+        assert_eq!(first.compute("1 + 1".parse().unwrap()).unwrap().value, 2);
+
+        // Both wait for the first to leave, and the interactive one goes ahead
+        let mut clients = [Priority::BULK, Priority::INTERACTIVE].map(|priority| {
+            let mut client = Client::connect(server, None).unwrap();
+            client.prioritize(priority).unwrap();
+            client.set_timeout(Some(Duration::from_secs(5))).unwrap();
+            client.send_operation("3 + 4".parse().unwrap()).unwrap();
+            client
+        });
+        first.close().unwrap();
+        // The accumulator is shared, so it tells the order
+        let [bulk, interactive] = &mut clients;
+        assert_eq!(interactive.recv_answer().unwrap().value, 9);
+        interactive.close().unwrap();
+        assert_eq!(bulk.recv_answer().unwrap().value, 16);
+        bulk.close().unwrap();
+    }
+
+    #[test]
+    fn proxied_priorities() {
+        let server = spawn_server_with(ServerConfig {
+            priorities: true,
+            proxy_protocol: true,
+            ..Default::default()
+        });
+        let connect = |source: &str, frames: &[&[u8]]| {
+            let mut stream = TcpStream::connect(server).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let header = format!("PROXY TCP4 {source} 127.0.0.1 5000 6000\r\n");
+            stream.write_all(header.as_bytes()).unwrap();
+            for frame in frames {
+                stream.write_all(frame).unwrap();
+            }
+            stream
+        };
+        let answer = |stream: &mut TcpStream| {
+            let mut answer = [0; 10];
+            stream.read_exact(&mut answer).unwrap();
+            Answer::try_from(&answer[..]).unwrap().value
+        };
+        let sum = "3 + 4".parse::<Operation>().unwrap().encode();
+        let mut first = connect("192.0.2.1", &[&sum]);
+        assert_eq!(answer(&mut first), 7);
+
+        // Their priorities are read past the headers
+        let mut bulk = connect("192.0.2.2", &[&Priority::BULK.encode(), &sum]);
+        let mut interactive = connect("192.0.2.3", &[&Priority::INTERACTIVE.encode(), &sum]);
+        thread::sleep(Duration::from_millis(100));
+        drop(first);
+        assert_eq!(answer(&mut interactive), 14);
+        drop(interactive);
+        assert_eq!(answer(&mut bulk), 21);
+    }
+
+    #[test]
+    fn fair_queuing() {
+        let tenant = Tenant::new;
+        let server = spawn_server_with(ServerConfig {
+            tenants: [
+                ("ka".to_string(), tenant("a")),
+                ("kb".to_string(), tenant("b")),
+            ]
+            .into(),
+            ..Default::default()
+        });
+        let mut first = Client::connect(server, None).unwrap();
+        first.hello("ka").unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        // The second of a arrives before b, but b has been served less. Their
+        // hellos are answered only once served, so they do not wait for them
+        let mut clients = ["ka", "kb"].map(|key| {
+            let mut client = Client::connect(server, None).unwrap();
+            client.set_timeout(Some(Duration::from_secs(5))).unwrap();
+            let hello = Hello {
+                capabilities: Capabilities::BATCH,
+                challenge: None,
+                api_key: key.to_string(),
+            };
+            client.send(&hello.encode().unwrap()).unwrap();
+            client.send_operation("3 + 4".parse().unwrap()).unwrap();
+            client
+        });
+        first.close().unwrap();
+        let [a, b] = &mut clients;
+        b.receive(&[TlvType::Hello]).unwrap();
+        assert_eq!(b.recv_answer().unwrap().value, 7);
+        b.close().unwrap();
+        a.receive(&[TlvType::Hello]).unwrap();
+        assert_eq!(a.recv_answer().unwrap().value, 7);
+        a.close().unwrap();
+    }
+
+    #[test]
+    fn reconfigure_running_server() {
+        let mut server = Server::bind(ServerConfig::default()).unwrap();
+        let address = SocketAddr::from(([127, 0, 0, 1], server.local_addr().unwrap().port()));
+        let config = server.config_handle();
+        thread::spawn(move || server.run());
+
+        let mut client = Client::connect(address, None).unwrap();
+        assert!(client.compute("6!".parse().unwrap()).is_ok());
+        config.update(|config| {
+            config.max_factorial = Some(5);
+            config.disabled_operations = vec![TlvType::Sum];
+        });
+        assert!(matches!(
+            client.compute("6!".parse().unwrap()),
+            Err(ClientError::Rejected(Rejection::WrongDomain))
+        ));
+        assert!(matches!(
+            client.compute("1 + 1".parse().unwrap()),
+            Err(ClientError::Rejected(Rejection::Disabled))
+        ));
+        assert_eq!(client.compute("5!".parse().unwrap()).unwrap().value, 840);
+    }
+
+    #[test]
+    fn drain() {
+        let mut server = Server::bind(ServerConfig::default()).unwrap();
+        let address = SocketAddr::from(([127, 0, 0, 1], server.local_addr().unwrap().port()));
+        let drain = server.drain_handle().unwrap();
+        let server = thread::spawn(move || server.run());
+
+        let mut client = Client::connect(address, None).unwrap();
+        assert!(client.compute("1 + 1".parse().unwrap()).is_ok());
+        drain.drain(Duration::from_secs(5));
+        assert!(matches!(
+            client.recv_answer(),
+            Err(ClientError::GoingAway(retry_after)) if retry_after <= Duration::from_secs(5)
+        ));
+        assert!(client.close().is_ok());
+        assert!(server.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn no_requests_after_finish() {
+        let mut client = Client::connect(spawn_server(), None).unwrap();
+        client.send_operation("1 + 1".parse().unwrap()).unwrap();
+        assert_eq!(client.finish().unwrap().len(), 1);
+        assert!(matches!(
+            client.compute("1 + 1".parse().unwrap()),
+            Err(ClientError::Session(_))
+        ));
+    }
+
+    /// Starts a fake server that answers the first request with an extra Pong before the answer
+    fn spawn_chatty_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&Pong([0; 8]).encode()).unwrap();
+            stream.write_all(&Answer::from(42).encode()).unwrap();
+            thread::sleep(Duration::from_millis(100));
+        });
+
+        address
+    }
+
+    #[test]
+    fn unsolicited_error() {
+        let mut client = Client::connect(spawn_chatty_server(), None).unwrap();
+        assert!(client.recv_answer().is_err());
+    }
+
+    #[test]
+    fn unsolicited_skip() {
+        let mut client = Client::connect(spawn_chatty_server(), None).unwrap();
+        client.set_unsolicited_policy(UnsolicitedPolicy::Skip);
+        assert_eq!(client.recv_answer().unwrap().value, 42);
+    }
+
+    #[test]
+    fn unsolicited_handler() {
+        let mut client = Client::connect(spawn_chatty_server(), None).unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_by_handler = Arc::clone(&seen);
+        client
+            .set_unsolicited_handler(move |frame| seen_by_handler.lock().unwrap().push(frame.tag));
+        assert_eq!(client.recv_answer().unwrap().value, 42);
+        assert_eq!(seen.lock().unwrap()[..], [TlvType::Pong]);
+    }
+}
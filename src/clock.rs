@@ -0,0 +1,116 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Source of the time for the timeouts, deadlines, rate limits, throttles and
+//! tarpits, so that tests can drive them with a [`FakeClock`] instead of
+//! sleeping.
+
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Tells the time and waits.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+
+    fn sleep(&self, duration: Duration);
+
+    /// Time that has passed since `earlier`.
+    fn since(&self, earlier: Instant) -> Duration {
+        self.now().saturating_duration_since(earlier)
+    }
+}
+
+/// The time of the system, that passes on its own.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// A clock that only moves when told to, or when something sleeps on it, which
+/// returns at once. Its clones share the time.
+#[derive(Clone, Debug)]
+pub struct FakeClock(Arc<Mutex<Instant>>);
+
+impl FakeClock {
+    /// Starts at the current time of the system.
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(Instant::now())))
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+}
+
+impl Default for FakeClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// The clock of the system, to share.
+pub(crate) fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Clock, FakeClock};
+
+    #[test]
+    fn fake_clock() {
+        let clock = FakeClock::new();
+        let start = clock.now();
+        let shared = clock.clone();
+        shared.sleep(Duration::from_secs(3600));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(clock.since(start), Duration::from_secs(3601));
+        assert_eq!(shared.now(), clock.now());
+        // Without waiting for it
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(Instant::now() < clock.now());
+    }
+}
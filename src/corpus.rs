@@ -0,0 +1,143 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Replays the inputs stored in `tests/corpus`. Each file there is an input
+//! that once crashed or hung some part of the crate, and it is fed to the TLV
+//! decoder, to the operation parser and to a running server, so that it keeps
+//! working as a regression test. Adding a new case is just dropping the
+//! offending bytes in a new file. Every error they cause must be in the
+//! [catalog](crate::errors::catalog).
+
+use std::{
+    fs,
+    io::{ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    errors::{lookup, Coded},
+    tlv::{Decoder, TlvIterator},
+    Client, Operation, ParserOptions, Server, ServerConfig,
+};
+
+const CORPUS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/corpus");
+
+/// Checks that the error of `result`, if any, has a known code.
+fn cataloged<T>(name: &str, result: Result<T, impl Coded>) {
+    if let Err(e) = result {
+        assert!(
+            lookup(e.code()).is_some(),
+            "Uncataloged error with {name}: {e}"
+        );
+    }
+}
+
+/// Stored inputs, by file name.
+fn inputs() -> Vec<(String, Vec<u8>)> {
+    let mut inputs: Vec<_> = fs::read_dir(CORPUS)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, fs::read(&path).unwrap())
+        })
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty(), "No inputs in {CORPUS}");
+
+    inputs
+}
+
+#[test]
+fn decoder() {
+    for (name, input) in inputs() {
+        println!("Decoding {name}");
+        let mut decoder = Decoder::new();
+        // One byte at a time, to exercise reassembly as well
+        for byte in &input {
+            decoder.extend(std::slice::from_ref(byte));
+            loop {
+                match decoder.next_frame() {
+                    Ok(Some(frame)) => cataloged(&name, Operation::try_from(frame.as_tlv())),
+                    Ok(None) => break,
+                    // The offending frame has already been skipped
+                    Err(e) => cataloged(&name, Err::<(), _>(e)),
+                }
+            }
+        }
+
+        for tlv in TlvIterator::process(&input) {
+            cataloged(&name, Operation::try_from(tlv));
+        }
+    }
+}
+
+#[test]
+fn parser() {
+    let lenient = ParserOptions::lenient();
+    for (name, input) in inputs() {
+        println!("Parsing {name}");
+        for line in String::from_utf8_lossy(&input).lines() {
+            cataloged(&name, line.parse::<Operation>());
+            cataloged(&name, Operation::parse_with(line, &lenient));
+        }
+    }
+}
+
+#[test]
+fn server() {
+    let mut server = Server::bind(ServerConfig {
+        ascii_compat: true,
+        ..Default::default()
+    })
+    .unwrap();
+    let addr = SocketAddr::from(([127, 0, 0, 1], server.local_addr().unwrap().port()));
+    thread::spawn(move || server.run());
+
+    for (name, input) in inputs() {
+        println!("Sending {name}");
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        // The server may hang up before reading everything
+        let _ = stream.write_all(&input);
+        let _ = stream.shutdown(Shutdown::Write);
+        let mut answer = Vec::new();
+        if let Err(e) = stream.read_to_end(&mut answer) {
+            assert!(
+                !matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut),
+                "The server hung with {name}"
+            );
+        }
+
+        // The accumulator is shared, so only check that there is an answer
+        let mut client = Client::connect(addr, None).unwrap();
+        assert!(
+            client.compute("3 + 4".parse().unwrap()).is_ok(),
+            "The server misbehaves after {name}"
+        );
+        client.close().unwrap();
+    }
+}
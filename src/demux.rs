@@ -0,0 +1,442 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Client that keeps several requests in flight over a single connection. A
+//! background thread reads the answers and hands each one to the request it
+//! belongs to, so that callers can wait for them in any order.
+//!
+//! The protocol has no correlation identifiers in the answers, but the server
+//! answers in order, so the oldest request still waiting gets the next answer.
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll, Waker},
+    thread::{self, JoinHandle},
+};
+
+use crate::{Answer, Bye, Cancel, ClientError, Decoder, Operation, Profile, Progress, TlvType};
+
+/// Where the reader leaves the outcome of a request.
+#[derive(Default)]
+struct Slot {
+    outcome: Option<Result<Answer, ClientError>>,
+    /// The last one the server sent about the request
+    progress: Option<Progress>,
+    waker: Option<Waker>,
+}
+
+#[derive(Default)]
+struct Shared {
+    slot: Mutex<Slot>,
+    ready: Condvar,
+}
+
+impl Shared {
+    fn fulfil(&self, outcome: Result<Answer, ClientError>) {
+        let mut slot = self.slot.lock().unwrap();
+        slot.outcome = Some(outcome);
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+        self.ready.notify_all();
+    }
+}
+
+/// Answer of a request sent with [`AsyncClient::compute_async`]. Either `.await`
+/// it in any executor or block on it with [`PendingAnswer::wait`].
+pub struct PendingAnswer {
+    id: u64,
+    shared: Arc<Shared>,
+}
+
+impl PendingAnswer {
+    /// Correlation identifier of the request, increasing in the order they were
+    /// sent. It is also the number of the request for the server.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// How much of the operation the server calculated, if it said so. See
+    /// [`crate::Client::set_progress_handler`].
+    pub fn progress(&self) -> Option<Progress> {
+        self.shared.slot.lock().unwrap().progress
+    }
+
+    /// Blocks until the answer arrives.
+    pub fn wait(self) -> Result<Answer, ClientError> {
+        let mut slot = self.shared.slot.lock().unwrap();
+        loop {
+            if let Some(outcome) = slot.outcome.take() {
+                return outcome;
+            }
+            slot = self.shared.ready.wait(slot).unwrap();
+        }
+    }
+}
+
+impl Future for PendingAnswer {
+    type Output = Result<Answer, ClientError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.shared.slot.lock().unwrap();
+        match slot.outcome.take() {
+            Some(outcome) => Poll::Ready(outcome),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Errors of the reader, not tied to any request, kept until taken.
+const MAX_ERRORS: usize = 64;
+
+/// Requests waiting for their answers, oldest first.
+#[derive(Default)]
+struct Pending {
+    next_id: u64,
+    requests: VecDeque<(u64, Arc<Shared>)>,
+    /// Set when the reader stops, failing the requests sent afterwards
+    closed: bool,
+    /// Of the frames the reader ignored, oldest first
+    errors: Vec<ClientError>,
+}
+
+impl Pending {
+    /// Keeps the error of an ignored frame, unless too many are kept already.
+    fn ignore(&mut self, e: ClientError) {
+        if self.errors.len() < MAX_ERRORS {
+            self.errors.push(e);
+        }
+    }
+}
+
+/// Client with a background reader that demultiplexes the answers, created with
+/// [`crate::Client::into_async`]. It can be shared between threads.
+pub struct AsyncClient {
+    stream: Mutex<TcpStream>,
+    pending: Arc<Mutex<Pending>>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl AsyncClient {
+    /// Takes over the connection after the client sent `requests` operations,
+    /// decoding the answers as in `profile`.
+    pub(crate) fn new(
+        stream: TcpStream,
+        decoder: Decoder,
+        requests: u64,
+        profile: Profile,
+    ) -> io::Result<Self> {
+        let pending = Arc::new(Mutex::new(Pending {
+            next_id: requests,
+            ..Pending::default()
+        }));
+        let reader = {
+            let stream = stream.try_clone()?;
+            let pending = Arc::clone(&pending);
+            thread::spawn(move || read_answers(stream, decoder, profile, &pending))
+        };
+
+        Ok(Self {
+            stream: Mutex::new(stream),
+            pending,
+            reader: Some(reader),
+        })
+    }
+
+    /// Sends the operation without waiting for its answer.
+    pub fn compute_async(&self, operation: Operation) -> Result<PendingAnswer, ClientError> {
+        let shared = Arc::new(Shared::default());
+        // Held until written, so that the queue keeps the order of the stream. The
+        // queue is not, so the reader never waits for a blocked write
+        let mut stream = self.stream.lock().unwrap();
+        let id = {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.closed {
+                return Err(io::Error::from(io::ErrorKind::NotConnected).into());
+            }
+            let id = pending.next_id;
+            pending.next_id += 1;
+            pending.requests.push_back((id, Arc::clone(&shared)));
+            id
+        };
+        // Should it fail, the reader fails the request when the connection ends
+        stream.write_all(&operation.encode())?;
+
+        Ok(PendingAnswer { id, shared })
+    }
+
+    /// Asks the server to abandon the request with this id. If it is still
+    /// being calculated, it fails with [`crate::Rejection::Cancelled`].
+    pub fn cancel(&self, id: u64) -> Result<(), ClientError> {
+        self.stream
+            .lock()
+            .unwrap()
+            .write_all(&Cancel(id).encode())?;
+        Ok(())
+    }
+
+    /// Takes the errors of the frames that the reader ignored because they
+    /// belonged to no request, such as unsolicited or malformed progress
+    /// frames, oldest first. Only the first few are kept until taken.
+    pub fn take_errors(&self) -> Vec<ClientError> {
+        std::mem::take(&mut self.pending.lock().unwrap().errors)
+    }
+
+    /// Says goodbye, once the answers still pending arrive.
+    pub fn close(mut self) -> Result<(), ClientError> {
+        self.stream.lock().unwrap().write_all(&Bye.encode())?;
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for AsyncClient {
+    fn drop(&mut self) {
+        // Wakes up the reader
+        let _ = self.stream.lock().unwrap().shutdown(Shutdown::Both);
+    }
+}
+
+/// Hands every answer to the oldest request waiting, until the server says
+/// goodbye or the connection ends. Then fails the requests still waiting. A
+/// malformed frame fails the oldest request, as it was likely its answer.
+fn read_answers(
+    mut stream: TcpStream,
+    mut decoder: Decoder,
+    profile: Profile,
+    pending: &Mutex<Pending>,
+) {
+    let mut buffer = [0u8; 2048];
+    // Of the requests still waiting when the connection ends
+    let mut failure = io::ErrorKind::UnexpectedEof;
+    'read: loop {
+        loop {
+            let frame = match decoder.next_frame() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(e) => {
+                    let mut pending = pending.lock().unwrap();
+                    match pending.requests.pop_front() {
+                        Some((_, shared)) => shared.fulfil(Err(e.into())),
+                        None => pending.ignore(e.into()),
+                    }
+                    continue;
+                }
+            };
+            let outcome = match frame.tag {
+                TlvType::Numi64 => profile
+                    .decode_answer(frame.as_tlv())
+                    .map_err(ClientError::from),
+                TlvType::Rejection => match frame.as_tlv().try_into() {
+                    Ok(rejection) => Err(ClientError::Rejected(rejection)),
+                    Err(e) => Err(ClientError::from(e)),
+                },
+                TlvType::Bye => break 'read,
+                // About the oldest request, still waiting
+                TlvType::Progress => {
+                    let mut pending = pending.lock().unwrap();
+                    match (Progress::try_from(frame.as_tlv()), pending.requests.front()) {
+                        (Ok(progress), Some((_, shared))) => {
+                            shared.slot.lock().unwrap().progress = Some(progress)
+                        }
+                        (Err(e), _) => pending.ignore(e.into()),
+                        (Ok(_), None) => pending.ignore(ClientError::Unexpected),
+                    }
+                    continue;
+                }
+                _ => {
+                    pending.lock().unwrap().ignore(ClientError::Unexpected);
+                    continue;
+                }
+            };
+            let mut pending = pending.lock().unwrap();
+            match pending.requests.pop_front() {
+                Some((_, shared)) => shared.fulfil(outcome),
+                None => pending.ignore(ClientError::Unexpected),
+            }
+        }
+
+        match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(len) => decoder.extend(&buffer[..len]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                failure = e.kind();
+                break;
+            }
+        }
+    }
+
+    let mut pending = pending.lock().unwrap();
+    pending.closed = true;
+    for (_, shared) in pending.requests.drain(..) {
+        shared.fulfil(Err(io::Error::from(failure).into()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        io::{Read, Write},
+        net::{Ipv4Addr, SocketAddr, TcpListener},
+        pin::pin,
+        sync::Arc,
+        task::{Context, Poll, Waker},
+        thread,
+        time::Duration,
+    };
+
+    use crate::{
+        Answer, Client, ClientError, Operation, Ping, Pong, Rejection, Server, ServerConfig,
+    };
+
+    fn connect() -> Client {
+        let mut server = Server::bind(ServerConfig::default()).unwrap();
+        let addr = SocketAddr::from(([127, 0, 0, 1], server.local_addr().unwrap().port()));
+        thread::spawn(move || server.run());
+
+        Client::connect(addr, None).unwrap()
+    }
+
+    #[test]
+    fn answers_in_any_order() {
+        let client = connect().into_async().unwrap();
+        let first = client.compute_async("3 + 4".parse().unwrap()).unwrap();
+        let second = client.compute_async(Operation::Fact(21.into())).unwrap();
+        let third = client.compute_async("2 * 3".parse().unwrap()).unwrap();
+        assert!(first.id() < second.id() && second.id() < third.id());
+
+        assert_eq!(third.wait().unwrap().value, 13);
+        assert!(matches!(
+            second.wait(),
+            Err(ClientError::Rejected(Rejection::WrongDomain))
+        ));
+        let mut first = pin!(first);
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(matches!(
+            first.as_mut().poll(&mut cx),
+            Poll::Ready(Ok(answer)) if answer.value == 7
+        ));
+        client.close().unwrap();
+    }
+
+    #[test]
+    fn cancel() {
+        let mut server = Server::bind(ServerConfig {
+            delay: Duration::from_millis(200),
+            ..Default::default()
+        })
+        .unwrap();
+        let addr = SocketAddr::from(([127, 0, 0, 1], server.local_addr().unwrap().port()));
+        thread::spawn(move || server.run());
+
+        let mut client = Client::connect(addr, None).unwrap();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        let client = client.into_async().unwrap();
+        let first = client.compute_async("1 + 1".parse().unwrap()).unwrap();
+        assert_eq!(first.id(), 1);
+        client.cancel(first.id()).unwrap();
+        assert!(matches!(
+            first.wait(),
+            Err(ClientError::Rejected(Rejection::Cancelled))
+        ));
+        // The accumulator is as before, and a cancel too late does nothing
+        let second = client.compute_async("0 + 0".parse().unwrap()).unwrap();
+        let id = second.id();
+        assert_eq!(second.wait().unwrap().value, 7);
+        client.cancel(id).unwrap();
+        client.close().unwrap();
+    }
+
+    #[test]
+    fn reader_errors() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = "0 + 0".parse::<Operation>().unwrap().encode();
+            let mut received = vec![0; 2 * request.len()];
+            stream.read_exact(&mut received).unwrap();
+            // Nobody asked for the pong, and the unknown tag is the first answer
+            stream
+                .write_all(&Pong::from(Ping([0; 8])).encode())
+                .unwrap();
+            stream.write_all(&[0xee, 1, 0]).unwrap();
+            stream.write_all(&Answer::from(7).encode()).unwrap();
+        });
+
+        let client = Client::connect(addr, None).unwrap().into_async().unwrap();
+        let first = client.compute_async("0 + 0".parse().unwrap()).unwrap();
+        let second = client.compute_async("0 + 0".parse().unwrap()).unwrap();
+        assert!(matches!(first.wait(), Err(ClientError::Tlv(_))));
+        assert_eq!(second.wait().unwrap().value, 7);
+        assert!(matches!(
+            client.take_errors()[..],
+            [ClientError::Unexpected]
+        ));
+        assert!(client.take_errors().is_empty());
+        // The connection ends without a goodbye
+        let third = client.compute_async("0 + 0".parse().unwrap());
+        assert!(third.map_or(true, |third| third.wait().is_err()));
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let client = Arc::new(connect().into_async().unwrap());
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let client = Arc::clone(&client);
+                thread::spawn(move || {
+                    let pending: Vec<_> = (0..25)
+                        .map(|_| client.compute_async("1 + 0".parse().unwrap()).unwrap())
+                        .collect();
+                    let values: Vec<i64> = pending
+                        .into_iter()
+                        .map(|answer| answer.wait().unwrap().value)
+                        .collect();
+                    // Sent in order, so their answers grow
+                    assert!(values.windows(2).all(|pair| pair[0] < pair[1]));
+                    values
+                })
+            })
+            .collect();
+
+        let mut values: Vec<i64> = workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect();
+        values.sort();
+        assert_eq!(values, (1..=100).collect::<Vec<_>>());
+    }
+}
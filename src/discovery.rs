@@ -0,0 +1,122 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Announcement and discovery of servers in the local network with DNS-SD over
+//! multicast DNS, so that nobody needs to know the address of the server.
+
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use thiserror::Error;
+
+/// DNS-SD service type of the calculator.
+pub const SERVICE_TYPE: &str = "_tcp1._tcp.local.";
+
+#[derive(Error, Debug)]
+#[error("Service discovery error. {0}")]
+pub struct DiscoveryError(#[from] mdns_sd::Error);
+
+/// Keeps a server announced until dropped.
+pub struct Announcement {
+    daemon: ServiceDaemon,
+}
+
+impl Announcement {
+    /// Announces a server listening on `port` of every address of this host.
+    /// The `name` defaults to the host name and the port.
+    pub fn new(name: Option<&str>, port: u16) -> Result<Self, DiscoveryError> {
+        let host = hostname();
+        let name = name.map_or_else(|| format!("{host}-{port}"), str::to_string);
+        let info = ServiceInfo::new(
+            SERVICE_TYPE,
+            &name,
+            &format!("{host}.local."),
+            "",
+            port,
+            None,
+        )?
+        .enable_addr_auto();
+        let daemon = ServiceDaemon::new()?;
+        daemon.register(info)?;
+
+        Ok(Self { daemon })
+    }
+}
+
+impl Drop for Announcement {
+    fn drop(&mut self) {
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// A server found in the local network.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Announced {
+    /// Instance name, as announced
+    pub name: String,
+    pub addresses: Vec<SocketAddr>,
+}
+
+/// Lists the servers that answer within `timeout`, sorted by name.
+pub fn discover(timeout: Duration) -> Result<Vec<Announced>, DiscoveryError> {
+    let daemon = ServiceDaemon::new()?;
+    let events = daemon.browse(SERVICE_TYPE)?;
+    let deadline = Instant::now() + timeout;
+
+    let mut found = BTreeMap::new();
+    while let Ok(event) = events.recv_deadline(deadline) {
+        if let ServiceEvent::ServiceResolved(info) = event {
+            let name = info
+                .get_fullname()
+                .trim_end_matches(SERVICE_TYPE)
+                .trim_end_matches('.')
+                .to_string();
+            let mut addresses: Vec<_> = info
+                .get_addresses()
+                .iter()
+                .map(|ip| SocketAddr::from((*ip, info.get_port())))
+                .collect();
+            // IPv4 first, as link-local IPv6 addresses need a scope to be used
+            addresses.sort_by_key(|addr| (addr.is_ipv6(), *addr));
+            found.insert(name.clone(), Announced { name, addresses });
+        }
+    }
+    let _ = daemon.shutdown();
+
+    Ok(found.into_values().collect())
+}
+
+fn hostname() -> String {
+    #[cfg(unix)]
+    if let Some(name) = nix::unistd::gethostname()
+        .ok()
+        .and_then(|name| name.into_string().ok())
+    {
+        return name;
+    }
+
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "tcp1".to_string())
+}
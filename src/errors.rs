@@ -0,0 +1,425 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Stable codes of the errors of the library, so that programs, the
+//! documentation and the tests can tell them apart without matching their
+//! messages. Every error type implements [`Coded`], and [`catalog`] lists all
+//! the codes, including those of the errors of optional features.
+//!
+//! Errors that wrap another one, such as [`ClientError::Tlv`], take the code
+//! of the wrapped error, which is also their [`Error::source`].
+
+use std::{error::Error, fmt};
+
+#[cfg(feature = "auth")]
+use crate::auth::AuthError;
+use crate::{
+    format::RadixError, net::CidrError, ClientError, OperationError, ProfileError, ProxyError,
+    ProxyHeaderError, Rejection, SessionError, TCPLibError, TlvError,
+};
+
+/// Broad classes of errors, that decide the exit code of the command line tools.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// What the user typed could not be parsed
+    Parse,
+    /// The command line or a configuration file is wrong
+    Config,
+    /// The operation cannot be calculated
+    Calculation,
+    /// The connection could not be established or was lost
+    Connection,
+    /// The peer did not answer in time
+    Timeout,
+    /// The peer broke the protocol or sent malformed frames
+    Protocol,
+    /// The server rejected a request
+    Rejected,
+    /// Either end could not prove it knows the shared secret
+    Auth,
+    /// A local file could not be written
+    Io,
+}
+
+impl ErrorKind {
+    /// Code that the command line tools exit with. Parse errors share `2` with
+    /// the wrong command lines that the argument parser rejects.
+    pub const fn exit_code(self) -> u8 {
+        match self {
+            ErrorKind::Parse | ErrorKind::Config => 2,
+            ErrorKind::Connection => 3,
+            ErrorKind::Protocol => 4,
+            ErrorKind::Timeout => 5,
+            ErrorKind::Rejected | ErrorKind::Calculation => 6,
+            ErrorKind::Auth => 7,
+            ErrorKind::Io => 8,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// An error with a code of the [`catalog`].
+pub trait Coded: Error {
+    /// Number of the error, that is kept between versions
+    fn code(&self) -> u16;
+
+    fn kind(&self) -> ErrorKind {
+        lookup(self.code()).map_or(ErrorKind::Protocol, |entry| entry.kind)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CatalogEntry {
+    pub code: u16,
+    pub kind: ErrorKind,
+    /// The error type and its variant
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+const fn entry(
+    code: u16,
+    kind: ErrorKind,
+    name: &'static str,
+    description: &'static str,
+) -> CatalogEntry {
+    CatalogEntry {
+        code,
+        kind,
+        name,
+        description,
+    }
+}
+
+use ErrorKind::{Auth, Calculation, Config, Connection, Parse, Protocol, Rejected, Timeout};
+
+#[rustfmt::skip]
+const CATALOG: &[CatalogEntry] = &[
+    entry(100, Parse, "OperationError::UnsupportedOperation", "The operation is not one of the protocol"),
+    entry(101, Parse, "OperationError::Parse", "Unexpected character in a typed operation"),
+    entry(102, Parse, "OperationError::Literal", "An operand does not fit in an i8"),
+    entry(103, Protocol, "OperationError::NotEnoughData", "The TLV lacks some operand"),
+    entry(104, Parse, "OperationError::InvalidParameter", "An operand is not valid for the operation, as a zero divisor"),
+    entry(105, Parse, "OperationError::ParseIntError", "An operand is not an integer"),
+    entry(106, Parse, "OperationError::OperandCount", "A multiple sum without operands or with too many"),
+    entry(107, Parse, "OperationError::Hex", "Invalid hexadecimal bytes"),
+    entry(108, Calculation, "OperationError::WrongDomain", "Operand out of the domain of the operation"),
+    entry(109, Calculation, "OperationError::Overflow", "The result does not fit in the accumulator"),
+    entry(110, Protocol, "OperationError::Generic", "The TLV is not an operation"),
+    entry(200, Protocol, "TlvError::TagUnknown", "Unknown tag"),
+    entry(201, Parse, "TlvError::NameUnknown", "Unknown tag name"),
+    entry(202, Protocol, "TlvError::Truncated", "The TLV is longer than the bytes left"),
+    entry(203, Protocol, "TlvError::FrameTooLarge", "The frame is over the size limit"),
+    entry(204, Protocol, "TlvError::ExcessiveLength", "Too much data for a TLV"),
+    entry(205, Protocol, "TlvError::Trailing", "Bytes left after the TLV"),
+    entry(300, Protocol, "TCPLibError::UnsupportedOperation", "The TLV is not an operation"),
+    entry(301, Parse, "TCPLibError::Parse", "The operation could not be parsed"),
+    entry(302, Protocol, "TCPLibError::NotEnoughData", "The TLV lacks some field"),
+    entry(303, Protocol, "TCPLibError::InvalidParameter", "A field is out of range"),
+    entry(304, Parse, "TCPLibError::ParseIntError", "Not an integer"),
+    entry(305, Protocol, "TCPLibError::Generic", "The TLV is not the message expected"),
+    entry(400, Connection, "ClientError::Io", "The connection failed"),
+    entry(401, Timeout, "ClientError::Io, timed out", "The server did not answer in time"),
+    entry(402, Protocol, "ClientError::Unexpected", "The server sent a frame out of place"),
+    entry(403, Rejected, "ClientError::Rejected", "The server rejected the request"),
+    entry(404, Auth, "ClientError::Rejected, unauthorized", "The server did not accept the credentials"),
+    entry(405, Connection, "ClientError::GoingAway", "The server is shutting down"),
+    entry(500, Protocol, "SessionError", "The frame is not allowed in the state of the session"),
+    entry(600, Connection, "ProxyError::Io", "Could not talk to the proxy"),
+    entry(601, Config, "ProxyError::InvalidUrl", "Invalid proxy URL"),
+    entry(602, Connection, "ProxyError::AuthenticationRequired", "The proxy asks for credentials"),
+    entry(603, Connection, "ProxyError::Refused", "The proxy refused the connection"),
+    entry(604, Protocol, "ProxyError::Protocol", "Unexpected answer from the proxy"),
+    entry(700, Connection, "ProxyHeaderError::Io", "Could not read the PROXY header"),
+    entry(701, Protocol, "ProxyHeaderError::Missing", "Missing PROXY header"),
+    entry(702, Protocol, "ProxyHeaderError::Malformed", "Malformed PROXY header"),
+    entry(703, Protocol, "ProxyHeaderError::UnsupportedVersion", "Unsupported PROXY protocol version"),
+    entry(800, Auth, "AuthError::Mac", "The MAC does not match the message"),
+    entry(801, Auth, "AuthError::Replay", "The nonce was already used"),
+    entry(802, Config, "AuthError::Secret", "A secret is not written as ID:SECRET"),
+    entry(900, Config, "CidrError::Address", "Invalid address in a network"),
+    entry(901, Config, "CidrError::Prefix", "Invalid prefix length in a network"),
+    entry(902, Config, "ProfileError", "Unknown protocol profile"),
+    entry(903, Config, "RadixError", "Unknown radix"),
+    entry(1000, Connection, "QuicError::Io", "The QUIC connection failed"),
+    entry(1001, Connection, "QuicError::Tls", "The TLS handshake failed"),
+    entry(1002, Connection, "QuicError::Connect", "Could not connect over QUIC"),
+    entry(1003, Connection, "QuicError::Connection", "The QUIC connection was lost"),
+    entry(1004, Connection, "QuicError::Write", "Could not send the request"),
+    entry(1005, Connection, "QuicError::Closed", "The stream was closed before sending the request"),
+    entry(1006, Connection, "QuicError::Read", "Could not read the answer"),
+    entry(1007, Rejected, "QuicError::Rejected", "The server rejected the request"),
+    entry(1100, Config, "ScriptError::Io", "Could not read the handler script"),
+    entry(1101, Config, "ScriptError::Parse", "Could not compile the handler script"),
+    entry(1102, Config, "ScriptError::NoEntryPoint", "The handler script lacks its entry point"),
+    entry(1103, Calculation, "ScriptError::Eval", "The handler script failed"),
+    entry(1104, Calculation, "ScriptError::Return", "The handler script returned something else than an answer"),
+    entry(1200, Connection, "DiscoveryError", "Multicast DNS failed"),
+];
+
+/// Every error code, in increasing order.
+pub fn catalog() -> &'static [CatalogEntry] {
+    CATALOG
+}
+
+pub fn lookup(code: u16) -> Option<&'static CatalogEntry> {
+    CATALOG.iter().find(|entry| entry.code == code)
+}
+
+impl Coded for OperationError {
+    fn code(&self) -> u16 {
+        match self {
+            OperationError::UnsupportedOperation(_) => 100,
+            OperationError::Parse { .. } => 101,
+            OperationError::Literal { .. } => 102,
+            OperationError::NotEnoughData(_) => 103,
+            OperationError::InvalidParameter(_) => 104,
+            OperationError::ParseIntError(_) => 105,
+            OperationError::OperandCount(_) => 106,
+            OperationError::Hex(_) => 107,
+            OperationError::Tlv(e) => e.code(),
+            OperationError::WrongDomain => 108,
+            OperationError::Overflow => 109,
+            OperationError::Generic => 110,
+        }
+    }
+}
+
+impl Coded for TlvError {
+    fn code(&self) -> u16 {
+        match self {
+            TlvError::TagUnknown { .. } => 200,
+            TlvError::NameUnknown(_) => 201,
+            TlvError::Truncated { .. } => 202,
+            TlvError::FrameTooLarge { .. } => 203,
+            TlvError::ExcessiveLength(_) => 204,
+            TlvError::Trailing { .. } => 205,
+        }
+    }
+}
+
+impl Coded for TCPLibError {
+    fn code(&self) -> u16 {
+        match self {
+            TCPLibError::OperationError(e) => e.code(),
+            TCPLibError::UnsupportedOperation(_) => 300,
+            TCPLibError::Parse => 301,
+            TCPLibError::NotEnoughData(_) => 302,
+            TCPLibError::InvalidParameter(_) => 303,
+            TCPLibError::ParseIntError(_) => 304,
+            TCPLibError::Tlv(e) => e.code(),
+            TCPLibError::Generic => 305,
+        }
+    }
+}
+
+impl Coded for ClientError {
+    fn code(&self) -> u16 {
+        match self {
+            ClientError::Io(_) if self.is_timeout() => 401,
+            ClientError::Io(_) => 400,
+            ClientError::Proxy(e) => e.code(),
+            ClientError::Tlv(e) => e.code(),
+            ClientError::Answer(e) => e.code(),
+            ClientError::Unexpected => 402,
+            ClientError::Rejected(Rejection::Unauthorized) => 404,
+            ClientError::Rejected(_) => 403,
+            ClientError::GoingAway(_) => 405,
+            ClientError::Session(e) => e.code(),
+            #[cfg(feature = "auth")]
+            ClientError::Auth(e) => e.code(),
+        }
+    }
+}
+
+impl Coded for SessionError {
+    fn code(&self) -> u16 {
+        500
+    }
+}
+
+impl Coded for ProxyError {
+    fn code(&self) -> u16 {
+        match self {
+            ProxyError::Io(_) => 600,
+            ProxyError::InvalidUrl(_) => 601,
+            ProxyError::AuthenticationRequired => 602,
+            ProxyError::Refused(_) => 603,
+            ProxyError::Protocol => 604,
+        }
+    }
+}
+
+impl Coded for ProxyHeaderError {
+    fn code(&self) -> u16 {
+        match self {
+            ProxyHeaderError::Io(_) => 700,
+            ProxyHeaderError::Missing => 701,
+            ProxyHeaderError::Malformed => 702,
+            ProxyHeaderError::UnsupportedVersion(_) => 703,
+        }
+    }
+}
+
+#[cfg(feature = "auth")]
+impl Coded for AuthError {
+    fn code(&self) -> u16 {
+        match self {
+            AuthError::Mac => 800,
+            AuthError::Replay => 801,
+            AuthError::Secret => 802,
+        }
+    }
+}
+
+impl Coded for CidrError {
+    fn code(&self) -> u16 {
+        match self {
+            CidrError::Address(_) => 900,
+            CidrError::Prefix(_) => 901,
+        }
+    }
+}
+
+impl Coded for ProfileError {
+    fn code(&self) -> u16 {
+        902
+    }
+}
+
+impl Coded for RadixError {
+    fn code(&self) -> u16 {
+        903
+    }
+}
+
+#[cfg(feature = "quic")]
+impl Coded for crate::QuicError {
+    fn code(&self) -> u16 {
+        use crate::QuicError;
+
+        match self {
+            QuicError::Io(_) => 1000,
+            QuicError::Tls(_) => 1001,
+            QuicError::Connect(_) => 1002,
+            QuicError::Connection(_) => 1003,
+            QuicError::Write(_) => 1004,
+            QuicError::Closed(_) => 1005,
+            QuicError::Read(_) => 1006,
+            QuicError::Tlv(e) => e.code(),
+            QuicError::Answer(e) => e.code(),
+            QuicError::Rejected(_) => 1007,
+        }
+    }
+}
+
+#[cfg(feature = "script")]
+impl Coded for crate::ScriptError {
+    fn code(&self) -> u16 {
+        use crate::ScriptError;
+
+        match self {
+            ScriptError::Io(_) => 1100,
+            ScriptError::Parse(_) => 1101,
+            ScriptError::NoEntryPoint => 1102,
+            ScriptError::Eval(_) => 1103,
+            ScriptError::Return(_) => 1104,
+        }
+    }
+}
+
+#[cfg(feature = "mdns")]
+impl Coded for crate::DiscoveryError {
+    fn code(&self) -> u16 {
+        1200
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, error::Error, io};
+
+    use super::{catalog, lookup, Coded, ErrorKind};
+    use crate::{
+        ClientError, Operation, OperationError, ParserOptions, Rejection, TCPLibError, Tlv,
+        TlvError,
+    };
+
+    const README: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/README.md"));
+
+    #[test]
+    fn unique() {
+        let codes: Vec<u16> = catalog().iter().map(|entry| entry.code).collect();
+        assert!(codes.windows(2).all(|pair| pair[0] < pair[1]));
+        let names: HashSet<&str> = catalog().iter().map(|entry| entry.name).collect();
+        assert_eq!(names.len(), catalog().len());
+    }
+
+    #[test]
+    fn documented() {
+        for entry in catalog() {
+            let row = format!("| {} | {} | `{}` |", entry.code, entry.kind, entry.name);
+            assert!(README.contains(&row), "The README lacks {row}");
+        }
+    }
+
+    #[test]
+    fn codes() {
+        let parse = |s| Operation::parse_with(s, &ParserOptions::lenient()).unwrap_err();
+        assert_eq!(parse("3 $ 4").code(), 101);
+        assert_eq!(parse("3 + 0x80").kind(), ErrorKind::Parse);
+        assert_eq!(parse("-1!").kind(), ErrorKind::Calculation);
+
+        let truncated = Tlv::try_from(&[1, 2, 3][..]).unwrap_err();
+        assert!(matches!(truncated, TlvError::Truncated { .. }));
+        // The wrapper takes the code of the cause, which is also its source
+        let wrapped = ClientError::Tlv(truncated);
+        assert_eq!(wrapped.code(), 202);
+        assert_eq!(wrapped.kind(), ErrorKind::Protocol);
+        assert!(wrapped.source().unwrap().is::<TlvError>());
+        assert_eq!(
+            ClientError::Answer(TCPLibError::from(OperationError::Overflow)).code(),
+            109
+        );
+
+        let timeout = ClientError::Io(io::ErrorKind::TimedOut.into());
+        assert_eq!(timeout.kind(), ErrorKind::Timeout);
+        assert_eq!(timeout.kind().exit_code(), 5);
+        let refused = ClientError::Io(io::ErrorKind::ConnectionRefused.into());
+        assert_eq!(refused.kind().exit_code(), 3);
+        assert_eq!(ErrorKind::Parse.exit_code(), 2);
+        assert_eq!(
+            ClientError::Rejected(Rejection::Unauthorized).kind(),
+            ErrorKind::Auth
+        );
+        assert_eq!(
+            ClientError::Rejected(Rejection::Overflow).kind(),
+            ErrorKind::Rejected
+        );
+        assert!(lookup(0).is_none());
+    }
+}
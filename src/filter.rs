@@ -0,0 +1,353 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Expressions that select the frames of a capture by their fields, as in
+//! `tag==Div || length>2`, for `tcp1dump --filter`.
+//!
+//! A comparison takes a field on the left: `tag`, compared by name with `==`
+//! or `!=`, or `length` (that of the data, as in its header) and `offset`,
+//! compared with a number by any of `==`, `!=`, `<`, `<=`, `>` and `>=`.
+//! They combine with `!`, `&&` and `||`, in that order of precedence, and
+//! parentheses.
+
+use std::{fmt, str::FromStr};
+
+use thiserror::Error;
+
+use crate::{capture::Captured, TlvType};
+
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum FilterError {
+    #[error("Expected {expected} at column {column}")]
+    Expected {
+        expected: &'static str,
+        column: usize,
+    },
+    #[error("Unknown tag {name:?} at column {column}")]
+    UnknownTag { name: String, column: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    fn holds(self, left: u64, right: u64) -> bool {
+        match self {
+            Comparison::Eq => left == right,
+            Comparison::Ne => left != right,
+            Comparison::Lt => left < right,
+            Comparison::Le => left <= right,
+            Comparison::Gt => left > right,
+            Comparison::Ge => left >= right,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Length,
+    Offset,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    /// Frames with an unknown tag are never equal to any
+    Tag(TlvType),
+    Number(Field, Comparison, u64),
+}
+
+impl Expr {
+    fn matches(&self, frame: &Captured) -> bool {
+        match self {
+            Expr::Or(left, right) => left.matches(frame) || right.matches(frame),
+            Expr::And(left, right) => left.matches(frame) && right.matches(frame),
+            Expr::Not(expr) => !expr.matches(frame),
+            Expr::Tag(tag) => frame.bytes.first() == Some(&(*tag as u8)),
+            Expr::Number(field, comparison, value) => {
+                let left = match field {
+                    Field::Length => frame.bytes.get(1).copied().unwrap_or_default().into(),
+                    Field::Offset => frame.offset as u64,
+                };
+                comparison.holds(left, *value)
+            }
+        }
+    }
+}
+
+/// A parsed filter expression.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Filter {
+    source: String,
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn matches(&self, frame: &Captured) -> bool {
+        self.expr.matches(frame)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            chars: s.chars().collect(),
+            position: 0,
+        };
+        let expr = parser.or()?;
+        parser.skip_spaces();
+        if parser.position < parser.chars.len() {
+            return Err(parser.expected("&&, || or the end"));
+        }
+
+        Ok(Filter {
+            source: s.to_string(),
+            expr,
+        })
+    }
+}
+
+/// Recursive descent parser, one rule per level of precedence.
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn expected(&self, expected: &'static str) -> FilterError {
+        FilterError::Expected {
+            expected,
+            column: self.position + 1,
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while self
+            .chars
+            .get(self.position)
+            .is_some_and(|c| c.is_whitespace())
+        {
+            self.position += 1;
+        }
+    }
+
+    /// Consumes `symbol` if it comes next.
+    fn eat(&mut self, symbol: &str) -> bool {
+        self.skip_spaces();
+        let end = self.position + symbol.chars().count();
+        let found = self
+            .chars
+            .get(self.position..end)
+            .is_some_and(|next| next.iter().copied().eq(symbol.chars()));
+        if found {
+            self.position = end;
+        }
+        found
+    }
+
+    /// The longest run of characters that satisfy `accept`.
+    fn word(&mut self, accept: fn(&char) -> bool) -> String {
+        self.skip_spaces();
+        let start = self.position;
+        while self.chars.get(self.position).is_some_and(accept) {
+            self.position += 1;
+        }
+        self.chars[start..self.position].iter().collect()
+    }
+
+    fn or(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.unary()?;
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, FilterError> {
+        if self.eat("(") {
+            let expr = self.or()?;
+            return match self.eat(")") {
+                true => Ok(expr),
+                false => Err(self.expected(")")),
+            };
+        }
+        // Not to be confused with !=, that needs a field before
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+
+        self.comparison()
+    }
+
+    /// Consumes the comparison operator that comes next, if any.
+    fn operator(&mut self) -> Option<Comparison> {
+        const OPERATORS: [(&str, Comparison); 6] = [
+            ("==", Comparison::Eq),
+            ("!=", Comparison::Ne),
+            ("<=", Comparison::Le),
+            (">=", Comparison::Ge),
+            ("<", Comparison::Lt),
+            (">", Comparison::Gt),
+        ];
+        OPERATORS
+            .iter()
+            .find(|(symbol, _)| self.eat(symbol))
+            .map(|&(_, comparison)| comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, FilterError> {
+        let field = self.word(char::is_ascii_alphabetic);
+        let field = match field.to_ascii_lowercase().as_str() {
+            "tag" => None,
+            "length" => Some(Field::Length),
+            "offset" => Some(Field::Offset),
+            _ => {
+                self.position -= field.chars().count();
+                return Err(self.expected("tag, length or offset"));
+            }
+        };
+        self.skip_spaces();
+        let column = self.position + 1;
+        let operator = self.operator();
+
+        let Some(field) = field else {
+            let equal = match operator {
+                Some(Comparison::Eq) => true,
+                Some(Comparison::Ne) => false,
+                _ => {
+                    return Err(FilterError::Expected {
+                        expected: "== or !=",
+                        column,
+                    })
+                }
+            };
+            self.skip_spaces();
+            let column = self.position + 1;
+            let name = self.word(char::is_ascii_alphanumeric);
+            let tag = TlvType::try_from(name.as_str()).map_err(|_| match name.is_empty() {
+                true => self.expected("a tag name"),
+                false => FilterError::UnknownTag { name, column },
+            })?;
+            return Ok(match equal {
+                true => Expr::Tag(tag),
+                false => Expr::Not(Box::new(Expr::Tag(tag))),
+            });
+        };
+
+        let comparison = operator.ok_or_else(|| self.expected("a comparison"))?;
+        let value = self
+            .word(char::is_ascii_digit)
+            .parse()
+            .map_err(|_| self.expected("a number"))?;
+        Ok(Expr::Number(field, comparison, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Filter, FilterError};
+    use crate::capture::frames;
+
+    #[test]
+    fn filter() {
+        // Sum 1+2, Fact 5!, Div 8/2 and Bye
+        let capture = [1, 2, 1, 2, 6, 1, 5, 4, 2, 8, 2, 19, 0];
+        let selected = |filter: &str| {
+            let filter: Filter = filter.parse().unwrap();
+            frames(&capture)
+                .iter()
+                .filter(|frame| filter.matches(frame))
+                .map(|frame| frame.offset)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(selected("tag==Div || length>2"), [7]);
+        assert_eq!(selected("tag==Div || length<2"), [4, 7, 11]);
+        assert_eq!(selected("tag != sum && !(offset >= 11)"), [4, 7]);
+        assert_eq!(selected("!tag==Fact&&length==2"), [0, 7]);
+        assert_eq!(selected("(tag==Sum || tag==Fact) && offset>0"), [4]);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |filter: &str| filter.parse::<Filter>().unwrap_err();
+        assert_eq!(
+            error("tag==Div ||"),
+            FilterError::Expected {
+                expected: "tag, length or offset",
+                column: 12
+            }
+        );
+        assert_eq!(
+            error("tag < Div"),
+            FilterError::Expected {
+                expected: "== or !=",
+                column: 5
+            }
+        );
+        assert_eq!(
+            error("tag == Root"),
+            FilterError::UnknownTag {
+                name: "Root".to_string(),
+                column: 8
+            }
+        );
+        assert_eq!(
+            error("(length > two"),
+            FilterError::Expected {
+                expected: "a number",
+                column: 11
+            }
+        );
+        assert_eq!(
+            error("length > 2)"),
+            FilterError::Expected {
+                expected: "&&, || or the end",
+                column: 11
+            }
+        );
+    }
+}
@@ -0,0 +1,159 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Rendering of the answers for humans, in other bases and with their digits
+//! grouped, for the number representation exercises.
+
+use std::{fmt, str::FromStr};
+
+use thiserror::Error;
+
+use crate::Answer;
+
+/// Base in which numbers are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Radix {
+    Binary,
+    Octal,
+    #[default]
+    Decimal,
+    Hex,
+}
+
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+#[error("Unknown radix {0:?}, expected bin, oct, dec or hex")]
+pub struct RadixError(String);
+
+impl Radix {
+    pub fn base(self) -> u32 {
+        match self {
+            Radix::Binary => 2,
+            Radix::Octal => 8,
+            Radix::Decimal => 10,
+            Radix::Hex => 16,
+        }
+    }
+
+    /// Written before the digits, as in Rust.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Radix::Binary => "0b",
+            Radix::Octal => "0o",
+            Radix::Decimal => "",
+            Radix::Hex => "0x",
+        }
+    }
+
+    /// Digits between two separators: nibbles for the bases that are powers
+    /// of two, and thousands for the others.
+    fn group(self) -> usize {
+        match self {
+            Radix::Binary | Radix::Hex => 4,
+            Radix::Octal | Radix::Decimal => 3,
+        }
+    }
+}
+
+impl FromStr for Radix {
+    type Err = RadixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bin" | "binary" | "2" => Ok(Radix::Binary),
+            "oct" | "octal" | "8" => Ok(Radix::Octal),
+            "dec" | "decimal" | "10" => Ok(Radix::Decimal),
+            "hex" | "hexadecimal" | "16" => Ok(Radix::Hex),
+            _ => Err(RadixError(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Radix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Radix::Binary => write!(f, "bin"),
+            Radix::Octal => write!(f, "oct"),
+            Radix::Decimal => write!(f, "dec"),
+            Radix::Hex => write!(f, "hex"),
+        }
+    }
+}
+
+/// Writes `value` in `radix`, with its prefix and the digits grouped by `_`
+/// from the right, as in `-0x7fff_ffff`.
+pub fn format_integer(value: i64, radix: Radix) -> String {
+    let magnitude = value.unsigned_abs();
+    let digits = match radix {
+        Radix::Binary => format!("{magnitude:b}"),
+        Radix::Octal => format!("{magnitude:o}"),
+        Radix::Decimal => magnitude.to_string(),
+        Radix::Hex => format!("{magnitude:x}"),
+    };
+    let groups: Vec<&str> = digits
+        .as_bytes()
+        .rchunks(radix.group())
+        .rev()
+        .map(|group| std::str::from_utf8(group).expect("digits are ASCII"))
+        .collect();
+    let sign = if value < 0 { "-" } else { "" };
+
+    format!("{sign}{}{}", radix.prefix(), groups.join("_"))
+}
+
+impl Answer {
+    /// Like its `Display`, but in `radix` and with the digits grouped.
+    pub fn format(&self, radix: Radix) -> String {
+        let mut formatted = format_integer(self.value, radix);
+        if self.overflow {
+            formatted += Answer::OVERFLOW_NOTE;
+        }
+
+        formatted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_integer, Radix};
+    use crate::Answer;
+
+    #[test]
+    fn format() {
+        assert_eq!(format_integer(1234567, Radix::Decimal), "1_234_567");
+        assert_eq!(format_integer(-255, Radix::Hex), "-0xff");
+        assert_eq!(format_integer(10, Radix::Binary), "0b1010");
+        assert_eq!(format_integer(0x1f, Radix::Binary), "0b1_1111");
+        assert_eq!(format_integer(8, Radix::Octal), "0o10");
+        assert_eq!(format_integer(0, Radix::Hex), "0x0");
+        assert_eq!(
+            format_integer(i64::MIN, Radix::Hex),
+            "-0x8000_0000_0000_0000"
+        );
+        assert_eq!(
+            Answer::saturated(i64::MAX).format(Radix::Hex),
+            "0x7fff_ffff_ffff_ffff (overflow: the accumulator saturated)"
+        );
+        assert_eq!("HEX".parse(), Ok(Radix::Hex));
+        assert_eq!("2".parse(), Ok(Radix::Binary));
+        assert!("base64".parse::<Radix>().is_err());
+    }
+}
@@ -0,0 +1,120 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Checks the exact encoding of every message against the fixtures in
+//! `tests/golden`, so that a change to a tag value or to the byte order is
+//! noticed before it reaches the students.
+
+use std::fmt::Write;
+
+use crate::{Answer, Operation, Rejection, Tlv};
+
+const WIRE: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/golden/wire.txt"
+));
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Encodes the message written in the fixture.
+fn encode(message: &str) -> Box<[u8]> {
+    if let Some(answer) = message.strip_prefix("= ") {
+        let (value, overflow) = match answer.strip_suffix(" overflow") {
+            Some(value) => (value, true),
+            None => (answer, false),
+        };
+        Answer {
+            value: value.parse().unwrap(),
+            overflow,
+        }
+        .encode()
+    } else if let Some(reason) = message.strip_prefix("! ") {
+        Rejection::ALL
+            .iter()
+            .copied()
+            .find(|rejection| format!("{rejection:?}") == reason)
+            .unwrap_or_else(|| panic!("Unknown rejection {reason}"))
+            .encode()
+    } else {
+        message.parse::<Operation>().unwrap().encode()
+    }
+}
+
+/// Decodes the bytes back into the notation of the fixture.
+fn decode(bytes: &[u8]) -> String {
+    let tlv = Tlv::try_from(bytes).unwrap();
+    if let Ok(answer) = Answer::try_from(tlv) {
+        let suffix = if answer.overflow { " overflow" } else { "" };
+        format!("= {}{suffix}", answer.value)
+    } else if let Ok(rejection) = Rejection::try_from(tlv) {
+        format!("! {rejection:?}")
+    } else {
+        Operation::try_from(tlv).unwrap().to_string()
+    }
+}
+
+#[test]
+fn wire_format() {
+    let mut mismatches = String::new();
+    for (number, line) in WIRE.lines().enumerate() {
+        let Some((message, expected)) = line.split_once("=>") else {
+            continue;
+        };
+        let (message, expected) = (message.trim(), expected.trim());
+
+        let actual = hex(&encode(message));
+        if actual != expected {
+            writeln!(
+                mismatches,
+                "line {}: {message}\n  - expected: {expected}\n  + actual:   {actual}",
+                number + 1
+            )
+            .unwrap();
+        }
+
+        let bytes: Vec<u8> = expected
+            .split_whitespace()
+            .map(|byte| u8::from_str_radix(byte, 16).unwrap())
+            .collect();
+        // Operations are written back in their canonical spelling
+        let canonical = message
+            .parse::<Operation>()
+            .map_or_else(|_| message.to_string(), |operation| operation.to_string());
+        let decoded = decode(&bytes);
+        if decoded != canonical {
+            writeln!(
+                mismatches,
+                "line {}: {expected}\n  - expected: {canonical}\n  + decoded:  {decoded}",
+                number + 1
+            )
+            .unwrap();
+        }
+    }
+
+    assert!(mismatches.is_empty(), "Wire format changed:\n{mismatches}");
+}
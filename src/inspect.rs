@@ -0,0 +1,473 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! A man-in-the-middle proxy that decodes the frames it relays between clients
+//! and a server, and that can drop, delay or corrupt the next one on request.
+//! It is the engine of `tcp1proxy`, to show what travels on the wire and what
+//! happens when the network misbehaves.
+//!
+//! Faults can also come from a [`Schedule`] that names the frames to tamper
+//! with, so that a run can be reproduced exactly.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::{Answer, Operation, Rejection, Tlv, TlvType};
+
+/// Frames remembered per connection. Older ones are forgotten.
+const HISTORY: usize = 256;
+
+/// Time between the two parts of a split frame, for the receiver to see them apart.
+const SPLIT_PAUSE: Duration = Duration::from_millis(50);
+
+/// Which way a frame goes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// From the client to the server
+    #[default]
+    Request,
+    /// From the server to the client
+    Answer,
+}
+
+/// What to do to a frame instead of just relaying it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tamper {
+    Drop,
+    Delay(Duration),
+    /// Flips the bits of its last byte
+    Corrupt,
+    /// Flips the bits of the byte at this position, if there is one
+    CorruptByte(usize),
+    /// Sends the first bytes, and the rest a bit later
+    Split(usize),
+}
+
+impl fmt::Display for Tamper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tamper::Drop => write!(f, "dropped"),
+            Tamper::Delay(delay) => write!(f, "delayed {delay:?}"),
+            Tamper::Corrupt => write!(f, "corrupted"),
+            Tamper::CorruptByte(byte) => write!(f, "byte {byte} corrupted"),
+            Tamper::Split(after) => write!(f, "split after {after} bytes"),
+        }
+    }
+}
+
+/// Faults to inject at given frames, read from TOML like:
+///
+/// ```toml
+/// [[fault]]
+/// frame = 3
+/// action = "split"
+/// after = 1
+///
+/// [[fault]]
+/// frame = 7
+/// direction = "answer"
+/// action = "corrupt"
+/// byte = 2
+/// ```
+///
+/// Frames are counted from 1 for each connection and direction.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schedule {
+    #[serde(default, rename = "fault")]
+    pub faults: Vec<Fault>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub struct Fault {
+    pub frame: usize,
+    #[serde(default)]
+    pub direction: Direction,
+    #[serde(flatten)]
+    pub action: FaultAction,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum FaultAction {
+    Drop,
+    Delay {
+        ms: u64,
+    },
+    /// The last byte, unless another one is given
+    Corrupt {
+        byte: Option<usize>,
+    },
+    Split {
+        after: usize,
+    },
+}
+
+impl From<FaultAction> for Tamper {
+    fn from(action: FaultAction) -> Self {
+        match action {
+            FaultAction::Drop => Tamper::Drop,
+            FaultAction::Delay { ms } => Tamper::Delay(Duration::from_millis(ms)),
+            FaultAction::Corrupt { byte: None } => Tamper::Corrupt,
+            FaultAction::Corrupt { byte: Some(byte) } => Tamper::CorruptByte(byte),
+            FaultAction::Split { after } => Tamper::Split(after),
+        }
+    }
+}
+
+impl Schedule {
+    /// What to do to the frame number `frame` going in `direction`.
+    pub fn tamper(&self, frame: usize, direction: Direction) -> Option<Tamper> {
+        self.faults
+            .iter()
+            .find(|fault| fault.frame == frame && fault.direction == direction)
+            .map(|fault| fault.action.into())
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = toml::de::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s)
+    }
+}
+
+/// A frame seen by the proxy.
+#[derive(Clone, Debug)]
+pub struct Relayed {
+    pub direction: Direction,
+    /// The decoded frame, as it arrived
+    pub description: String,
+    pub tamper: Option<Tamper>,
+}
+
+/// A client relayed to the server.
+#[derive(Debug)]
+pub struct Connection {
+    pub peer: SocketAddr,
+    /// Number of directions still open
+    open: u8,
+    pub frames: VecDeque<Relayed>,
+    next: [Option<Tamper>; 2],
+    /// Frames relayed in each direction
+    count: [usize; 2],
+}
+
+impl Connection {
+    pub fn is_open(&self) -> bool {
+        self.open > 0
+    }
+
+    /// What will happen to the next frame going in `direction`.
+    pub fn next_tamper(&self, direction: Direction) -> Option<Tamper> {
+        self.next[direction as usize]
+    }
+}
+
+/// Relays connections, keeping the frames of each for inspection. Clones share
+/// the same connections.
+#[derive(Clone, Debug, Default)]
+pub struct Inspector {
+    connections: Arc<Mutex<Vec<Connection>>>,
+    schedule: Arc<Schedule>,
+}
+
+impl Inspector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also tampers with the frames of every connection given in `schedule`.
+    pub fn with_schedule(schedule: Schedule) -> Self {
+        Self {
+            schedule: Arc::new(schedule),
+            ..Default::default()
+        }
+    }
+
+    /// Accepts clients on `listener` forever, relaying each of them to `server`.
+    pub fn run(&self, listener: TcpListener, server: SocketAddr) -> io::Result<()> {
+        loop {
+            let (client, peer) = listener.accept()?;
+            let upstream = match TcpStream::connect(server) {
+                Ok(upstream) => upstream,
+                Err(e) => {
+                    eprintln!("Could not connect to {server} for {peer}. {e}");
+                    continue;
+                }
+            };
+
+            // So that split frames really leave in two segments
+            client.set_nodelay(true)?;
+            upstream.set_nodelay(true)?;
+
+            let id = {
+                let mut connections = self.connections();
+                connections.push(Connection {
+                    peer,
+                    open: 2,
+                    frames: VecDeque::new(),
+                    next: [None, None],
+                    count: [0, 0],
+                });
+                connections.len() - 1
+            };
+            for (from, to, direction) in [
+                (
+                    client.try_clone()?,
+                    upstream.try_clone()?,
+                    Direction::Request,
+                ),
+                (upstream, client, Direction::Answer),
+            ] {
+                let inspector = self.clone();
+                thread::spawn(move || inspector.relay(id, from, to, direction));
+            }
+        }
+    }
+
+    /// The connections relayed so far, in order of arrival.
+    pub fn connections(&self) -> MutexGuard<'_, Vec<Connection>> {
+        self.connections.lock().unwrap()
+    }
+
+    /// Tampers with the next frame of connection `id` going in `direction`.
+    pub fn tamper_next(&self, id: usize, direction: Direction, tamper: Option<Tamper>) {
+        if let Some(connection) = self.connections().get_mut(id) {
+            connection.next[direction as usize] = tamper;
+        }
+    }
+
+    fn relay(&self, id: usize, mut from: TcpStream, mut to: TcpStream, direction: Direction) {
+        let mut pending = Vec::new();
+        let mut buffer = [0u8; 2048];
+        while let Ok(len @ 1..) = from.read(&mut buffer) {
+            pending.extend_from_slice(&buffer[..len]);
+            while let Some(&length) = pending.get(1) {
+                if pending.len() < 2 + length as usize {
+                    break;
+                }
+                let mut frame: Vec<u8> = pending.drain(..2 + length as usize).collect();
+                let Some(split) = self.forward(id, direction, &mut frame) else {
+                    continue;
+                };
+                let (first, rest) = frame.split_at(split.min(frame.len()));
+                if to.write_all(first).is_err() {
+                    break;
+                }
+                if !rest.is_empty() {
+                    thread::sleep(SPLIT_PAUSE);
+                    if to.write_all(rest).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Whatever did not make a whole frame
+        let _ = to.write_all(&pending);
+        let _ = to.shutdown(Shutdown::Write);
+        if let Some(connection) = self.connections().get_mut(id) {
+            connection.open -= 1;
+        }
+    }
+
+    /// Records the frame and applies the pending tampering to it, returning how
+    /// many bytes to send before a pause, or `None` to drop it.
+    fn forward(&self, id: usize, direction: Direction, frame: &mut [u8]) -> Option<usize> {
+        let tamper = {
+            let mut connections = self.connections();
+            let connection = &mut connections[id];
+            connection.count[direction as usize] += 1;
+            let tamper = connection.next[direction as usize].take().or_else(|| {
+                self.schedule
+                    .tamper(connection.count[direction as usize], direction)
+            });
+            if connection.frames.len() == HISTORY {
+                connection.frames.pop_front();
+            }
+            connection.frames.push_back(Relayed {
+                direction,
+                description: describe(frame),
+                tamper,
+            });
+            tamper
+        };
+
+        let flip = |byte: Option<&mut u8>| {
+            if let Some(byte) = byte {
+                *byte = !*byte;
+            }
+        };
+        match tamper {
+            None => (),
+            Some(Tamper::Drop) => return None,
+            Some(Tamper::Delay(delay)) => thread::sleep(delay),
+            Some(Tamper::Corrupt) => flip(frame.last_mut()),
+            Some(Tamper::CorruptByte(byte)) => flip(frame.get_mut(byte)),
+            Some(Tamper::Split(after)) => return Some(after),
+        }
+
+        Some(frame.len())
+    }
+}
+
+/// Decodes a frame for humans.
+pub fn describe(frame: &[u8]) -> String {
+    let hex = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let Ok(tlv) = Tlv::try_from(frame) else {
+        return format!("Invalid [{}]", hex(frame));
+    };
+
+    let decoded = match tlv.tag {
+        TlvType::Numi64 => Answer::try_from(tlv).map(|answer| answer.to_string()).ok(),
+        TlvType::Rejection => Rejection::try_from(tlv)
+            .map(|rejection| rejection.to_string())
+            .ok(),
+        _ => Operation::try_from(tlv)
+            .map(|operation| operation.to_string())
+            .ok(),
+    };
+    match decoded {
+        Some(decoded) => format!("{} {decoded}", tlv.tag.name()),
+        None if tlv.data.is_empty() => tlv.tag.name().to_string(),
+        None => format!("{} [{}]", tlv.tag.name(), hex(tlv.data)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{SocketAddr, TcpListener},
+        thread,
+    };
+
+    use super::{describe, Direction, Inspector, Schedule, Tamper};
+    use crate::{Client, Server, ServerConfig};
+
+    #[test]
+    fn describe_frames() {
+        assert_eq!(describe(&[1, 2, 3, 4]), "Sum 3+4");
+        assert_eq!(
+            describe(&[20, 1, 2]),
+            "Rejection The result does not fit in the accumulator"
+        );
+        assert_eq!(describe(&[19, 0]), "Bye");
+        assert_eq!(describe(&[17, 2, 0, 1]), "Ping [00 01]");
+        assert_eq!(describe(&[42, 1, 0]), "Invalid [2a 01 00]");
+    }
+
+    /// Starts a server and a proxy to it, returning the address of the proxy.
+    fn spawn_proxy(inspector: &Inspector) -> SocketAddr {
+        let mut server = Server::bind(ServerConfig::default()).unwrap();
+        let server_addr = SocketAddr::from(([127, 0, 0, 1], server.local_addr().unwrap().port()));
+        thread::spawn(move || server.run());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = listener.local_addr().unwrap();
+        let running = inspector.clone();
+        thread::spawn(move || running.run(listener, server_addr));
+
+        proxy
+    }
+
+    #[test]
+    fn tamper() {
+        let inspector = Inspector::new();
+        let mut client = Client::connect(spawn_proxy(&inspector), None).unwrap();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        // 4 becomes -5
+        inspector.tamper_next(0, Direction::Request, Some(Tamper::Corrupt));
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 5);
+        client.close().unwrap();
+
+        let connections = inspector.connections();
+        let frames: Vec<_> = connections[0]
+            .frames
+            .iter()
+            .map(|frame| (frame.direction, frame.description.as_str(), frame.tamper))
+            .collect();
+        assert_eq!(
+            frames[..4],
+            [
+                (Direction::Request, "Sum 3+4", None),
+                (Direction::Answer, "Numi64 7", None),
+                (Direction::Request, "Sum 3+4", Some(Tamper::Corrupt)),
+                (Direction::Answer, "Numi64 5", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn schedule() {
+        let schedule: Schedule = r#"
+            [[fault]]
+            frame = 1
+            direction = "answer"
+            action = "split"
+            after = 1
+
+            [[fault]]
+            frame = 2
+            action = "corrupt"
+            byte = 3
+        "#
+        .parse()
+        .unwrap();
+        assert_eq!(
+            schedule.tamper(2, Direction::Request),
+            Some(Tamper::CorruptByte(3))
+        );
+        assert_eq!(schedule.tamper(2, Direction::Answer), None);
+        assert!("[[fault]]\nframe = 1\naction = \"explode\""
+            .parse::<Schedule>()
+            .is_err());
+
+        let inspector = Inspector::with_schedule(schedule);
+        let mut client = Client::connect(spawn_proxy(&inspector), None).unwrap();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 5);
+        client.close().unwrap();
+        assert_eq!(
+            inspector.connections()[0].frames[1].tamper,
+            Some(Tamper::Split(1))
+        );
+    }
+}
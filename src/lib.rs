@@ -20,20 +20,101 @@
  *
  */
 
-use operation::OperationError;
 use std::array::TryFromSliceError;
+use std::fmt;
 use std::num::{ParseIntError, TryFromIntError};
-use std::u8;
+use std::ops::BitOr;
+use std::str::FromStr;
+use std::time::Duration;
 
 use thiserror::Error;
-use tlv::TlvType;
 
+mod arena;
+mod audit;
+#[cfg(feature = "auth")]
+pub mod auth;
+#[cfg(feature = "cli")]
+mod capture;
+mod chunked;
+#[cfg(feature = "cli")]
+pub mod cli;
+mod client;
+mod clock;
+#[cfg(test)]
+mod corpus;
+mod demux;
+#[cfg(feature = "mdns")]
+mod discovery;
+pub mod errors;
+#[cfg(feature = "cli")]
+mod filter;
+pub mod format;
+#[cfg(test)]
+mod golden;
+pub mod inspect;
+mod math;
+mod middleware;
+pub mod net;
 mod operation;
+mod profile;
+mod proxy;
+mod proxy_protocol;
+#[cfg(feature = "quic")]
+mod quic;
+mod scheduler;
+#[cfg(feature = "script")]
+mod script;
+#[cfg(all(feature = "sctp", target_os = "linux"))]
+mod sctp;
+mod server;
+mod session;
+mod stats;
+pub mod store;
+mod tenant;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod throttle;
 mod tlv;
+mod tournament;
+#[cfg(any(feature = "quic", all(feature = "sctp", target_os = "linux")))]
+mod transport;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
 
-pub use operation::Operation;
+pub use chunked::ChunkedWriter;
+#[cfg(feature = "audit")]
+pub use client::AuditReport;
+pub use client::{Channel, Client, ClientError, QuizReport, UnsolicitedPolicy};
+pub use clock::{Clock, FakeClock, SystemClock};
+pub use demux::{AsyncClient, PendingAnswer};
+#[cfg(feature = "mdns")]
+pub use discovery::{discover, Announced, Announcement, DiscoveryError, SERVICE_TYPE};
+pub use operation::{Operation, OperationError, OperatorInfo, ParserOptions};
+pub use profile::{Profile, ProfileError};
+pub use proxy::{Proxy, ProxyError, ProxyKind};
+pub use proxy_protocol::{ProxyHeader, ProxyHeaderError};
+#[cfg(feature = "quic")]
+pub use quic::{QuicClient, QuicError, QuicServer};
+#[cfg(feature = "script")]
+pub use script::{ScriptError, ScriptHandler};
+#[cfg(all(feature = "sctp", target_os = "linux"))]
+pub use sctp::{SctpClient, SctpServer};
+pub use server::{ConfigHandle, DrainHandle, Server, ServerConfig};
+pub use session::{Peer, Session, SessionError, SessionState};
+pub use stats::{
+    ConnectHook, ConnectionStats, DisconnectHook, ErrorHook, RequestObserver, ServerEvent, Summary,
+};
+pub use tenant::Tenant;
+pub use throttle::ThrottledStream;
+pub use tlv::RawTlvIterator;
 pub use tlv::Tlv;
+pub use tlv::TlvError;
 pub use tlv::TlvIterator;
+pub use tlv::TlvType;
+pub use tlv::{Decoder, Frame};
+pub use tournament::Tournament;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub use uring::UringServer;
 
 #[derive(Clone, Error, Debug)]
 pub enum TCPLibError {
@@ -49,42 +130,998 @@ pub enum TCPLibError {
     InvalidParameter(#[from] TryFromIntError),
     #[error("Could not parse integer")]
     ParseIntError(#[from] ParseIntError),
+    #[error("Malformed TLV. {0}")]
+    Tlv(#[from] TlvError),
     #[error("Something wrong")]
     Generic,
 }
 
-#[derive(Debug, PartialEq)]
-pub struct Answer(pub i64);
+/// Accumulated value sent by the server.
+///
+/// When the accumulator saturated at the bounds of i64 the server sends the
+/// extended form of the answer, with a trailing flags byte, so that the client
+/// can tell the value is not exact.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Answer {
+    pub value: i64,
+    pub overflow: bool,
+}
+
+impl Answer {
+    const OVERFLOW_FLAG: u8 = 0x01;
+    const OVERFLOW_NOTE: &'static str = " (overflow: the accumulator saturated)";
+
+    pub fn saturated(value: i64) -> Self {
+        Self {
+            value,
+            overflow: true,
+        }
+    }
+
+    /// Adds `result` to the accumulator `acc`, saturating at the bounds of i64.
+    pub fn accumulate(acc: i64, result: i64) -> Self {
+        match math::sat_add_i64(acc, result) {
+            (acc, false) => Self::from(acc),
+            (acc, true) => Self::saturated(acc),
+        }
+    }
+}
 
 impl<'a> TryFrom<Tlv<'a>> for Answer {
     type Error = TCPLibError;
 
     fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
-        if tlv.tag == TlvType::Numi64 && tlv.length == 8 {
-            Ok(Answer(i64::from_be_bytes(tlv.data.try_into()?)))
+        match (tlv.tag, tlv.length) {
+            (TlvType::Numi64, 8) => Ok(Answer::from(i64::from_be_bytes(tlv.data.try_into()?))),
+            (TlvType::Numi64, 9) => Ok(Answer {
+                value: i64::from_be_bytes(tlv.data[..8].try_into()?),
+                overflow: tlv.data[8] & Self::OVERFLOW_FLAG != 0,
+            }),
+            _ => Err(TCPLibError::Generic),
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for Answer {
+    type Error = TCPLibError;
+
+    /// Decodes a buffer with a single answer.
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        Answer::try_from(Tlv::whole(bytes)?)
+    }
+}
+
+impl Answer {
+    pub fn encode(self) -> Box<[u8]> {
+        let mut data = self.value.to_be_bytes().to_vec();
+        if self.overflow {
+            data.push(Self::OVERFLOW_FLAG);
+        }
+
+        Tlv::new(TlvType::Numi64, &data).unwrap().encode()
+    }
+}
+
+impl From<i64> for Answer {
+    fn from(value: i64) -> Self {
+        Self {
+            value,
+            overflow: false,
+        }
+    }
+}
+
+impl fmt::Display for Answer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value)?;
+        if self.overflow {
+            f.write_str(Self::OVERFLOW_NOTE)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Keep-alive request. The payload is opaque and must be echoed back in the [`Pong`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ping(pub [u8; 8]);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pong(pub [u8; 8]);
+
+impl<'a> TryFrom<Tlv<'a>> for Ping {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        if tlv.tag == TlvType::Ping && tlv.length == 8 {
+            Ok(Ping(tlv.data.try_into()?))
         } else {
             Err(TCPLibError::Generic)
         }
     }
 }
 
-impl Answer {
+impl<'a> TryFrom<Tlv<'a>> for Pong {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        if tlv.tag == TlvType::Pong && tlv.length == 8 {
+            Ok(Pong(tlv.data.try_into()?))
+        } else {
+            Err(TCPLibError::Generic)
+        }
+    }
+}
+
+impl Ping {
+    pub fn encode(self) -> Box<[u8]> {
+        Tlv::new(TlvType::Ping, &self.0).unwrap().encode()
+    }
+}
+
+impl Pong {
+    pub fn encode(self) -> Box<[u8]> {
+        Tlv::new(TlvType::Pong, &self.0).unwrap().encode()
+    }
+}
+
+/// Saves the accumulator in the register of the session with this name. The
+/// server answers with the accumulator, untouched.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Store(pub u8);
+
+/// Sets the accumulator to the value saved in the register with this name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Load(pub u8);
+
+impl<'a> TryFrom<Tlv<'a>> for Store {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        match (tlv.tag, tlv.data) {
+            (TlvType::Store, &[name]) => Ok(Store(name)),
+            _ => Err(TCPLibError::Generic),
+        }
+    }
+}
+
+impl<'a> TryFrom<Tlv<'a>> for Load {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        match (tlv.tag, tlv.data) {
+            (TlvType::Load, &[name]) => Ok(Load(name)),
+            _ => Err(TCPLibError::Generic),
+        }
+    }
+}
+
+impl Store {
+    pub fn encode(self) -> Box<[u8]> {
+        Tlv::new(TlvType::Store, &[self.0]).unwrap().encode()
+    }
+}
+
+impl Load {
+    pub fn encode(self) -> Box<[u8]> {
+        Tlv::new(TlvType::Load, &[self.0]).unwrap().encode()
+    }
+}
+
+impl From<Ping> for Pong {
+    fn from(ping: Ping) -> Self {
+        Self(ping.0)
+    }
+}
+
+/// Orderly end of the session. Sent by the client and echoed by the server before closing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bye;
+
+impl<'a> TryFrom<Tlv<'a>> for Bye {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        if tlv.tag == TlvType::Bye && tlv.length == 0 {
+            Ok(Bye)
+        } else {
+            Err(TCPLibError::Generic)
+        }
+    }
+}
+
+impl Bye {
+    pub fn encode(self) -> Box<[u8]> {
+        Tlv::new(TlvType::Bye, &[]).unwrap().encode()
+    }
+}
+
+/// Sent by the server instead of an [`Answer`] when it cannot calculate the
+/// operation. The accumulator is left untouched.
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum Rejection {
+    #[error("Operand out of the domain of the operation")]
+    WrongDomain = 1,
+    #[error("The result does not fit in the accumulator")]
+    Overflow = 2,
+    #[error("The operation is disabled in this server")]
+    Disabled = 3,
+    #[error("The API key is missing or unknown")]
+    Unauthorized = 4,
+    #[error("The rate limit of the tenant was exceeded")]
+    RateLimited = 5,
+    #[error("Too many connections from the same address")]
+    TooManyConnections = 6,
+    #[error("Nothing was stored in the register")]
+    UnknownRegister = 7,
+    #[error("No more registers fit in the session")]
+    TooManyRegisters = 8,
+    #[error("The operation was cancelled")]
+    Cancelled = 9,
+    #[error("The operation took longer than the server allows")]
+    Timeout = 10,
+    #[error("The answer would arrive after the deadline of the client")]
+    DeadlineExceeded = 11,
+    #[error("The operation could not be calculated")]
+    Other = 255,
+}
+
+impl<'a> TryFrom<Tlv<'a>> for Rejection {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        match (tlv.tag, tlv.data) {
+            (TlvType::Rejection, [1]) => Ok(Rejection::WrongDomain),
+            (TlvType::Rejection, [2]) => Ok(Rejection::Overflow),
+            (TlvType::Rejection, [3]) => Ok(Rejection::Disabled),
+            (TlvType::Rejection, [4]) => Ok(Rejection::Unauthorized),
+            (TlvType::Rejection, [5]) => Ok(Rejection::RateLimited),
+            (TlvType::Rejection, [6]) => Ok(Rejection::TooManyConnections),
+            (TlvType::Rejection, [7]) => Ok(Rejection::UnknownRegister),
+            (TlvType::Rejection, [8]) => Ok(Rejection::TooManyRegisters),
+            (TlvType::Rejection, [9]) => Ok(Rejection::Cancelled),
+            (TlvType::Rejection, [10]) => Ok(Rejection::Timeout),
+            (TlvType::Rejection, [11]) => Ok(Rejection::DeadlineExceeded),
+            (TlvType::Rejection, [_]) => Ok(Rejection::Other),
+            _ => Err(TCPLibError::Generic),
+        }
+    }
+}
+
+impl Rejection {
+    /// Every reason, in increasing order of code.
+    pub const ALL: &'static [Rejection] = &[
+        Rejection::WrongDomain,
+        Rejection::Overflow,
+        Rejection::Disabled,
+        Rejection::Unauthorized,
+        Rejection::RateLimited,
+        Rejection::TooManyConnections,
+        Rejection::UnknownRegister,
+        Rejection::TooManyRegisters,
+        Rejection::Cancelled,
+        Rejection::Timeout,
+        Rejection::DeadlineExceeded,
+        Rejection::Other,
+    ];
+
     pub fn encode(self) -> Box<[u8]> {
-        Tlv::new(TlvType::Numi64, &self.0.to_be_bytes())
+        Tlv::new(TlvType::Rejection, &[self.code()])
             .unwrap()
             .encode()
     }
+
+    /// Value carried by the TLV.
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    /// What the user can do about it, to show next to the reason.
+    pub fn hint(self) -> &'static str {
+        match self {
+            Rejection::WrongDomain => {
+                "Avoid dividing by zero and factorials of negative numbers or above 20"
+            }
+            Rejection::Overflow => "Use smaller operands, as the result needs more than 64 bits",
+            Rejection::Disabled => "Ask the administrator of the server to enable the operation",
+            Rejection::Unauthorized => "Identify with a valid API key",
+            Rejection::RateLimited => "Wait a moment before sending more operations",
+            Rejection::TooManyConnections => "Close some other connection to the server first",
+            Rejection::UnknownRegister => "Store a value in the register before loading it",
+            Rejection::TooManyRegisters => "Store the value in a register already in use",
+            Rejection::Cancelled => "Send the operation again to get its answer",
+            Rejection::Timeout => "Try a cheaper operation, or a server with a longer timeout",
+            Rejection::DeadlineExceeded => "Give the operation a longer deadline",
+            Rejection::Other => "Check that the server supports the operation",
+        }
+    }
 }
 
-impl From<i64> for Answer {
-    fn from(num: i64) -> Self {
-        Self(num)
+impl From<&OperationError> for Rejection {
+    fn from(e: &OperationError) -> Self {
+        match e {
+            OperationError::WrongDomain => Rejection::WrongDomain,
+            OperationError::Overflow => Rejection::Overflow,
+            _ => Rejection::Other,
+        }
+    }
+}
+
+/// Identifies a request, so that the server can replay the answer instead of
+/// applying the operation again when a client sends it twice. It goes right
+/// before the operation it identifies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IdempotencyKey(pub u64);
+
+impl<'a> TryFrom<Tlv<'a>> for IdempotencyKey {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        if tlv.tag == TlvType::IdempotencyKey && tlv.length == 8 {
+            Ok(IdempotencyKey(u64::from_be_bytes(tlv.data.try_into()?)))
+        } else {
+            Err(TCPLibError::Generic)
+        }
+    }
+}
+
+impl IdempotencyKey {
+    pub fn encode(self) -> Box<[u8]> {
+        Tlv::new(TlvType::IdempotencyKey, &self.0.to_be_bytes())
+            .unwrap()
+            .encode()
+    }
+}
+
+/// Milliseconds that the client still waits for the answer of the operation
+/// right after it. The server skips the operation, or abandons it, once they
+/// run out, answering with [`Rejection::DeadlineExceeded`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Deadline(pub u32);
+
+impl<'a> TryFrom<Tlv<'a>> for Deadline {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        if tlv.tag == TlvType::Deadline && tlv.length == 4 {
+            Ok(Deadline(u32::from_be_bytes(tlv.data.try_into()?)))
+        } else {
+            Err(TCPLibError::Generic)
+        }
+    }
+}
+
+impl Deadline {
+    /// The deadline `left` from now, saturating at the longest one.
+    pub fn after(left: Duration) -> Self {
+        Self(u32::try_from(left.as_millis()).unwrap_or(u32::MAX))
+    }
+
+    pub fn encode(self) -> Box<[u8]> {
+        Tlv::new(TlvType::Deadline, &self.0.to_be_bytes())
+            .unwrap()
+            .encode()
+    }
+}
+
+/// Urgency of the operations of a connection, sent before the first of them.
+/// A server with [`ServerConfig::priorities`] serves the most urgent of the
+/// connections waiting for their turn first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Priority(pub u8);
+
+impl<'a> TryFrom<Tlv<'a>> for Priority {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        if tlv.tag == TlvType::Priority && tlv.length == 1 {
+            Ok(Priority(tlv.data[0]))
+        } else {
+            Err(TCPLibError::Generic)
+        }
+    }
+}
+
+impl Priority {
+    /// Of benchmarks and other traffic that nobody is waiting for
+    pub const BULK: Priority = Priority(0);
+    /// Of the connections that do not send any
+    pub const NORMAL: Priority = Priority(1);
+    /// Of someone waiting for the answers, such as at the prompt of tcp1cli
+    pub const INTERACTIVE: Priority = Priority(2);
+
+    pub fn encode(self) -> Box<[u8]> {
+        Tlv::new(TlvType::Priority, &[self.0]).unwrap().encode()
+    }
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Priority::NORMAL
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Priority::BULK => write!(f, "bulk"),
+            Priority::NORMAL => write!(f, "normal"),
+            Priority::INTERACTIVE => write!(f, "interactive"),
+            Priority(level) => write!(f, "{level}"),
+        }
+    }
+}
+
+impl FromStr for Priority {
+    type Err = ParseIntError;
+
+    /// Either the name of one of the constants, in lowercase, or a number.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bulk" => Ok(Priority::BULK),
+            "normal" => Ok(Priority::NORMAL),
+            "interactive" => Ok(Priority::INTERACTIVE),
+            _ => s.parse().map(Priority),
+        }
+    }
+}
+
+/// Sent by a draining server to ask the client to disconnect and to come back
+/// after `retry_after`, when a new instance of the server will be accepting.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GoAway {
+    pub retry_after: Duration,
+}
+
+impl<'a> TryFrom<Tlv<'a>> for GoAway {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        if tlv.tag == TlvType::GoAway && tlv.length == 4 {
+            Ok(GoAway {
+                retry_after: Duration::from_millis(u32::from_be_bytes(tlv.data.try_into()?).into()),
+            })
+        } else {
+            Err(TCPLibError::Generic)
+        }
+    }
+}
+
+impl GoAway {
+    pub fn encode(self) -> Box<[u8]> {
+        let millis = u32::try_from(self.retry_after.as_millis()).unwrap_or(u32::MAX);
+        Tlv::new(TlvType::GoAway, &millis.to_be_bytes())
+            .unwrap()
+            .encode()
+    }
+}
+
+/// Correlates a request with the logs of the server, like the W3C `traceparent`
+/// header. It goes right before the operation it belongs to, and it is displayed
+/// in the `traceparent` format: `00-<trace id>-<parent id>-<flags>`, in hex.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub parent_id: [u8; 8],
+    pub flags: u8,
+}
+
+impl TraceContext {
+    /// Starts a new trace, with random ids.
+    pub fn new() -> Self {
+        Self {
+            trace_id: fastrand::u128(1..).to_be_bytes(),
+            parent_id: fastrand::u64(1..).to_be_bytes(),
+            flags: 1,
+        }
+    }
+
+    /// A context in the same trace for a new request.
+    pub fn next(self) -> Self {
+        Self {
+            parent_id: fastrand::u64(1..).to_be_bytes(),
+            ..self
+        }
+    }
+
+    pub fn encode(self) -> Box<[u8]> {
+        let data: Vec<u8> = [&self.trace_id[..], &self.parent_id, &[self.flags]].concat();
+        Tlv::new(TlvType::TraceContext, &data).unwrap().encode()
+    }
+}
+
+impl Default for TraceContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> TryFrom<Tlv<'a>> for TraceContext {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        if tlv.tag == TlvType::TraceContext && tlv.length == 25 {
+            Ok(TraceContext {
+                trace_id: tlv.data[..16].try_into()?,
+                parent_id: tlv.data[16..24].try_into()?,
+                flags: tlv.data[24],
+            })
+        } else {
+            Err(TCPLibError::Generic)
+        }
+    }
+}
+
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "00-{:032x}-{:016x}-{:02x}",
+            u128::from_be_bytes(self.trace_id),
+            u64::from_be_bytes(self.parent_id),
+            self.flags
+        )
+    }
+}
+
+/// Asks the peer for its [`AuditDigest`]. Either side can send it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AuditQuery;
+
+impl<'a> TryFrom<Tlv<'a>> for AuditQuery {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        if tlv.tag == TlvType::AuditQuery && tlv.length == 0 {
+            Ok(AuditQuery)
+        } else {
+            Err(TCPLibError::Generic)
+        }
+    }
+}
+
+impl AuditQuery {
+    pub fn encode(self) -> Box<[u8]> {
+        Tlv::new(TlvType::AuditQuery, &[]).unwrap().encode()
+    }
+}
+
+/// SHA-256 digests of the frames a peer has received and sent so far, leaving
+/// out the audit frames themselves. If nothing altered the stream, they match
+/// the digests of the frames the other side sent and received.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AuditDigest {
+    pub received: [u8; 32],
+    pub sent: [u8; 32],
+}
+
+impl<'a> TryFrom<Tlv<'a>> for AuditDigest {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        if tlv.tag == TlvType::AuditDigest && tlv.length == 64 {
+            Ok(AuditDigest {
+                received: tlv.data[..32].try_into()?,
+                sent: tlv.data[32..].try_into()?,
+            })
+        } else {
+            Err(TCPLibError::Generic)
+        }
+    }
+}
+
+impl AuditDigest {
+    pub fn encode(self) -> Box<[u8]> {
+        Tlv::new(TlvType::AuditDigest, &[self.received, self.sent].concat())
+            .unwrap()
+            .encode()
+    }
+}
+
+/// Features of the protocol beyond the basic operations, as a bitmap, so that
+/// each end can tell what the other one understands.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+    pub const BATCH: Self = Self(1 << 0);
+    pub const SUM_N: Self = Self(1 << 1);
+    pub const REGISTERS: Self = Self(1 << 2);
+    /// Operations as lines of text, in the same connection
+    pub const TEXT: Self = Self(1 << 3);
+    pub const AUDIT: Self = Self(1 << 4);
+    /// Logical sessions multiplexed over the connection
+    pub const CHANNELS: Self = Self(1 << 5);
+    /// [`Progress`] frames before slow answers
+    pub const PROGRESS: Self = Self(1 << 6);
+    /// Answers encoded as in [`Profile::LE32`], when the client asks for them
+    pub const LE32: Self = Self(1 << 7);
+    /// A [`Hello`] with a [`Hello::challenge`], that the server sends when it
+    /// accepts an [`crate::auth::Auth`]
+    pub const CHALLENGE: Self = Self(1 << 8);
+
+    /// Every capability with its name, in the order of its bit.
+    pub const ALL: &'static [(Capabilities, &'static str)] = &[
+        (Self::BATCH, "batch"),
+        (Self::SUM_N, "sum-n"),
+        (Self::REGISTERS, "registers"),
+        (Self::TEXT, "text"),
+        (Self::AUDIT, "audit"),
+        (Self::CHANNELS, "channels"),
+        (Self::PROGRESS, "progress"),
+        (Self::LE32, "le32"),
+        (Self::CHALLENGE, "challenge"),
+    ];
+
+    /// Those implemented by this library, as built.
+    pub const SUPPORTED: Self = Self(
+        Self::BATCH.0
+            | Self::SUM_N.0
+            | Self::REGISTERS.0
+            | Self::TEXT.0
+            | Self::CHANNELS.0
+            | Self::PROGRESS.0
+            | Self::LE32.0
+            | if cfg!(feature = "audit") {
+                Self::AUDIT.0
+            } else {
+                0
+            },
+    );
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = Capabilities::ALL
+            .iter()
+            .filter(|(capability, _)| self.contains(*capability))
+            .map(|(_, name)| *name)
+            .collect();
+        match names.is_empty() {
+            true => write!(f, "none"),
+            false => write!(f, "{}", names.join(", ")),
+        }
+    }
+}
+
+/// Exchange of [`Capabilities`], that also identifies the client with an API
+/// key. The key must come before the operations when the server has tenants, and is
+/// ignored otherwise. The server answers every one with its own, without key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hello {
+    /// Those of the sender. [`Capabilities::CHALLENGE`] is only set if there
+    /// is a challenge
+    pub capabilities: Capabilities,
+    /// Fresh bytes that the next [`crate::auth::Auth`] of the client must sign,
+    /// so that it cannot be replayed in another connection. They go right
+    /// after the capabilities
+    pub challenge: Option<[u8; Hello::CHALLENGE_LEN]>,
+    /// Empty to only exchange the capabilities
+    pub api_key: String,
+}
+
+impl<'a> TryFrom<Tlv<'a>> for Hello {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        if tlv.tag != TlvType::Hello || tlv.data.len() < 4 {
+            return Err(TCPLibError::Generic);
+        }
+        let (capabilities, mut api_key) = tlv.data.split_at(4);
+        let capabilities = Capabilities(u32::from_be_bytes(capabilities.try_into()?));
+        let mut challenge = None;
+        if capabilities.contains(Capabilities::CHALLENGE) {
+            let (bytes, rest) = api_key.split_first_chunk().ok_or(TCPLibError::Generic)?;
+            challenge = Some(*bytes);
+            api_key = rest;
+        }
+        Ok(Hello {
+            capabilities,
+            challenge,
+            api_key: String::from_utf8(api_key.to_vec()).map_err(|_| TCPLibError::Generic)?,
+        })
+    }
+}
+
+impl Hello {
+    /// Bytes of a [`Hello::challenge`].
+    pub const CHALLENGE_LEN: usize = 16;
+
+    pub fn encode(&self) -> Result<Box<[u8]>, TlvError> {
+        let mut capabilities = self.capabilities;
+        capabilities.remove(Capabilities::CHALLENGE);
+        if self.challenge.is_some() {
+            capabilities = capabilities | Capabilities::CHALLENGE;
+        }
+        let challenge = self
+            .challenge
+            .as_ref()
+            .map_or(&[][..], |challenge| challenge);
+        let data = [
+            &capabilities.0.to_be_bytes(),
+            challenge,
+            self.api_key.as_bytes(),
+        ]
+        .concat();
+        Ok(Tlv::new(TlvType::Hello, &data)?.encode())
+    }
+}
+
+/// Several operations in a single frame, at most some sixty of them. The server
+/// calculates them in order and answers all of them with [`AnswerBatch`] frames
+/// instead of one frame per operation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Batch(pub Vec<Operation>);
+
+impl Batch {
+    pub fn encode(&self) -> Result<Box<[u8]>, TlvError> {
+        let data: Vec<u8> = self
+            .0
+            .iter()
+            .flat_map(|operation| operation.clone().encode().into_vec())
+            .collect();
+        Ok(Tlv::new(TlvType::Batch, &data)?.encode())
+    }
+}
+
+/// The results of a [`Batch`], in order. Each one takes 9 bytes: a big-endian
+/// i64 and a flags byte. For answers the flags are those of an extended
+/// [`Answer`]; rejections set the top bit and carry the reason as the value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnswerBatch(pub Vec<Result<Answer, Rejection>>);
+
+impl AnswerBatch {
+    /// Number of results that fit in a frame.
+    pub const MAX_RESULTS: usize = u8::MAX as usize / Self::ENTRY;
+    const ENTRY: usize = 9;
+    const REJECTION_FLAG: u8 = 0x80;
+
+    /// Encodes the results in as many frames as needed, and at least one.
+    pub fn encode(&self) -> Box<[u8]> {
+        let mut bytes = Vec::new();
+        Self::encode_into(&self.0, &mut bytes);
+        bytes.into()
+    }
+
+    /// Like [`AnswerBatch::encode`], appending the frames of `results` to `out`
+    /// without any intermediate buffer.
+    pub(crate) fn encode_into(results: &[Result<Answer, Rejection>], out: &mut impl Extend<u8>) {
+        if results.is_empty() {
+            out.extend([TlvType::AnswerBatch as u8, 0]);
+        }
+        for results in results.chunks(Self::MAX_RESULTS) {
+            let length = results.len() * Self::ENTRY;
+            out.extend([TlvType::AnswerBatch as u8, length as u8]);
+            for result in results {
+                let (value, flags) = match result {
+                    Ok(answer) if answer.overflow => (answer.value, Answer::OVERFLOW_FLAG),
+                    Ok(answer) => (answer.value, 0),
+                    Err(rejection) => (*rejection as i64, Self::REJECTION_FLAG),
+                };
+                out.extend(value.to_be_bytes());
+                out.extend([flags]);
+            }
+        }
+    }
+}
+
+impl<'a> TryFrom<Tlv<'a>> for AnswerBatch {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        if tlv.tag != TlvType::AnswerBatch || !tlv.data.len().is_multiple_of(Self::ENTRY) {
+            return Err(TCPLibError::Generic);
+        }
+
+        tlv.data
+            .chunks(Self::ENTRY)
+            .map(|entry| {
+                let value = i64::from_be_bytes(entry[..8].try_into()?);
+                let flags = entry[8];
+                if flags & Self::REJECTION_FLAG == 0 {
+                    return Ok(Ok(Answer {
+                        value,
+                        overflow: flags & Answer::OVERFLOW_FLAG != 0,
+                    }));
+                }
+                let reason = u8::try_from(value).map_err(|_| TCPLibError::Generic)?;
+                Ok(Err(Rejection::try_from(Tlv {
+                    tag: TlvType::Rejection,
+                    length: 1,
+                    data: &[reason],
+                })?))
+            })
+            .collect::<Result<_, _>>()
+            .map(AnswerBatch)
+    }
+}
+
+/// Score of a player in the quizzes of a server.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Standing {
+    /// Address of the client
+    pub player: String,
+    /// Quizzes taken
+    pub rounds: u16,
+    pub right: u16,
+    pub asked: u16,
+}
+
+/// The best players of the quizzes so far, best first, sent by the server at
+/// the end of every quiz. Each standing takes the rounds, right answers and
+/// questions asked as big-endian u16, followed by the length of the name of
+/// the player and the name itself.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Leaderboard(pub Vec<Standing>);
+
+impl Leaderboard {
+    /// Standings that always fit in a frame, even with IPv6 addresses.
+    pub const MAX_STANDINGS: usize = 5;
+
+    pub fn encode(&self) -> Result<Box<[u8]>, TlvError> {
+        let mut data = Vec::new();
+        for standing in &self.0 {
+            data.extend_from_slice(&standing.rounds.to_be_bytes());
+            data.extend_from_slice(&standing.right.to_be_bytes());
+            data.extend_from_slice(&standing.asked.to_be_bytes());
+            data.push(standing.player.len().try_into()?);
+            data.extend_from_slice(standing.player.as_bytes());
+        }
+        Ok(Tlv::new(TlvType::Leaderboard, &data)?.encode())
+    }
+}
+
+impl<'a> TryFrom<Tlv<'a>> for Leaderboard {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        if tlv.tag != TlvType::Leaderboard {
+            return Err(TCPLibError::Generic);
+        }
+
+        let mut standings = Vec::new();
+        let mut data = tlv.data;
+        while !data.is_empty() {
+            let (header, rest) = data.split_at_checked(7).ok_or(TCPLibError::Generic)?;
+            let (player, rest) = rest
+                .split_at_checked(header[6].into())
+                .ok_or(TCPLibError::Generic)?;
+            standings.push(Standing {
+                player: String::from_utf8(player.to_vec()).map_err(|_| TCPLibError::Generic)?,
+                rounds: u16::from_be_bytes(header[..2].try_into()?),
+                right: u16::from_be_bytes(header[2..4].try_into()?),
+                asked: u16::from_be_bytes(header[4..6].try_into()?),
+            });
+            data = rest;
+        }
+
+        Ok(Leaderboard(standings))
+    }
+}
+
+impl fmt::Display for Leaderboard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (position, standing) in self.0.iter().enumerate() {
+            writeln!(
+                f,
+                "{}. {} {} of {} right in {} rounds",
+                position + 1,
+                standing.player,
+                standing.right,
+                standing.asked,
+                standing.rounds
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Asks the server to abandon a request, numbered from zero among the
+/// operations of the connection sent one by one. If the server is still
+/// calculating it, it answers with [`Rejection::Cancelled`] instead, leaving
+/// the accumulator untouched. Otherwise it is ignored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cancel(pub u64);
+
+impl<'a> TryFrom<Tlv<'a>> for Cancel {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        if tlv.tag == TlvType::Cancel && tlv.length == 8 {
+            Ok(Cancel(u64::from_be_bytes(tlv.data.try_into()?)))
+        } else {
+            Err(TCPLibError::Generic)
+        }
+    }
+}
+
+impl Cancel {
+    pub fn encode(self) -> Box<[u8]> {
+        Tlv::new(TlvType::Cancel, &self.0.to_be_bytes())
+            .unwrap()
+            .encode()
+    }
+}
+
+/// How much of a slow operation the server has calculated, as a percentage
+/// below 100, sent before its answer to the clients that support it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress(pub u8);
+
+impl<'a> TryFrom<Tlv<'a>> for Progress {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv) -> Result<Self, Self::Error> {
+        match (tlv.tag, tlv.data) {
+            (TlvType::Progress, &[percent]) if percent < 100 => Ok(Progress(percent)),
+            _ => Err(TCPLibError::Generic),
+        }
+    }
+}
+
+impl Progress {
+    pub fn encode(self) -> Box<[u8]> {
+        Tlv::new(TlvType::Progress, &[self.0]).unwrap().encode()
+    }
+}
+
+/// A frame of one of the logical sessions multiplexed over the connection,
+/// each with its own accumulator and registers. The data is the channel,
+/// followed by the whole frame: an operation from the client, or its answer
+/// or rejection from the server.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelFrame<'a> {
+    pub channel: u8,
+    pub frame: Tlv<'a>,
+}
+
+impl<'a> TryFrom<Tlv<'a>> for ChannelFrame<'a> {
+    type Error = TCPLibError;
+
+    fn try_from(tlv: Tlv<'a>) -> Result<Self, Self::Error> {
+        match (tlv.tag, tlv.data) {
+            (TlvType::Channel, [channel, frame @ ..]) => Ok(ChannelFrame {
+                channel: *channel,
+                frame: Tlv::whole(frame)?,
+            }),
+            _ => Err(TCPLibError::Generic),
+        }
+    }
+}
+
+impl ChannelFrame<'_> {
+    pub fn encode(self) -> Result<Box<[u8]>, TlvError> {
+        let data = [&[self.channel][..], &self.frame.encode()].concat();
+        Ok(Tlv::new(TlvType::Channel, &data)?.encode())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{Answer, Tlv};
+    use std::time::Duration;
+
+    use crate::{
+        Answer, AnswerBatch, AuditDigest, AuditQuery, Batch, Bye, Capabilities, ChannelFrame,
+        GoAway, Hello, Leaderboard, Load, Ping, Pong, Progress, Rejection, Standing, Store,
+        TCPLibError, Tlv, TlvError, TlvType, TraceContext,
+    };
 
     #[test]
     fn parse_answer_1() {
@@ -115,10 +1152,263 @@ mod tests {
 
     #[test]
     fn encode_answer() {
-        assert_eq!(Answer(1).encode()[..], [16u8, 8, 0, 0, 0, 0, 0, 0, 0, 1]);
         assert_eq!(
-            Answer(-1).encode()[..],
+            Answer::from(1).encode()[..],
+            [16u8, 8, 0, 0, 0, 0, 0, 0, 0, 1]
+        );
+        assert_eq!(
+            Answer::from(-1).encode()[..],
             [16u8, 8, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
         );
     }
+
+    #[test]
+    fn overflow_answer() {
+        let encoded = Answer::saturated(i64::MAX).encode();
+        assert_eq!(
+            encoded[..],
+            [16u8, 9, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 1]
+        );
+
+        let answer = Answer::try_from(&encoded[..]).unwrap();
+        assert_eq!(answer, Answer::saturated(i64::MAX));
+        assert!(matches!(
+            Answer::try_from(&[&encoded[..], &[19, 0]].concat()[..]),
+            Err(TCPLibError::Tlv(TlvError::Trailing { offset: 11, .. }))
+        ));
+        assert_eq!(
+            answer.to_string(),
+            "9223372036854775807 (overflow: the accumulator saturated)"
+        );
+    }
+
+    #[test]
+    fn leaderboard() {
+        let leaderboard = Leaderboard(vec![
+            Standing {
+                player: "10.0.0.1".to_string(),
+                rounds: 2,
+                right: 9,
+                asked: 10,
+            },
+            Standing {
+                player: "::1".to_string(),
+                rounds: 1,
+                right: 1,
+                asked: 5,
+            },
+        ]);
+        let encoded = leaderboard.encode().unwrap();
+        assert_eq!(
+            encoded[..17],
+            [29u8, 25, 0, 2, 0, 9, 0, 10, 8, b'1', b'0', b'.', b'0', b'.', b'0', b'.', b'1']
+        );
+        let parsed = Leaderboard::try_from(Tlv::whole(&encoded).unwrap()).unwrap();
+        assert_eq!(parsed, leaderboard);
+        assert_eq!(
+            parsed.to_string(),
+            "1. 10.0.0.1 9 of 10 right in 2 rounds\n2. ::1 1 of 5 right in 1 rounds\n"
+        );
+
+        let truncated = [29u8, 8, 0, 1, 0, 1, 0, 1, 3, b'a'];
+        assert!(Leaderboard::try_from(Tlv::try_from(&truncated[..]).unwrap()).is_err());
+    }
+
+    #[test]
+    fn channel_frame() {
+        let answer = Answer::from(7).encode();
+        let frame = ChannelFrame {
+            channel: 3,
+            frame: Tlv::whole(&answer).unwrap(),
+        };
+        let encoded = frame.encode().unwrap();
+        assert_eq!(encoded[..5], [30u8, 11, 3, 16, 8]);
+        let parsed = ChannelFrame::try_from(Tlv::whole(&encoded).unwrap()).unwrap();
+        assert_eq!(parsed, frame);
+
+        // A single frame, with nothing after it
+        let extra = [30u8, 6, 3, 19, 0, 19, 0, 0];
+        assert!(ChannelFrame::try_from(Tlv::whole(&extra).unwrap()).is_err());
+        let empty = [30u8, 0];
+        assert!(ChannelFrame::try_from(Tlv::whole(&empty).unwrap()).is_err());
+    }
+
+    #[test]
+    fn progress() {
+        assert_eq!(Progress(42).encode()[..], [31u8, 1, 42]);
+        let tlv: Tlv = (&[31u8, 1, 99][..]).try_into().unwrap();
+        assert_eq!(Progress::try_from(tlv).unwrap(), Progress(99));
+        let tlv: Tlv = (&[31u8, 1, 100][..]).try_into().unwrap();
+        assert!(Progress::try_from(tlv).is_err());
+    }
+
+    #[test]
+    fn ping_pong() {
+        let ping = Ping([1, 2, 3, 4, 5, 6, 7, 8]);
+        let encoded = ping.encode();
+        assert_eq!(encoded[..], [17u8, 8, 1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let parsed: Ping = Tlv::try_from(&encoded[..]).unwrap().try_into().unwrap();
+        assert_eq!(parsed, ping);
+        assert_eq!(
+            Pong::from(parsed).encode()[..],
+            [18u8, 8, 1, 2, 3, 4, 5, 6, 7, 8]
+        );
+    }
+
+    #[test]
+    fn registers() {
+        assert_eq!(Store(b'x').encode()[..], [10u8, 1, b'x']);
+        assert_eq!(Load(b'x').encode()[..], [11u8, 1, b'x']);
+        let tlv: Tlv = (&[11u8, 1, b'y'][..]).try_into().unwrap();
+        assert_eq!(Load::try_from(tlv).unwrap(), Load(b'y'));
+        assert!(Store::try_from(tlv).is_err());
+        let tlv: Tlv = (&[10u8, 0][..]).try_into().unwrap();
+        assert!(Store::try_from(tlv).is_err());
+    }
+
+    #[test]
+    fn parse_ping_err_short() {
+        let tlv: Tlv = (&[17u8, 4, 1, 2, 3, 4][..]).try_into().unwrap();
+        assert!(Ping::try_from(tlv).is_err());
+    }
+
+    #[test]
+    fn rejection() {
+        assert_eq!(Rejection::Overflow.encode()[..], [20u8, 1, 2]);
+        assert_eq!(Rejection::TooManyRegisters.code(), 8);
+        let tlv: Tlv = (&[20u8, 1, 9][..]).try_into().unwrap();
+        assert_eq!(Rejection::try_from(tlv).unwrap(), Rejection::Cancelled);
+        assert_eq!(Rejection::Other.code(), 255);
+        let tlv: Tlv = (&[20u8, 1, 1][..]).try_into().unwrap();
+        assert_eq!(Rejection::try_from(tlv).unwrap(), Rejection::WrongDomain);
+        let tlv: Tlv = (&[20u8, 0][..]).try_into().unwrap();
+        assert!(Rejection::try_from(tlv).is_err());
+        for &rejection in Rejection::ALL {
+            let encoded = rejection.encode();
+            let tlv = Tlv::try_from(&encoded[..]).unwrap();
+            assert_eq!(Rejection::try_from(tlv).unwrap(), rejection);
+        }
+        // Any other code is one that this side does not know yet
+        let known: Vec<u8> = Rejection::ALL.iter().map(|r| r.code()).collect();
+        for code in (1..=u8::MAX).filter(|code| !known.contains(code)) {
+            let data = [code];
+            let tlv = Tlv::new(TlvType::Rejection, &data).unwrap();
+            assert_eq!(Rejection::try_from(tlv).unwrap(), Rejection::Other);
+        }
+    }
+
+    #[test]
+    fn go_away() {
+        let go_away = GoAway {
+            retry_after: Duration::from_millis(1500),
+        };
+        assert_eq!(go_away.encode()[..], [22u8, 4, 0, 0, 5, 220]);
+        let tlv: Tlv = (&[22u8, 4, 0, 0, 5, 220][..]).try_into().unwrap();
+        assert_eq!(GoAway::try_from(tlv).unwrap(), go_away);
+    }
+
+    #[test]
+    fn trace_context() {
+        let context = TraceContext {
+            trace_id: 0x4bf92f3577b34da6a3ce929d0e0e4736u128.to_be_bytes(),
+            parent_id: 0x00f067aa0ba902b7u64.to_be_bytes(),
+            flags: 1,
+        };
+        assert_eq!(
+            context.to_string(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+
+        let encoded = context.encode();
+        assert_eq!(encoded[..2], [23u8, 25]);
+        let parsed: TraceContext = Tlv::try_from(&encoded[..]).unwrap().try_into().unwrap();
+        assert_eq!(parsed, context);
+
+        let next = context.next();
+        assert_eq!(next.trace_id, context.trace_id);
+    }
+
+    #[test]
+    fn hello() {
+        let hello = Hello {
+            capabilities: Capabilities::BATCH | Capabilities::AUDIT,
+            challenge: None,
+            api_key: "s3cret".to_string(),
+        };
+        let encoded = hello.encode().unwrap();
+        assert_eq!(encoded[..6], [26u8, 10, 0, 0, 0, 0x11]);
+        let parsed: Hello = Tlv::try_from(&encoded[..]).unwrap().try_into().unwrap();
+        assert_eq!(parsed, hello);
+        assert_eq!(parsed.capabilities.to_string(), "batch, audit");
+        assert_eq!(Capabilities::default().to_string(), "none");
+
+        let tlv: Tlv = (&[26u8, 3, 0, 0, 0][..]).try_into().unwrap();
+        assert!(Hello::try_from(tlv).is_err());
+        // The challenge goes before the key, and sets its capability
+        let hello = Hello {
+            capabilities: Capabilities::BATCH,
+            challenge: Some([7; 16]),
+            api_key: "k".to_string(),
+        };
+        let encoded = hello.encode().unwrap();
+        assert_eq!(encoded[..7], [26u8, 21, 0, 0, 1, 1, 7]);
+        let parsed: Hello = Tlv::try_from(&encoded[..]).unwrap().try_into().unwrap();
+        assert_eq!(parsed.challenge, hello.challenge);
+        assert_eq!(parsed.api_key, "k");
+        assert!(parsed.capabilities.contains(Capabilities::CHALLENGE));
+        let tlv: Tlv = (&[26u8, 5, 0, 0, 1, 0, 7][..]).try_into().unwrap();
+        assert!(Hello::try_from(tlv).is_err());
+        assert!(Hello {
+            capabilities: Capabilities::default(),
+            challenge: None,
+            api_key: "k".repeat(252)
+        }
+        .encode()
+        .is_err());
+    }
+
+    #[test]
+    fn audit_frames() {
+        assert_eq!(AuditQuery.encode()[..], [24u8, 0]);
+
+        let digest = AuditDigest {
+            received: [1; 32],
+            sent: [2; 32],
+        };
+        let encoded = digest.encode();
+        assert_eq!(encoded[..2], [25u8, 64]);
+        let parsed: AuditDigest = Tlv::try_from(&encoded[..]).unwrap().try_into().unwrap();
+        assert_eq!(parsed, digest);
+    }
+
+    #[test]
+    fn bye() {
+        assert_eq!(Bye.encode()[..], [19u8, 0]);
+        let tlv: Tlv = (&[19u8, 0][..]).try_into().unwrap();
+        assert_eq!(Bye::try_from(tlv).unwrap(), Bye);
+    }
+
+    #[test]
+    fn batches() {
+        let batch = Batch(vec!["3 + 4".parse().unwrap(), "5!".parse().unwrap()]);
+        assert_eq!(batch.encode().unwrap()[..], [27u8, 7, 1, 2, 3, 4, 6, 1, 5]);
+        assert!(Batch(vec!["1 + 1".parse().unwrap(); 64]).encode().is_err());
+
+        let results = vec![
+            Ok(Answer::from(-2)),
+            Err(Rejection::Overflow),
+            Ok(Answer::saturated(i64::MAX)),
+        ];
+        let encoded = AnswerBatch(results.clone()).encode();
+        assert_eq!(encoded[..2], [28u8, 27]);
+        assert_eq!(encoded[11..20], [0, 0, 0, 0, 0, 0, 0, 2, 0x80]);
+        let parsed: AnswerBatch = Tlv::try_from(&encoded[..]).unwrap().try_into().unwrap();
+        assert_eq!(parsed.0, results);
+
+        assert_eq!(AnswerBatch(vec![]).encode()[..], [28u8, 0]);
+        // Split in frames of 28 results
+        let encoded = AnswerBatch(vec![Ok(Answer::from(1)); 30]).encode();
+        assert_eq!(encoded.len(), 2 + 28 * 9 + 2 + 2 * 9);
+    }
 }
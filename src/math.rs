@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Arithmetic shared by the operations and the accumulator. Operands are
+//! widened to i64 before operating, so that only the results can overflow.
+
+/// Adds `b` to `a`, saturating at the bounds of i64. Also tells whether it
+/// saturated.
+pub(crate) fn sat_add_i64(a: i64, b: i64) -> (i64, bool) {
+    match a.checked_add(b) {
+        Some(sum) => (sum, false),
+        None => (a.saturating_add(b), true),
+    }
+}
+
+/// Factorial of `n`, or `None` if `n` is negative or the result overflows.
+pub(crate) fn checked_fact(n: i64) -> Option<i64> {
+    match n {
+        0.. => (1..=n).try_fold(1i64, |acc, e| acc.checked_mul(e)),
+        _ => None,
+    }
+}
+
+/// Euclidean quotient and remainder of `a` divided by `b`, so that the
+/// remainder is never negative. `None` if `b` is zero or the quotient
+/// overflows.
+pub(crate) fn euclid_div(a: i64, b: i64) -> Option<(i64, i64)> {
+    Some((a.checked_div_euclid(b)?, a.checked_rem_euclid(b)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checked_fact, euclid_div, sat_add_i64};
+
+    #[test]
+    fn saturating_addition() {
+        assert_eq!(sat_add_i64(3, -4), (-1, false));
+        assert_eq!(sat_add_i64(i64::MAX, 1), (i64::MAX, true));
+        assert_eq!(sat_add_i64(i64::MIN, -1), (i64::MIN, true));
+        assert_eq!(sat_add_i64(i64::MIN, i64::MAX), (-1, false));
+    }
+
+    #[test]
+    fn factorial() {
+        assert_eq!(checked_fact(0), Some(1));
+        assert_eq!(checked_fact(5), Some(120));
+        assert_eq!(checked_fact(20), Some(2_432_902_008_176_640_000));
+        assert_eq!(checked_fact(21), None);
+        assert_eq!(checked_fact(-1), None);
+    }
+
+    #[test]
+    fn euclidean_division() {
+        assert_eq!(euclid_div(7, 2), Some((3, 1)));
+        assert_eq!(euclid_div(-7, 2), Some((-4, 1)));
+        assert_eq!(euclid_div(-7, -2), Some((4, 1)));
+        assert_eq!(euclid_div(7, 0), None);
+        assert_eq!(euclid_div(i64::MIN, -1), None);
+    }
+}
@@ -0,0 +1,610 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! The steps that every operation goes through in the server, as middlewares
+//! layered around the handler that calculates it.
+//!
+//! Each [`Middleware`] either answers the request itself, usually rejecting
+//! it, or passes it on to the next. [`Chain`] composes them in the order they
+//! are added, the first one the outermost, as [`chain`] does for the server.
+
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    server::{outcome, store_key, Registers, ServerConfig},
+    tenant::TenantState,
+    Answer, Operation, Rejection, Tenant, Tlv, TlvType,
+};
+
+/// An operation on its way through the chain, with everything the steps may
+/// look at or update.
+pub(crate) struct Request<'a> {
+    /// The client, behind the proxy if there is one
+    pub peer: SocketAddr,
+    pub tlv: Tlv<'a>,
+    /// Appended to the log lines of the request
+    pub context: String,
+    pub config: &'a ServerConfig,
+    pub tenant: Option<&'a Tenant>,
+    /// Key ID the client authenticated with
+    #[cfg(feature = "auth")]
+    pub key_id: Option<&'a str>,
+    pub state: &'a mut TenantState,
+    /// That of a channel, instead of the one of the tenant
+    pub channel_acc: Option<&'a mut i64>,
+    pub registers: &'a mut Registers,
+    /// When the client stops waiting for the answer
+    pub deadline: Option<Instant>,
+    /// Sent by the client to replay the answer if the request is repeated
+    pub idempotency_key: Option<u64>,
+    /// When the request went into the chain, by the clock of the server
+    pub now: Instant,
+    /// Time the answer has to take, as if calculating it were slow
+    pub work: Duration,
+    /// What the operation added to the shared accumulator in the store
+    pub added: Option<i64>,
+    /// Whether the answer is that of an earlier request, not to be counted again
+    pub replayed: bool,
+}
+
+impl Request<'_> {
+    /// The accumulator that the operation updates.
+    pub fn acc(&mut self) -> &mut i64 {
+        match &mut self.channel_acc {
+            Some(acc) => acc,
+            None => &mut self.state.acc,
+        }
+    }
+
+    /// Counts a rejection for the tenant and logs it, returning its encoding.
+    fn reject(&mut self, rejection: Rejection, reason: &str) -> Box<[u8]> {
+        self.state.rejections += 1;
+        eprintln!("Rejecting {}{reason}{}", self.tlv.tag.name(), self.context);
+        rejection.encode()
+    }
+}
+
+/// Answers a [`Request`], with the encoded answer or rejection.
+pub(crate) trait Handler {
+    fn call(&mut self, request: &mut Request) -> Box<[u8]>;
+}
+
+/// A step of the chain, that may answer the request itself or pass it on to
+/// the `next` handler.
+pub(crate) trait Middleware {
+    fn call(&mut self, request: &mut Request, next: &mut dyn Handler) -> Box<[u8]>;
+}
+
+/// Wraps a handler into another.
+pub(crate) trait Layer<H> {
+    type Handler: Handler;
+
+    fn layer(&self, inner: H) -> Self::Handler;
+}
+
+/// A [`Middleware`] in front of the handler `H`.
+pub(crate) struct Layered<M, H> {
+    middleware: M,
+    inner: H,
+}
+
+impl<M: Middleware, H: Handler> Handler for Layered<M, H> {
+    fn call(&mut self, request: &mut Request) -> Box<[u8]> {
+        self.middleware.call(request, &mut self.inner)
+    }
+}
+
+impl<M: Middleware + Clone, H: Handler> Layer<H> for M {
+    type Handler = Layered<M, H>;
+
+    fn layer(&self, inner: H) -> Self::Handler {
+        Layered {
+            middleware: self.clone(),
+            inner,
+        }
+    }
+}
+
+/// The layer that leaves the handler as it is.
+pub(crate) struct Identity;
+
+impl<H: Handler> Layer<H> for Identity {
+    type Handler = H;
+
+    fn layer(&self, inner: H) -> H {
+        inner
+    }
+}
+
+/// The layer `outer` around the layer `inner`.
+pub(crate) struct Stack<I, O> {
+    inner: I,
+    outer: O,
+}
+
+impl<H, I: Layer<H>, O: Layer<I::Handler>> Layer<H> for Stack<I, O> {
+    type Handler = O::Handler;
+
+    fn layer(&self, handler: H) -> Self::Handler {
+        self.outer.layer(self.inner.layer(handler))
+    }
+}
+
+/// Builds a handler from layers, the first added running first.
+pub(crate) struct Chain<L>(L);
+
+impl Chain<Identity> {
+    pub fn new() -> Self {
+        Self(Identity)
+    }
+}
+
+impl<L> Chain<L> {
+    pub fn layer<T>(self, layer: T) -> Chain<Stack<T, L>> {
+        Chain(Stack {
+            inner: layer,
+            outer: self.0,
+        })
+    }
+
+    pub fn handler<H>(self, handler: H) -> L::Handler
+    where
+        L: Layer<H>,
+    {
+        self.0.layer(handler)
+    }
+}
+
+/// The chain of the server: who may calculate, what, how often and how, before
+/// calculating it.
+pub(crate) fn chain() -> impl Handler {
+    let chain = Chain::new()
+        .layer(Networks)
+        .layer(Authorization)
+        .layer(Idempotency)
+        .layer(Deadlines)
+        .layer(DisabledOperations)
+        .layer(RateLimit)
+        .layer(RegisterAccess)
+        .layer(Budget);
+    #[cfg(feature = "script")]
+    let chain = chain.layer(Script);
+
+    chain.handler(Calculator)
+}
+
+/// Rejects the operations of clients whose network is no longer allowed, as
+/// after a [`ConfigHandle::update`](crate::ConfigHandle::update) since they
+/// connected. The others were already refused when connecting.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Networks;
+
+impl Middleware for Networks {
+    fn call(&mut self, request: &mut Request, next: &mut dyn Handler) -> Box<[u8]> {
+        if !request.config.admits(request.peer.ip()) {
+            let tag = request.tlv.tag.name();
+            eprintln!(
+                "Rejecting {tag} from a network not allowed{}",
+                request.context
+            );
+            return Rejection::Unauthorized.encode();
+        }
+        next.call(request)
+    }
+}
+
+/// Rejects the operations of clients without a known API key when there are
+/// tenants, or without authenticating when there are secrets.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Authorization;
+
+impl Middleware for Authorization {
+    fn call(&mut self, request: &mut Request, next: &mut dyn Handler) -> Box<[u8]> {
+        let tag = request.tlv.tag.name();
+        if !request.config.tenants.is_empty() && request.tenant.is_none() {
+            eprintln!("Rejecting {tag} without an API key{}", request.context);
+            return Rejection::Unauthorized.encode();
+        }
+        #[cfg(feature = "auth")]
+        if !request.config.secrets.is_empty() && request.key_id.is_none() {
+            eprintln!("Rejecting {tag} without authentication{}", request.context);
+            return Rejection::Unauthorized.encode();
+        }
+        next.call(request)
+    }
+}
+
+/// Replays the answer to a request sent again by the same client with the
+/// same [`IdempotencyKey`](crate::IdempotencyKey), without calculating it,
+/// and remembers the answers to the others.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Idempotency;
+
+impl Middleware for Idempotency {
+    fn call(&mut self, request: &mut Request, next: &mut dyn Handler) -> Box<[u8]> {
+        let Some(key) = request.idempotency_key else {
+            return next.call(request);
+        };
+        let client = request.peer.ip();
+        if let Some(reply) = request.state.replay(client, key) {
+            println!("Replaying the answer for key {key:016x}{}", request.context);
+            request.replayed = true;
+            return reply;
+        }
+        let reply = next.call(request);
+        request.state.remember(client, key, reply.clone());
+        reply
+    }
+}
+
+/// Skips the operations whose deadline already passed, as the client is no
+/// longer waiting for them.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Deadlines;
+
+impl Middleware for Deadlines {
+    fn call(&mut self, request: &mut Request, next: &mut dyn Handler) -> Box<[u8]> {
+        match request.deadline {
+            Some(deadline) if deadline <= request.now => {
+                request.reject(Rejection::DeadlineExceeded, " past its deadline")
+            }
+            _ => next.call(request),
+        }
+    }
+}
+
+/// Rejects the [`ServerConfig::disabled_operations`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DisabledOperations;
+
+impl Middleware for DisabledOperations {
+    fn call(&mut self, request: &mut Request, next: &mut dyn Handler) -> Box<[u8]> {
+        if request
+            .config
+            .disabled_operations
+            .contains(&request.tlv.tag)
+        {
+            let tag = request.tlv.tag.name();
+            eprintln!("Rejecting disabled operation {tag}{}", request.context);
+            return Rejection::Disabled.encode();
+        }
+        next.call(request)
+    }
+}
+
+/// Rejects the operations of a tenant over its rate limit.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RateLimit;
+
+impl Middleware for RateLimit {
+    fn call(&mut self, request: &mut Request, next: &mut dyn Handler) -> Box<[u8]> {
+        let limit = request.tenant.and_then(|tenant| tenant.rate_limit);
+        if !request.state.admit(limit, request.now) {
+            return request.reject(Rejection::RateLimited, " over the rate limit");
+        }
+        next.call(request)
+    }
+}
+
+/// Answers the [`Store`](crate::Store) and [`Load`](crate::Load) operations
+/// with the registers of the session.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RegisterAccess;
+
+impl Middleware for RegisterAccess {
+    fn call(&mut self, request: &mut Request, next: &mut dyn Handler) -> Box<[u8]> {
+        if !matches!(request.tlv.tag, TlvType::Store | TlvType::Load) {
+            return next.call(request);
+        }
+        let acc = *request.acc();
+        match request.registers.apply(request.tlv, acc) {
+            Ok(value) => {
+                *request.acc() = value;
+                request.state.operations += 1;
+                println!(
+                    "{} {} = {value}{}",
+                    request.tlv.tag.name(),
+                    request.tlv.data.escape_ascii(),
+                    request.context
+                );
+                Answer::from(value).encode()
+            }
+            Err(rejection) => request.reject(rejection, &format!(". {rejection}")),
+        }
+    }
+}
+
+/// Rejects the operations that would take longer than the
+/// [`ServerConfig::op_timeout`], or finish after their deadline, after
+/// spending the time left, and otherwise sets the time that the answer takes.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Budget;
+
+impl Middleware for Budget {
+    fn call(&mut self, request: &mut Request, next: &mut dyn Handler) -> Box<[u8]> {
+        let delay = request.config.answer_delay();
+        if let Some(budget) = request.config.op_timeout.filter(|&budget| delay > budget) {
+            // Spent calculating until the budget ran out
+            request.work = budget;
+            return request.reject(
+                Rejection::Timeout,
+                &format!(" over its budget of {budget:?}"),
+            );
+        }
+        let left = request
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(request.now));
+        if let Some(left) = left.filter(|&left| delay > left) {
+            // Abandoned when the client stops waiting
+            request.work = left;
+            return request.reject(Rejection::DeadlineExceeded, " that would miss its deadline");
+        }
+        let reply = next.call(request);
+        if outcome(&reply).is_ok() {
+            request.work = delay;
+        }
+        reply
+    }
+}
+
+/// Lets the [`ServerConfig::handler`] script answer instead of the calculator.
+#[cfg(feature = "script")]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Script;
+
+#[cfg(feature = "script")]
+impl Middleware for Script {
+    fn call(&mut self, request: &mut Request, next: &mut dyn Handler) -> Box<[u8]> {
+        let Some(handler) = &request.config.handler else {
+            return next.call(request);
+        };
+        let acc = *request.acc();
+        match handler.answer(request.tlv, acc) {
+            Ok(None) => next.call(request),
+            Ok(Some(Ok(answer))) => {
+                *request.acc() = answer.value;
+                request.state.operations += 1;
+                let tag = request.tlv.tag.name();
+                println!("{tag} = {answer} by the script{}", request.context);
+                answer.encode()
+            }
+            Ok(Some(Err(rejection))) => request.reject(rejection, " by the script"),
+            Err(e) => {
+                request.state.rejections += 1;
+                eprintln!("{e}{}", request.context);
+                Rejection::Other.encode()
+            }
+        }
+    }
+}
+
+/// Calculates the operation and adds the result to the accumulator, or to the
+/// shared one in the store with [`ServerConfig::shared_accumulator`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Calculator;
+
+impl Handler for Calculator {
+    fn call(&mut self, request: &mut Request) -> Box<[u8]> {
+        let max_factorial = request
+            .config
+            .max_factorial
+            .unwrap_or(Operation::MAX_FACTORIAL);
+        let calculated = Operation::try_from(request.tlv)
+            .and_then(|op| op.reduce_with(max_factorial).map(|res| (op, res)));
+        let (operation, result) = match calculated {
+            Ok(calculated) => calculated,
+            Err(e) => {
+                request.state.rejections += 1;
+                eprintln!("Could not calculate answer. {e}{}", request.context);
+                return Rejection::from(&e).encode();
+            }
+        };
+
+        let shared = (request.channel_acc.is_none() && request.config.shared_accumulator)
+            .then_some(request.config.store.as_ref())
+            .flatten();
+        // Other servers may have added to it meanwhile
+        let key = store_key(request.tenant);
+        let previous = match shared.map(|store| store.lock().unwrap().fetch_add(&key, result)) {
+            Some(Ok(previous)) => Some(previous),
+            Some(Err(e)) => {
+                eprintln!("Could not add to the shared accumulator. {e}");
+                None
+            }
+            None => None,
+        };
+        let answer = Answer::accumulate(previous.unwrap_or(*request.acc()), result);
+        request.added = previous.map(|previous| answer.value - previous);
+        if answer.overflow {
+            eprintln!("Accumulator saturated after {operation}");
+        }
+        *request.acc() = answer.value;
+        request.state.operations += 1;
+        println!("{operation} = {result}{}", request.context);
+        answer.encode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        net::{Ipv4Addr, SocketAddr},
+        rc::Rc,
+        time::{Duration, Instant},
+    };
+
+    use super::{
+        Budget, Calculator, Chain, DisabledOperations, Handler, Idempotency, Middleware, Networks,
+        Request,
+    };
+    use crate::{
+        server::{outcome, Registers, ServerConfig},
+        tenant::TenantState,
+        Clock, FakeClock, Operation, Rejection, TlvType,
+    };
+
+    /// Notes its name, then passes the request on.
+    #[derive(Clone)]
+    struct Trace(&'static str, Rc<RefCell<Vec<&'static str>>>);
+
+    impl Middleware for Trace {
+        fn call(&mut self, request: &mut Request, next: &mut dyn Handler) -> Box<[u8]> {
+            self.1.borrow_mut().push(self.0);
+            next.call(request)
+        }
+    }
+
+    fn call(handler: &mut impl Handler, config: &ServerConfig, operation: &str) -> Box<[u8]> {
+        call_at(handler, config, operation, None, Instant::now())
+    }
+
+    /// Like `call`, for a request with a `deadline` that goes in at `now`.
+    fn call_at(
+        handler: &mut impl Handler,
+        config: &ServerConfig,
+        operation: &str,
+        deadline: Option<Instant>,
+        now: Instant,
+    ) -> Box<[u8]> {
+        let operation: Operation = operation.parse().unwrap();
+        let encoded = operation.encode();
+        let mut state = TenantState::default();
+        let mut registers = Registers::default();
+        let mut request = Request {
+            peer: SocketAddr::from((Ipv4Addr::LOCALHOST, 1)),
+            tlv: (&encoded[..]).try_into().unwrap(),
+            context: String::new(),
+            config,
+            tenant: None,
+            #[cfg(feature = "auth")]
+            key_id: None,
+            state: &mut state,
+            channel_acc: None,
+            registers: &mut registers,
+            deadline,
+            idempotency_key: None,
+            now,
+            work: Duration::ZERO,
+            added: None,
+            replayed: false,
+        };
+        handler.call(&mut request)
+    }
+
+    #[test]
+    fn order() {
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        let mut handler = Chain::new()
+            .layer(Trace("outer", trace.clone()))
+            .layer(DisabledOperations)
+            .layer(Trace("inner", trace.clone()))
+            .handler(Calculator);
+        let mut config = ServerConfig::default();
+
+        let reply = call(&mut handler, &config, "3 + 4");
+        assert_eq!(outcome(&reply).unwrap().value, 7);
+        assert_eq!(*trace.borrow(), ["outer", "inner"]);
+
+        // Those after a middleware that answers are skipped
+        trace.borrow_mut().clear();
+        config.disabled_operations = vec![TlvType::Sum];
+        let reply = call(&mut handler, &config, "3 + 4");
+        assert_eq!(outcome(&reply), Err(Rejection::Disabled));
+        assert_eq!(*trace.borrow(), ["outer"]);
+    }
+
+    #[test]
+    fn networks() {
+        let mut handler = Chain::new().layer(Networks).handler(Calculator);
+        let mut config = ServerConfig::default();
+        assert!(outcome(&call(&mut handler, &config, "3 + 4")).is_ok());
+
+        // Denied after connecting
+        config.denied_networks = ["127.0.0.0/8".parse().unwrap()].into_iter().collect();
+        let reply = call(&mut handler, &config, "3 + 4");
+        assert_eq!(outcome(&reply), Err(Rejection::Unauthorized));
+    }
+
+    #[test]
+    fn idempotency() {
+        let mut handler = Chain::new().layer(Idempotency).handler(Calculator);
+        let config = ServerConfig::default();
+        let mut state = TenantState::default();
+        let mut registers = Registers::default();
+        let mut send = |peer: SocketAddr, operation: &str| {
+            let encoded = operation.parse::<Operation>().unwrap().encode();
+            let mut request = Request {
+                peer,
+                tlv: (&encoded[..]).try_into().unwrap(),
+                context: String::new(),
+                config: &config,
+                tenant: None,
+                #[cfg(feature = "auth")]
+                key_id: None,
+                state: &mut state,
+                channel_acc: None,
+                registers: &mut registers,
+                deadline: None,
+                idempotency_key: Some(7),
+                now: Instant::now(),
+                work: Duration::ZERO,
+                added: None,
+                replayed: false,
+            };
+            let reply = handler.call(&mut request);
+            (outcome(&reply).unwrap().value, request.replayed)
+        };
+        let alice = SocketAddr::from((Ipv4Addr::new(10, 0, 0, 1), 1));
+        let bob = SocketAddr::from((Ipv4Addr::new(10, 0, 0, 2), 1));
+
+        assert_eq!(send(alice, "3 + 4"), (7, false));
+        // From another connection of the same client
+        assert_eq!(send(SocketAddr::new(alice.ip(), 2), "3 + 4"), (7, true));
+        // Others get their own
+        assert_eq!(send(bob, "3 + 4"), (14, false));
+    }
+
+    #[test]
+    fn budget() {
+        let clock = FakeClock::new();
+        let mut handler = Chain::new().layer(Budget).handler(Calculator);
+        let config = ServerConfig {
+            delay: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let deadline = Some(clock.now() + Duration::from_millis(250));
+
+        let reply = call_at(&mut handler, &config, "3 + 4", deadline, clock.now());
+        assert_eq!(outcome(&reply).unwrap().value, 7);
+        // With only 200ms left it would still make it, but not with less
+        clock.advance(Duration::from_millis(50));
+        let reply = call_at(&mut handler, &config, "3 + 4", deadline, clock.now());
+        assert!(outcome(&reply).is_ok());
+        clock.advance(Duration::from_millis(1));
+        let reply = call_at(&mut handler, &config, "3 + 4", deadline, clock.now());
+        assert_eq!(outcome(&reply), Err(Rejection::DeadlineExceeded));
+    }
+}
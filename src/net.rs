@@ -0,0 +1,324 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream},
+    str::FromStr,
+    time::Duration,
+};
+
+#[cfg(all(feature = "tcp-info", target_os = "linux"))]
+use std::{mem, os::fd::AsRawFd};
+
+use thiserror::Error;
+
+/// What the client and the server need of a connection, so that their I/O can
+/// also be tested over the `TestStream` of the `testing` module.
+pub trait Stream: Read + Write {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Stream for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+/// The address of a peer as it should be shown and counted. A dual-stack socket
+/// sees IPv4 clients as IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`), so they
+/// are turned back into IPv4 to give each host a single identity.
+pub fn canonical_peer(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::from((ip, v6.port())),
+            None => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum CidrError {
+    #[error("Invalid address in {0:?}")]
+    Address(String),
+    #[error("Invalid prefix length in {0:?}")]
+    Prefix(String),
+}
+
+/// Block of addresses, as in `10.1.2.0/24` or `2001:db8::/32`. A bare address
+/// is a block with just that address. Host bits set in the network are ignored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Whether `ip` belongs to the block. IPv4-mapped IPv6 addresses match
+    /// the IPv4 blocks.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        ip.is_ipv4() == self.network.is_ipv4() && mask(ip, self.prefix) == self.network
+    }
+}
+
+/// Keeps the first `prefix` bits of `ip`, clearing the rest.
+fn mask(ip: IpAddr, prefix: u8) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            Ipv4Addr::from(u32::from(ip) & mask).into()
+        }
+        IpAddr::V6(ip) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            Ipv6Addr::from(u128::from(ip) & mask).into()
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = CidrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let address = address
+            .parse::<IpAddr>()
+            .map_err(|_| CidrError::Address(s.to_string()))?
+            .to_canonical();
+        let bits = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|&prefix| prefix <= bits)
+                .ok_or_else(|| CidrError::Prefix(s.to_string()))?,
+            None => bits,
+        };
+
+        Ok(Cidr {
+            network: mask(address, prefix),
+            prefix,
+        })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// Set of [`Cidr`] blocks. Looking an address up masks it once per prefix length
+/// in the set, so the cost does not grow with the number of blocks.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CidrSet(BTreeMap<(bool, u8), HashSet<IpAddr>>);
+
+impl CidrSet {
+    pub fn insert(&mut self, cidr: Cidr) {
+        let key = (cidr.network.is_ipv4(), cidr.prefix);
+        self.0.entry(key).or_default().insert(cidr.network);
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.0
+            .range((ip.is_ipv4(), 0)..=(ip.is_ipv4(), u8::MAX))
+            .any(|(&(_, prefix), networks)| networks.contains(&mask(ip, prefix)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromIterator<Cidr> for CidrSet {
+    fn from_iter<T: IntoIterator<Item = Cidr>>(iter: T) -> Self {
+        let mut set = CidrSet::default();
+        for cidr in iter {
+            set.insert(cidr);
+        }
+
+        set
+    }
+}
+
+/// Largest segment sent in the connection of `stream`, as negotiated with the
+/// peer (`TCP_MAXSEG`). Only available on Unix.
+#[cfg(unix)]
+pub fn mss(stream: &TcpStream) -> io::Result<u32> {
+    socket2::SockRef::from(stream).mss()
+}
+
+#[cfg(not(unix))]
+pub fn mss(_stream: &TcpStream) -> io::Result<u32> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// What the kernel knows of a TCP connection, as `ss --info` shows it.
+#[cfg(all(feature = "tcp-info", target_os = "linux"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TcpInfo {
+    /// Smoothed round trip time
+    pub rtt: Duration,
+    /// Mean deviation of the round trip time
+    pub rtt_var: Duration,
+    /// Congestion window, in segments
+    pub cwnd: u32,
+    /// Maximum segment size used to send
+    pub mss: u32,
+    /// Segments retransmitted over the whole connection
+    pub retransmits: u32,
+}
+
+#[cfg(all(feature = "tcp-info", target_os = "linux"))]
+impl fmt::Display for TcpInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rtt {:?} ± {:?}, cwnd {} segments of {} bytes, {} retransmissions",
+            self.rtt, self.rtt_var, self.cwnd, self.mss, self.retransmits
+        )
+    }
+}
+
+/// Asks the kernel for the `TCP_INFO` of the connection of `socket`.
+#[cfg(all(feature = "tcp-info", target_os = "linux"))]
+pub fn tcp_info(socket: &impl AsRawFd) -> io::Result<TcpInfo> {
+    let mut info = mem::MaybeUninit::<libc::tcp_info>::zeroed();
+    let mut len = mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+    // SAFETY: the kernel writes at most len bytes into info, which has room for them
+    let result = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            info.as_mut_ptr().cast(),
+            &mut len,
+        )
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: zeroed is a valid tcp_info, even if older kernels fill less of it
+    let info = unsafe { info.assume_init() };
+
+    Ok(TcpInfo {
+        rtt: Duration::from_micros(info.tcpi_rtt.into()),
+        rtt_var: Duration::from_micros(info.tcpi_rttvar.into()),
+        cwnd: info.tcpi_snd_cwnd,
+        mss: info.tcpi_snd_mss,
+        retransmits: info.tcpi_total_retrans,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+
+    use super::{canonical_peer, Cidr, CidrError, CidrSet};
+
+    #[test]
+    fn cidr() {
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        let lab: Cidr = "10.1.2.77/24".parse().unwrap();
+        assert_eq!(lab.to_string(), "10.1.2.0/24");
+        assert!(lab.contains(ip("10.1.2.200")));
+        assert!(lab.contains(ip("::ffff:10.1.2.200")));
+        assert!(!lab.contains(ip("10.1.3.1")));
+        assert!(!lab.contains(ip("2001:db8::1")));
+
+        assert_eq!(
+            "192.0.2.7".parse::<Cidr>().unwrap().to_string(),
+            "192.0.2.7/32"
+        );
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(ip("8.8.8.8")));
+        assert!("2001:db8::/32"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains(ip("2001:db8:ffff::1")));
+        assert_eq!(
+            "10.1.2.0/33".parse::<Cidr>(),
+            Err(CidrError::Prefix("10.1.2.0/33".to_string()))
+        );
+        assert!(matches!(
+            "lab/24".parse::<Cidr>(),
+            Err(CidrError::Address(_))
+        ));
+    }
+
+    #[test]
+    fn cidr_set() {
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        let set: CidrSet = ["10.1.2.0/24", "10.9.0.0/16", "192.0.2.7", "2001:db8::/32"]
+            .iter()
+            .map(|cidr| cidr.parse::<Cidr>().unwrap())
+            .collect();
+        assert!(set.contains(ip("10.1.2.1")));
+        assert!(set.contains(ip("10.9.200.1")));
+        assert!(set.contains(ip("::ffff:192.0.2.7")));
+        assert!(set.contains(ip("2001:db8::1")));
+        assert!(!set.contains(ip("192.0.2.8")));
+        assert!(!set.contains(ip("10.1.3.1")));
+        assert!(!set.contains(ip("2001:db9::1")));
+        assert!(CidrSet::default().is_empty());
+    }
+
+    #[test]
+    fn ipv4_mapped() {
+        let canonical = |addr: &str| canonical_peer(addr.parse().unwrap()).to_string();
+        assert_eq!(canonical("[::ffff:192.0.2.7]:4000"), "192.0.2.7:4000");
+        assert_eq!(canonical("192.0.2.7:4000"), "192.0.2.7:4000");
+        assert_eq!(canonical("[2001:db8::1]:4000"), "[2001:db8::1]:4000");
+        // IPv4-compatible addresses are deprecated, and not the same host
+        assert_eq!(canonical("[::192.0.2.7]:4000"), "[::c000:207]:4000");
+        assert_eq!(
+            canonical_peer(SocketAddr::from(([0, 0, 0, 0, 0, 0xffff, 0x7f00, 1], 1))),
+            SocketAddr::from(([127, 0, 0, 1], 1))
+        );
+    }
+
+    #[cfg(all(feature = "tcp-info", target_os = "linux"))]
+    #[test]
+    fn tcp_info() {
+        use std::{
+            io::Write,
+            net::{TcpListener, TcpStream},
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        stream.write_all(b"hello").unwrap();
+        let info = super::tcp_info(&stream).unwrap();
+        assert!(info.cwnd > 0);
+        assert!(info.mss > 0);
+        assert_eq!(info.retransmits, 0);
+    }
+}
@@ -27,25 +27,39 @@ use std::{
     str::FromStr,
 };
 
-use regex::Regex;
 use thiserror::Error;
 
-use crate::{tlv::TlvType, Tlv};
+use crate::{
+    format::Radix,
+    math::{checked_fact, euclid_div, sat_add_i64},
+    tlv::TlvType,
+    Tlv, TlvError, TlvIterator,
+};
 
 #[derive(Clone, Error, Debug)]
 pub enum OperationError {
     #[error("Unsupported operation {0}")]
     UnsupportedOperation(String),
-    #[error("Could not parse operation")]
-    Parse,
+    #[error("Could not parse operation: unexpected {found} at column {}", .position + 1)]
+    Parse { position: usize, found: String },
+    #[error("Operand {literal} at column {} does not fit in an i8", .position + 1)]
+    Literal { position: usize, literal: String },
     #[error("Not enough data in TLV")]
     NotEnoughData(#[from] TryFromSliceError),
     #[error("Invalid parameter")]
     InvalidParameter(#[from] TryFromIntError),
     #[error("Could not parse integer")]
     ParseIntError(#[from] ParseIntError),
+    #[error("A multiple sum takes from 1 to 255 operands, not {0}")]
+    OperandCount(usize),
+    #[error("Invalid hexadecimal bytes {0:?}")]
+    Hex(String),
+    #[error("Malformed TLV. {0}")]
+    Tlv(#[from] TlvError),
     #[error("Wrong domain")]
     WrongDomain,
+    #[error("The result does not fit in the accumulator")]
+    Overflow,
     #[error("Something wrong")]
     Generic,
 }
@@ -56,6 +70,11 @@ pub struct BinomialOperationData<T1, T2>(T1, T2);
 #[derive(Clone, Debug, PartialEq)]
 pub struct MonomialOperationData<T1>(T1);
 
+/// From 1 to [`MultinomialOperationData::MAX_OPERANDS`] operands, so that they
+/// fit in a TLV.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MultinomialOperationData(Vec<i8>);
+
 impl<T1, T2> BinomialOperationData<T1, T2>
 where
     T1: Into<i8>,
@@ -75,6 +94,14 @@ where
     }
 }
 
+impl MultinomialOperationData {
+    pub const MAX_OPERANDS: usize = u8::MAX as usize;
+
+    pub fn encode(self) -> Vec<u8> {
+        self.0.into_iter().map(|operand| operand as u8).collect()
+    }
+}
+
 impl From<[u8; 2]> for BinomialOperationData<i8, i8> {
     fn from(value: [u8; 2]) -> Self {
         (i8::from_be_bytes([value[0]]), i8::from_be_bytes([value[1]])).into()
@@ -114,6 +141,29 @@ impl From<i8> for MonomialOperationData<i8> {
     }
 }
 
+impl TryFrom<Vec<i8>> for MultinomialOperationData {
+    type Error = OperationError;
+
+    fn try_from(operands: Vec<i8>) -> Result<Self, Self::Error> {
+        match operands.len() {
+            1..=Self::MAX_OPERANDS => Ok(Self(operands)),
+            count => Err(OperationError::OperandCount(count)),
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for MultinomialOperationData {
+    type Error = OperationError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        bytes
+            .iter()
+            .map(|&byte| byte as i8)
+            .collect::<Vec<_>>()
+            .try_into()
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Operation {
     Sum(BinomialOperationData<i8, i8>),
@@ -121,24 +171,167 @@ pub enum Operation {
     Mul(BinomialOperationData<i8, i8>),
     Div(BinomialOperationData<i8, NonZeroI8>),
     Rem(BinomialOperationData<i8, NonZeroI8>),
+    /// Division rounding towards negative infinity, so that the remainder is never negative
+    DivEuclid(BinomialOperationData<i8, NonZeroI8>),
+    RemEuclid(BinomialOperationData<i8, NonZeroI8>),
     Fact(MonomialOperationData<i8>),
+    /// Sum of all the operands
+    SumN(MultinomialOperationData),
+}
+
+/// Description of an operator accepted by the parser, also used to build the help.
+#[derive(Debug, PartialEq, Eq)]
+pub struct OperatorInfo {
+    pub name: &'static str,
+    /// Accepted symbols. The first one is the canonical one.
+    pub symbols: &'static [&'static str],
+    pub example: &'static str,
+    /// Written before all the operands instead of after the first one
+    pub prefix: bool,
 }
 
 impl Operation {
+    pub const OPERATORS: &'static [OperatorInfo] = &[
+        OperatorInfo {
+            name: "Sum",
+            symbols: &["+"],
+            example: "3 + 4",
+            prefix: false,
+        },
+        OperatorInfo {
+            name: "Subtraction",
+            symbols: &["-"],
+            example: "3 - -4",
+            prefix: false,
+        },
+        OperatorInfo {
+            name: "Multiplication",
+            symbols: &["*", "×", "x"],
+            example: "10 * 3",
+            prefix: false,
+        },
+        OperatorInfo {
+            name: "Division",
+            symbols: &["/", "÷"],
+            example: "7 / 2",
+            prefix: false,
+        },
+        OperatorInfo {
+            name: "Remainder",
+            symbols: &["%"],
+            example: "7 % 2",
+            prefix: false,
+        },
+        OperatorInfo {
+            name: "Euclidean division",
+            symbols: &["//"],
+            example: "-7 // 2",
+            prefix: false,
+        },
+        OperatorInfo {
+            name: "Euclidean remainder",
+            symbols: &["mod"],
+            example: "-7 mod 2",
+            prefix: false,
+        },
+        OperatorInfo {
+            name: "Factorial",
+            symbols: &["!"],
+            example: "5!",
+            prefix: false,
+        },
+        OperatorInfo {
+            name: "Multiple sum",
+            symbols: &["sum"],
+            example: "sum 1 2 3 4",
+            prefix: true,
+        },
+    ];
+
+    /// Largest factorial that fits in the accumulator.
+    pub const MAX_FACTORIAL: i8 = 20;
+
+    /// An operation of any kind with random operands, never dividing by zero
+    /// nor asking for factorials that do not fit in the accumulator.
+    pub fn random() -> Self {
+        Self::random_with(Self::MAX_FACTORIAL)
+    }
+
+    /// Like [`Operation::random`], but with factorials of at most
+    /// `max_factorial`, as in [`Operation::reduce_with`].
+    pub fn random_with(max_factorial: i8) -> Self {
+        let a = fastrand::i8(..);
+        let b = fastrand::i8(..);
+        let divisor = NonZeroI8::new(b).unwrap_or(NonZeroI8::MIN);
+        match fastrand::u8(0..9) {
+            0 => Operation::Sum((a, b).into()),
+            1 => Operation::Sub((a, b).into()),
+            2 => Operation::Mul((a, b).into()),
+            3 => Operation::Div((a, divisor).into()),
+            4 => Operation::Rem((a, divisor).into()),
+            5 => Operation::DivEuclid((a, divisor).into()),
+            6 => Operation::RemEuclid((a, divisor).into()),
+            7 => Operation::Fact(fastrand::i8(0..=max_factorial.max(0)).into()),
+            _ => Operation::SumN(MultinomialOperationData(
+                (0..fastrand::usize(1..=4))
+                    .map(|_| fastrand::i8(..))
+                    .collect(),
+            )),
+        }
+    }
+
+    /// Calculates the operation. All the arithmetic is checked and done in i64, so
+    /// no operand can make it panic.
     pub fn reduce(&self) -> Result<i64, OperationError> {
-        Ok(match *self {
-            Operation::Sum(BinomialOperationData(a, b)) => (a as i16 + b as i16).into(),
-            Operation::Sub(BinomialOperationData(a, b)) => (a as i16 - b as i16).into(),
-            Operation::Mul(BinomialOperationData(a, b)) => (a as i16 * b as i16).into(),
-            Operation::Div(BinomialOperationData(a, b)) => (a / b.get()).into(),
-            Operation::Rem(BinomialOperationData(a, b)) => (a % b.get()).into(),
-            Operation::Fact(MonomialOperationData(a)) if a == 0 => 1,
-            Operation::Fact(MonomialOperationData(a)) if a > 0 => (1..=a as i64)
-                .reduce(|acc, e| acc.saturating_mul(e))
-                .unwrap(),
-            _ => return Err(OperationError::WrongDomain),
-        })
+        self.reduce_with(Self::MAX_FACTORIAL)
+    }
+
+    /// Like [`Operation::reduce`], but factorials of numbers above `max_factorial`
+    /// are out of the domain.
+    pub fn reduce_with(&self, max_factorial: i8) -> Result<i64, OperationError> {
+        match *self {
+            Operation::Sum(BinomialOperationData(a, b)) => i64::from(a).checked_add(b.into()),
+            Operation::Sub(BinomialOperationData(a, b)) => i64::from(a).checked_sub(b.into()),
+            Operation::Mul(BinomialOperationData(a, b)) => i64::from(a).checked_mul(b.into()),
+            Operation::Div(BinomialOperationData(a, b)) => i64::from(a).checked_div(b.get().into()),
+            Operation::Rem(BinomialOperationData(a, b)) => i64::from(a).checked_rem(b.get().into()),
+            Operation::DivEuclid(BinomialOperationData(a, b)) => {
+                euclid_div(a.into(), b.get().into()).map(|(quotient, _)| quotient)
+            }
+            Operation::RemEuclid(BinomialOperationData(a, b)) => {
+                euclid_div(a.into(), b.get().into()).map(|(_, remainder)| remainder)
+            }
+            Operation::Fact(MonomialOperationData(a))
+                if (0..=max_factorial.min(Self::MAX_FACTORIAL)).contains(&a) =>
+            {
+                checked_fact(a.into())
+            }
+            Operation::Fact(_) => return Err(OperationError::WrongDomain),
+            // Cannot saturate with at most 255 operands, but the fold stays total
+            Operation::SumN(MultinomialOperationData(ref operands)) => Some(
+                operands
+                    .iter()
+                    .fold(0i64, |acc, &operand| sat_add_i64(acc, operand.into()).0),
+            ),
+        }
+        .ok_or(OperationError::Overflow)
     }
+
+    /// Tag of the TLV of the operation.
+    pub fn tag(&self) -> TlvType {
+        match self {
+            Operation::Sum(_) => TlvType::Sum,
+            Operation::Sub(_) => TlvType::Sub,
+            Operation::Mul(_) => TlvType::Mul,
+            Operation::Div(_) => TlvType::Div,
+            Operation::Rem(_) => TlvType::Rem,
+            Operation::DivEuclid(_) => TlvType::DivEuclid,
+            Operation::RemEuclid(_) => TlvType::RemEuclid,
+            Operation::Fact(_) => TlvType::Fact,
+            Operation::SumN(_) => TlvType::SumN,
+        }
+    }
+
     pub fn encode(self) -> Box<[u8]> {
         match self {
             Operation::Sum(data) => Tlv::new(TlvType::Sum, &data.encode()).unwrap().encode(),
@@ -146,7 +339,98 @@ impl Operation {
             Operation::Mul(data) => Tlv::new(TlvType::Mul, &data.encode()).unwrap().encode(),
             Operation::Div(data) => Tlv::new(TlvType::Div, &data.encode()).unwrap().encode(),
             Operation::Rem(data) => Tlv::new(TlvType::Rem, &data.encode()).unwrap().encode(),
+            Operation::DivEuclid(data) => Tlv::new(TlvType::DivEuclid, &data.encode())
+                .unwrap()
+                .encode(),
+            Operation::RemEuclid(data) => Tlv::new(TlvType::RemEuclid, &data.encode())
+                .unwrap()
+                .encode(),
             Operation::Fact(data) => Tlv::new(TlvType::Fact, &data.encode()).unwrap().encode(),
+            Operation::SumN(data) => Tlv::new(TlvType::SumN, &data.encode()).unwrap().encode(),
+        }
+    }
+
+    /// Encoding of the operation as hexadecimal bytes separated by spaces.
+    pub fn to_hex(&self) -> String {
+        self.clone()
+            .encode()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Decodes an operation written as hexadecimal bytes, with or without spaces
+    /// between them, like the ones [`Operation::to_hex`] returns.
+    pub fn from_hex(hex: &str) -> Result<Self, OperationError> {
+        let digits: Vec<char> = hex.chars().filter(|c| !c.is_whitespace()).collect();
+        let bytes = digits
+            .chunks(2)
+            .map(|pair| {
+                let pair: String = pair.iter().collect();
+                match pair.len() {
+                    2 => u8::from_str_radix(&pair, 16).map_err(|_| OperationError::Hex(pair)),
+                    _ => Err(OperationError::Hex(pair)),
+                }
+            })
+            .collect::<Result<Vec<u8>, _>>()?;
+
+        Operation::try_from(&bytes[..])
+    }
+
+    /// The operation as a command: the name of its tag in upper case followed
+    /// by its operands in decimal, as in `SUM 3 4` or `FACT 5`. Unlike the infix
+    /// notation, every operation has a single spelling.
+    pub fn to_command(&self) -> String {
+        let encoded = self.clone().encode();
+        let operands = encoded[2..].iter().map(|&byte| format!(" {}", byte as i8));
+
+        std::iter::once(self.tag().name().to_uppercase())
+            .chain(operands)
+            .collect()
+    }
+
+    /// Parses a command like those of [`Operation::to_command`], ignoring the
+    /// case of the name.
+    pub fn from_command(s: &str) -> Result<Self, OperationError> {
+        let chars: Vec<(usize, char)> = s.chars().enumerate().collect();
+        let mut tokens = Tokenizer::new(&chars, chars.len(), Radix::Decimal);
+
+        let name = tokens.word()?;
+        let tag = TlvType::try_from(name.as_str())
+            .map_err(|_| OperationError::UnsupportedOperation(name.clone()))?;
+        let arity = match tag {
+            TlvType::Sum
+            | TlvType::Sub
+            | TlvType::Mul
+            | TlvType::Div
+            | TlvType::Rem
+            | TlvType::DivEuclid
+            | TlvType::RemEuclid => Some(2),
+            TlvType::Fact => Some(1),
+            TlvType::SumN => None,
+            _ => return Err(OperationError::UnsupportedOperation(name)),
+        };
+
+        let mut operands = Vec::new();
+        while arity.map_or(!tokens.at_end(), |arity| operands.len() < arity) {
+            operands.push(tokens.operand()? as u8);
+        }
+        tokens.end()?;
+
+        Operation::try_from(Tlv::new(tag, &operands)?)
+    }
+
+    /// Decodes a buffer of consecutive operations, such as the requests of a batch.
+    pub fn decode_all(bytes: &[u8]) -> Result<Vec<Self>, OperationError> {
+        let mut tlvs = TlvIterator::process(bytes);
+        let operations = tlvs
+            .by_ref()
+            .map(Operation::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        match tlvs.error() {
+            Some(e) => Err(e.clone().into()),
+            None => Ok(operations),
         }
     }
 }
@@ -171,14 +455,30 @@ impl<'a> TryFrom<Tlv<'a>> for Operation {
             TlvType::Rem if tlv.length == 2 => {
                 Operation::Rem(<[u8; 2]>::try_from(tlv.data)?.try_into()?)
             }
+            TlvType::DivEuclid if tlv.length == 2 => {
+                Operation::DivEuclid(<[u8; 2]>::try_from(tlv.data)?.try_into()?)
+            }
+            TlvType::RemEuclid if tlv.length == 2 => {
+                Operation::RemEuclid(<[u8; 2]>::try_from(tlv.data)?.try_into()?)
+            }
             TlvType::Fact if tlv.length == 1 => {
                 Operation::Fact(<[u8; 1]>::try_from(tlv.data)?.into())
             }
+            TlvType::SumN => Operation::SumN(tlv.data.try_into()?),
             _ => return Err(OperationError::Generic),
         })
     }
 }
 
+impl TryFrom<&[u8]> for Operation {
+    type Error = OperationError;
+
+    /// Decodes a buffer with a single operation.
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        Operation::try_from(Tlv::whole(bytes)?)
+    }
+}
+
 impl Display for Operation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -187,49 +487,296 @@ impl Display for Operation {
             Operation::Mul(BinomialOperationData(a, b)) => write!(f, "{}×{}", a, b),
             Operation::Div(BinomialOperationData(a, b)) => write!(f, "{}÷{}", a, b),
             Operation::Rem(BinomialOperationData(a, b)) => write!(f, "{}%{}", a, b),
+            Operation::DivEuclid(BinomialOperationData(a, b)) => write!(f, "{}//{}", a, b),
+            Operation::RemEuclid(BinomialOperationData(a, b)) => write!(f, "{} mod {}", a, b),
             Operation::Fact(MonomialOperationData(a)) => write!(f, "{}!", a),
+            Operation::SumN(MultinomialOperationData(operands)) => {
+                write!(f, "sum")?;
+                operands
+                    .iter()
+                    .try_for_each(|operand| write!(f, " {}", operand))
+            }
         }
     }
 }
 
-impl FromStr for Operation {
-    type Err = OperationError;
+/// Optional spellings accepted by [`Operation::parse_with`], so that input pasted
+/// from documents and spreadsheets can be parsed too.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ParserOptions {
+    /// Accept `:` for division, `·` for multiplication and `−` (unicode minus) for subtraction
+    pub alternative_symbols: bool,
+    /// Accept `_` between digits, as in `1_000`
+    pub digit_separators: bool,
+    /// Base of the operands
+    pub radix: Radix,
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let regex = Regex::new(r"^\s*(\-?\d+)\s*([+\-*×x/÷%!])\s*(\-?\d+)?\s*$").unwrap();
-        let elements: Box<_> = match regex.captures(s) {
-            Some(captures) => captures.iter().skip(1).collect(),
-            None => return Err(OperationError::Parse),
-        };
+impl ParserOptions {
+    pub fn lenient() -> Self {
+        Self {
+            alternative_symbols: true,
+            digit_separators: true,
+            radix: Radix::Decimal,
+        }
+    }
+
+    /// Rewrites the accepted alternative spellings into the canonical ones, keeping
+    /// the column of every character in the original input for error reporting.
+    fn normalize(&self, s: &str) -> Vec<(usize, char)> {
+        let mut normalized: Vec<(usize, char)> = Vec::with_capacity(s.len());
+        let mut chars = s.chars().enumerate().peekable();
+
+        while let Some((column, c)) = chars.next() {
+            let c = match c {
+                ':' if self.alternative_symbols => '/',
+                '·' | '⋅' if self.alternative_symbols => '*',
+                '−' if self.alternative_symbols => '-',
+                '_' if self.digit_separators
+                    && normalized
+                        .last()
+                        .is_some_and(|(_, p)| p.is_ascii_hexdigit())
+                    && chars.peek().is_some_and(|(_, n)| n.is_ascii_hexdigit()) =>
+                {
+                    continue
+                }
+                c => c,
+            };
+            normalized.push((column, c));
+        }
+
+        normalized
+    }
+}
 
-        let (a, b) = match elements[..] {
-            [Some(match_a), Some(_), Some(match_b)] => {
-                (match_a.as_str().parse()?, match_b.as_str().parse()?)
+/// Hand-written tokenizer for the infix operations typed by the user.
+struct Tokenizer<'a> {
+    chars: &'a [(usize, char)],
+    index: usize,
+    end: usize,
+    radix: Radix,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(chars: &'a [(usize, char)], end: usize, radix: Radix) -> Self {
+        Self {
+            chars,
+            index: 0,
+            end,
+            radix,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .chars
+            .get(self.index)
+            .is_some_and(|(_, c)| c.is_whitespace())
+        {
+            self.index += 1;
+        }
+    }
+
+    fn unexpected(&self) -> OperationError {
+        match self.chars.get(self.index) {
+            Some(&(position, c)) => OperationError::Parse {
+                position,
+                found: format!("'{c}'"),
+            },
+            None => OperationError::Parse {
+                position: self.end,
+                found: "end of input".to_string(),
+            },
+        }
+    }
+
+    /// An optionally signed integer, in the radix of the tokenizer unless it has
+    /// a `0x`, `0o` or `0b` prefix. The sign must be immediately followed by the
+    /// digits.
+    fn operand(&mut self) -> Result<i8, OperationError> {
+        self.skip_whitespace();
+
+        let start = self.index;
+        let mut literal = String::new();
+        if let Some(&(_, sign @ ('-' | '+'))) = self.chars.get(self.index) {
+            literal.push(sign);
+            self.index += 1;
+        }
+        let prefix = self.prefix();
+        let radix = prefix.unwrap_or(self.radix);
+        let digits = self.index;
+        while let Some(&(_, digit)) = self.chars.get(self.index) {
+            if !digit.is_digit(radix.base()) {
+                break;
             }
-            [Some(match_a), Some(_), None] => (match_a.as_str().parse()?, 0i8),
-            _ => {
-                return Err(OperationError::Parse);
+            literal.push(digit);
+            self.index += 1;
+        }
+
+        if self.index == digits {
+            // A prefix must be followed by digits, and a sign by anything
+            if prefix.is_none() {
+                self.index = start;
             }
-        };
+            return Err(self.unexpected());
+        }
+        i8::from_str_radix(&literal, radix.base()).map_err(|_| OperationError::Literal {
+            position: self.chars[start].0,
+            literal: self.chars[start..self.index]
+                .iter()
+                .map(|&(_, c)| c)
+                .collect(),
+        })
+    }
 
-        let operation = match elements[1].map(|m| m.as_str()) {
-            Some("+") if elements[2].is_some() => Operation::Sum((a, b).into()),
-            Some("-") if elements[2].is_some() => Operation::Sub((a, b).into()),
-            Some("*" | "×" | "x") if elements[2].is_some() => Operation::Mul((a, b).into()),
-            Some("/" | "÷") if elements[2].is_some() => Operation::Div((a, b.try_into()?).into()),
-            Some("%") if elements[2].is_some() => Operation::Rem((a, b.try_into()?).into()),
-            Some("!") if elements[2].is_none() && a >= 0 => Operation::Fact(a.into()),
-            Some(op) => return Err(OperationError::UnsupportedOperation(op.to_string())),
-            None => return Err(OperationError::Parse),
-        };
+    /// Consumes the prefix of a literal in another radix, unless its letter is a
+    /// digit of the radix of the tokenizer, as the `b` of `0b1` in hexadecimal.
+    fn prefix(&mut self) -> Option<Radix> {
+        let radix = [Radix::Hex, Radix::Octal, Radix::Binary]
+            .into_iter()
+            .find(|radix| self.lookahead(radix.prefix()))?;
+        if self
+            .chars
+            .get(self.index + 1)
+            .is_some_and(|(_, c)| c.is_digit(self.radix.base()))
+        {
+            return None;
+        }
+        self.index += radix.prefix().chars().count();
+
+        Some(radix)
+    }
+
+    /// The letters at the current position.
+    fn word(&mut self) -> Result<String, OperationError> {
+        self.skip_whitespace();
+
+        let word: String = self.chars[self.index..]
+            .iter()
+            .map(|&(_, c)| c)
+            .take_while(|c| c.is_alphabetic())
+            .collect();
+        match word.is_empty() {
+            true => Err(self.unexpected()),
+            false => {
+                self.index += word.chars().count();
+                Ok(word)
+            }
+        }
+    }
+
+    /// Whether the input at the current position starts with `symbol`.
+    fn lookahead(&self, symbol: &str) -> bool {
+        let mut rest = self.chars[self.index..].iter().map(|&(_, c)| c);
+        symbol.chars().all(|c| rest.next() == Some(c))
+    }
+
+    /// The longest operator symbol at the current position, in its canonical spelling.
+    fn operator(&mut self) -> Result<&'static str, OperationError> {
+        self.skip_whitespace();
+
+        let found = Operation::OPERATORS
+            .iter()
+            .filter(|info| !info.prefix)
+            .flat_map(|info| info.symbols.iter().map(move |symbol| (info, *symbol)))
+            .filter(|(_, symbol)| self.lookahead(symbol))
+            .max_by_key(|(_, symbol)| symbol.chars().count());
+
+        match found {
+            Some((info, symbol)) => {
+                self.index += symbol.chars().count();
+                Ok(info.symbols[0])
+            }
+            None => Err(self.unexpected()),
+        }
+    }
+
+    /// Consumes `word` if it is at the current position, followed by whitespace
+    /// or the end of the input. Keywords are lower case, so that they are never
+    /// confused with the names of the commands of [`Operation::to_command`].
+    fn keyword(&mut self, word: &str) -> bool {
+        self.skip_whitespace();
+
+        let after = self.index + word.chars().count();
+        let found =
+            self.lookahead(word) && self.chars.get(after).is_none_or(|(_, c)| c.is_whitespace());
+        if found {
+            self.index = after;
+        }
+
+        found
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip_whitespace();
+
+        self.index == self.chars.len()
+    }
+
+    fn end(&mut self) -> Result<(), OperationError> {
+        self.skip_whitespace();
+
+        match self.index < self.chars.len() {
+            true => Err(self.unexpected()),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Operation {
+    /// Parses the operation also accepting the alternative spellings enabled in `options`.
+    pub fn parse_with(s: &str, options: &ParserOptions) -> Result<Self, OperationError> {
+        let chars = options.normalize(s);
+        let mut tokens = Tokenizer::new(&chars, s.chars().count(), options.radix);
+
+        if tokens.keyword("sum") {
+            let mut operands = Vec::new();
+            while !tokens.at_end() {
+                operands.push(tokens.operand()?);
+            }
+            return Ok(Operation::SumN(operands.try_into()?));
+        }
+
+        let a = tokens.operand()?;
+        let operator = tokens.operator()?;
+        if operator == "!" {
+            tokens.end()?;
+            return match a {
+                0..=Self::MAX_FACTORIAL => Ok(Operation::Fact(a.into())),
+                _ => Err(OperationError::WrongDomain),
+            };
+        }
+        let b = tokens.operand()?;
+        tokens.end()?;
+
+        Ok(match operator {
+            "+" => Operation::Sum((a, b).into()),
+            "-" => Operation::Sub((a, b).into()),
+            "*" => Operation::Mul((a, b).into()),
+            "/" => Operation::Div((a, b.try_into()?).into()),
+            "%" => Operation::Rem((a, b.try_into()?).into()),
+            "//" => Operation::DivEuclid((a, b.try_into()?).into()),
+            "mod" => Operation::RemEuclid((a, b.try_into()?).into()),
+            _ => unreachable!("the tokenizer only returns canonical operators"),
+        })
+    }
+}
 
-        Ok(operation)
+impl FromStr for Operation {
+    type Err = OperationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_with(s, &ParserOptions::default())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{Operation, Tlv};
+    use std::num::NonZeroI8;
+
+    use crate::{format::Radix, Operation, ParserOptions, Tlv, TlvError};
+
+    use super::{BinomialOperationData, OperationError};
 
     #[test]
     fn parse_operation_sum() {
@@ -268,6 +815,105 @@ mod tests {
         assert_eq!(res.unwrap(), 1);
     }
 
+    #[test]
+    fn operation_fact_max() {
+        assert_eq!(
+            Operation::Fact(20.into()).reduce().unwrap(),
+            2432902008176640000
+        );
+        assert!(matches!(
+            Operation::Fact(21.into()).reduce(),
+            Err(OperationError::WrongDomain)
+        ));
+        assert!(Operation::Fact(10.into()).reduce_with(10).is_ok());
+        assert!(Operation::Fact(11.into()).reduce_with(10).is_err());
+        assert!(matches!(
+            "21!".parse::<Operation>(),
+            Err(OperationError::WrongDomain)
+        ));
+    }
+
+    #[test]
+    fn sum_n() {
+        let operation: Operation = "sum 1 2 3 -4".parse().unwrap();
+        assert_eq!(operation.reduce().unwrap(), 2);
+        assert_eq!(operation.to_string(), "sum 1 2 3 -4");
+        // The command of a binary sum, not the keyword
+        assert!("SUM 1 2 3 -4".parse::<Operation>().is_err());
+        assert!(matches!(
+            "sum".parse::<Operation>(),
+            Err(OperationError::OperandCount(0))
+        ));
+        assert!(matches!(
+            "sum1 2".parse::<Operation>(),
+            Err(OperationError::Parse { position: 0, .. })
+        ));
+        assert!(Operation::try_from(Tlv::try_from(&[9u8, 0][..]).unwrap()).is_err());
+    }
+
+    #[test]
+    fn sum_n_length_boundary() {
+        let typed = |count: usize| format!("sum{}", " -128".repeat(count));
+
+        // The largest one uses the whole length byte
+        let operation: Operation = typed(255).parse().unwrap();
+        assert_eq!(operation.reduce().unwrap(), 255 * -128);
+        let encoded = operation.clone().encode();
+        assert_eq!(encoded.len(), 257);
+        assert_eq!(encoded[..2], [9, 255]);
+        let decoded = Operation::try_from(Tlv::try_from(&encoded[..]).unwrap()).unwrap();
+        assert_eq!(decoded, operation);
+
+        assert!(matches!(
+            typed(256).parse::<Operation>(),
+            Err(OperationError::OperandCount(256))
+        ));
+    }
+
+    #[test]
+    fn random_operations() {
+        for _ in 0..1000 {
+            let operation = Operation::random();
+            assert!(operation.reduce().is_ok(), "{operation}");
+            assert_eq!(
+                operation.to_string().parse::<Operation>().unwrap(),
+                operation
+            );
+            let operation = Operation::random_with(5);
+            assert!(operation.reduce_with(5).is_ok(), "{operation}");
+        }
+    }
+
+    #[test]
+    fn reduce_whole_operand_space() {
+        for a in i8::MIN..=i8::MAX {
+            assert_eq!(
+                Operation::Fact(a.into()).reduce().is_ok(),
+                (0..=20).contains(&a)
+            );
+            for b in i8::MIN..=i8::MAX {
+                let (wa, wb) = (i64::from(a), i64::from(b));
+                assert_eq!(Operation::Sum((a, b).into()).reduce().unwrap(), wa + wb);
+                assert_eq!(Operation::Sub((a, b).into()).reduce().unwrap(), wa - wb);
+                assert_eq!(Operation::Mul((a, b).into()).reduce().unwrap(), wa * wb);
+                let Ok(nonzero) = NonZeroI8::try_from(b) else {
+                    continue;
+                };
+                let data = BinomialOperationData(a, nonzero);
+                assert_eq!(Operation::Div(data.clone()).reduce().unwrap(), wa / wb);
+                assert_eq!(Operation::Rem(data.clone()).reduce().unwrap(), wa % wb);
+                assert_eq!(
+                    Operation::DivEuclid(data.clone()).reduce().unwrap(),
+                    wa.div_euclid(wb)
+                );
+                assert_eq!(
+                    Operation::RemEuclid(data).reduce().unwrap(),
+                    wa.rem_euclid(wb)
+                );
+            }
+        }
+    }
+
     #[test]
     fn encode_sub() {
         assert_eq!(
@@ -280,4 +926,407 @@ mod tests {
     fn encode_fact() {
         assert_eq!(Operation::Fact((100).into()).encode()[..], [6u8, 1, 100]);
     }
+
+    #[test]
+    fn hex() {
+        let operation: Operation = "3 - -4".parse().unwrap();
+        assert_eq!(operation.to_hex(), "02 02 03 fc");
+        assert_eq!(Operation::from_hex("02 02 03 fc").unwrap(), operation);
+        assert_eq!(Operation::from_hex("020203FC").unwrap(), operation);
+        assert!(matches!(
+            Operation::from_hex("02 02 03"),
+            Err(OperationError::Tlv(_))
+        ));
+        assert!(matches!(
+            Operation::from_hex("02 02 03 fc 00"),
+            Err(OperationError::Tlv(TlvError::Trailing { remaining: 1, .. }))
+        ));
+        assert!(matches!(
+            Operation::from_hex("02 02 03 f"),
+            Err(OperationError::Hex(_))
+        ));
+        assert!(matches!(
+            Operation::from_hex("02 02 03 zz"),
+            Err(OperationError::Hex(_))
+        ));
+    }
+
+    #[test]
+    fn decode_slices() {
+        assert_eq!(
+            Operation::try_from(&[1u8, 2, 3, 4][..]).unwrap(),
+            Operation::Sum((3, 4).into())
+        );
+        assert!(Operation::try_from(&[1u8, 2, 3, 4, 19, 0][..]).is_err());
+
+        let batch = [1u8, 2, 3, 4, 6, 1, 5, 9, 2, 1, 2];
+        assert_eq!(
+            Operation::decode_all(&batch).unwrap(),
+            [
+                Operation::Sum((3, 4).into()),
+                Operation::Fact(5.into()),
+                Operation::SumN(vec![1, 2].try_into().unwrap())
+            ]
+        );
+        assert!(Operation::decode_all(&[]).unwrap().is_empty());
+        assert!(matches!(
+            Operation::decode_all(&batch[..9]),
+            Err(OperationError::Tlv(TlvError::Truncated { offset: 7, .. }))
+        ));
+        assert!(matches!(
+            Operation::decode_all(&[19u8, 0]),
+            Err(OperationError::Generic)
+        ));
+    }
+
+    #[test]
+    fn parse_alternative_symbols() {
+        let options = ParserOptions::lenient();
+        assert_eq!(
+            Operation::parse_with("7 : 2", &options).unwrap(),
+            Operation::Div((7, 2.try_into().unwrap()).into())
+        );
+        assert_eq!(
+            Operation::parse_with("3·4", &options).unwrap(),
+            Operation::Mul((3, 4).into())
+        );
+        assert_eq!(
+            Operation::parse_with("−5 − 1", &options).unwrap(),
+            Operation::Sub((-5, 1).into())
+        );
+        assert!("7 : 2".parse::<Operation>().is_err());
+    }
+
+    #[test]
+    fn parse_digit_separators() {
+        let options = ParserOptions::lenient();
+        assert_eq!(
+            Operation::parse_with("1_00 + 2_7", &options).unwrap(),
+            Operation::Sum((100, 27).into())
+        );
+        assert!(Operation::parse_with("1__00 + 1", &options).is_err());
+        assert!(Operation::parse_with("_1 + 1", &options).is_err());
+        assert!("1_00 + 1".parse::<Operation>().is_err());
+    }
+
+    #[test]
+    fn parse_radix() {
+        let options = |radix| ParserOptions {
+            radix,
+            ..ParserOptions::lenient()
+        };
+        assert_eq!(
+            Operation::parse_with("7f + -a", &options(Radix::Hex)).unwrap(),
+            Operation::Sum((127, -10).into())
+        );
+        assert_eq!(
+            Operation::parse_with("1_01 * 11", &options(Radix::Binary)).unwrap(),
+            Operation::Mul((5, 3).into())
+        );
+        assert_eq!(
+            Operation::parse_with("sum 10 -7", &options(Radix::Octal)).unwrap(),
+            Operation::SumN(vec![8, -7].try_into().unwrap())
+        );
+        assert!(matches!(
+            Operation::parse_with("1 + 2", &options(Radix::Binary)),
+            Err(OperationError::Parse { position: 4, .. })
+        ));
+        assert!(Operation::parse_with("80 + 1", &options(Radix::Hex)).is_err());
+    }
+
+    #[test]
+    fn parse_prefixed_literals() {
+        assert_eq!(
+            "0x7f + 0b1010".parse::<Operation>().unwrap(),
+            Operation::Sum((127, 10).into())
+        );
+        assert_eq!(
+            "-0x80 * 0o17".parse::<Operation>().unwrap(),
+            Operation::Mul((-128, 15).into())
+        );
+        assert_eq!(
+            Operation::parse_with("0b1_0000 - 0x1_0", &ParserOptions::lenient()).unwrap(),
+            Operation::Sub((16, 16).into())
+        );
+        assert_eq!(
+            Operation::parse_with(
+                "0b + 0x1",
+                &ParserOptions {
+                    radix: Radix::Hex,
+                    ..ParserOptions::lenient()
+                }
+            )
+            .unwrap(),
+            Operation::Sum((11, 1).into())
+        );
+        assert!(matches!(
+            "3 + 0x80".parse::<Operation>(),
+            Err(OperationError::Literal { position: 4, literal }) if literal == "0x80"
+        ));
+        assert!(matches!(
+            "200 + 1".parse::<Operation>(),
+            Err(OperationError::Literal { position: 0, .. })
+        ));
+        assert!(matches!(
+            "0b12 + 1".parse::<Operation>(),
+            Err(OperationError::Parse { position: 3, .. })
+        ));
+        assert!(matches!(
+            "0x + 1".parse::<Operation>(),
+            Err(OperationError::Parse { position: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn command_round_trip() {
+        let binomials: [fn(i8, i8) -> Option<Operation>; 7] = [
+            |a, b| Some(Operation::Sum((a, b).into())),
+            |a, b| Some(Operation::Sub((a, b).into())),
+            |a, b| Some(Operation::Mul((a, b).into())),
+            |a, b| Some(Operation::Div((a, NonZeroI8::new(b)?).into())),
+            |a, b| Some(Operation::Rem((a, NonZeroI8::new(b)?).into())),
+            |a, b| Some(Operation::DivEuclid((a, NonZeroI8::new(b)?).into())),
+            |a, b| Some(Operation::RemEuclid((a, NonZeroI8::new(b)?).into())),
+        ];
+        let operations = (i8::MIN..=i8::MAX)
+            .flat_map(|a| (i8::MIN..=i8::MAX).map(move |b| (a, b)))
+            .flat_map(|(a, b)| binomials.iter().filter_map(move |new| new(a, b)))
+            .chain((i8::MIN..=i8::MAX).map(|a| Operation::Fact(a.into())))
+            .chain((1..=255).map(|n| {
+                let operands = (0..n)
+                    .map(|i| (i * 37 % 256) as u8 as i8)
+                    .collect::<Vec<_>>();
+                Operation::SumN(operands.try_into().unwrap())
+            }));
+        for operation in operations {
+            let command = operation.to_command();
+            assert_eq!(
+                Operation::from_command(&command).unwrap(),
+                operation,
+                "{command}"
+            );
+        }
+
+        assert_eq!(Operation::Sum((3, 4).into()).to_command(), "SUM 3 4");
+        assert_eq!(Operation::Fact(5.into()).to_command(), "FACT 5");
+        assert_eq!(
+            Operation::SumN(vec![1, -2, 3].try_into().unwrap()).to_command(),
+            "SUMN 1 -2 3"
+        );
+        assert_eq!(
+            Operation::from_command("  diveuclid -7   2 ").unwrap(),
+            Operation::DivEuclid((-7, 2.try_into().unwrap()).into())
+        );
+    }
+
+    #[test]
+    fn command_errors() {
+        assert!(matches!(
+            Operation::from_command("POW 2 3"),
+            Err(OperationError::UnsupportedOperation(name)) if name == "POW"
+        ));
+        assert!(matches!(
+            Operation::from_command("PING 1"),
+            Err(OperationError::UnsupportedOperation(_))
+        ));
+        assert!(matches!(
+            Operation::from_command("3 + 4"),
+            Err(OperationError::Parse { position: 0, .. })
+        ));
+        assert!(matches!(
+            Operation::from_command("SUM 3"),
+            Err(OperationError::Parse { position: 5, found }) if found == "end of input"
+        ));
+        assert!(matches!(
+            Operation::from_command("FACT 5 6"),
+            Err(OperationError::Parse { position: 7, .. })
+        ));
+        assert!(matches!(
+            Operation::from_command("SUM 3 200"),
+            Err(OperationError::Literal { position: 6, .. })
+        ));
+        assert!(Operation::from_command("DIV 3 0").is_err());
+        assert!(matches!(
+            Operation::from_command("SUMN"),
+            Err(OperationError::OperandCount(0))
+        ));
+    }
+
+    #[test]
+    fn parse_signed_operands() {
+        assert_eq!(
+            "3 - -4".parse::<Operation>().unwrap(),
+            Operation::Sub((3, -4).into())
+        );
+        assert_eq!(
+            "3--4".parse::<Operation>().unwrap(),
+            Operation::Sub((3, -4).into())
+        );
+        assert_eq!(
+            "-3 + +4".parse::<Operation>().unwrap(),
+            Operation::Sum((-3, 4).into())
+        );
+        assert_eq!(
+            " 5 ! ".parse::<Operation>().unwrap(),
+            Operation::Fact(5.into())
+        );
+        assert!(matches!(
+            "-5!".parse::<Operation>(),
+            Err(OperationError::WrongDomain)
+        ));
+    }
+
+    #[test]
+    fn parse_error_position() {
+        assert!(matches!(
+            "3 $ 4".parse::<Operation>(),
+            Err(OperationError::Parse { position: 2, found }) if found == "'$'"
+        ));
+        assert!(matches!(
+            "3 - - 4".parse::<Operation>(),
+            Err(OperationError::Parse { position: 4, .. })
+        ));
+        assert!(matches!(
+            "10 +".parse::<Operation>(),
+            Err(OperationError::Parse { position: 4, found }) if found == "end of input"
+        ));
+        assert!(matches!(
+            "5! 3".parse::<Operation>(),
+            Err(OperationError::Parse { position: 3, .. })
+        ));
+        assert!(matches!(
+            Operation::parse_with("1_0 − x", &ParserOptions::lenient()),
+            Err(OperationError::Parse { position: 6, .. })
+        ));
+    }
+
+    #[test]
+    fn euclidean_division() {
+        assert_eq!(
+            "-7 // 2".parse::<Operation>().unwrap(),
+            Operation::DivEuclid((-7, 2.try_into().unwrap()).into())
+        );
+        assert_eq!(
+            "-7 mod -2".parse::<Operation>().unwrap(),
+            Operation::RemEuclid((-7, (-2).try_into().unwrap()).into())
+        );
+        assert!("7 // 0".parse::<Operation>().is_err());
+
+        let reduce = |s: &str| s.parse::<Operation>().unwrap().reduce().unwrap();
+        assert_eq!(reduce("-7 / 2"), -3);
+        assert_eq!(reduce("-7 % 2"), -1);
+        assert_eq!(reduce("-7 // 2"), -4);
+        assert_eq!(reduce("-7 mod 2"), 1);
+        assert_eq!(reduce("-7 // -2"), 4);
+        assert_eq!(reduce("-7 mod -2"), 1);
+        assert_eq!(reduce("7 // -2"), -3);
+        assert_eq!(reduce("7 mod -2"), 1);
+        assert_eq!(reduce("-128 // -1"), 128);
+    }
+
+    #[test]
+    fn encode_euclidean() {
+        let operation = Operation::RemEuclid((-7, 2.try_into().unwrap()).into());
+        let encoded = operation.clone().encode();
+        assert_eq!(encoded[..], [8u8, 2, 249, 2]);
+        assert_eq!(
+            Operation::try_from(Tlv::try_from(&encoded[..]).unwrap()).unwrap(),
+            operation
+        );
+    }
+
+    #[test]
+    fn operator_examples_parse() {
+        for info in Operation::OPERATORS {
+            assert!(info.example.parse::<Operation>().is_ok(), "{}", info.name);
+        }
+    }
+}
+
+/// Compares [`Operation::reduce_with`] with a plain i128 evaluation for every
+/// pair of operands, so that any change to the overflow rules is deliberate.
+#[cfg(test)]
+mod differential {
+    use std::num::NonZeroI8;
+
+    use super::{Operation, OperationError};
+
+    /// How an operation is built from its operands, and its reference result.
+    type Case<B> = (fn((i8, B)) -> Operation, fn(i128, i128) -> i128);
+
+    /// What the answer should be: the exact result if it fits in the i64
+    /// accumulator, an overflow otherwise.
+    fn expected(result: i128) -> Result<i64, ()> {
+        i64::try_from(result).map_err(|_| ())
+    }
+
+    fn actual(operation: &Operation, max_factorial: i8) -> Result<i64, ()> {
+        operation.reduce_with(max_factorial).map_err(|e| {
+            assert!(matches!(e, OperationError::Overflow), "{operation}: {e}");
+        })
+    }
+
+    #[test]
+    fn binary_operations() {
+        let binary: [Case<i8>; 3] = [
+            (|ab| Operation::Sum(ab.into()), |a, b| a + b),
+            (|ab| Operation::Sub(ab.into()), |a, b| a - b),
+            (|ab| Operation::Mul(ab.into()), |a, b| a * b),
+        ];
+        let division: [Case<NonZeroI8>; 4] = [
+            (|ab| Operation::Div(ab.into()), |a, b| a / b),
+            (|ab| Operation::Rem(ab.into()), |a, b| a % b),
+            (|ab| Operation::DivEuclid(ab.into()), i128::div_euclid),
+            (|ab| Operation::RemEuclid(ab.into()), i128::rem_euclid),
+        ];
+
+        for a in i8::MIN..=i8::MAX {
+            for b in i8::MIN..=i8::MAX {
+                let (wide_a, wide_b) = (i128::from(a), i128::from(b));
+                for (operation, reference) in binary {
+                    let operation = operation((a, b));
+                    assert_eq!(
+                        actual(&operation, Operation::MAX_FACTORIAL),
+                        expected(reference(wide_a, wide_b)),
+                        "{operation}"
+                    );
+                }
+                // Division by zero cannot even be represented
+                let Some(b) = NonZeroI8::new(b) else {
+                    continue;
+                };
+                for (operation, reference) in division {
+                    let operation = operation((a, b));
+                    assert_eq!(
+                        actual(&operation, Operation::MAX_FACTORIAL),
+                        expected(reference(wide_a, wide_b)),
+                        "{operation}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn factorial() {
+        for a in i8::MIN..=i8::MAX {
+            for max_factorial in i8::MIN..=i8::MAX {
+                let operation = Operation::Fact(a.into());
+                let result = operation.reduce_with(max_factorial);
+                if a < 0 || a > max_factorial.min(Operation::MAX_FACTORIAL) {
+                    assert!(
+                        matches!(result, Err(OperationError::WrongDomain)),
+                        "{operation} with max {max_factorial}"
+                    );
+                } else {
+                    let reference = (1..=i128::from(a)).product();
+                    assert_eq!(
+                        result.map_err(|_| ()),
+                        expected(reference),
+                        "{operation} with max {max_factorial}"
+                    );
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,279 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Variants of the protocol used in some editions of the course, that differ in
+//! how the answers are encoded. Everything else is the same.
+
+use std::{borrow::Cow, fmt, str::FromStr};
+
+use thiserror::Error;
+
+use crate::{Answer, AnswerBatch, ChannelFrame, Rejection, TCPLibError, Tlv, TlvIterator, TlvType};
+
+/// How the accumulator is encoded in the answers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Profile {
+    /// Big-endian i64, as specified
+    #[default]
+    Classic,
+    /// Little-endian i32, saturating the accumulator at its bounds
+    LE32,
+}
+
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+#[error("Unknown profile {0:?}, expected classic or le32")]
+pub struct ProfileError(String);
+
+impl Profile {
+    pub fn encode_answer(self, answer: Answer) -> Box<[u8]> {
+        match self {
+            Profile::Classic => answer.encode(),
+            Profile::LE32 => {
+                let (value, overflow) = saturate(answer);
+                let mut data = value.to_le_bytes().to_vec();
+                if overflow {
+                    data.push(Answer::OVERFLOW_FLAG);
+                }
+
+                Tlv::new(TlvType::Numi64, &data).unwrap().encode()
+            }
+        }
+    }
+
+    pub fn decode_answer(self, tlv: Tlv) -> Result<Answer, TCPLibError> {
+        match (self, tlv.tag, tlv.length) {
+            (Profile::Classic, _, _) => Answer::try_from(tlv),
+            (Profile::LE32, TlvType::Numi64, 4 | 5) => Ok(Answer {
+                value: i32::from_le_bytes(tlv.data[..4].try_into()?).into(),
+                overflow: tlv
+                    .data
+                    .get(4)
+                    .is_some_and(|flags| flags & Answer::OVERFLOW_FLAG != 0),
+            }),
+            _ => Err(TCPLibError::Generic),
+        }
+    }
+
+    /// Encodes the results of a batch in as many [`AnswerBatch`] frames as
+    /// needed. In `le32` every result takes 5 bytes: a little-endian i32,
+    /// saturated like the answers, and the flags byte.
+    pub fn encode_batch(self, results: &[Result<Answer, Rejection>]) -> Box<[u8]> {
+        match self {
+            Profile::Classic => AnswerBatch(results.to_vec()).encode(),
+            Profile::LE32 => {
+                let mut bytes = Vec::new();
+                for results in results.chunks(AnswerBatch::MAX_RESULTS) {
+                    bytes.extend([TlvType::AnswerBatch as u8, (results.len() * 5) as u8]);
+                    for result in results {
+                        let (value, flags) = match result {
+                            Ok(answer) => match saturate(*answer) {
+                                (value, true) => (value, Answer::OVERFLOW_FLAG),
+                                (value, false) => (value, 0),
+                            },
+                            Err(rejection) => (*rejection as i32, AnswerBatch::REJECTION_FLAG),
+                        };
+                        bytes.extend(value.to_le_bytes());
+                        bytes.push(flags);
+                    }
+                }
+                if results.is_empty() {
+                    bytes.extend([TlvType::AnswerBatch as u8, 0]);
+                }
+                bytes.into()
+            }
+        }
+    }
+
+    pub fn decode_batch(self, tlv: Tlv) -> Result<Vec<Result<Answer, Rejection>>, TCPLibError> {
+        match self {
+            Profile::Classic => Ok(AnswerBatch::try_from(tlv)?.0),
+            Profile::LE32 => {
+                if tlv.tag != TlvType::AnswerBatch || !tlv.data.len().is_multiple_of(5) {
+                    return Err(TCPLibError::Generic);
+                }
+                tlv.data
+                    .chunks(5)
+                    .map(|entry| {
+                        let value = i32::from_le_bytes(entry[..4].try_into()?);
+                        let flags = entry[4];
+                        if flags & AnswerBatch::REJECTION_FLAG == 0 {
+                            return Ok(Ok(Answer {
+                                value: value.into(),
+                                overflow: flags & Answer::OVERFLOW_FLAG != 0,
+                            }));
+                        }
+                        let reason = u8::try_from(value).map_err(|_| TCPLibError::Generic)?;
+                        Ok(Err(Rejection::try_from(Tlv {
+                            tag: TlvType::Rejection,
+                            length: 1,
+                            data: &[reason],
+                        })?))
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Encodes in this profile the answers in whole `frames` written in the
+    /// classic one: plain answers, batches and those of channels. Any other
+    /// frame is left as it is.
+    pub fn transcode(self, frames: &[u8]) -> Cow<'_, [u8]> {
+        if self == Profile::Classic {
+            return Cow::Borrowed(frames);
+        }
+        let mut tlvs = TlvIterator::process(frames);
+        let mut transcoded = Vec::with_capacity(frames.len());
+        for tlv in tlvs.by_ref() {
+            self.transcode_into(tlv, &mut transcoded);
+        }
+        match tlvs.error() {
+            // Not whole frames, so not for us to change
+            Some(_) => Cow::Borrowed(frames),
+            None => Cow::Owned(transcoded),
+        }
+    }
+
+    fn transcode_into(self, tlv: Tlv, out: &mut Vec<u8>) {
+        match tlv.tag {
+            TlvType::Numi64 => match Answer::try_from(tlv) {
+                Ok(answer) => out.extend_from_slice(&self.encode_answer(answer)),
+                Err(_) => out.extend_from_slice(&tlv.encode()),
+            },
+            TlvType::AnswerBatch => match AnswerBatch::try_from(tlv) {
+                Ok(AnswerBatch(results)) => out.extend_from_slice(&self.encode_batch(&results)),
+                Err(_) => out.extend_from_slice(&tlv.encode()),
+            },
+            TlvType::Channel => match ChannelFrame::try_from(tlv) {
+                Ok(ChannelFrame { channel, frame }) => {
+                    let mut inner = Vec::new();
+                    self.transcode_into(frame, &mut inner);
+                    // Never longer than the classic frame
+                    let frame = Tlv::whole(&inner).expect("transcoding keeps whole frames");
+                    let reply = ChannelFrame { channel, frame }.encode();
+                    out.extend_from_slice(&reply.expect("transcoding never grows a frame"));
+                }
+                Err(_) => out.extend_from_slice(&tlv.encode()),
+            },
+            _ => out.extend_from_slice(&tlv.encode()),
+        }
+    }
+}
+
+/// The value of `answer` as an i32 and whether it overflowed, saturated at the
+/// bounds of the i32.
+fn saturate(answer: Answer) -> (i32, bool) {
+    match i32::try_from(answer.value) {
+        Ok(value) => (value, answer.overflow),
+        Err(_) if answer.value < 0 => (i32::MIN, true),
+        Err(_) => (i32::MAX, true),
+    }
+}
+
+impl FromStr for Profile {
+    type Err = ProfileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "classic" => Ok(Profile::Classic),
+            "le32" => Ok(Profile::LE32),
+            _ => Err(ProfileError(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Profile::Classic => write!(f, "classic"),
+            Profile::LE32 => write!(f, "le32"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Profile;
+    use crate::{Answer, AnswerBatch, ChannelFrame, Rejection, Tlv, TlvIterator};
+
+    #[test]
+    fn le32() {
+        let encoded = Profile::LE32.encode_answer(Answer::from(-2));
+        assert_eq!(&encoded[..], [16, 4, 0xfe, 0xff, 0xff, 0xff]);
+        let tlv = Tlv::whole(&encoded).unwrap();
+        assert_eq!(Profile::LE32.decode_answer(tlv).unwrap(), Answer::from(-2));
+        assert!(Profile::Classic.decode_answer(tlv).is_err());
+
+        let encoded = Profile::LE32.encode_answer(Answer::from(i64::from(i32::MAX) + 1));
+        let tlv = Tlv::whole(&encoded).unwrap();
+        assert_eq!(
+            Profile::LE32.decode_answer(tlv).unwrap(),
+            Answer::saturated(i32::MAX.into())
+        );
+    }
+
+    #[test]
+    fn transcode() {
+        let classic = Answer::from(7).encode();
+        assert_eq!(Profile::Classic.transcode(&classic), &classic[..]);
+        assert_eq!(
+            Profile::LE32.transcode(&classic),
+            &Profile::LE32.encode_answer(Answer::from(7))[..]
+        );
+        let bye = crate::Bye.encode();
+        assert_eq!(Profile::LE32.transcode(&bye), &bye[..]);
+        // Every answer of several frames, also in batches and channels
+        let results = [Ok(Answer::from(i64::MAX)), Err(Rejection::WrongDomain)];
+        let inner = Answer::from(-2).encode();
+        let channel = ChannelFrame {
+            channel: 3,
+            frame: Tlv::whole(&inner).unwrap(),
+        };
+        let frames = [
+            AnswerBatch(results.to_vec()).encode(),
+            channel.encode().unwrap(),
+            bye.clone(),
+        ]
+        .concat();
+        let transcoded = Profile::LE32.transcode(&frames);
+        let mut tlvs = TlvIterator::process(&transcoded);
+        let batch = Profile::LE32.decode_batch(tlvs.next().unwrap()).unwrap();
+        assert_eq!(
+            batch,
+            [
+                Ok(Answer::saturated(i32::MAX.into())),
+                Err(Rejection::WrongDomain)
+            ]
+        );
+        let channel = ChannelFrame::try_from(tlvs.next().unwrap()).unwrap();
+        assert_eq!(channel.channel, 3);
+        assert_eq!(
+            Profile::LE32.decode_answer(channel.frame).unwrap(),
+            Answer::from(-2)
+        );
+        assert_eq!(tlvs.next().unwrap().encode(), bye);
+        assert!(tlvs.next().is_none());
+        // A frame cut short is left alone
+        assert_eq!(Profile::LE32.transcode(&frames[..4]), &frames[..4]);
+        assert_eq!("LE32".parse(), Ok(Profile::LE32));
+        assert!("be16".parse::<Profile>().is_err());
+    }
+}
@@ -0,0 +1,292 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Minimal SOCKS5 (RFC 1928) and HTTP CONNECT clients, just enough to reach
+//! the server from networks where direct connections are not allowed.
+
+use std::{
+    fmt::Display,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    str::FromStr,
+    time::Duration,
+};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ProxyError {
+    #[error("Could not talk to the proxy")]
+    Io(#[from] io::Error),
+    #[error("Invalid proxy URL {0}. Use socks5://host:port or http://host:port")]
+    InvalidUrl(String),
+    #[error("The proxy does not accept unauthenticated connections")]
+    AuthenticationRequired,
+    #[error("The proxy refused the connection (code {0})")]
+    Refused(u16),
+    #[error("Unexpected answer from the proxy")]
+    Protocol,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProxyKind {
+    Socks5,
+    HttpConnect,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Proxy {
+    pub kind: ProxyKind,
+    pub address: String,
+}
+
+impl FromStr for Proxy {
+    type Err = ProxyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, address) = match s.split_once("://") {
+            Some(("socks5" | "socks5h", address)) => (ProxyKind::Socks5, address),
+            Some(("http", address)) => (ProxyKind::HttpConnect, address),
+            _ => return Err(ProxyError::InvalidUrl(s.to_string())),
+        };
+
+        let address = address.trim_end_matches('/');
+        match address.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(Self {
+                kind,
+                address: address.to_string(),
+            }),
+            _ => Err(ProxyError::InvalidUrl(s.to_string())),
+        }
+    }
+}
+
+impl Display for Proxy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            ProxyKind::Socks5 => write!(f, "socks5://{}", self.address),
+            ProxyKind::HttpConnect => write!(f, "http://{}", self.address),
+        }
+    }
+}
+
+impl Proxy {
+    /// Opens a TCP connection to `target` tunneled through the proxy.
+    pub fn connect(&self, target: SocketAddr) -> Result<TcpStream, ProxyError> {
+        let mut stream = TcpStream::connect(self.address.as_str())?;
+        self.handshake(&mut stream, target)?;
+
+        Ok(stream)
+    }
+
+    /// Like [`Proxy::connect`], giving up if the proxy does not accept the
+    /// connection, or does not finish the handshake, within `timeout`.
+    pub fn connect_timeout(
+        &self,
+        target: SocketAddr,
+        timeout: Duration,
+    ) -> Result<TcpStream, ProxyError> {
+        let mut failure = io::Error::from(io::ErrorKind::NotFound);
+        for address in self.address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, timeout) {
+                Ok(mut stream) => {
+                    stream.set_read_timeout(Some(timeout))?;
+                    stream.set_write_timeout(Some(timeout))?;
+                    self.handshake(&mut stream, target)?;
+                    stream.set_read_timeout(None)?;
+                    stream.set_write_timeout(None)?;
+                    return Ok(stream);
+                }
+                Err(e) => failure = e,
+            }
+        }
+
+        Err(failure.into())
+    }
+
+    fn handshake(&self, stream: &mut TcpStream, target: SocketAddr) -> Result<(), ProxyError> {
+        match self.kind {
+            ProxyKind::Socks5 => socks5_handshake(stream, target),
+            ProxyKind::HttpConnect => http_connect_handshake(stream, target),
+        }
+    }
+}
+
+fn socks5_handshake<S: Read + Write>(stream: &mut S, target: SocketAddr) -> Result<(), ProxyError> {
+    // Greeting: version 5, one method, "no authentication"
+    stream.write_all(&[5, 1, 0])?;
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice)?;
+    match choice {
+        [5, 0] => (),
+        [5, _] => return Err(ProxyError::AuthenticationRequired),
+        _ => return Err(ProxyError::Protocol),
+    }
+
+    let mut request = vec![5, 1, 0]; // CONNECT
+    match target {
+        SocketAddr::V4(addr) => {
+            request.push(1);
+            request.extend(addr.ip().octets());
+        }
+        SocketAddr::V6(addr) => {
+            request.push(4);
+            request.extend(addr.ip().octets());
+        }
+    }
+    request.extend(target.port().to_be_bytes());
+    stream.write_all(&request)?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply)?;
+    match reply {
+        [5, 0, _, _] => (),
+        [5, code, _, _] => return Err(ProxyError::Refused(code.into())),
+        _ => return Err(ProxyError::Protocol),
+    }
+
+    // Skip the bound address, we have no use for it
+    let bound_length = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => {
+            let mut length = [0u8; 1];
+            stream.read_exact(&mut length)?;
+            length[0].into()
+        }
+        _ => return Err(ProxyError::Protocol),
+    };
+    let mut bound = vec![0u8; bound_length + 2];
+    stream.read_exact(&mut bound)?;
+
+    Ok(())
+}
+
+fn http_connect_handshake<S: Read + Write>(
+    stream: &mut S,
+    target: SocketAddr,
+) -> Result<(), ProxyError> {
+    write!(
+        stream,
+        "CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n\r\n"
+    )?;
+
+    // Read the response byte by byte so we do not consume any TLV data
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > 8192 {
+            return Err(ProxyError::Protocol);
+        }
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte)?;
+        response.push(byte[0]);
+    }
+
+    let status_line = String::from_utf8_lossy(&response);
+    match status_line.split_whitespace().nth(1).map(str::parse::<u16>) {
+        Some(Ok(200..=299)) => Ok(()),
+        Some(Ok(code)) => Err(ProxyError::Refused(code)),
+        _ => Err(ProxyError::Protocol),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use super::{Proxy, ProxyKind};
+
+    #[test]
+    fn parse_proxy_url() {
+        assert_eq!(
+            "socks5://proxy.example.org:1080".parse::<Proxy>().unwrap(),
+            Proxy {
+                kind: ProxyKind::Socks5,
+                address: "proxy.example.org:1080".to_string()
+            }
+        );
+        assert_eq!(
+            "http://[::1]:3128/".parse::<Proxy>().unwrap(),
+            Proxy {
+                kind: ProxyKind::HttpConnect,
+                address: "[::1]:3128".to_string()
+            }
+        );
+        assert!("ftp://proxy:21".parse::<Proxy>().is_err());
+        assert!("socks5://proxy".parse::<Proxy>().is_err());
+    }
+
+    #[test]
+    fn socks5_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = Proxy {
+            kind: ProxyKind::Socks5,
+            address: listener.local_addr().unwrap().to_string(),
+        };
+
+        let fake_proxy = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            assert_eq!(greeting, [5, 1, 0]);
+            stream.write_all(&[5, 0]).unwrap();
+
+            let mut request = [0u8; 10];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(request, [5, 1, 0, 1, 10, 0, 0, 1, 0x1f, 0x90]);
+            stream.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+            stream.write_all(&[16, 8]).unwrap();
+        });
+
+        let mut stream = proxy.connect("10.0.0.1:8080".parse().unwrap()).unwrap();
+        let mut data = [0u8; 2];
+        stream.read_exact(&mut data).unwrap();
+        assert_eq!(data, [16, 8]);
+        fake_proxy.join().unwrap();
+    }
+
+    #[test]
+    fn http_connect_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = Proxy {
+            kind: ProxyKind::HttpConnect,
+            address: listener.local_addr().unwrap().to_string(),
+        };
+
+        let fake_proxy = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 16];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(&request, b"CONNECT 10.0.0.1");
+            stream
+                .write_all(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+        });
+
+        assert!(proxy.connect("10.0.0.1:8080".parse().unwrap()).is_err());
+        fake_proxy.join().unwrap();
+    }
+}
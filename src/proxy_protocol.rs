@@ -0,0 +1,247 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Parsing of the HAProxy PROXY protocol header (versions 1 and 2).
+//!
+//! When the server sits behind a TCP load balancer, the balancer prepends
+//! this header to the stream so that the address of the real client is not
+//! lost. See <https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt>.
+
+use std::{
+    io::{self, Read},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use thiserror::Error;
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LENGTH: usize = 107;
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+#[derive(Error, Debug)]
+pub enum ProxyHeaderError {
+    #[error("Could not read PROXY header")]
+    Io(#[from] io::Error),
+    #[error("Missing PROXY header")]
+    Missing,
+    #[error("Malformed PROXY header")]
+    Malformed,
+    #[error("Unsupported PROXY protocol version {0}")]
+    UnsupportedVersion(u8),
+}
+
+/// Addresses conveyed by a PROXY header.
+///
+/// Both addresses are `None` for `UNKNOWN` (v1) or `LOCAL` (v2) connections,
+/// in which case the address of the socket itself should be used.
+#[derive(Debug, PartialEq, Eq, Default)]
+pub struct ProxyHeader {
+    pub source: Option<SocketAddr>,
+    pub destination: Option<SocketAddr>,
+}
+
+impl ProxyHeader {
+    /// Reads a PROXY header from the start of the stream.
+    ///
+    /// Only the bytes belonging to the header are consumed, so the TLV stream
+    /// can be read afterwards from the same reader.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self, ProxyHeaderError> {
+        let mut start = [0u8; 8];
+        reader.read_exact(&mut start)?;
+
+        if start.starts_with(V1_PREFIX) {
+            let mut line = start.to_vec();
+            while !line.ends_with(b"\r\n") {
+                if line.len() >= V1_MAX_LENGTH {
+                    return Err(ProxyHeaderError::Malformed);
+                }
+                let mut byte = [0u8; 1];
+                reader.read_exact(&mut byte)?;
+                line.push(byte[0]);
+            }
+            Self::parse_v1(&line)
+        } else if start == V2_SIGNATURE[..8] {
+            let mut rest = [0u8; 8];
+            reader.read_exact(&mut rest)?;
+            if rest[..4] != V2_SIGNATURE[8..] {
+                return Err(ProxyHeaderError::Missing);
+            }
+            let mut addresses = vec![0u8; u16::from_be_bytes([rest[6], rest[7]]).into()];
+            reader.read_exact(&mut addresses)?;
+            Self::parse_v2(rest[4], rest[5], &addresses)
+        } else {
+            Err(ProxyHeaderError::Missing)
+        }
+    }
+
+    fn parse_v1(line: &[u8]) -> Result<Self, ProxyHeaderError> {
+        let line = std::str::from_utf8(line).map_err(|_| ProxyHeaderError::Malformed)?;
+        let fields: Box<[&str]> = line.trim_end_matches("\r\n").split(' ').collect();
+
+        match fields[..] {
+            ["PROXY", "UNKNOWN", ..] => Ok(Self::default()),
+            ["PROXY", "TCP4" | "TCP6", src, dst, sport, dport] => {
+                let parse_addr = |ip: &str, port: &str| -> Result<SocketAddr, ProxyHeaderError> {
+                    Ok(SocketAddr::new(
+                        ip.parse().map_err(|_| ProxyHeaderError::Malformed)?,
+                        port.parse().map_err(|_| ProxyHeaderError::Malformed)?,
+                    ))
+                };
+                Ok(Self {
+                    source: Some(parse_addr(src, sport)?),
+                    destination: Some(parse_addr(dst, dport)?),
+                })
+            }
+            _ => Err(ProxyHeaderError::Malformed),
+        }
+    }
+
+    fn parse_v2(ver_cmd: u8, family: u8, addresses: &[u8]) -> Result<Self, ProxyHeaderError> {
+        match ver_cmd >> 4 {
+            2 => (),
+            version => return Err(ProxyHeaderError::UnsupportedVersion(version)),
+        }
+
+        match (ver_cmd & 0x0f, family) {
+            (0x0, _) => Ok(Self::default()), // LOCAL: health checks from the balancer itself
+            (0x1, 0x11) if addresses.len() >= 12 => {
+                let ip = |offset: usize| {
+                    IpAddr::from(Ipv4Addr::from(
+                        <[u8; 4]>::try_from(&addresses[offset..offset + 4]).unwrap(),
+                    ))
+                };
+                let port =
+                    |offset: usize| u16::from_be_bytes([addresses[offset], addresses[offset + 1]]);
+                Ok(Self {
+                    source: Some(SocketAddr::new(ip(0), port(8))),
+                    destination: Some(SocketAddr::new(ip(4), port(10))),
+                })
+            }
+            (0x1, 0x21) if addresses.len() >= 36 => {
+                let ip = |offset: usize| {
+                    IpAddr::from(Ipv6Addr::from(
+                        <[u8; 16]>::try_from(&addresses[offset..offset + 16]).unwrap(),
+                    ))
+                };
+                let port =
+                    |offset: usize| u16::from_be_bytes([addresses[offset], addresses[offset + 1]]);
+                Ok(Self {
+                    source: Some(SocketAddr::new(ip(0), port(32))),
+                    destination: Some(SocketAddr::new(ip(16), port(34))),
+                })
+            }
+            // Too short for the addresses of TCP over IPv4 or IPv6
+            (0x1, 0x11 | 0x21) => Err(ProxyHeaderError::Malformed),
+            (0x1, _) => Ok(Self::default()), // UNSPEC or non-IP families
+            _ => Err(ProxyHeaderError::Malformed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::{ProxyHeader, ProxyHeaderError, V2_SIGNATURE};
+
+    #[test]
+    fn parse_v1_tcp4() {
+        let mut stream = &b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n\x10\x08"[..];
+        let header = ProxyHeader::read_from(&mut stream).unwrap();
+        assert_eq!(header.source, Some("192.168.0.1:56324".parse().unwrap()));
+        assert_eq!(
+            header.destination,
+            Some("192.168.0.11:443".parse().unwrap())
+        );
+
+        // The TLV stream must be left untouched
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, [0x10, 0x08]);
+    }
+
+    #[test]
+    fn parse_v1_tcp6() {
+        let mut stream = &b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 12345\r\n"[..];
+        let header = ProxyHeader::read_from(&mut stream).unwrap();
+        assert_eq!(header.source, Some("[2001:db8::1]:4000".parse().unwrap()));
+    }
+
+    #[test]
+    fn parse_v1_unknown() {
+        let mut stream = &b"PROXY UNKNOWN\r\n"[..];
+        assert_eq!(
+            ProxyHeader::read_from(&mut stream).unwrap(),
+            ProxyHeader::default()
+        );
+    }
+
+    #[test]
+    fn parse_v1_err_unterminated() {
+        let mut stream = &[b"PROXY TCP4 ".as_slice(), &[b'1'; 200]].concat()[..];
+        assert!(ProxyHeader::read_from(&mut stream).is_err());
+    }
+
+    #[test]
+    fn parse_v2_tcp4() {
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend([0x21, 0x11, 0, 12]);
+        bytes.extend([10, 0, 0, 1, 10, 0, 0, 2, 0x1f, 0x90, 0x00, 0x50]);
+        bytes.extend([1u8, 2, 3, 4]);
+        let mut stream = &bytes[..];
+        let header = ProxyHeader::read_from(&mut stream).unwrap();
+        assert_eq!(header.source, Some("10.0.0.1:8080".parse().unwrap()));
+        assert_eq!(header.destination, Some("10.0.0.2:80".parse().unwrap()));
+        assert_eq!(stream, [1u8, 2, 3, 4]);
+    }
+
+    #[test]
+    fn parse_v2_short_addresses() {
+        for (family, len) in [(0x11, 11), (0x21, 35), (0x21, 12)] {
+            let mut bytes = V2_SIGNATURE.to_vec();
+            bytes.extend([0x21, family, 0, len]);
+            bytes.extend(vec![1; len.into()]);
+            assert!(
+                matches!(
+                    ProxyHeader::read_from(&mut &bytes[..]),
+                    Err(ProxyHeaderError::Malformed)
+                ),
+                "{family:#x} with {len} bytes"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_v2_local() {
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend([0x20, 0x00, 0, 0]);
+        assert_eq!(
+            ProxyHeader::read_from(&mut &bytes[..]).unwrap(),
+            ProxyHeader::default()
+        );
+    }
+
+    #[test]
+    fn parse_missing() {
+        assert!(ProxyHeader::read_from(&mut &[1u8, 2, 3, 4, 5, 6, 7, 8, 9][..]).is_err());
+    }
+}
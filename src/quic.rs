@@ -0,0 +1,287 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Experimental QUIC transport. Every exchange runs on its own bidirectional
+//! stream: the client writes the request TLVs and finishes its side, and the
+//! server answers with the same TLVs as over TCP and finishes its own.
+//!
+//! The server uses a fresh self-signed certificate and the client does not
+//! check it, so the connection is encrypted but not authenticated.
+
+use std::{
+    io,
+    net::{Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+
+use quinn::{
+    crypto::rustls::{QuicClientConfig, QuicServerConfig},
+    rustls::{
+        self,
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::CryptoProvider,
+        pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+        DigitallySignedStruct, SignatureScheme,
+    },
+    Endpoint,
+};
+use thiserror::Error;
+use tokio::runtime::Runtime;
+
+use crate::{net::canonical_peer, transport, Answer, Operation, Rejection, TCPLibError, TlvError};
+
+/// ALPN protocol name of the calculator.
+const ALPN: &[u8] = b"tcp1";
+
+/// Largest request or answer read from a stream.
+const MAX_MESSAGE: usize = 4096;
+
+#[derive(Error, Debug)]
+pub enum QuicError {
+    #[error("Connection error")]
+    Io(#[from] io::Error),
+    #[error("TLS error. {0}")]
+    Tls(#[from] rustls::Error),
+    #[error("Could not connect. {0}")]
+    Connect(#[from] quinn::ConnectError),
+    #[error("Connection error. {0}")]
+    Connection(#[from] quinn::ConnectionError),
+    #[error("Could not send the request. {0}")]
+    Write(#[from] quinn::WriteError),
+    #[error("Could not send the request. {0}")]
+    Closed(#[from] quinn::ClosedStream),
+    #[error("Could not read the answer. {0}")]
+    Read(#[from] quinn::ReadToEndError),
+    #[error("Malformed answer. {0}")]
+    Tlv(#[from] TlvError),
+    #[error("Invalid answer")]
+    Answer(#[from] TCPLibError),
+    #[error("Operation rejected by the server: {0}")]
+    Rejected(Rejection),
+}
+
+fn runtime() -> io::Result<Runtime> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+}
+
+/// Calculator server over QUIC. Every connection gets its own accumulator.
+pub struct QuicServer {
+    runtime: Runtime,
+    endpoint: Endpoint,
+}
+
+impl QuicServer {
+    pub fn bind(port: u16) -> Result<Self, QuicError> {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+        let mut tls = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![certified.cert.der().clone()], key.into())?;
+        tls.alpn_protocols = vec![ALPN.to_vec()];
+        let config = quinn::ServerConfig::with_crypto(Arc::new(
+            QuicServerConfig::try_from(tls).map_err(|e| io::Error::other(e.to_string()))?,
+        ));
+
+        let runtime = runtime()?;
+        let endpoint = runtime.block_on(async {
+            Endpoint::server(config, SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)))
+        })?;
+
+        Ok(Self { runtime, endpoint })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.endpoint.local_addr()
+    }
+
+    /// Serves clients, concurrently, forever.
+    pub fn run(&self) -> io::Result<()> {
+        self.runtime.block_on(async {
+            while let Some(incoming) = self.endpoint.accept().await {
+                tokio::spawn(async move {
+                    let peer = canonical_peer(incoming.remote_address());
+                    println!("New QUIC connection from {peer}");
+                    match serve(incoming).await {
+                        Ok(()) => println!("QUIC connection from {peer} closed"),
+                        Err(e) => eprintln!("QUIC connection from {peer} aborted. {e}"),
+                    }
+                });
+            }
+        });
+
+        Ok(())
+    }
+}
+
+async fn serve(incoming: quinn::Incoming) -> Result<(), QuicError> {
+    let connection = incoming.await?;
+    let mut acc = 0;
+    loop {
+        // One at a time, so that the operations apply in order
+        let (mut send, mut recv) = match connection.accept_bi().await {
+            Ok(stream) => stream,
+            Err(quinn::ConnectionError::ApplicationClosed(_)) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let request = recv.read_to_end(MAX_MESSAGE).await?;
+        send.write_all(&transport::reply(&mut acc, &request))
+            .await?;
+        send.finish()?;
+    }
+}
+
+/// Client of the QUIC server, with a blocking interface like [`crate::Client`].
+pub struct QuicClient {
+    runtime: Runtime,
+    endpoint: Endpoint,
+    connection: quinn::Connection,
+}
+
+impl QuicClient {
+    pub fn connect(server: SocketAddr) -> Result<Self, QuicError> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut tls = rustls::ClientConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(SkipServerVerification(provider)))
+            .with_no_client_auth();
+        tls.alpn_protocols = vec![ALPN.to_vec()];
+        let config = quinn::ClientConfig::new(Arc::new(
+            QuicClientConfig::try_from(tls).map_err(|e| io::Error::other(e.to_string()))?,
+        ));
+
+        let runtime = runtime()?;
+        let local = match server {
+            SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let (endpoint, connection) = runtime.block_on(async {
+            let mut endpoint = Endpoint::client(local)?;
+            endpoint.set_default_client_config(config);
+            let connection = endpoint.connect(server, "localhost")?.await?;
+            Ok::<_, QuicError>((endpoint, connection))
+        })?;
+
+        Ok(Self {
+            runtime,
+            endpoint,
+            connection,
+        })
+    }
+
+    /// Sends the operation on a new stream and waits for the updated accumulator.
+    pub fn compute(&mut self, operation: Operation) -> Result<Answer, QuicError> {
+        let answer = self.exchange(&operation.encode())?;
+        transport::parse_answer(&answer)?.map_err(QuicError::Rejected)
+    }
+
+    fn exchange(&mut self, request: &[u8]) -> Result<Vec<u8>, QuicError> {
+        self.runtime.block_on(async {
+            let (mut send, mut recv) = self.connection.open_bi().await?;
+            send.write_all(request).await?;
+            send.finish()?;
+            Ok(recv.read_to_end(MAX_MESSAGE).await?)
+        })
+    }
+
+    /// Closes the connection, waiting for the server to learn about it.
+    pub fn close(self) {
+        self.connection.close(0u32.into(), b"bye");
+        self.runtime.block_on(self.endpoint.wait_idle());
+    }
+}
+
+/// Accepts any certificate, as the server makes up its own.
+#[derive(Debug)]
+struct SkipServerVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, thread};
+
+    use super::{QuicClient, QuicServer};
+
+    #[test]
+    fn compute_over_quic() {
+        let server = QuicServer::bind(0).unwrap();
+        let addr = SocketAddr::from(([127, 0, 0, 1], server.local_addr().unwrap().port()));
+        thread::spawn(move || server.run());
+
+        let mut client = QuicClient::connect(addr).unwrap();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        assert_eq!(client.compute("2 * 3".parse().unwrap()).unwrap().value, 13);
+
+        // With an accumulator of its own
+        let mut other = QuicClient::connect(addr).unwrap();
+        assert_eq!(other.compute("1 + 1".parse().unwrap()).unwrap().value, 2);
+        other.close();
+        client.close();
+    }
+}
@@ -0,0 +1,330 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Order in which the server takes the connections waiting for their turn.
+
+use std::{
+    collections::HashMap,
+    num::NonZeroU32,
+    time::{Duration, Instant},
+};
+
+use crate::{tlv::TlvError, Hello, Priority, Tenant, Tlv, TlvType};
+
+/// Connections waiting for their turn. The tenants share the server by weighted
+/// fair queuing: the next connection is one of the tenant that has been served
+/// the least time, relative to its weight. Among those of that tenant it takes
+/// the one with the highest [`Priority`], and every [`AGING`] that a connection
+/// waits counts as a level more, so that bulk connections are served even under
+/// a stream of interactive ones. Those of the same priority are taken in the
+/// order they arrived.
+pub(crate) struct Scheduler<T> {
+    waiting: Vec<Waiting<T>>,
+    aging: Duration,
+    /// Time spent serving each tenant, divided by its weight
+    served: HashMap<String, Duration>,
+    /// What the tenant of the last connection taken had been served by then. A
+    /// tenant that was idle starts from here, so that it does not take over the
+    /// server to make up for the time it did not need it
+    clock: Duration,
+}
+
+/// Time that a connection has to wait to overtake those one level of priority above it.
+pub(crate) const AGING: Duration = Duration::from_secs(1);
+
+/// What the scheduler knows of a connection, from what its client sent first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Class {
+    pub priority: Priority,
+    /// Of the key in its hello. Empty for the clients of no tenant
+    pub tenant: String,
+    pub weight: NonZeroU32,
+}
+
+impl Default for Class {
+    fn default() -> Self {
+        Self {
+            priority: Priority::default(),
+            tenant: String::new(),
+            weight: NonZeroU32::MIN,
+        }
+    }
+}
+
+impl Class {
+    /// Bytes to peek at the start of a connection to classify it.
+    pub(crate) const START: usize = 3 + 2 + u8::MAX as usize;
+
+    /// Classifies a connection by its `start`: a [`Priority`] and then a
+    /// [`Hello`] with the key of one of the `tenants`, both optional. `None`
+    /// while they have not arrived.
+    pub(crate) fn parse(start: &[u8], tenants: &HashMap<String, Tenant>) -> Option<Self> {
+        let mut class = Class::default();
+        let mut rest = start;
+        loop {
+            let tlv = match Tlv::try_from(rest) {
+                Ok(tlv) => tlv,
+                // Without tenants, there is no hello worth waiting for
+                Err(TlvError::Truncated { .. }) if rest == start || !tenants.is_empty() => {
+                    return None
+                }
+                Err(_) => return Some(class),
+            };
+            match tlv.tag {
+                TlvType::Priority if rest == start => {
+                    class.priority = Priority::try_from(tlv).unwrap_or_default();
+                }
+                TlvType::Hello => {
+                    let hello = Hello::try_from(tlv).ok();
+                    if let Some(tenant) = hello.and_then(|hello| tenants.get(&hello.api_key)) {
+                        class.tenant = tenant.name.clone();
+                        class.weight = tenant.weight;
+                    }
+                    return Some(class);
+                }
+                _ => return Some(class),
+            }
+            rest = &rest[2 + usize::from(tlv.length)..];
+        }
+    }
+}
+
+struct Waiting<T> {
+    item: T,
+    /// Unknown until the client sends something, if ever
+    class: Option<Class>,
+    queued: Instant,
+}
+
+impl<T> Waiting<T> {
+    fn tenant(&self) -> &str {
+        self.class.as_ref().map_or("", |class| &class.tenant)
+    }
+
+    /// When it would be taken if it had the highest priority. The earliest goes first.
+    fn turn(&self, aging: Duration) -> Instant {
+        let Priority(level) =
+            (self.class.as_ref()).map_or(Priority::default(), |class| class.priority);
+        self.queued + aging * u32::from(u8::MAX - level)
+    }
+}
+
+impl<T> Scheduler<T> {
+    pub(crate) fn new(aging: Duration) -> Self {
+        Self {
+            waiting: Vec::new(),
+            aging,
+            served: HashMap::new(),
+            clock: Duration::ZERO,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.waiting.is_empty()
+    }
+
+    pub(crate) fn push(&mut self, item: T) {
+        self.waiting.push(Waiting {
+            item,
+            class: None,
+            queued: Instant::now(),
+        });
+    }
+
+    /// Asks `probe` for the class of those waiting with none yet.
+    pub(crate) fn learn(&mut self, mut probe: impl FnMut(&T) -> Option<Class>) {
+        for next in 0..self.waiting.len() {
+            if self.waiting[next].class.is_some() {
+                continue;
+            }
+            let Some(class) = probe(&self.waiting[next].item) else {
+                continue;
+            };
+            if !self
+                .waiting
+                .iter()
+                .any(|waiting| waiting.tenant() == class.tenant)
+            {
+                let served = self.served.entry(class.tenant.clone()).or_default();
+                *served = (*served).max(self.clock);
+            }
+            self.waiting[next].class = Some(class);
+        }
+    }
+
+    /// Takes the next connection to serve, with its class and how long it waited.
+    pub(crate) fn pop(&mut self) -> Option<(T, Class, Duration)> {
+        let served =
+            |waiting: &Waiting<T>| (self.served.get(waiting.tenant()).copied()).unwrap_or_default();
+        // The first of the earliest, as the connections arrived in order
+        let (next, waiting) = (self.waiting.iter().enumerate())
+            .min_by_key(|(_, waiting)| (served(waiting), waiting.turn(self.aging)))?;
+        self.clock = served(waiting);
+        let waiting = self.waiting.remove(next);
+        Some((
+            waiting.item,
+            waiting.class.unwrap_or_default(),
+            waiting.queued.elapsed(),
+        ))
+    }
+
+    /// Counts `busy` as time spent serving the tenant of `class`.
+    pub(crate) fn charge(&mut self, class: &Class, busy: Duration) {
+        *self.served.entry(class.tenant.clone()).or_default() += busy / class.weight.get();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, num::NonZeroU32, thread, time::Duration};
+
+    use super::{Class, Scheduler};
+    use crate::{Capabilities, Hello, Priority, Tenant};
+
+    fn class(priority: Priority, tenant: &str, weight: u32) -> Class {
+        Class {
+            priority,
+            tenant: tenant.to_string(),
+            weight: weight.try_into().unwrap(),
+        }
+    }
+
+    #[test]
+    fn order() {
+        let mut scheduler = Scheduler::new(Duration::from_secs(60));
+        for (name, priority) in [
+            ("bench", Priority::BULK),
+            ("script", Priority::NORMAL),
+            ("repl", Priority::INTERACTIVE),
+            ("unknown", Priority::NORMAL),
+            ("bench 2", Priority::BULK),
+        ] {
+            scheduler.push((name, priority));
+        }
+        scheduler.learn(|&(name, priority)| (name != "unknown").then(|| class(priority, "", 1)));
+        let order: Vec<_> = std::iter::from_fn(|| scheduler.pop())
+            .map(|((name, _), class, _)| (name, class.priority))
+            .collect();
+        assert_eq!(
+            order,
+            [
+                ("repl", Priority::INTERACTIVE),
+                ("script", Priority::NORMAL),
+                ("unknown", Priority::NORMAL),
+                ("bench", Priority::BULK),
+                ("bench 2", Priority::BULK),
+            ]
+        );
+    }
+
+    #[test]
+    fn aging() {
+        let mut scheduler = Scheduler::new(Duration::from_millis(20));
+        scheduler.push(Priority::BULK);
+        thread::sleep(Duration::from_millis(50));
+        scheduler.push(Priority::INTERACTIVE);
+        scheduler.push(Priority::NORMAL);
+        scheduler.learn(|&priority| Some(class(priority, "", 1)));
+
+        // Two levels below, but it waited for more than two agings
+        let (first, _, waited) = scheduler.pop().unwrap();
+        assert_eq!(first, Priority::BULK);
+        assert!(waited >= Duration::from_millis(50));
+        assert_eq!(scheduler.pop().unwrap().0, Priority::INTERACTIVE);
+        assert_eq!(scheduler.pop().unwrap().0, Priority::NORMAL);
+        assert!(scheduler.is_empty());
+    }
+
+    #[test]
+    fn fair_queuing() {
+        let mut scheduler = Scheduler::new(Duration::from_secs(60));
+        let mut order = String::new();
+        // A load generator of a, always waiting, and b with twice the weight
+        for _ in 0..6 {
+            scheduler.push("a");
+            scheduler.push("b");
+            scheduler.learn(|&tenant| {
+                Some(class(
+                    Priority::NORMAL,
+                    tenant,
+                    1 + u32::from(tenant == "b"),
+                ))
+            });
+            let (tenant, class, _) = scheduler.pop().unwrap();
+            order.push_str(tenant);
+            scheduler.charge(&class, Duration::from_secs(1));
+        }
+        assert_eq!(order, "abbabb");
+
+        // c was idle, so it starts level with the last tenant served instead
+        // of from nothing, and takes turns with the others instead of catching up
+        for _ in 0..3 {
+            scheduler.push("c");
+        }
+        scheduler.learn(|&tenant| Some(class(Priority::NORMAL, tenant, 1)));
+        let mut next = || {
+            let (tenant, class, _) = scheduler.pop().unwrap();
+            scheduler.charge(&class, Duration::from_secs(1));
+            tenant
+        };
+        assert_eq!(
+            [next(), next(), next(), next(), next()],
+            ["c", "a", "b", "b", "c"]
+        );
+    }
+
+    #[test]
+    fn parse() {
+        let tenants = HashMap::from([(
+            "key".to_string(),
+            Tenant::new("lab").with_weight(NonZeroU32::new(3).unwrap()),
+        )]);
+        let hello = Hello {
+            capabilities: Capabilities::SUPPORTED,
+            challenge: None,
+            api_key: "key".to_string(),
+        }
+        .encode()
+        .unwrap();
+        let priority = Priority::INTERACTIVE.encode();
+        let start = [&priority[..], &hello].concat();
+
+        assert_eq!(
+            Class::parse(&start, &tenants),
+            Some(class(Priority::INTERACTIVE, "lab", 3))
+        );
+        assert_eq!(
+            Class::parse(&hello, &tenants),
+            Some(class(Priority::NORMAL, "lab", 3))
+        );
+        // The rest of the hello is still to come
+        assert_eq!(Class::parse(&start[..6], &tenants), None);
+        assert_eq!(Class::parse(&priority, &tenants), None);
+        assert_eq!(
+            Class::parse(&priority, &HashMap::new()),
+            Some(class(Priority::INTERACTIVE, "", 1))
+        );
+        assert_eq!(Class::parse(&[], &HashMap::new()), None);
+        // Any other frame means that none is coming
+        assert_eq!(Class::parse(&[16, 0], &tenants), Some(Class::default()));
+    }
+}
@@ -0,0 +1,180 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Handler scripts, written in [Rhai](https://rhai.rs), that decide the answers
+//! of the server instead of the calculator. They let the staff simulate buggy or
+//! adversarial servers without recompiling.
+//!
+//! The script must define `fn answer(command, acc)`, called for every
+//! operation with its command, as written by [`Operation::to_command`] (`"SUM 3
+//! 4"`, `"FACT 5"`…), and the accumulator. It returns the new accumulator, the
+//! name of a [`Rejection`] (`"Overflow"`…) to reject the operation, or `()` to
+//! let the server calculate it as usual.
+
+use std::{fmt, fs, io, path::Path};
+
+use rhai::{Dynamic, Engine, Scope, AST};
+use thiserror::Error;
+
+use crate::{Answer, Operation, Rejection, Tlv};
+
+/// Name of the function of the script that answers the operations.
+const ENTRY_POINT: &str = "answer";
+
+/// Limit of the steps of a call, so that a script cannot hang the server.
+const MAX_OPERATIONS: u64 = 100_000;
+
+#[derive(Error, Debug)]
+pub enum ScriptError {
+    #[error("Could not read the script")]
+    Io(#[from] io::Error),
+    #[error("Could not compile the script. {0}")]
+    Parse(String),
+    #[error("The script does not define fn {ENTRY_POINT}(command, acc)")]
+    NoEntryPoint,
+    #[error("The script failed. {0}")]
+    Eval(String),
+    #[error("The script returned {0}, instead of an integer, a rejection or ()")]
+    Return(String),
+}
+
+/// A compiled handler script.
+pub struct ScriptHandler {
+    engine: Engine,
+    ast: AST,
+}
+
+impl fmt::Debug for ScriptHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScriptHandler").finish_non_exhaustive()
+    }
+}
+
+impl ScriptHandler {
+    pub fn load(path: &Path) -> Result<Self, ScriptError> {
+        Self::compile(&fs::read_to_string(path)?)
+    }
+
+    pub fn compile(source: &str) -> Result<Self, ScriptError> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let ast = engine
+            .compile(source)
+            .map_err(|e| ScriptError::Parse(e.to_string()))?;
+        if !ast
+            .iter_functions()
+            .any(|function| function.name == ENTRY_POINT && function.params.len() == 2)
+        {
+            return Err(ScriptError::NoEntryPoint);
+        }
+
+        Ok(Self { engine, ast })
+    }
+
+    /// Asks the script for the outcome of the operation in `tlv`, or `None` if
+    /// the server must calculate it, as it must those that are not valid
+    /// operations.
+    pub fn answer(
+        &self,
+        tlv: Tlv,
+        acc: i64,
+    ) -> Result<Option<Result<Answer, Rejection>>, ScriptError> {
+        let Ok(operation) = Operation::try_from(tlv) else {
+            return Ok(None);
+        };
+        let result: Dynamic = self
+            .engine
+            .call_fn(
+                &mut Scope::new(),
+                &self.ast,
+                ENTRY_POINT,
+                (operation.to_command(), acc),
+            )
+            .map_err(|e| ScriptError::Eval(e.to_string()))?;
+
+        if result.is_unit() {
+            return Ok(None);
+        }
+        if let Ok(value) = result.as_int() {
+            return Ok(Some(Ok(Answer::from(value))));
+        }
+        match result.into_immutable_string() {
+            Ok(name) => Ok(Some(Err(Rejection::ALL
+                .iter()
+                .copied()
+                .find(|rejection| format!("{rejection:?}") == name.as_str())
+                .unwrap_or(Rejection::Other)))),
+            Err(kind) => Err(ScriptError::Return(kind.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ScriptError, ScriptHandler};
+    use crate::{Answer, Operation, Rejection, Tlv};
+
+    #[test]
+    fn answers() {
+        let handler = ScriptHandler::compile(
+            r#"
+            fn answer(command, acc) {
+                let words = command.split(" ");
+                if words[0] == "SUM" && words[2] == "4" { return acc + 8; }
+                if command == "FACT 5" { return "Overflow"; }
+                if command == "FACT 6" { return "Bogus"; }
+                if words[0] == "DIV" { return "Timeout"; }
+                if words[0] == "MUL" { return 1.5; }
+                if words[0] == "SUB" { loop {} }
+                if words[0] == "SUMN" { return words.len(); }
+            }
+            "#,
+        )
+        .unwrap();
+        let answer = |operation: &str, acc| {
+            let encoded = operation.parse::<Operation>().unwrap().encode();
+            handler.answer(Tlv::try_from(&encoded[..]).unwrap(), acc)
+        };
+
+        assert_eq!(answer("3 + 4", 10).unwrap(), Some(Ok(Answer::from(18))));
+        assert_eq!(answer("3 + 5", 10).unwrap(), None);
+        assert_eq!(answer("5!", 0).unwrap(), Some(Err(Rejection::Overflow)));
+        assert_eq!(answer("6!", 0).unwrap(), Some(Err(Rejection::Other)));
+        // Every operand of a multiple sum, after its name
+        assert_eq!(answer("sum 1 2 3 4", 0).unwrap(), Some(Ok(Answer::from(5))));
+        assert_eq!(answer("7 / 2", 0).unwrap(), Some(Err(Rejection::Timeout)));
+        assert!(matches!(answer("2 * 3", 0), Err(ScriptError::Return(_))));
+        assert!(matches!(answer("2 - 3", 0), Err(ScriptError::Eval(_))));
+    }
+
+    #[test]
+    fn entry_point() {
+        assert!(matches!(
+            ScriptHandler::compile("fn answer(op, a, b, acc) { 1 }"),
+            Err(ScriptError::NoEntryPoint)
+        ));
+        assert!(matches!(
+            ScriptHandler::compile("fn answer(command, acc) {"),
+            Err(ScriptError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! SCTP transport, for Linux. SCTP keeps the boundaries of the messages, so
+//! every operation goes in a message of its own and every read returns one
+//! whole answer, unlike with the byte stream of TCP.
+
+use std::{
+    io::{self, Read},
+    net::{Ipv6Addr, SocketAddr},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::{net::canonical_peer, transport, Answer, ClientError, Operation};
+
+/// Largest message read at once. Longer messages would be truncated.
+const MAX_MESSAGE: usize = 2048;
+
+fn local_addr(socket: &Socket) -> io::Result<SocketAddr> {
+    socket
+        .local_addr()?
+        .as_socket()
+        .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))
+}
+
+/// Calculator server over SCTP, serving one association after the other.
+pub struct SctpServer {
+    listener: Socket,
+}
+
+impl SctpServer {
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::SCTP))?;
+        listener.set_only_v6(false)?;
+        listener.set_reuse_address(true)?;
+        listener.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+        listener.listen(128)?;
+
+        Ok(Self { listener })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        local_addr(&self.listener)
+    }
+
+    /// Serves clients, one after the other, forever.
+    pub fn run(&self) -> io::Result<()> {
+        loop {
+            let (socket, addr) = self.listener.accept()?;
+            let peer = addr.as_socket().map_or("unknown".to_string(), |addr| {
+                canonical_peer(addr).to_string()
+            });
+            println!("New SCTP association from {peer}");
+            match serve(socket) {
+                Ok(()) => println!("SCTP association from {peer} closed"),
+                Err(e) => eprintln!("SCTP association from {peer} aborted. {e}"),
+            }
+        }
+    }
+}
+
+fn serve(mut socket: Socket) -> io::Result<()> {
+    let mut acc = 0;
+    let mut buffer = [0u8; MAX_MESSAGE];
+    loop {
+        // A whole request, as sent
+        let len = socket.read(&mut buffer)?;
+        if len == 0 {
+            return Ok(());
+        }
+        socket.send(&transport::reply(&mut acc, &buffer[..len]))?;
+    }
+}
+
+/// Client of the SCTP server.
+pub struct SctpClient {
+    socket: Socket,
+}
+
+impl SctpClient {
+    pub fn connect(server: SocketAddr) -> Result<Self, ClientError> {
+        let socket = Socket::new(
+            Domain::for_address(server),
+            Type::STREAM,
+            Some(Protocol::SCTP),
+        )?;
+        socket.connect(&server.into())?;
+
+        Ok(Self { socket })
+    }
+
+    /// Sends the operation in a message and waits for the one with the updated accumulator.
+    pub fn compute(&mut self, operation: Operation) -> Result<Answer, ClientError> {
+        self.socket.send(&operation.encode())?;
+
+        let mut buffer = [0u8; MAX_MESSAGE];
+        let len = self.socket.read(&mut buffer)?;
+        if len == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        transport::parse_answer(&buffer[..len])?.map_err(ClientError::Rejected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, thread};
+
+    use nix::errno::Errno;
+
+    use super::{SctpClient, SctpServer};
+
+    #[test]
+    fn compute_over_sctp() {
+        let server = match SctpServer::bind(0) {
+            Ok(server) => server,
+            Err(e) if e.raw_os_error() == Some(Errno::EPROTONOSUPPORT as i32) => {
+                eprintln!("Skipping: this kernel does not support SCTP");
+                return;
+            }
+            Err(e) => panic!("{e}"),
+        };
+        let addr = SocketAddr::from(([127, 0, 0, 1], server.local_addr().unwrap().port()));
+        thread::spawn(move || server.run());
+
+        let mut client = SctpClient::connect(addr).unwrap();
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        assert_eq!(client.compute("2 * 3".parse().unwrap()).unwrap().value, 13);
+    }
+}
@@ -0,0 +1,2032 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{self, Read, Write},
+    mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    num::{NonZeroU64, NonZeroUsize},
+    sync::{Arc, Mutex, RwLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use socket2::{Domain, Socket, Type};
+
+#[cfg(feature = "auth")]
+use crate::auth::{Auth, Nonces};
+#[cfg(feature = "script")]
+use crate::ScriptHandler;
+use crate::{
+    arena::Scratch,
+    audit::Transcript,
+    clock,
+    middleware::{self, Handler, Request},
+    net::{canonical_peer, CidrSet},
+    scheduler::{self, Class, Scheduler},
+    store::SharedStore,
+    tenant::TenantState,
+    tlv::TlvIterator,
+    Answer, AnswerBatch, Bye, Cancel, Capabilities, ChannelFrame, ChunkedWriter, Clock,
+    ConnectHook, ConnectionStats, Deadline, Decoder, DisconnectHook, ErrorHook, GoAway, Hello,
+    IdempotencyKey, Load, Operation, Peer, Ping, Pong, Priority, Profile, Progress, ProxyHeader,
+    ProxyHeaderError, Rejection, RequestObserver, ServerEvent, Session, Store, Summary, Tenant,
+    ThrottledStream, Tlv, TlvType, Tournament, TraceContext,
+};
+
+/// Key of the accumulator of the `tenant` in the [`ServerConfig::store`].
+pub(crate) fn store_key(tenant: Option<&Tenant>) -> String {
+    format!("acc:{}", tenant.map_or("", |tenant| &tenant.name))
+}
+
+/// Nonces of [`Auth`] frames remembered, over all the connections, to reject
+/// them if sent again.
+#[cfg(feature = "auth")]
+const AUTH_NONCES: NonZeroUsize = NonZeroUsize::new(4096).unwrap();
+
+/// Registers a session may keep with [`Store`].
+const MAX_REGISTERS: usize = 16;
+
+/// Longest line accepted from clients speaking text.
+const MAX_TEXT_LINE: usize = 1024;
+
+/// Pause after running out of file descriptors, to let connections finish.
+pub(crate) const EXHAUSTION_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How often an idle connection checks whether the server is draining.
+const DRAIN_POLL: Duration = Duration::from_millis(100);
+
+/// Time after which half of the invalid frames of an address are forgotten.
+const OFFENCE_HALF_LIFE: Duration = Duration::from_secs(600);
+
+/// Time the load balancer has to send the PROXY header of a connection.
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The [`Class`] of a waiting connection, if its client already sent enough.
+fn peek_class(stream: &TcpStream, config: &ServerConfig) -> Option<Class> {
+    let mut start = [0; Class::START];
+    stream.set_nonblocking(true).ok()?;
+    let peeked = stream.peek(&mut start);
+    stream.set_nonblocking(false).ok()?;
+    let class = Class::parse(&start[..peeked.ok()?], &config.tenants)?;
+    Some(match config.priorities {
+        true => class,
+        false => Class {
+            priority: Priority::default(),
+            ..class
+        },
+    })
+}
+
+/// Whether the process or the system ran out of file descriptors.
+pub(crate) fn is_fd_exhaustion(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        use nix::errno::Errno;
+        matches!(
+            e.raw_os_error().map(Errno::from_raw),
+            Some(Errno::EMFILE | Errno::ENFILE)
+        )
+    }
+    #[cfg(windows)]
+    {
+        // WSAEMFILE
+        e.raw_os_error() == Some(10024)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = e;
+        false
+    }
+}
+
+/// Opens a socket of `domain` listening on the port of `config`, with its options.
+fn listen(config: &ServerConfig, domain: Domain) -> io::Result<Socket> {
+    // We need to use the socket2 create to properly support Windows
+    let socket = Socket::new(domain, Type::STREAM, None)?;
+    if domain == Domain::IPV6 {
+        socket.set_only_v6(false)?;
+    }
+    socket.set_reuse_address(config.reuse_address)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(config.reuse_port)?;
+    #[cfg(not(unix))]
+    if config.reuse_port {
+        eprintln!("Reusing the port is not supported in this system");
+    }
+    if let Some(size) = config.recv_buffer {
+        socket.set_recv_buffer_size(size)?;
+    }
+    if let Some(size) = config.send_buffer {
+        socket.set_send_buffer_size(size)?;
+    }
+    // Inherited by the connections, whose SYN-ACK advertises it
+    #[cfg(unix)]
+    if let Some(mss) = config.mss {
+        socket.set_mss(mss)?;
+    }
+    #[cfg(not(unix))]
+    if config.mss.is_some() {
+        eprintln!("Setting the segment size is not supported in this system");
+    }
+    let address = match domain {
+        Domain::IPV6 => IpAddr::from(Ipv6Addr::UNSPECIFIED),
+        _ => IpAddr::from(Ipv4Addr::UNSPECIFIED),
+    };
+    socket.bind(&SocketAddr::from((address, config.port)).into())?;
+    socket.listen(config.backlog)?;
+
+    Ok(socket)
+}
+
+/// Whether `e`, from listening with IPv6, means that the system has no IPv6.
+fn ipv6_unavailable(e: &io::Error) -> bool {
+    // When it was disabled, or not even built in
+    #[cfg(unix)]
+    if e.raw_os_error() == Some(nix::errno::Errno::EAFNOSUPPORT as i32) {
+        return true;
+    }
+    e.kind() == io::ErrorKind::AddrNotAvailable
+}
+
+/// Opens a descriptor kept in reserve for when the others run out.
+pub(crate) fn spare_fd() -> Option<File> {
+    File::open(if cfg!(windows) { "NUL" } else { "/dev/null" }).ok()
+}
+
+/// Decodes an encoded reply back into the answer or the rejection.
+pub(crate) fn outcome(reply: &[u8]) -> Result<Answer, Rejection> {
+    let reply = Tlv::try_from(reply).expect("the server encodes valid TLVs");
+    match Rejection::try_from(reply) {
+        Ok(rejection) => Err(rejection),
+        Err(_) => Answer::try_from(reply).map_err(|_| Rejection::Other),
+    }
+}
+
+/// Writing end of a connection, holding the frames queued until flushed.
+struct ProtocolWriter {
+    stream: Box<dyn Write>,
+    buffer: Vec<u8>,
+    /// When the oldest frame of the buffer was queued
+    since: Option<Instant>,
+    /// Encoding of the answers written
+    profile: Profile,
+}
+
+impl ProtocolWriter {
+    /// Writes to `stream` at most `throttle` bytes per second by the `clock`, if
+    /// any, with the answers in the `profile`.
+    fn new(
+        stream: TcpStream,
+        throttle: Option<NonZeroU64>,
+        profile: Profile,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self {
+            stream: match throttle {
+                Some(rate) => Box::new(ThrottledStream::with_clock(stream, rate, clock)),
+                None => Box::new(stream),
+            },
+            buffer: Vec::new(),
+            since: None,
+            profile,
+        }
+    }
+}
+
+/// Invalid frames received from an address, over all its connections, half of
+/// which are forgotten every [`OFFENCE_HALF_LIFE`].
+#[derive(Clone, Copy, Debug)]
+struct Offences {
+    count: u64,
+    since: Instant,
+}
+
+impl Offences {
+    /// What is left of the count at `now`.
+    fn at(&self, now: Instant) -> u64 {
+        let halvings =
+            now.saturating_duration_since(self.since).as_secs() / OFFENCE_HALF_LIFE.as_secs();
+        self.count
+            .checked_shr(halvings.try_into().unwrap_or(u32::MAX))
+            .unwrap_or(0)
+    }
+}
+
+/// Pauses of a tarpitted connection, and how long they may still take.
+#[derive(Clone, Copy, Debug)]
+struct Tarpit {
+    delay: Duration,
+    left: Duration,
+}
+
+impl Tarpit {
+    /// Takes the pauses before `bytes` bytes from what is left, failing once
+    /// the connection would hold the server longer than allowed.
+    fn spend(&mut self, bytes: usize) -> io::Result<()> {
+        let pauses = self
+            .delay
+            .saturating_mul(bytes.try_into().unwrap_or(u32::MAX));
+        match self.left.checked_sub(pauses) {
+            Some(left) => {
+                self.left = left;
+                Ok(())
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the connection spent its time in the tarpit",
+            )),
+        }
+    }
+}
+
+/// Named registers of a session, to save and restore the accumulator.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Registers(HashMap<u8, i64>);
+
+impl Registers {
+    /// Applies a [`Store`] or a [`Load`] to the accumulator, returning its new value.
+    pub(crate) fn apply(&mut self, tlv: Tlv, acc: i64) -> Result<i64, Rejection> {
+        if let Ok(Store(name)) = Store::try_from(tlv) {
+            if self.0.len() == MAX_REGISTERS && !self.0.contains_key(&name) {
+                return Err(Rejection::TooManyRegisters);
+            }
+            self.0.insert(name, acc);
+            return Ok(acc);
+        }
+        let Load(name) = Load::try_from(tlv).map_err(|_| Rejection::Other)?;
+        self.0.get(&name).copied().ok_or(Rejection::UnknownRegister)
+    }
+}
+
+/// A logical session multiplexed over the connection with [`ChannelFrame`]s.
+#[derive(Debug, Default)]
+struct Channel {
+    acc: i64,
+    registers: Registers,
+}
+
+/// Where the time went between receiving a frame and writing its answer.
+#[derive(Clone, Copy, Debug)]
+struct Timing {
+    /// Since the frame was received until its calculation started
+    queued: Duration,
+    computed: Duration,
+    written: Duration,
+}
+
+impl Timing {
+    fn new(received: Instant, started: Instant, computed: Instant, written: Instant) -> Self {
+        Self {
+            queued: started.saturating_duration_since(received),
+            computed: computed.saturating_duration_since(started),
+            written: written.saturating_duration_since(computed),
+        }
+    }
+}
+
+impl Timing {
+    /// From the arrival of the request to the write of its answer.
+    fn total(&self) -> Duration {
+        self.queued + self.computed + self.written
+    }
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "queued={:?} computed={:?} written={:?}",
+            self.queued, self.computed, self.written
+        )
+    }
+}
+
+fn is_poll_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted
+    )
+}
+
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    pub port: u16,
+    /// Expect a PROXY protocol header at the start of every connection
+    pub proxy_protocol: bool,
+    pub delay: Duration,
+    /// Maximum random variation added to or subtracted from `delay`
+    pub jitter: Duration,
+    pub chunked_writes: Option<NonZeroUsize>,
+    /// Largest factorial calculated. Defaults to [`Operation::MAX_FACTORIAL`]
+    pub max_factorial: Option<i8>,
+    /// Maximum number of connections waiting to be accepted
+    pub backlog: i32,
+    pub reuse_address: bool,
+    /// Let other sockets bind to the same port, so that a new server can start
+    /// while the old one drains. Only available on Unix
+    pub reuse_port: bool,
+    /// Size of the socket receive buffer. `None` keeps the system default
+    pub recv_buffer: Option<usize>,
+    /// Size of the socket send buffer. `None` keeps the system default
+    pub send_buffer: Option<usize>,
+    /// Operations rejected with [`Rejection::Disabled`]
+    pub disabled_operations: Vec<TlvType>,
+    /// Tenants by API key. When there are any, clients must say [`Hello`] with
+    /// one of the keys, and each tenant gets its own accumulator
+    pub tenants: HashMap<String, Tenant>,
+    /// Also accept operations as lines of text, answered in decimal, from
+    /// clients whose first bytes are printable
+    pub ascii_compat: bool,
+    /// Connections from the same address, waiting or being served, above which
+    /// new ones are refused with [`Rejection::TooManyConnections`]
+    pub max_conns_per_ip: Option<NonZeroUsize>,
+    /// When not empty, only clients in these networks are served. With
+    /// [`Self::proxy_protocol`], the addresses are those of the clients in the
+    /// headers, not those of the proxies
+    pub allowed_networks: CidrSet,
+    /// Clients in these networks are refused, even if they are also allowed
+    pub denied_networks: CidrSet,
+    /// Invalid frames from an address, over all its connections, after which
+    /// its new connections are tarpitted: read and written a byte at a time
+    pub tarpit_after: Option<NonZeroU64>,
+    /// Pause before every byte read from or written to a tarpitted client
+    pub tarpit_delay: Duration,
+    /// Time that the pauses of a tarpitted connection may take in total, as
+    /// nobody else is served meanwhile. The connection is closed once spent
+    pub tarpit_budget: Duration,
+    /// Coalesce the answers into a single write once the oldest one waited this
+    /// long, checked after every read. `None` writes every answer at once
+    pub flush_interval: Option<Duration>,
+    /// Ask every client this many random operations, scoring its answers,
+    /// instead of answering those of the client
+    pub quiz: Option<NonZeroUsize>,
+    /// Send a [`Progress`] this often while the `delay` of an answer goes by, to
+    /// the clients that support them
+    pub progress_interval: Option<Duration>,
+    /// Time an operation may take. Those that would take longer are abandoned
+    /// once it runs out, and answered with [`Rejection::Timeout`]
+    pub op_timeout: Option<Duration>,
+    /// Bytes per second at which the answers are written to every client
+    pub throttle: Option<NonZeroU64>,
+    /// Largest segment the clients may send, set with `TCP_MAXSEG`. Only on Unix
+    pub mss: Option<u32>,
+    /// Encoding of the answers, unless the client asks for [`Profile::LE32`] in
+    /// its [`Hello`]
+    pub profile: Profile,
+    /// Script that decides the answers instead of the calculator
+    #[cfg(feature = "script")]
+    pub handler: Option<Arc<ScriptHandler>>,
+    /// Secrets by key ID. When there are any, clients must prove they know
+    /// one with an [`Auth`] frame before their operations are calculated.
+    /// Accepting several lets the clients move to a new one gradually
+    #[cfg(feature = "auth")]
+    pub secrets: HashMap<String, Vec<u8>>,
+    /// Keeps the accumulators, read before and written after every operation,
+    /// so that they outlive the server and other servers can share them
+    pub store: Option<SharedStore>,
+    /// Adds the results to the accumulators in the [`Self::store`] in a single
+    /// step, so that the servers sharing it agree on the totals even when they
+    /// calculate at the same time
+    pub shared_accumulator: bool,
+    /// Serves the waiting connections by the [`Priority`] that their clients
+    /// send first, instead of in the order they arrived
+    pub priorities: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            port: 0,
+            proxy_protocol: false,
+            delay: Duration::ZERO,
+            jitter: Duration::ZERO,
+            chunked_writes: None,
+            max_factorial: None,
+            backlog: 128,
+            reuse_address: true,
+            reuse_port: false,
+            recv_buffer: None,
+            send_buffer: None,
+            disabled_operations: Vec::new(),
+            tenants: HashMap::new(),
+            ascii_compat: false,
+            max_conns_per_ip: None,
+            allowed_networks: CidrSet::default(),
+            denied_networks: CidrSet::default(),
+            tarpit_after: None,
+            tarpit_delay: Duration::from_secs(1),
+            tarpit_budget: Duration::from_secs(10),
+            flush_interval: None,
+            quiz: None,
+            progress_interval: None,
+            op_timeout: None,
+            throttle: None,
+            mss: None,
+            profile: Profile::Classic,
+            #[cfg(feature = "script")]
+            handler: None,
+            #[cfg(feature = "auth")]
+            secrets: HashMap::new(),
+            store: None,
+            shared_accumulator: false,
+            priorities: false,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Whether the networks allowed and denied let `ip` in.
+    pub(crate) fn admits(&self, ip: IpAddr) -> bool {
+        !self.denied_networks.contains(ip)
+            && (self.allowed_networks.is_empty() || self.allowed_networks.contains(ip))
+    }
+
+    pub(crate) fn answer_delay(&self) -> Duration {
+        let jitter = match self.jitter.as_micros() as i64 {
+            0 => 0,
+            j => fastrand::i64(-j..=j),
+        };
+
+        Duration::from_micros((self.delay.as_micros() as u64).saturating_add_signed(jitter))
+    }
+}
+
+/// Handle to change the configuration of a running [`Server`]. Changes apply from
+/// the next request on, also in the connections already established.
+#[derive(Clone, Debug)]
+pub struct ConfigHandle(Arc<RwLock<Arc<ServerConfig>>>);
+
+impl ConfigHandle {
+    /// The configuration in force, that later updates leave untouched.
+    pub fn get(&self) -> Arc<ServerConfig> {
+        Arc::clone(&self.0.read().unwrap())
+    }
+
+    /// Replaces the configuration with a copy changed by `f`.
+    pub fn update<F: FnOnce(&mut ServerConfig)>(&self, f: F) {
+        let mut config = self.0.write().unwrap();
+        f(Arc::make_mut(&mut config))
+    }
+}
+
+/// Handle to drain a running [`Server`]: it stops accepting connections, asks the
+/// connected client to go away and returns from [`Server::run`] once the client
+/// disconnects or the deadline passes.
+#[derive(Clone, Debug)]
+pub struct DrainHandle {
+    deadline: Arc<Mutex<Option<Instant>>>,
+    port: u16,
+    clock: Arc<dyn Clock>,
+}
+
+impl DrainHandle {
+    pub fn drain(&self, timeout: Duration) {
+        *self.deadline.lock().unwrap() = Some(self.clock.now() + timeout);
+        // Wake up the server if it is waiting for a connection
+        let _ = TcpStream::connect((Ipv4Addr::LOCALHOST, self.port));
+    }
+}
+
+pub struct Server {
+    listener: TcpListener,
+    config: ConfigHandle,
+    drain_deadline: Arc<Mutex<Option<Instant>>>,
+    /// State of every tenant, by name. Clients without a tenant share the
+    /// entry with the empty name
+    tenants: HashMap<String, TenantState>,
+    /// Accounting of the connection being served
+    stats: ConnectionStats,
+    observer: Option<RequestObserver>,
+    on_connect: Option<ConnectHook>,
+    on_disconnect: Option<DisconnectHook>,
+    on_error: Option<ErrorHook>,
+    /// Freed to accept and close a connection when out of file descriptors
+    spare_fd: Option<File>,
+    /// Replaces the capabilities deduced from the configuration
+    advertised: Option<Capabilities>,
+    /// Scores of the quizzes, by address of the client
+    tournament: Tournament,
+    /// Invalid frames received from every address, over all its connections
+    offences: HashMap<IpAddr, Offences>,
+    /// Pause before every byte read or written, if the client is tarpitted
+    tarpit: Option<Tarpit>,
+    /// Time the last answer still has to take, as if calculating it were slow
+    work: Duration,
+    /// What the last operation added to a shared accumulator, to take it back
+    /// if cancelled
+    added: Option<i64>,
+    /// When the client stops waiting for the answer of the operation
+    deadline: Option<Instant>,
+    /// Sent by the client to replay the answer of the operation if repeated
+    idempotency_key: Option<u64>,
+    /// Whether the last answer was replayed instead of calculated
+    replayed: bool,
+    /// Of the timeouts, deadlines, rate limits, throttles and tarpits
+    clock: Arc<dyn Clock>,
+    /// Of the connection being served, when serving by priority
+    priority: Option<Priority>,
+    /// Of the connection last served, if it said hello with the key of one
+    served_tenant: Option<Tenant>,
+    /// Totals of the whole run, reported when it ends
+    summary: Summary,
+    /// Whether to keep the time series in the summary, see [`Server::keep_series`]
+    series: bool,
+    /// Key ID the client being served authenticated with
+    #[cfg(feature = "auth")]
+    key_id: Option<String>,
+    /// Sent in the last [`Hello`] to the client being served, for its [`Auth`]
+    #[cfg(feature = "auth")]
+    challenge: Option<[u8; Hello::CHALLENGE_LEN]>,
+    #[cfg(feature = "auth")]
+    nonces: Nonces,
+}
+
+impl Server {
+    pub fn bind(config: ServerConfig) -> io::Result<Self> {
+        let socket = match listen(&config, Domain::IPV6) {
+            // Still serving the IPv4 clients
+            Err(e) if ipv6_unavailable(&e) => {
+                eprintln!("IPv6 is not available in this system, so only IPv4 clients will be served. {e}");
+                listen(&config, Domain::IPV4)?
+            }
+            socket => socket?,
+        };
+
+        // The system may adjust the buffer sizes (Linux doubles them), so show the real ones
+        println!(
+            "Listening on port {} with backlog {}, reuse address {}, receive buffer {} bytes and send buffer {} bytes",
+            socket.local_addr()?.as_socket().map_or(config.port, |address| address.port()),
+            config.backlog,
+            socket.reuse_address()?,
+            socket.recv_buffer_size()?,
+            socket.send_buffer_size()?
+        );
+
+        Ok(Self {
+            listener: socket.into(),
+            config: ConfigHandle(Arc::new(RwLock::new(Arc::new(config)))),
+            drain_deadline: Arc::new(Mutex::new(None)),
+            tenants: HashMap::new(),
+            stats: ConnectionStats::default(),
+            observer: None,
+            on_connect: None,
+            on_disconnect: None,
+            on_error: None,
+            spare_fd: spare_fd(),
+            advertised: None,
+            tournament: Tournament::default(),
+            offences: HashMap::new(),
+            tarpit: None,
+            work: Duration::ZERO,
+            added: None,
+            deadline: None,
+            idempotency_key: None,
+            replayed: false,
+            clock: clock::system(),
+            priority: None,
+            served_tenant: None,
+            summary: Summary::default(),
+            series: false,
+            #[cfg(feature = "auth")]
+            key_id: None,
+            #[cfg(feature = "auth")]
+            challenge: None,
+            #[cfg(feature = "auth")]
+            nonces: Nonces::new(AUTH_NONCES),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Totals of the connections served so far.
+    pub fn summary(&self) -> &Summary {
+        &self.summary
+    }
+
+    /// Keeps the value of the accumulator after every operation in the
+    /// [`Summary::series`], growing for as long as the server runs.
+    pub fn keep_series(&mut self) {
+        self.series = true;
+    }
+
+    pub fn config_handle(&self) -> ConfigHandle {
+        self.config.clone()
+    }
+
+    /// Scores of the quizzes served so far, also readable while the server runs.
+    pub fn tournament(&self) -> Tournament {
+        self.tournament.clone()
+    }
+
+    pub fn drain_handle(&self) -> io::Result<DrainHandle> {
+        Ok(DrainHandle {
+            deadline: Arc::clone(&self.drain_deadline),
+            port: self.local_addr()?.port(),
+            clock: Arc::clone(&self.clock),
+        })
+    }
+
+    /// Tells the time with `clock` instead of the one of the system, as with a
+    /// [`FakeClock`](crate::FakeClock) in tests. Set it before taking a
+    /// [`DrainHandle`].
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Arc::new(clock);
+    }
+
+    /// Delivers every [`ServerEvent`] to `observer`.
+    pub fn set_observer<F>(&mut self, observer: F)
+    where
+        F: FnMut(&ServerEvent) + Send + 'static,
+    {
+        self.observer = Some(Box::new(observer));
+    }
+
+    /// Calls `hook` with the address of every client as it starts serving it.
+    pub fn on_connect<F>(&mut self, hook: F)
+    where
+        F: FnMut(SocketAddr) + Send + 'static,
+    {
+        self.on_connect = Some(Box::new(hook));
+    }
+
+    /// Calls `hook` with the address and the [`ConnectionStats`] of every client
+    /// that leaves, for whatever reason.
+    pub fn on_disconnect<F>(&mut self, hook: F)
+    where
+        F: FnMut(SocketAddr, &ConnectionStats) + Send + 'static,
+    {
+        self.on_disconnect = Some(Box::new(hook));
+    }
+
+    /// Calls `hook` when a connection ends with an error, such as the client
+    /// resetting it, before the hook of [`Server::on_disconnect`].
+    pub fn on_error<F>(&mut self, hook: F)
+    where
+        F: FnMut(SocketAddr, &io::Error, &ConnectionStats) + Send + 'static,
+    {
+        self.on_error = Some(Box::new(hook));
+    }
+
+    /// Advertises `capabilities` in the [`Hello`] of the server, instead of those
+    /// of its configuration. Useful to test how clients deal with older servers.
+    pub fn advertise(&mut self, capabilities: Capabilities) {
+        self.advertised = Some(capabilities);
+    }
+
+    /// What the server advertises with its configuration.
+    fn capabilities(&self) -> Capabilities {
+        if let Some(advertised) = self.advertised {
+            return advertised;
+        }
+
+        let config = self.config.get();
+        let mut capabilities = Capabilities::SUPPORTED;
+        if !config.ascii_compat {
+            capabilities.remove(Capabilities::TEXT);
+        }
+        if config.progress_interval.is_none() {
+            capabilities.remove(Capabilities::PROGRESS);
+        }
+        let disabled = |tag| config.disabled_operations.contains(&tag);
+        if disabled(TlvType::SumN) {
+            capabilities.remove(Capabilities::SUM_N);
+        }
+        if disabled(TlvType::Store) || disabled(TlvType::Load) {
+            capabilities.remove(Capabilities::REGISTERS);
+        }
+
+        capabilities
+    }
+
+    /// A fresh challenge for the [`Auth`] of the client, replacing the last one,
+    /// if the server has secrets.
+    #[cfg(feature = "auth")]
+    fn challenge(&mut self) -> Option<[u8; Hello::CHALLENGE_LEN]> {
+        self.challenge = (!self.config.get().secrets.is_empty())
+            .then(|| std::array::from_fn(|_| fastrand::u8(..)));
+        self.challenge
+    }
+
+    #[cfg(not(feature = "auth"))]
+    fn challenge(&mut self) -> Option<[u8; Hello::CHALLENGE_LEN]> {
+        None
+    }
+
+    fn drain_deadline(&self) -> Option<Instant> {
+        *self.drain_deadline.lock().unwrap()
+    }
+
+    /// Serves clients, one after the other, until drained.
+    pub fn run(&mut self) -> io::Result<()> {
+        // Connections accepted and waiting for their turn, and how many per address
+        let mut queue = Scheduler::new(scheduler::AGING);
+        let mut per_ip = HashMap::new();
+        loop {
+            if queue.is_empty() {
+                let connection = self.accept()?;
+                self.admit(connection, &mut queue, &mut per_ip);
+            }
+            let config = self.config.get();
+            if config.max_conns_per_ip.is_some() || config.priorities || !config.tenants.is_empty()
+            {
+                // Take those in the backlog too, so that they count for the
+                // limit and compete for their turn
+                self.listener.set_nonblocking(true)?;
+                while let Ok(connection) = self.listener.accept() {
+                    connection.0.set_nonblocking(false)?;
+                    self.admit(connection, &mut queue, &mut per_ip);
+                }
+                self.listener.set_nonblocking(false)?;
+            }
+
+            // Past the header of the proxy, already read when admitted
+            if config.priorities || !config.tenants.is_empty() {
+                queue.learn(|(stream, _)| peek_class(stream, &config));
+            }
+            let Some(((stream, addr), class, waited)) = queue.pop() else {
+                // What woke up the server to drain it is never queued
+                if self.drain_deadline().is_some() {
+                    println!("Server drained");
+                    return Ok(());
+                }
+                continue;
+            };
+            self.priority = config.priorities.then_some(class.priority);
+            if let Some(priority) = self.priority {
+                self.summary.add_wait(priority, waited);
+            }
+            if self.drain_deadline().is_none() {
+                let started = self.clock.now();
+                if let Err(e) = self.serve(stream, addr) {
+                    eprintln!("Connection from {addr} aborted. {e}");
+                }
+                // Known for sure only once served, as it may have been taken
+                // before its hello arrived
+                let class = match self.served_tenant.take() {
+                    Some(tenant) => Class {
+                        tenant: tenant.name,
+                        weight: tenant.weight,
+                        ..class
+                    },
+                    None => Class {
+                        priority: class.priority,
+                        ..Class::default()
+                    },
+                };
+                queue.charge(&class, self.clock.since(started));
+            }
+            if let Some(count) = per_ip.get_mut(&addr.ip()) {
+                *count -= 1;
+            }
+            if self.drain_deadline().is_some() {
+                println!("Server drained");
+                return Ok(());
+            }
+        }
+    }
+
+    /// Accepts a connection. When out of file descriptors, it uses the spare one
+    /// to accept and close the first pending connection, so that the client does
+    /// not hang and the backlog does not fill, and tries again after a while.
+    fn accept(&mut self) -> io::Result<(TcpStream, SocketAddr)> {
+        loop {
+            match self.listener.accept() {
+                Err(e) if is_fd_exhaustion(&e) => {
+                    self.spare_fd = None;
+                    match self.listener.accept() {
+                        Ok((_, addr)) => eprintln!(
+                            "Out of file descriptors, closing {}. {e}",
+                            canonical_peer(addr)
+                        ),
+                        Err(_) => eprintln!("Out of file descriptors. {e}"),
+                    }
+                    self.spare_fd = spare_fd();
+                    thread::sleep(EXHAUSTION_BACKOFF);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Queues the connection, with the address of the client behind the proxy
+    /// if there is one, unless its network is not allowed or the address
+    /// already has as many as allowed.
+    fn admit(
+        &mut self,
+        (mut stream, addr): (TcpStream, SocketAddr),
+        queue: &mut Scheduler<(TcpStream, SocketAddr)>,
+        per_ip: &mut HashMap<IpAddr, usize>,
+    ) {
+        // Likely the connection that wakes up the server to drain it
+        if self.drain_deadline().is_some() {
+            return;
+        }
+        let mut addr = canonical_peer(addr);
+        let config = self.config.get();
+        if config.proxy_protocol {
+            // Nobody else is admitted while waiting for it
+            let header = stream
+                .set_read_timeout(Some(PROXY_HEADER_TIMEOUT))
+                .map_err(ProxyHeaderError::from)
+                .and_then(|()| ProxyHeader::read_from(&mut stream));
+            match header {
+                Ok(header) => addr = header.source.map_or(addr, canonical_peer),
+                Err(e) => {
+                    eprintln!("Dropping connection from {addr}. {e}");
+                    return;
+                }
+            }
+        }
+        if !config.admits(addr.ip()) {
+            eprintln!("Refusing connection from {addr}: its network is not allowed");
+            self.summary.count_error("denied connections");
+            self.notify(&ServerEvent::Denied { peer: addr });
+            return;
+        }
+        let count = per_ip.entry(addr.ip()).or_insert(0);
+        if let Some(max) = config.max_conns_per_ip {
+            if *count >= max.get() {
+                eprintln!("Refusing connection from {addr}: it already has {count} connections");
+                let _ = stream.write_all(&Rejection::TooManyConnections.encode());
+                self.summary.count_error("refused connections");
+                self.notify(&ServerEvent::Refused { peer: addr });
+                return;
+            }
+        }
+        *count += 1;
+        queue.push((stream, addr));
+    }
+
+    /// Serves the client at `peer`, behind the proxy if there is one.
+    fn serve(&mut self, stream: TcpStream, peer: SocketAddr) -> io::Result<()> {
+        let started = self.clock.now();
+        match crate::net::mss(&stream) {
+            Ok(mss) => println!("New connection from {peer} with segments of {mss} bytes"),
+            Err(_) => println!("New connection from {peer}"),
+        }
+        self.notify(&ServerEvent::Connected { peer });
+        if let Some(hook) = &mut self.on_connect {
+            hook(peer);
+        }
+
+        let config = self.config.get();
+        let offences = self
+            .offences
+            .get(&peer.ip())
+            .map_or(0, |offences| offences.at(started));
+        self.tarpit = match config.tarpit_after {
+            Some(after) if offences >= after.get() => {
+                println!("Tarpitting {peer} after {offences} invalid frames");
+                self.notify(&ServerEvent::Tarpitted { peer });
+                Some(Tarpit {
+                    delay: config.tarpit_delay,
+                    left: config.tarpit_budget,
+                })
+            }
+            _ => None,
+        };
+
+        let mut tenant = None;
+        self.stats = ConnectionStats::default();
+        self.deadline = None;
+        #[cfg(feature = "auth")]
+        {
+            self.key_id = None;
+            self.challenge = None;
+        }
+        // Keeps the connection open to ask the kernel about it at the end
+        #[cfg(all(feature = "tcp-info", target_os = "linux"))]
+        let socket = stream.try_clone();
+        let result = match self.config.get().quiz {
+            Some(questions) => self.quiz(stream, peer, questions.get()),
+            None => self.converse(stream, peer, &mut tenant),
+        };
+        let stats = mem::take(&mut self.stats);
+        #[cfg(all(feature = "tcp-info", target_os = "linux"))]
+        let stats = ConnectionStats {
+            tcp: socket.and_then(|socket| crate::net::tcp_info(&socket)).ok(),
+            ..stats
+        };
+        println!("Connection from {peer}: {stats}");
+        self.summary.add_connection(&stats);
+        if stats.invalid_frames > 0 {
+            let now = self.clock.now();
+            let offences = self.offences.entry(peer.ip()).or_insert(Offences {
+                count: 0,
+                since: now,
+            });
+            *offences = Offences {
+                count: offences.at(now) + stats.invalid_frames,
+                since: now,
+            };
+            self.offences.retain(|_, offences| offences.at(now) > 0);
+        }
+        if let Some(Tenant { name, .. }) = &tenant {
+            let state = self.tenants.entry(name.clone()).or_default();
+            state.bytes_in += stats.bytes_in;
+            state.bytes_out += stats.bytes_out;
+            println!(
+                "Tenant {name}: {} operations, {} rejections, {} bytes in and {} bytes out so far",
+                state.operations, state.rejections, state.bytes_in, state.bytes_out
+            );
+            let operations = stats.operations.values().sum();
+            self.summary
+                .add_service(name, operations, self.clock.since(started));
+        }
+        self.served_tenant = tenant;
+        if let (Err(e), Some(hook)) = (&result, &mut self.on_error) {
+            hook(peer, e, &stats);
+        }
+        if let Some(hook) = &mut self.on_disconnect {
+            hook(peer, &stats);
+        }
+        self.notify(&ServerEvent::Disconnected { peer, stats });
+
+        result
+    }
+
+    /// Serves a client of the binary protocol. Unlike the text one, it needs a
+    /// `TcpStream`, to write from a clone of it and to peek for cancels.
+    fn converse(
+        &mut self,
+        mut stream: TcpStream,
+        peer: SocketAddr,
+        tenant: &mut Option<Tenant>,
+    ) -> io::Result<()> {
+        // Wake up periodically to notice when the server starts draining, and
+        // to flush the answers in time
+        let poll = match self.config.get().flush_interval {
+            Some(interval) if !interval.is_zero() => interval.min(DRAIN_POLL),
+            _ => DRAIN_POLL,
+        };
+        stream.set_read_timeout(Some(poll))?;
+        let config = self.config.get();
+        let mut writer = ProtocolWriter::new(
+            stream.try_clone()?,
+            config.throttle,
+            config.profile,
+            Arc::clone(&self.clock),
+        );
+        let mut session = Session::new();
+        let mut transcript = Transcript::default();
+
+        let mut buffer = [0u8; 2048];
+        let mut decoder = Decoder::new();
+        let mut scratch = Scratch::default();
+        let mut pending_key = None;
+        let mut pending_trace = None;
+        let mut pending_deadline = None;
+        let mut registers = Registers::default();
+        let mut channels: HashMap<u8, Channel> = HashMap::new();
+        let mut first_read = true;
+        let mut progress = false;
+        // Operations answered one by one, to identify them in a [`Cancel`]
+        let mut requests = 0u64;
+        loop {
+            let len = match self.read(&mut stream, &mut buffer) {
+                Ok(len) => len,
+                Err(e) if is_poll_timeout(&e) => {
+                    self.flush(&mut writer, false)?;
+                    let Some(deadline) = self.drain_deadline() else {
+                        continue;
+                    };
+                    let now = self.clock.now();
+                    if now >= deadline {
+                        println!("Closing connection from {peer} at the end of the drain");
+                        return self.flush(&mut writer, true);
+                    }
+                    if session.advance(Peer::Server, TlvType::GoAway).is_ok() {
+                        println!("Asking {peer} to go away");
+                        let retry_after = deadline - now;
+                        self.write(
+                            &mut writer,
+                            &mut transcript,
+                            &GoAway { retry_after }.encode(),
+                        )?;
+                        self.flush(&mut writer, true)?;
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if len == 0 {
+                println!("Connection from {peer} closed without saying goodbye");
+                // It may have only closed its side, and still wait for the answers
+                return self.flush(&mut writer, true);
+            }
+            // Text if it does not start with a tag
+            if mem::take(&mut first_read)
+                && self.config.get().ascii_compat
+                && TlvType::try_from(buffer[0]).is_err()
+            {
+                return self.converse_text(&mut stream, peer, &buffer[..len]);
+            }
+
+            self.stats.bytes_in += len as u64;
+            // What the previous read needed is no longer in use
+            scratch.reset();
+            decoder.extend_at(&buffer[..len], self.clock.now());
+            loop {
+                let frame = match decoder.next_frame() {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => break,
+                    Err(e) => {
+                        eprintln!("Ignoring message from {peer}. {e}");
+                        self.stats.invalid_frames += 1;
+                        continue;
+                    }
+                };
+                let tlv = frame.as_tlv();
+                if let Err(e) = session.advance(Peer::Client, tlv.tag) {
+                    eprintln!("Ignoring message from {peer}. {e}");
+                    self.stats.invalid_frames += 1;
+                    continue;
+                }
+                transcript.received(tlv);
+                match tlv.tag {
+                    TlvType::Ping => match Ping::try_from(tlv) {
+                        Ok(ping) => {
+                            self.write(&mut writer, &mut transcript, &Pong::from(ping).encode())?
+                        }
+                        Err(e) => {
+                            eprintln!("Invalid ping. {e}");
+                            self.stats.invalid_frames += 1;
+                        }
+                    },
+                    TlvType::Bye => {
+                        session
+                            .advance(Peer::Server, TlvType::Bye)
+                            .expect("the server can always answer the Bye of the client");
+                        self.write(&mut writer, &mut transcript, &Bye.encode())?;
+                        println!("Connection from {peer} closed");
+                        return self.flush(&mut writer, true);
+                    }
+                    #[cfg(feature = "audit")]
+                    TlvType::AuditQuery => {
+                        let digest = transcript.digest();
+                        self.write(&mut writer, &mut transcript, &digest.encode())?
+                    }
+                    #[cfg(not(feature = "audit"))]
+                    TlvType::AuditQuery => {
+                        eprintln!("Rejecting audit query from {peer}: auditing is not built in");
+                        self.write(&mut writer, &mut transcript, &Rejection::Disabled.encode())?
+                    }
+                    TlvType::Hello => match Hello::try_from(tlv) {
+                        Ok(hello) => {
+                            println!("{peer} supports {}", hello.capabilities);
+                            progress = hello.capabilities.contains(Capabilities::PROGRESS);
+                            // Again, as the client may change its mind
+                            writer.profile = match hello.capabilities.contains(Capabilities::LE32) {
+                                true => Profile::LE32,
+                                false => self.config.get().profile,
+                            };
+                            // An empty key only asks for the capabilities
+                            match (hello.api_key.is_empty(), tenant.is_some()) {
+                                (true, _) => (),
+                                (false, true) => eprintln!("Ignoring repeated hello from {peer}"),
+                                (false, false) => {
+                                    match self.config.get().tenants.get(&hello.api_key).cloned() {
+                                        Some(found) => {
+                                            println!("{peer} is tenant {}", found.name);
+                                            *tenant = Some(found);
+                                        }
+                                        None => eprintln!("Unknown API key from {peer}"),
+                                    }
+                                }
+                            }
+                            let hello = Hello {
+                                capabilities: self.capabilities(),
+                                challenge: self.challenge(),
+                                api_key: String::new(),
+                            };
+                            let reply = hello.encode().expect("an empty key always fits");
+                            self.write(&mut writer, &mut transcript, &reply)?;
+                        }
+                        Err(e) => {
+                            eprintln!("Invalid hello. {e}");
+                            self.stats.invalid_frames += 1;
+                        }
+                    },
+                    TlvType::TraceContext => match TraceContext::try_from(tlv) {
+                        Ok(trace) => pending_trace = Some(trace),
+                        Err(e) => {
+                            eprintln!("Invalid trace context. {e}");
+                            self.stats.invalid_frames += 1;
+                        }
+                    },
+                    TlvType::Deadline => match Deadline::try_from(tlv) {
+                        Ok(Deadline(left)) => {
+                            let left = Duration::from_millis(left.into());
+                            pending_deadline = Some(frame.received + left);
+                        }
+                        Err(e) => {
+                            eprintln!("Invalid deadline. {e}");
+                            self.stats.invalid_frames += 1;
+                        }
+                    },
+                    // Only matters while the connection waits for its turn
+                    TlvType::Priority => {
+                        if let Err(e) = Priority::try_from(tlv) {
+                            eprintln!("Invalid priority. {e}");
+                            self.stats.invalid_frames += 1;
+                        }
+                    }
+                    TlvType::IdempotencyKey => match IdempotencyKey::try_from(tlv) {
+                        Ok(IdempotencyKey(key)) => pending_key = Some(key),
+                        Err(e) => {
+                            eprintln!("Invalid idempotency key. {e}");
+                            self.stats.invalid_frames += 1;
+                        }
+                    },
+                    TlvType::Batch => {
+                        // The key would identify a single operation
+                        if pending_key.take().is_some() {
+                            eprintln!("Ignoring idempotency key for a batch from {peer}");
+                        }
+                        let trace = pending_trace.take();
+                        self.deadline = pending_deadline.take();
+                        let started = self.clock.now();
+                        let mut operations = TlvIterator::process(tlv.data);
+                        let mut results = scratch.vec();
+                        results.extend(operations.by_ref().map(|operation| {
+                            let reply = self.calculate(
+                                peer,
+                                operation,
+                                &mut registers,
+                                None,
+                                trace,
+                                tenant.as_ref(),
+                            );
+                            self.clock.sleep(mem::take(&mut self.work));
+                            outcome(&reply)
+                        }));
+                        if let Some(e) = operations.error() {
+                            eprintln!("Truncated batch from {peer}. {e}");
+                            self.stats.invalid_frames += 1;
+                        }
+                        let computed = self.clock.now();
+                        let mut answers = scratch.vec();
+                        AnswerBatch::encode_into(&results, &mut answers);
+                        self.write(&mut writer, &mut transcript, &answers)?;
+                        let timing =
+                            Timing::new(frame.received, started, computed, self.clock.now());
+                        self.log_timing(peer, tlv.tag, trace, timing);
+                    }
+                    TlvType::Channel => match ChannelFrame::try_from(tlv) {
+                        // Tags up to Load are those of the operations
+                        Ok(ChannelFrame {
+                            channel,
+                            frame: request,
+                        }) if request.tag as u8 <= TlvType::Load as u8 => {
+                            if pending_key.take().is_some() {
+                                eprintln!(
+                                    "Ignoring idempotency key in channel {channel} from {peer}"
+                                );
+                            }
+                            let trace = pending_trace.take();
+                            self.deadline = pending_deadline.take();
+                            let started = self.clock.now();
+                            let Channel { acc, registers } = channels.entry(channel).or_default();
+                            let reply = self.calculate(
+                                peer,
+                                request,
+                                registers,
+                                Some(acc),
+                                trace,
+                                tenant.as_ref(),
+                            );
+                            self.clock.sleep(mem::take(&mut self.work));
+                            let computed = self.clock.now();
+                            let reply = ChannelFrame {
+                                channel,
+                                frame: Tlv::whole(&reply).expect("replies are single TLVs"),
+                            };
+                            let reply = reply.encode().expect("answers fit in a channel frame");
+                            self.write(&mut writer, &mut transcript, &reply)?;
+                            let timing =
+                                Timing::new(frame.received, started, computed, self.clock.now());
+                            self.log_timing(peer, request.tag, trace, timing);
+                        }
+                        Ok(ChannelFrame {
+                            channel,
+                            frame: request,
+                        }) => {
+                            eprintln!(
+                                "Ignoring {} in channel {channel} from {peer}",
+                                request.tag.name()
+                            );
+                            self.stats.invalid_frames += 1;
+                        }
+                        Err(e) => {
+                            eprintln!("Invalid channel frame. {e}");
+                            self.stats.invalid_frames += 1;
+                        }
+                    },
+                    TlvType::Cancel => match Cancel::try_from(tlv) {
+                        Ok(Cancel(request)) => {
+                            eprintln!("Ignoring cancel of request {request} from {peer}, not being calculated")
+                        }
+                        Err(e) => {
+                            eprintln!("Invalid cancel. {e}");
+                            self.stats.invalid_frames += 1;
+                        }
+                    },
+                    #[cfg(feature = "auth")]
+                    TlvType::Auth => {
+                        let reply = self.authenticate(peer, tlv);
+                        self.write(&mut writer, &mut transcript, &reply)?
+                    }
+                    #[cfg(not(feature = "auth"))]
+                    TlvType::Auth => {
+                        eprintln!("Rejecting authentication from {peer}: it is not built in");
+                        self.write(&mut writer, &mut transcript, &Rejection::Disabled.encode())?
+                    }
+                    _ => {
+                        let request = requests;
+                        requests += 1;
+                        let key = pending_key.take();
+                        let trace = pending_trace.take();
+                        self.deadline = pending_deadline.take();
+                        self.idempotency_key = key;
+                        let started = self.clock.now();
+                        // To undo the operation if cancelled
+                        let before = *self.accumulator(tenant.as_ref());
+                        let saved = registers.clone();
+                        let mut reply =
+                            self.answer(peer, tlv, &mut registers, None, trace, tenant.as_ref());
+                        let finished = self.work(
+                            &mut stream,
+                            &mut decoder,
+                            &mut writer,
+                            &mut transcript,
+                            progress,
+                            request,
+                        )?;
+                        if !finished {
+                            println!("Cancelled request {request} from {peer}");
+                            match self.added {
+                                // Keeping what other servers added since
+                                Some(added) => self.take_back(tenant.as_ref(), added),
+                                None => {
+                                    *self.accumulator(tenant.as_ref()) = before;
+                                    self.save_accumulator(tenant.as_ref());
+                                }
+                            }
+                            registers = saved;
+                            let state = self.tenant_state(tenant.as_ref());
+                            if outcome(&reply).is_ok() {
+                                state.operations = state.operations.saturating_sub(1);
+                                state.rejections += 1;
+                            }
+                            // It may be sent again with the same key
+                            if let Some(key) = key {
+                                state.forget(peer.ip(), key);
+                            }
+                            reply = Rejection::Cancelled.encode();
+                        }
+                        if !self.replayed {
+                            self.account(peer, tlv.tag, tenant.as_ref(), &reply);
+                        }
+                        let computed = self.clock.now();
+                        self.write(&mut writer, &mut transcript, &reply)?;
+                        self.log_timing(
+                            peer,
+                            tlv.tag,
+                            trace,
+                            Timing::new(frame.received, started, computed, self.clock.now()),
+                        );
+                    }
+                }
+            }
+            // The end of what the client sent at once
+            self.flush(&mut writer, false)?;
+        }
+    }
+
+    /// Asks `peer` random operations, one at a time, and scores its answers
+    /// against those of the calculator. After the last answer, the server sends
+    /// a GoAway and waits for the Bye of the client.
+    fn quiz(
+        &mut self,
+        mut stream: TcpStream,
+        peer: SocketAddr,
+        questions: usize,
+    ) -> io::Result<()> {
+        stream.set_read_timeout(Some(DRAIN_POLL))?;
+        let config = self.config.get();
+        let mut writer = ProtocolWriter::new(
+            stream.try_clone()?,
+            config.throttle,
+            config.profile,
+            Arc::clone(&self.clock),
+        );
+        let mut session = Session::reversed();
+        let mut transcript = Transcript::default();
+
+        let mut buffer = [0u8; 2048];
+        let mut decoder = Decoder::new();
+        let mut asked = 1;
+        let mut right = 0;
+        let mut scored = false;
+        let mut question = Some(self.ask(&mut writer, &mut transcript, &mut session)?);
+        self.flush(&mut writer, true)?;
+        loop {
+            let len = match self.read(&mut stream, &mut buffer) {
+                Ok(len) => len,
+                Err(e) if is_poll_timeout(&e) => match self.drain_deadline() {
+                    Some(deadline) if self.clock.now() >= deadline => {
+                        println!("Closing connection from {peer} at the end of the drain");
+                        return Ok(());
+                    }
+                    _ => continue,
+                },
+                Err(e) => return Err(e),
+            };
+            if len == 0 {
+                println!("Connection from {peer} closed without saying goodbye");
+                if !scored {
+                    self.score(peer, right, questions);
+                }
+                return self.flush(&mut writer, true);
+            }
+
+            self.stats.bytes_in += len as u64;
+            decoder.extend_at(&buffer[..len], self.clock.now());
+            while let Some(frame) = decoder.next_frame().transpose() {
+                let frame = match frame {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        eprintln!("Ignoring message from {peer}. {e}");
+                        self.stats.invalid_frames += 1;
+                        continue;
+                    }
+                };
+                let tlv = frame.as_tlv();
+                if let Err(e) = session.advance(Peer::Client, tlv.tag) {
+                    eprintln!("Ignoring message from {peer}. {e}");
+                    self.stats.invalid_frames += 1;
+                    continue;
+                }
+                transcript.received(tlv);
+                match tlv.tag {
+                    TlvType::Numi64 | TlvType::Rejection => {
+                        let Some(operation) = question.take() else {
+                            eprintln!("Ignoring answer from {peer}: nothing was asked");
+                            self.stats.invalid_frames += 1;
+                            continue;
+                        };
+                        let max_factorial = self.config.get().max_factorial;
+                        let expected = operation
+                            .reduce_with(max_factorial.unwrap_or(Operation::MAX_FACTORIAL))
+                            .map(Answer::from)
+                            .map_err(|e| Rejection::from(&e));
+                        let given = match tlv.tag {
+                            TlvType::Numi64 => Answer::try_from(tlv).map(Ok),
+                            _ => Rejection::try_from(tlv).map(Err),
+                        };
+                        match given {
+                            Ok(given) if given == expected => {
+                                right += 1;
+                                println!("Question {asked} to {peer}: {operation}, right");
+                            }
+                            Ok(Ok(answer)) => println!(
+                                "Question {asked} to {peer}: {operation}, wrong answer {answer}"
+                            ),
+                            Ok(Err(rejection)) => println!(
+                                "Question {asked} to {peer}: {operation}, wrongly rejected. {rejection}"
+                            ),
+                            Err(e) => {
+                                eprintln!("Invalid answer from {peer}. {e}");
+                                self.stats.invalid_frames += 1;
+                            }
+                        }
+
+                        if asked < questions {
+                            question =
+                                Some(self.ask(&mut writer, &mut transcript, &mut session)?);
+                            asked += 1;
+                        } else if session.advance(Peer::Server, TlvType::GoAway).is_ok() {
+                            self.score(peer, right, questions);
+                            scored = true;
+                            let leaderboard = self
+                                .tournament
+                                .leaderboard()
+                                .encode()
+                                .expect("the leaderboard fits in a frame");
+                            session
+                                .advance(Peer::Server, TlvType::Leaderboard)
+                                .expect("the server sends the leaderboard");
+                            self.write(&mut writer, &mut transcript, &leaderboard)?;
+                            let end = GoAway {
+                                retry_after: Duration::ZERO,
+                            };
+                            self.write(&mut writer, &mut transcript, &end.encode())?;
+                        }
+                    }
+                    TlvType::Bye => {
+                        session
+                            .advance(Peer::Server, TlvType::Bye)
+                            .expect("the server can always answer the Bye of the client");
+                        self.write(&mut writer, &mut transcript, &Bye.encode())?;
+                        println!("Connection from {peer} closed");
+                        if !scored {
+                            self.score(peer, right, questions);
+                        }
+                        return self.flush(&mut writer, true);
+                    }
+                    tag => {
+                        eprintln!("Ignoring {} from {peer} during the quiz", tag.name());
+                        self.stats.invalid_frames += 1;
+                    }
+                }
+            }
+            self.flush(&mut writer, true)?;
+        }
+    }
+
+    /// Adds the outcome of the quiz of `peer` to the [`Tournament`].
+    fn score(&mut self, peer: SocketAddr, right: usize, questions: usize) {
+        println!("Quiz of {peer}: {right} of {questions} right");
+        self.tournament.record(peer.ip(), right, questions);
+        self.notify(&ServerEvent::Scored {
+            peer,
+            right,
+            questions,
+        });
+    }
+
+    /// Sends a random operation for the client of a quiz to answer, returning it.
+    fn ask(
+        &mut self,
+        writer: &mut ProtocolWriter,
+        transcript: &mut Transcript,
+        session: &mut Session,
+    ) -> io::Result<Operation> {
+        // The client cannot know that the server rejects larger factorials
+        let max_factorial = self.config.get().max_factorial;
+        let operation = Operation::random_with(max_factorial.unwrap_or(Operation::MAX_FACTORIAL));
+        let encoded = operation.clone().encode();
+        let tlv = Tlv::try_from(&encoded[..]).expect("the server encodes valid TLVs");
+        session
+            .advance(Peer::Server, tlv.tag)
+            .expect("the server asks the operations of a quiz");
+        self.write(writer, transcript, &encoded)?;
+
+        Ok(operation)
+    }
+
+    /// Serves a client typing operations, one per line, with `nc` or `telnet`.
+    fn converse_text(
+        &mut self,
+        stream: &mut (impl Read + Write),
+        peer: SocketAddr,
+        received: &[u8],
+    ) -> io::Result<()> {
+        println!("{peer} speaks text");
+        let mut pending = received.to_vec();
+        let mut buffer = [0u8; 2048];
+        loop {
+            while let Some(end) = pending.iter().position(|&byte| byte == b'\n') {
+                let line: Vec<u8> = pending.drain(..=end).collect();
+                match String::from_utf8_lossy(&line).trim() {
+                    "" => (),
+                    "QUIT" => {
+                        self.write_text(stream, "BYE\n")?;
+                        println!("Connection from {peer} closed");
+                        return Ok(());
+                    }
+                    line => {
+                        let reply = self.calculate_text(peer, line);
+                        self.write_text(stream, &reply)?;
+                    }
+                }
+            }
+            if pending.len() > MAX_TEXT_LINE {
+                self.write_text(stream, "ERROR: Line too long\n")?;
+                eprintln!("Closing connection from {peer}: line too long");
+                return Ok(());
+            }
+
+            let len = match self.read(stream, &mut buffer) {
+                Ok(len) => len,
+                Err(e) if is_poll_timeout(&e) => {
+                    if self
+                        .drain_deadline()
+                        .is_some_and(|deadline| self.clock.now() >= deadline)
+                    {
+                        println!("Closing connection from {peer} at the end of the drain");
+                        return Ok(());
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if len == 0 {
+                println!("Connection from {peer} closed");
+                return Ok(());
+            }
+            self.stats.bytes_in += len as u64;
+            pending.extend_from_slice(&buffer[..len]);
+        }
+    }
+
+    fn write_text(&mut self, stream: &mut impl Write, text: &str) -> io::Result<()> {
+        match &mut self.tarpit {
+            Some(tarpit) => {
+                tarpit.spend(text.len())?;
+                ChunkedWriter::new(stream, NonZeroUsize::MIN)
+                    .with_pause(tarpit.delay)
+                    .with_clock(Arc::clone(&self.clock))
+                    .write_all(text.as_bytes())?
+            }
+            None => stream.write_all(text.as_bytes())?,
+        }
+        self.stats.bytes_out += text.len() as u64;
+
+        Ok(())
+    }
+
+    /// Checks the [`Auth`] of the client, answering with that of the server if
+    /// it is right.
+    #[cfg(feature = "auth")]
+    fn authenticate(&mut self, peer: SocketAddr, tlv: Tlv) -> Box<[u8]> {
+        let auth = match Auth::try_from(tlv) {
+            Ok(auth) => auth,
+            Err(e) => {
+                eprintln!("Invalid authentication. {e}");
+                self.stats.invalid_frames += 1;
+                return Rejection::Unauthorized.encode();
+            }
+        };
+        let config = self.config.get();
+        let Some(secret) = config.secrets.get(&auth.key_id) else {
+            eprintln!(
+                "Rejecting authentication of {peer} with unknown key {}",
+                auth.key_id
+            );
+            return Rejection::Unauthorized.encode();
+        };
+        // Once, so that an Auth seen in this connection cannot be sent again
+        let Some(challenge) = self.challenge.take() else {
+            eprintln!("Rejecting authentication of {peer} without a hello first");
+            return Rejection::Unauthorized.encode();
+        };
+        if let Err(e) = auth.verify(secret, &mut self.nonces, &challenge) {
+            eprintln!(
+                "Rejecting authentication of {peer} with key {}. {e}",
+                auth.key_id
+            );
+            return Rejection::Unauthorized.encode();
+        }
+
+        println!("{peer} authenticated with key {}", auth.key_id);
+        self.key_id = Some(auth.key_id.clone());
+        Auth::sign(&auth.key_id, secret, &auth.nonce)
+            .encode()
+            .expect("the key ID was received in a frame")
+    }
+
+    /// Calculates an operation written as text by `peer`, returning the answer as a line of text.
+    fn calculate_text(&mut self, peer: SocketAddr, line: &str) -> String {
+        // Commands are upper case, unlike the keyword of the infix sum, but
+        // other lines starting with a letter may be commands typed in lower case
+        let start = line.trim_start();
+        let parsed = match start.starts_with(|c: char| c.is_ascii_uppercase()) {
+            true => Operation::from_command(line),
+            false => line
+                .parse()
+                .or_else(|e| match start.starts_with(char::is_alphabetic) {
+                    true => Operation::from_command(line),
+                    false => Err(e),
+                }),
+        };
+        let operation = match parsed {
+            Ok(operation) => operation,
+            Err(e) => return format!("ERROR: {e}\n"),
+        };
+        let request = operation.encode();
+        let reply = self.calculate(
+            peer,
+            Tlv::try_from(&request[..]).expect("operations encode to valid TLVs"),
+            // Text clients cannot send a Store
+            &mut Registers::default(),
+            None,
+            None,
+            None,
+        );
+        self.clock.sleep(mem::take(&mut self.work));
+        match outcome(&reply) {
+            Ok(answer) => format!("{}\n", answer.value),
+            Err(rejection) => format!("ERROR: {rejection}\n"),
+        }
+    }
+
+    /// Calculates the operation of `peer`, accounting for it in the stats of the
+    /// connection.
+    fn calculate(
+        &mut self,
+        peer: SocketAddr,
+        tlv: Tlv,
+        registers: &mut Registers,
+        acc: Option<&mut i64>,
+        trace: Option<TraceContext>,
+        tenant: Option<&Tenant>,
+    ) -> Box<[u8]> {
+        let reply = self.answer(peer, tlv, registers, acc, trace, tenant);
+        if !self.replayed {
+            self.account(peer, tlv.tag, tenant, &reply);
+        }
+
+        reply
+    }
+
+    /// Calculates the operation of `peer`, keeping the accumulator, but leaves
+    /// accounting for it to [`Server::account`], as it may still be cancelled.
+    fn answer(
+        &mut self,
+        peer: SocketAddr,
+        tlv: Tlv,
+        registers: &mut Registers,
+        acc: Option<&mut i64>,
+        trace: Option<TraceContext>,
+        tenant: Option<&Tenant>,
+    ) -> Box<[u8]> {
+        // Those of the channels are only for their session
+        let stored = acc.is_none();
+        if stored {
+            self.load_accumulator(tenant);
+        }
+        self.added = None;
+        let reply = self.reply(peer, tlv, registers, acc, trace, tenant);
+        // Unless already added to the shared one, or not changed by a replay
+        if stored && outcome(&reply).is_ok() && self.added.is_none() && !self.replayed {
+            self.save_accumulator(tenant);
+        }
+        reply
+    }
+
+    /// Counts the `reply` to an operation of `peer` in the stats of the
+    /// connection and of the run, and tells the observer.
+    fn account(
+        &mut self,
+        peer: SocketAddr,
+        operation: TlvType,
+        tenant: Option<&Tenant>,
+        reply: &[u8],
+    ) {
+        self.stats.count_operation(operation);
+        match outcome(reply) {
+            Ok(answer) if self.series => {
+                let tenant = tenant
+                    .map(|tenant| tenant.name.as_str())
+                    .unwrap_or_default();
+                self.summary.record(tenant, operation, answer.value);
+            }
+            Ok(_) => (),
+            Err(rejection) => {
+                self.stats.rejections += 1;
+                self.summary.count_rejection(rejection);
+            }
+        }
+        if self.observer.is_some() {
+            self.notify(&ServerEvent::Answered {
+                peer,
+                operation,
+                outcome: outcome(reply),
+            });
+        }
+    }
+
+    /// Takes the time that the last answer has to take, sending [`Progress`]
+    /// frames meanwhile if the client supports them. Returns `false` if the
+    /// next thing the client sent, or sends in the meantime, is the [`Cancel`]
+    /// of the `request`, that it consumes.
+    fn work(
+        &mut self,
+        stream: &mut TcpStream,
+        decoder: &mut Decoder,
+        writer: &mut ProtocolWriter,
+        transcript: &mut Transcript,
+        progress: bool,
+        request: u64,
+    ) -> io::Result<bool> {
+        let work = mem::take(&mut self.work);
+        let interval = self
+            .config
+            .get()
+            .progress_interval
+            .filter(|interval| progress && !interval.is_zero());
+        let cancel = Cancel(request).encode();
+        if !work.is_zero() && decoder.buffered().starts_with(&cancel) {
+            let frame = decoder.next_frame().ok().flatten();
+            let frame = frame.expect("the cancel is a complete frame");
+            transcript.received(frame.as_tlv());
+            return Ok(false);
+        }
+        let mut next = vec![0u8; cancel.len()];
+        // Until the client sends something else
+        let mut watching = decoder.pending() == 0;
+        let poll = stream.read_timeout()?;
+        let started = self.clock.now();
+        loop {
+            let left = work.saturating_sub(self.clock.since(started));
+            if left.is_zero() {
+                break;
+            }
+            let step = interval.map_or(left, |interval| interval.min(left));
+            let deadline = self.clock.now() + step;
+            if watching {
+                stream.set_read_timeout(Some(step))?;
+                match stream.peek(&mut next) {
+                    Ok(len) if next[..len] == *cancel => {
+                        stream.read_exact(&mut next)?;
+                        stream.set_read_timeout(poll)?;
+                        self.stats.bytes_in += next.len() as u64;
+                        transcript.received(Tlv::whole(&next).expect("cancels are single TLVs"));
+                        return Ok(false);
+                    }
+                    Ok(_) => watching = false,
+                    Err(e) if is_poll_timeout(&e) => (),
+                    Err(e) => return Err(e),
+                }
+                stream.set_read_timeout(poll)?;
+            }
+            self.clock
+                .sleep(deadline.saturating_duration_since(self.clock.now()));
+
+            if interval.is_some() && self.clock.since(started) < work {
+                // The work may take less than a millisecond
+                let done = self.clock.since(started).as_secs_f64() / work.as_secs_f64();
+                let percent = done * 100.0;
+                self.write(writer, transcript, &Progress(percent as u8).encode())?;
+                self.flush(writer, true)?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Replaces the accumulator of the `tenant` with the one in the store, if
+    /// any, as other servers may have changed it.
+    fn load_accumulator(&mut self, tenant: Option<&Tenant>) {
+        let Some(store) = self.config.get().store.clone() else {
+            return;
+        };
+        let stored = store.lock().unwrap().get(&store_key(tenant));
+        match stored {
+            Ok(Some(value)) => *self.accumulator(tenant) = value,
+            Ok(None) => (),
+            Err(e) => eprintln!("Could not read the accumulator from the store. {e}"),
+        }
+    }
+
+    fn save_accumulator(&mut self, tenant: Option<&Tenant>) {
+        let Some(store) = self.config.get().store.clone() else {
+            return;
+        };
+        let value = *self.accumulator(tenant);
+        let stored = store.lock().unwrap().put(&store_key(tenant), value, None);
+        if let Err(e) = stored {
+            eprintln!("Could not write the accumulator to the store. {e}");
+        }
+    }
+
+    /// Subtracts what a cancelled operation `added` from the shared accumulator
+    /// of the `tenant`.
+    fn take_back(&mut self, tenant: Option<&Tenant>, added: i64) {
+        let Some(store) = self.config.get().store.clone() else {
+            return;
+        };
+        let taken = store
+            .lock()
+            .unwrap()
+            .fetch_add(&store_key(tenant), added.saturating_neg());
+        match taken {
+            Ok(previous) => *self.accumulator(tenant) = previous.saturating_sub(added),
+            Err(e) => eprintln!("Could not take back from the shared accumulator. {e}"),
+        }
+    }
+
+    /// The accumulator shared by the clients of the `tenant`.
+    fn accumulator(&mut self, tenant: Option<&Tenant>) -> &mut i64 {
+        &mut self.tenant_state(tenant).acc
+    }
+
+    fn tenant_state(&mut self, tenant: Option<&Tenant>) -> &mut TenantState {
+        let name = tenant.map(|tenant| tenant.name.clone()).unwrap_or_default();
+        self.tenants.entry(name).or_default()
+    }
+
+    /// Reads what the client sent, a byte at a time after a pause if it is
+    /// tarpitted.
+    fn read(&mut self, stream: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+        match &mut self.tarpit {
+            Some(tarpit) => {
+                tarpit.spend(1)?;
+                self.clock.sleep(tarpit.delay);
+                stream.read(&mut buffer[..1])
+            }
+            None => stream.read(buffer),
+        }
+    }
+
+    /// Writes the access log line of an answered frame, counting its latency.
+    fn log_timing(
+        &mut self,
+        peer: SocketAddr,
+        tag: TlvType,
+        trace: Option<TraceContext>,
+        timing: Timing,
+    ) {
+        self.summary.add_latency(timing.total());
+        if let Some(priority) = self.priority {
+            self.summary.add_priority_latency(priority, timing.total());
+        }
+        let trace = trace
+            .map(|trace| format!(" traceparent={trace}"))
+            .unwrap_or_default();
+        println!("Answered {} from {peer} {timing}{trace}", tag.name());
+    }
+
+    fn notify(&mut self, event: &ServerEvent) {
+        if let Some(observer) = &mut self.observer {
+            observer(event);
+        }
+    }
+
+    /// Calculates the operation of `peer` and updates the accumulator, that of
+    /// the tenant unless another one is given, returning the encoded answer, or
+    /// the rejection if the operation cannot be calculated.
+    fn reply(
+        &mut self,
+        peer: SocketAddr,
+        tlv: Tlv,
+        registers: &mut Registers,
+        acc: Option<&mut i64>,
+        trace: Option<TraceContext>,
+        tenant: Option<&Tenant>,
+    ) -> Box<[u8]> {
+        // Appended to the log lines of the request
+        let mut context = trace
+            .map(|trace| format!(" traceparent={trace}"))
+            .unwrap_or_default();
+        if let Some(tenant) = tenant {
+            context += &format!(" tenant={}", tenant.name);
+        }
+        #[cfg(feature = "auth")]
+        if let Some(key_id) = &self.key_id {
+            context += &format!(" key={key_id}");
+        }
+        let config = self.config.get();
+        let mut request = Request {
+            peer,
+            tlv,
+            context,
+            config: &config,
+            tenant,
+            #[cfg(feature = "auth")]
+            key_id: self.key_id.as_deref(),
+            state: self
+                .tenants
+                .entry(tenant.map(|tenant| tenant.name.clone()).unwrap_or_default())
+                .or_default(),
+            channel_acc: acc,
+            registers,
+            deadline: self.deadline,
+            idempotency_key: self.idempotency_key.take(),
+            now: self.clock.now(),
+            work: Duration::ZERO,
+            added: None,
+            replayed: false,
+        };
+        let reply = middleware::chain().call(&mut request);
+        self.work = request.work;
+        self.added = request.added;
+        self.replayed = request.replayed;
+
+        reply
+    }
+
+    /// Writes the frame, or queues it when coalescing the answers.
+    fn write(
+        &mut self,
+        writer: &mut ProtocolWriter,
+        transcript: &mut Transcript,
+        bytes: &[u8],
+    ) -> io::Result<()> {
+        let profile = writer.profile;
+        let bytes = &profile.transcode(bytes);
+        writer.buffer.extend_from_slice(bytes);
+        writer.since.get_or_insert_with(Instant::now);
+        transcript.sent(bytes);
+        self.stats.bytes_out += bytes.len() as u64;
+
+        match self.config.get().flush_interval {
+            Some(_) => Ok(()),
+            None => self.flush(writer, true),
+        }
+    }
+
+    /// Writes the queued frames if the oldest one waited for the flush interval,
+    /// or in any case if `force`d.
+    fn flush(&mut self, writer: &mut ProtocolWriter, force: bool) -> io::Result<()> {
+        let Some(since) = writer.since else {
+            return Ok(());
+        };
+        let config = self.config.get();
+        if !force && self.clock.since(since) < config.flush_interval.unwrap_or_default() {
+            return Ok(());
+        }
+
+        let bytes = mem::take(&mut writer.buffer);
+        writer.since = None;
+        match (&mut self.tarpit, config.chunked_writes) {
+            (Some(tarpit), _) => {
+                tarpit.spend(bytes.len())?;
+                ChunkedWriter::new(&mut writer.stream, NonZeroUsize::MIN)
+                    .with_pause(tarpit.delay)
+                    .with_clock(Arc::clone(&self.clock))
+                    .write_all(&bytes)
+            }
+            (None, Some(chunk_size)) => {
+                ChunkedWriter::new(&mut writer.stream, chunk_size).write_all(&bytes)
+            }
+            (None, None) => writer.stream.write_all(&bytes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::ErrorKind,
+        net::{Ipv4Addr, SocketAddr},
+    };
+
+    use super::{Server, ServerConfig};
+    use crate::{testing::TestStream, Rejection, TlvType};
+
+    #[test]
+    fn text_over_scripted_stream() {
+        let mut server = Server::bind(ServerConfig::default()).unwrap();
+        let peer = SocketAddr::from((Ipv4Addr::LOCALHOST, 1));
+        let mut stream = TestStream::new()
+            .reading(b"+ 4\n")
+            // The poll timeout, with nothing to do
+            .failing_read(ErrorKind::WouldBlock)
+            .reading(b"2 * 3\nQUIT\n")
+            .writing(1)
+            .failing_write(ErrorKind::Interrupted);
+        server.converse_text(&mut stream, peer, b"3 ").unwrap();
+        assert_eq!(stream.written(), b"7\n13\nBYE\n");
+
+        let mut stream = TestStream::new()
+            .reading(b"1 + 1\n")
+            .failing_write(ErrorKind::BrokenPipe);
+        let e = server.converse_text(&mut stream, peer, b"").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn text_commands_apart_from_infix() {
+        let config = ServerConfig {
+            disabled_operations: vec![TlvType::Sum],
+            ..Default::default()
+        };
+        let mut server = Server::bind(config).unwrap();
+        let peer = SocketAddr::from((Ipv4Addr::LOCALHOST, 1));
+        let mut stream = TestStream::new().reading(b"sum 1 2 3\nSUM 1 2\nSUMN 1 2\nfact 3\nQUIT\n");
+        server.converse_text(&mut stream, peer, b"").unwrap();
+        let written = String::from_utf8(stream.written().to_vec()).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines[0], "6");
+        // The binary sum of the command, not the multiple one
+        assert_eq!(lines[1], format!("ERROR: {}", Rejection::Disabled));
+        assert_eq!(lines[2..], ["9", "15", "BYE"]);
+    }
+}
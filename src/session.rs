@@ -0,0 +1,232 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+use thiserror::Error;
+
+use crate::TlvType;
+
+/// End of the connection that sends a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Peer {
+    Client,
+    Server,
+}
+
+/// Lifecycle of a connection, the same for both ends.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SessionState {
+    /// Requests and answers flow freely
+    #[default]
+    Established,
+    /// The server sent a GoAway. The client may still make requests until it leaves
+    Draining,
+    /// The client said Bye, so it only waits for the pending answers and the Bye of the server
+    Closing,
+    /// Both ends said Bye. Nothing else may be sent
+    Closed,
+}
+
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+#[error("The {from:?} cannot send {} when the session is {state:?}", .tag.name())]
+pub struct SessionError {
+    pub state: SessionState,
+    pub from: Peer,
+    pub tag: TlvType,
+}
+
+/// Checks every frame exchanged against the protocol lifecycle, rejecting those
+/// that are not allowed in the current state or from that end.
+#[derive(Clone, Debug, Default)]
+pub struct Session {
+    state: SessionState,
+    /// The server asks the operations and the client answers them, as in a quiz
+    reversed: bool,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A session where the server sends the operations and the client the
+    /// answers. The rest of the lifecycle is the same.
+    pub fn reversed() -> Self {
+        Self {
+            reversed: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn state(&self) -> SessionState {
+        self.state
+    }
+
+    /// Records a frame with `tag` sent by `from`, returning the new state. The
+    /// state is left untouched if the frame is not allowed.
+    pub fn advance(&mut self, from: Peer, tag: TlvType) -> Result<SessionState, SessionError> {
+        use Peer::{Client, Server};
+        use SessionState::{Closed, Closing, Draining, Established};
+
+        let asker = match self.reversed {
+            false => Client,
+            true => Server,
+        };
+        let next = match (self.state, from, tag) {
+            (Closed, _, _) => None,
+            (_, from, tag) if !may_send(from, tag, asker) => None,
+            (Established | Draining, Client, TlvType::Bye) => Some(Closing),
+            (Established | Draining, Server, TlvType::Bye) => None,
+            (Established, Server, TlvType::GoAway) => Some(Draining),
+            (Draining, Server, TlvType::GoAway) => None,
+            (Established | Draining, _, _) => Some(self.state),
+            (Closing, Server, TlvType::Bye) => Some(Closed),
+            (Closing, Server, _) => Some(Closing),
+            (Closing, Client, _) => None,
+        };
+
+        match next {
+            Some(state) => {
+                self.state = state;
+                Ok(state)
+            }
+            None => Err(SessionError {
+                state: self.state,
+                from,
+                tag,
+            }),
+        }
+    }
+}
+
+/// Whether the protocol lets `peer` send frames with `tag` at all, when the
+/// operations come from `asker`.
+fn may_send(peer: Peer, tag: TlvType, asker: Peer) -> bool {
+    match tag {
+        TlvType::Sum
+        | TlvType::Sub
+        | TlvType::Mul
+        | TlvType::Div
+        | TlvType::Rem
+        | TlvType::Fact
+        | TlvType::DivEuclid
+        | TlvType::RemEuclid
+        | TlvType::SumN
+        | TlvType::Store
+        | TlvType::Load
+        | TlvType::Ping
+        | TlvType::IdempotencyKey
+        | TlvType::TraceContext
+        | TlvType::Batch
+        | TlvType::Cancel
+        | TlvType::Deadline
+        | TlvType::Priority => peer == asker,
+        TlvType::Numi64
+        | TlvType::Pong
+        | TlvType::Rejection
+        | TlvType::AnswerBatch
+        | TlvType::Progress => peer != asker,
+        TlvType::GoAway | TlvType::Leaderboard => peer == Peer::Server,
+        TlvType::Bye
+        | TlvType::AuditQuery
+        | TlvType::AuditDigest
+        | TlvType::Hello
+        | TlvType::Channel
+        | TlvType::Auth => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Peer, Session, SessionState};
+    use crate::TlvType;
+
+    #[test]
+    fn orderly_close() {
+        let mut session = Session::new();
+        assert!(session.advance(Peer::Client, TlvType::Sum).is_ok());
+        assert!(session.advance(Peer::Server, TlvType::Numi64).is_ok());
+        assert_eq!(
+            session.advance(Peer::Client, TlvType::Bye),
+            Ok(SessionState::Closing)
+        );
+        assert!(session.advance(Peer::Server, TlvType::Rejection).is_ok());
+        assert_eq!(
+            session.advance(Peer::Server, TlvType::Bye),
+            Ok(SessionState::Closed)
+        );
+        assert!(session.advance(Peer::Client, TlvType::Bye).is_err());
+    }
+
+    #[test]
+    fn drain() {
+        let mut session = Session::new();
+        assert_eq!(
+            session.advance(Peer::Server, TlvType::GoAway),
+            Ok(SessionState::Draining)
+        );
+        assert!(session.advance(Peer::Server, TlvType::GoAway).is_err());
+        assert!(session.advance(Peer::Client, TlvType::Mul).is_ok());
+        assert_eq!(
+            session.advance(Peer::Client, TlvType::Bye),
+            Ok(SessionState::Closing)
+        );
+    }
+
+    #[test]
+    fn illegal_frames() {
+        let mut session = Session::new();
+        assert!(session.advance(Peer::Server, TlvType::Sum).is_err());
+        assert!(session.advance(Peer::Client, TlvType::Numi64).is_err());
+        assert!(session.advance(Peer::Server, TlvType::Bye).is_err());
+        assert_eq!(session.state(), SessionState::Established);
+
+        session.advance(Peer::Client, TlvType::Bye).unwrap();
+        let e = session.advance(Peer::Client, TlvType::Fact).unwrap_err();
+        assert_eq!(e.state, SessionState::Closing);
+        assert_eq!(
+            e.to_string(),
+            "The Client cannot send Fact when the session is Closing"
+        );
+    }
+
+    #[test]
+    fn quiz() {
+        let mut session = Session::reversed();
+        assert!(session.advance(Peer::Client, TlvType::Sum).is_err());
+        assert!(session.advance(Peer::Server, TlvType::Sum).is_ok());
+        assert!(session.advance(Peer::Server, TlvType::Numi64).is_err());
+        assert!(session.advance(Peer::Client, TlvType::Numi64).is_ok());
+        assert_eq!(
+            session.advance(Peer::Server, TlvType::GoAway),
+            Ok(SessionState::Draining)
+        );
+        assert!(session.advance(Peer::Client, TlvType::GoAway).is_err());
+        assert_eq!(
+            session.advance(Peer::Client, TlvType::Bye),
+            Ok(SessionState::Closing)
+        );
+        assert_eq!(
+            session.advance(Peer::Server, TlvType::Bye),
+            Ok(SessionState::Closed)
+        );
+    }
+}
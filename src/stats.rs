@@ -0,0 +1,603 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    io::{self, Write},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{Answer, Priority, Rejection, TlvType};
+
+/// Times that a [`Summary`] keeps of each kind for its percentiles. Beyond them,
+/// the percentiles are those of a uniform sample of this size.
+const SAMPLES: usize = 4096;
+
+/// Accounting of a connection to the server.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    /// Operations received, by type, including the rejected ones
+    pub operations: HashMap<TlvType, u64>,
+    /// Operations answered with a [`crate::Rejection`]
+    pub rejections: u64,
+    /// Frames ignored for being malformed or out of place
+    pub invalid_frames: u64,
+    /// What the kernel knew of the connection at its end
+    #[cfg(all(feature = "tcp-info", target_os = "linux"))]
+    pub tcp: Option<crate::net::TcpInfo>,
+}
+
+impl ConnectionStats {
+    pub(crate) fn count_operation(&mut self, tag: TlvType) {
+        *self.operations.entry(tag).or_default() += 1;
+    }
+}
+
+impl fmt::Display for ConnectionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes in, {} bytes out",
+            self.bytes_in, self.bytes_out
+        )?;
+        // In the order of the tags, to be stable
+        for tag in TlvType::ALL {
+            if let Some(count) = self.operations.get(tag) {
+                write!(f, ", {} {count}", tag.name())?;
+            }
+        }
+        write!(
+            f,
+            ", {} rejections and {} invalid frames",
+            self.rejections, self.invalid_frames
+        )?;
+        #[cfg(all(feature = "tcp-info", target_os = "linux"))]
+        if let Some(tcp) = &self.tcp {
+            write!(f, " ({tcp})")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Something that happened in a [`crate::Server`].
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum ServerEvent {
+    /// A client is being served
+    Connected { peer: SocketAddr },
+    /// An operation was calculated, or rejected
+    Answered {
+        peer: SocketAddr,
+        operation: TlvType,
+        outcome: Result<Answer, Rejection>,
+    },
+    /// A client left, for whatever reason
+    Disconnected {
+        peer: SocketAddr,
+        stats: ConnectionStats,
+    },
+    /// A connection was refused for exceeding the connections allowed per address
+    Refused { peer: SocketAddr },
+    /// A connection was refused because its network is not allowed
+    Denied { peer: SocketAddr },
+    /// A client with too many invalid frames connected, and is served slowly
+    Tarpitted { peer: SocketAddr },
+    /// A client of a quiz left, with `right` of the `questions` answered correctly
+    Scored {
+        peer: SocketAddr,
+        right: usize,
+        questions: usize,
+    },
+}
+
+/// Receives the [`ServerEvent`]s of a [`crate::Server`], for instance in tests.
+pub type RequestObserver = Box<dyn FnMut(&ServerEvent) + Send>;
+
+/// Called with the address of every client as the server starts serving it.
+pub type ConnectHook = Box<dyn FnMut(SocketAddr) + Send>;
+
+/// Called with the address and the accounting of every client that leaves.
+pub type DisconnectHook = Box<dyn FnMut(SocketAddr, &ConnectionStats) + Send>;
+
+/// Called when a connection ends with an error, before the [`DisconnectHook`].
+pub type ErrorHook = Box<dyn FnMut(SocketAddr, &io::Error, &ConnectionStats) + Send>;
+
+/// Totals of the whole run of a server or a client, to report when it exits.
+#[derive(Clone, Debug)]
+pub struct Summary {
+    started: Instant,
+    pub connections: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub operations: HashMap<TlvType, u64>,
+    /// Errors by category, such as a kind of rejection or invalid frames
+    pub errors: BTreeMap<String, u64>,
+    /// Time taken by the operations answered
+    latencies: Reservoir,
+    /// Times of the connections served by priority, see [`Summary::add_wait`]
+    priorities: BTreeMap<Priority, PriorityTimes>,
+    /// Service given to each tenant, see [`Summary::add_service`]
+    tenants: BTreeMap<String, TenantService>,
+    /// Accumulator after every operation answered, in order, if the server
+    /// keeps it (see [`crate::Server::keep_series`])
+    pub series: Vec<Sample>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct PriorityTimes {
+    /// Spent waiting for their turn, one per connection
+    waits: Reservoir,
+    latencies: Reservoir,
+}
+
+#[derive(Clone, Debug, Default)]
+struct TenantService {
+    connections: u64,
+    operations: u64,
+    /// Serving its connections
+    busy: Duration,
+}
+
+/// Value of an accumulator after an operation, a point of the time series.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sample {
+    /// Since the start of the run
+    pub at: Duration,
+    /// Empty for the shared accumulator
+    pub tenant: String,
+    pub operation: TlvType,
+    pub accumulator: i64,
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            connections: 0,
+            bytes_in: 0,
+            bytes_out: 0,
+            operations: HashMap::new(),
+            errors: BTreeMap::new(),
+            latencies: Reservoir::default(),
+            priorities: BTreeMap::new(),
+            tenants: BTreeMap::new(),
+            series: Vec::new(),
+        }
+    }
+}
+
+impl Summary {
+    /// Adds up a connection that finished.
+    pub fn add_connection(&mut self, stats: &ConnectionStats) {
+        self.connections += 1;
+        self.bytes_in += stats.bytes_in;
+        self.bytes_out += stats.bytes_out;
+        for (&tag, count) in &stats.operations {
+            *self.operations.entry(tag).or_default() += count;
+        }
+        if stats.invalid_frames > 0 {
+            *self.errors.entry("invalid frames".to_string()).or_default() += stats.invalid_frames;
+        }
+    }
+
+    pub fn count_operation(&mut self, tag: TlvType) {
+        *self.operations.entry(tag).or_default() += 1;
+    }
+
+    pub fn count_error(&mut self, category: impl Into<String>) {
+        *self.errors.entry(category.into()).or_default() += 1;
+    }
+
+    pub fn count_rejection(&mut self, rejection: Rejection) {
+        self.count_error(format!("{rejection:?}"));
+    }
+
+    pub fn add_latency(&mut self, latency: Duration) {
+        self.latencies.add(latency);
+    }
+
+    /// Adds the time that a connection of `priority` waited for its turn. The
+    /// report only has the priorities of the connections added this way.
+    pub fn add_wait(&mut self, priority: Priority, wait: Duration) {
+        (self.priorities.entry(priority).or_default().waits).add(wait);
+    }
+
+    /// Like [`Summary::add_latency`], for an operation of a connection of `priority`.
+    pub fn add_priority_latency(&mut self, priority: Priority, latency: Duration) {
+        (self.priorities.entry(priority).or_default().latencies).add(latency);
+    }
+
+    /// Adds a connection of `tenant` that took `busy` of the server to answer
+    /// its `operations`, for the throughput of every tenant in the report.
+    pub fn add_service(&mut self, tenant: &str, operations: u64, busy: Duration) {
+        let service = self.tenants.entry(tenant.to_string()).or_default();
+        service.connections += 1;
+        service.operations += operations;
+        service.busy += busy;
+    }
+
+    /// Adds the value of the accumulator of `tenant` after an `operation` to
+    /// the time series.
+    pub fn record(&mut self, tenant: &str, operation: TlvType, accumulator: i64) {
+        self.series.push(Sample {
+            at: self.started.elapsed(),
+            tenant: tenant.to_string(),
+            operation,
+            accumulator,
+        });
+    }
+
+    /// Writes the time series as CSV, with a row per operation answered. The
+    /// `count` column is the number of operations of its type answered so
+    /// far, so its last value for each type makes the histogram.
+    pub fn write_series(&self, mut out: impl Write) -> io::Result<()> {
+        writeln!(out, "secs,tenant,operation,accumulator,count")?;
+        let mut counts = HashMap::<TlvType, u64>::new();
+        for sample in &self.series {
+            let count = counts.entry(sample.operation).or_default();
+            *count += 1;
+            writeln!(
+                out,
+                "{:.6},{},{},{},{count}",
+                sample.at.as_secs_f64(),
+                csv_field(&sample.tenant),
+                sample.operation.name(),
+                sample.accumulator
+            )?;
+        }
+
+        out.flush()
+    }
+
+    /// Latency below which `percent` of the operations were answered.
+    pub fn percentile(&self, percent: u8) -> Option<Duration> {
+        percentile(&self.latencies.times, percent)
+    }
+}
+
+/// Uniform sample of at most [`SAMPLES`] of the times added, so that its
+/// memory does not grow with the run.
+#[derive(Clone, Debug, Default)]
+struct Reservoir {
+    /// Added so far, also those left out of the sample
+    added: u64,
+    times: Vec<Duration>,
+}
+
+impl Reservoir {
+    fn add(&mut self, time: Duration) {
+        self.added += 1;
+        if self.times.len() < SAMPLES {
+            self.times.push(time);
+        } else if let Ok(i) = usize::try_from(fastrand::u64(..self.added)) {
+            // Replacing one of the sample with the probability it deserves
+            if let Some(slot) = self.times.get_mut(i) {
+                *slot = time;
+            }
+        }
+    }
+}
+
+/// The nearest rank `percent` of `times`.
+fn percentile(times: &[Duration], percent: u8) -> Option<Duration> {
+    let mut times = times.to_vec();
+    times.sort_unstable();
+    let rank = (times.len() * usize::from(percent)).div_ceil(100);
+    times.get(rank.checked_sub(1)?).copied()
+}
+
+/// Quotes `field` if it has a comma, a quote or a line break.
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+/// [`Summary`] as written to the report, in TOML.
+#[derive(Serialize)]
+struct Report {
+    uptime_secs: f64,
+    connections: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+    operations: BTreeMap<&'static str, u64>,
+    errors: BTreeMap<String, u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<Latency>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    priorities: BTreeMap<String, PriorityReport>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    tenants: BTreeMap<String, TenantReport>,
+}
+
+#[derive(Serialize)]
+struct Latency {
+    p50: f64,
+    p95: f64,
+}
+
+impl Latency {
+    fn of(times: &[Duration]) -> Option<Self> {
+        let millis = |time: Duration| time.as_secs_f64() * 1000.0;
+        let (p50, p95) = percentile(times, 50).zip(percentile(times, 95))?;
+        Some(Latency {
+            p50: millis(p50),
+            p95: millis(p95),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct TenantReport {
+    connections: u64,
+    operations: u64,
+    /// Over the whole run
+    ops_per_sec: f64,
+    busy_secs: f64,
+    /// Of the time serving any tenant
+    share: f64,
+}
+
+#[derive(Serialize)]
+struct PriorityReport {
+    connections: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wait_ms: Option<Latency>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<Latency>,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let uptime = self.started.elapsed().as_secs_f64();
+        let busy: Duration = self.tenants.values().map(|service| service.busy).sum();
+        let report = Report {
+            uptime_secs: uptime,
+            connections: self.connections,
+            bytes_in: self.bytes_in,
+            bytes_out: self.bytes_out,
+            operations: (self.operations.iter())
+                .map(|(tag, &count)| (tag.name(), count))
+                .collect(),
+            errors: self.errors.clone(),
+            latency_ms: Latency::of(&self.latencies.times),
+            priorities: (self.priorities.iter())
+                .map(|(priority, times)| {
+                    let report = PriorityReport {
+                        connections: times.waits.added,
+                        wait_ms: Latency::of(&times.waits.times),
+                        latency_ms: Latency::of(&times.latencies.times),
+                    };
+                    (priority.to_string(), report)
+                })
+                .collect(),
+            tenants: (self.tenants.iter())
+                .map(|(tenant, service)| {
+                    let report = TenantReport {
+                        connections: service.connections,
+                        operations: service.operations,
+                        ops_per_sec: service.operations as f64 / uptime,
+                        busy_secs: service.busy.as_secs_f64(),
+                        // None of them kept the server busy yet
+                        share: match busy.is_zero() {
+                            true => 0.0,
+                            false => service.busy.as_secs_f64() / busy.as_secs_f64(),
+                        },
+                    };
+                    (tenant.clone(), report)
+                })
+                .collect(),
+        };
+
+        f.write_str(&toml::to_string(&report).map_err(|_| fmt::Error)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{ConnectionStats, Summary, SAMPLES};
+    use crate::{Priority, Rejection, TlvType};
+
+    #[test]
+    fn summary() {
+        let mut stats = ConnectionStats {
+            bytes_in: 12,
+            bytes_out: 30,
+            rejections: 1,
+            ..Default::default()
+        };
+        stats.count_operation(TlvType::Fact);
+        stats.count_operation(TlvType::Sum);
+        stats.count_operation(TlvType::Sum);
+        assert_eq!(
+            stats.to_string(),
+            "12 bytes in, 30 bytes out, Sum 2, Fact 1, 1 rejections and 0 invalid frames"
+        );
+    }
+
+    #[test]
+    fn run_summary() {
+        let mut summary = Summary::default();
+        let mut stats = ConnectionStats {
+            bytes_in: 12,
+            bytes_out: 30,
+            invalid_frames: 2,
+            ..Default::default()
+        };
+        stats.count_operation(TlvType::Sum);
+        summary.add_connection(&stats);
+        summary.add_connection(&stats);
+        summary.count_rejection(Rejection::WrongDomain);
+        for millis in 1..=20 {
+            summary.add_latency(Duration::from_millis(millis));
+        }
+        assert_eq!(summary.percentile(50), Some(Duration::from_millis(10)));
+        assert_eq!(summary.percentile(95), Some(Duration::from_millis(19)));
+
+        let report = summary.to_string();
+        let report = report.split_once('\n').unwrap().1;
+        assert_eq!(
+            report,
+            "connections = 2
+bytes_in = 24
+bytes_out = 60
+
+[operations]
+Sum = 2
+
+[errors]
+WrongDomain = 1
+\"invalid frames\" = 4
+
+[latency_ms]
+p50 = 10.0
+p95 = 19.0
+"
+        );
+        assert_eq!(Summary::default().percentile(50), None);
+    }
+
+    #[test]
+    fn bounded_latencies() {
+        let mut summary = Summary::default();
+        for millis in 0..4 * SAMPLES as u64 {
+            summary.add_latency(Duration::from_millis(millis % 100));
+        }
+        assert_eq!(summary.latencies.added, 4 * SAMPLES as u64);
+        assert_eq!(summary.latencies.times.len(), SAMPLES);
+        let median = summary.percentile(50).unwrap();
+        assert!((40..60).contains(&median.as_millis()), "{median:?}");
+
+        // Also those by priority, counting every connection
+        for millis in 0..2 * SAMPLES as u64 {
+            summary.add_wait(Priority::BULK, Duration::from_millis(millis));
+            summary.add_priority_latency(Priority::BULK, Duration::from_millis(millis));
+        }
+        let times = &summary.priorities[&Priority::BULK];
+        assert_eq!(times.waits.times.len(), SAMPLES);
+        assert_eq!(times.latencies.times.len(), SAMPLES);
+        let report = summary.to_string();
+        assert!(report.contains(&format!("connections = {}", 2 * SAMPLES)));
+    }
+
+    #[test]
+    fn priorities() {
+        let mut summary = Summary::default();
+        summary.add_wait(Priority::BULK, Duration::from_millis(40));
+        summary.add_wait(Priority::INTERACTIVE, Duration::from_millis(2));
+        summary.add_priority_latency(Priority::INTERACTIVE, Duration::from_millis(5));
+
+        let report = summary.to_string();
+        let priorities = &report[report.find("[priorities").unwrap()..];
+        assert_eq!(
+            priorities,
+            "[priorities.bulk]
+connections = 1
+
+[priorities.bulk.wait_ms]
+p50 = 40.0
+p95 = 40.0
+
+[priorities.interactive]
+connections = 1
+
+[priorities.interactive.wait_ms]
+p50 = 2.0
+p95 = 2.0
+
+[priorities.interactive.latency_ms]
+p50 = 5.0
+p95 = 5.0
+"
+        );
+    }
+
+    #[test]
+    fn tenants() {
+        let mut summary = Summary::default();
+        summary.add_service("a", 30, Duration::from_secs(3));
+        summary.add_service("b", 5, Duration::from_millis(500));
+        summary.add_service("b", 5, Duration::from_millis(500));
+
+        let report = summary.to_string();
+        let tenants = &report[report.find("[tenants.a]").unwrap()..];
+        let lines: Vec<_> = (tenants.lines())
+            .filter(|line| !line.starts_with("ops_per_sec"))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "[tenants.a]",
+                "connections = 1",
+                "operations = 30",
+                "busy_secs = 3.0",
+                "share = 0.75",
+                "",
+                "[tenants.b]",
+                "connections = 2",
+                "operations = 10",
+                "busy_secs = 1.0",
+                "share = 0.25",
+            ]
+        );
+
+        // Before any of them took time
+        let mut summary = Summary::default();
+        summary.add_service("a", 0, Duration::ZERO);
+        assert!(summary.to_string().contains("share = 0.0"));
+    }
+
+    #[test]
+    fn series() {
+        let mut summary = Summary::default();
+        summary.record("", TlvType::Sum, 3);
+        summary.record("lab, 2", TlvType::Mul, 12);
+        summary.record("", TlvType::Sum, 5);
+
+        let mut csv = Vec::new();
+        summary.write_series(&mut csv).unwrap();
+        let rows: Vec<_> = String::from_utf8(csv)
+            .unwrap()
+            .lines()
+            .map(|row| row.split_once(',').unwrap().1.to_string())
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                "tenant,operation,accumulator,count",
+                ",Sum,3,1",
+                "\"lab, 2\",Mul,12,1",
+                ",Sum,5,2"
+            ]
+        );
+    }
+}
@@ -0,0 +1,424 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Where the server keeps the accumulators, so that they outlive the process
+//! and can be shared by several of them. [`SessionStore`] is the interface, and
+//! there are implementations in memory, in a file and, with the `redis`
+//! feature, in a Redis server.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::math::sat_add_i64;
+
+/// Values by key, each of which may expire after a while.
+pub trait SessionStore: fmt::Debug + Send {
+    /// The value of `key`, unless it is missing or expired.
+    fn get(&mut self, key: &str) -> io::Result<Option<i64>>;
+
+    /// Sets the value of `key`, that expires after `ttl` if given.
+    fn put(&mut self, key: &str, value: i64, ttl: Option<Duration>) -> io::Result<()>;
+
+    /// Removes `key`, returning its value if it had one.
+    fn remove(&mut self, key: &str) -> io::Result<Option<i64>>;
+
+    /// Makes `key` expire after `ttl`, returning whether it exists.
+    fn expire(&mut self, key: &str, ttl: Duration) -> io::Result<bool>;
+
+    /// Adds `delta` to the value of `key`, 0 if missing, saturating at the
+    /// bounds of i64, in a single step that no other user of the store can
+    /// interleave with. Returns the previous value.
+    fn fetch_add(&mut self, key: &str, delta: i64) -> io::Result<i64>;
+}
+
+/// A store that several servers of the same process can use.
+pub type SharedStore = Arc<Mutex<dyn SessionStore>>;
+
+/// Opens the store at `location`: a `redis://` URL, or else the path of a
+/// file.
+pub fn open(location: &str) -> io::Result<SharedStore> {
+    if location.starts_with("redis://") || location.starts_with("rediss://") {
+        #[cfg(feature = "redis")]
+        return Ok(Arc::new(Mutex::new(RedisStore::connect(location)?)));
+        #[cfg(not(feature = "redis"))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Redis is not built in. Build with --features redis",
+        ));
+    }
+
+    Ok(Arc::new(Mutex::new(FileStore::new(location))))
+}
+
+/// A store that lives as long as the process, for tests and single servers.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    entries: HashMap<String, (i64, Option<Instant>)>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn get(&mut self, key: &str) -> io::Result<Option<i64>> {
+        match self.entries.get(key) {
+            Some(&(_, Some(deadline))) if deadline <= Instant::now() => {
+                self.entries.remove(key);
+                Ok(None)
+            }
+            entry => Ok(entry.map(|&(value, _)| value)),
+        }
+    }
+
+    fn put(&mut self, key: &str, value: i64, ttl: Option<Duration>) -> io::Result<()> {
+        let deadline = ttl.map(|ttl| Instant::now() + ttl);
+        self.entries.insert(key.to_string(), (value, deadline));
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> io::Result<Option<i64>> {
+        let value = self.get(key)?;
+        self.entries.remove(key);
+        Ok(value)
+    }
+
+    fn expire(&mut self, key: &str, ttl: Duration) -> io::Result<bool> {
+        let exists = self.get(key)?.is_some();
+        if let Some((_, deadline)) = self.entries.get_mut(key) {
+            *deadline = Some(Instant::now() + ttl);
+        }
+        Ok(exists)
+    }
+
+    fn fetch_add(&mut self, key: &str, delta: i64) -> io::Result<i64> {
+        let previous = self.get(key)?.unwrap_or_default();
+        let entry = self.entries.entry(key.to_string()).or_default();
+        entry.0 = sat_add_i64(previous, delta).0;
+        Ok(previous)
+    }
+}
+
+/// A store in a TOML file, so that it survives restarts and several processes
+/// on the same host see the same values. Writes replace the file atomically,
+/// and take turns with an advisory lock on a file next to it, that the system
+/// releases if the process dies. The file is read whole on every access, and
+/// only written back if it changes, so it suits a few servers; Redis scales
+/// further.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct FileEntries {
+    #[serde(default)]
+    entry: BTreeMap<String, FileEntry>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct FileEntry {
+    value: i64,
+    /// Milliseconds since the Unix epoch
+    expires: Option<u64>,
+}
+
+impl FileEntry {
+    fn expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// Milliseconds since the Unix epoch, for the expirations that other
+/// processes read.
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_millis() as u64)
+}
+
+impl FileStore {
+    /// A store in the file at `path`, created on the first write.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn load(&self) -> io::Result<FileEntries> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(FileEntries::default()),
+            Err(e) => return Err(e),
+        };
+        let mut entries: FileEntries =
+            toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let now = now_millis();
+        entries.entry.retain(|_, entry| !entry.expired(now));
+
+        Ok(entries)
+    }
+
+    fn save(&self, entries: &FileEntries) -> io::Result<()> {
+        let text = toml::to_string(entries).map_err(io::Error::other)?;
+        // Readers see either the old file or the new one
+        let partial = self.path.with_extension("partial");
+        fs::write(&partial, text)?;
+        fs::rename(&partial, &self.path)
+    }
+
+    /// Applies `change` to the entries holding the lock, saving them if it
+    /// tells that it changed them.
+    fn update<T>(&self, change: impl FnOnce(&mut FileEntries) -> (T, bool)) -> io::Result<T> {
+        let lock = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.path.with_extension("lock"))?;
+        // Released when closed, even if the process crashes
+        lock.lock()?;
+        let mut entries = self.load()?;
+        let (result, changed) = change(&mut entries);
+        if changed {
+            self.save(&entries)?;
+        }
+        Ok(result)
+    }
+}
+
+impl SessionStore for FileStore {
+    fn get(&mut self, key: &str) -> io::Result<Option<i64>> {
+        Ok(self.load()?.entry.get(key).map(|entry| entry.value))
+    }
+
+    fn put(&mut self, key: &str, value: i64, ttl: Option<Duration>) -> io::Result<()> {
+        let expires = ttl.map(|ttl| now_millis() + ttl.as_millis() as u64);
+        self.update(|entries| {
+            entries
+                .entry
+                .insert(key.to_string(), FileEntry { value, expires });
+            ((), true)
+        })
+    }
+
+    fn remove(&mut self, key: &str) -> io::Result<Option<i64>> {
+        let removed = self.update(|entries| {
+            let removed = entries.entry.remove(key);
+            let changed = removed.is_some();
+            (removed, changed)
+        })?;
+        Ok(removed.map(|entry| entry.value))
+    }
+
+    fn expire(&mut self, key: &str, ttl: Duration) -> io::Result<bool> {
+        let expires = now_millis() + ttl.as_millis() as u64;
+        self.update(|entries| {
+            let exists = entries
+                .entry
+                .get_mut(key)
+                .map(|entry| entry.expires = Some(expires))
+                .is_some();
+            (exists, exists)
+        })
+    }
+
+    fn fetch_add(&mut self, key: &str, delta: i64) -> io::Result<i64> {
+        self.update(|entries| {
+            let entry = entries.entry.entry(key.to_string()).or_insert(FileEntry {
+                value: 0,
+                expires: None,
+            });
+            let previous = entry.value;
+            entry.value = sat_add_i64(previous, delta).0;
+            (previous, true)
+        })
+    }
+}
+
+/// A store in a Redis server, that any number of servers in any host can share.
+#[cfg(feature = "redis")]
+pub struct RedisStore {
+    connection: redis::Connection,
+}
+
+#[cfg(feature = "redis")]
+impl fmt::Debug for RedisStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisStore").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "redis")]
+impl RedisStore {
+    pub fn connect(url: &str) -> io::Result<Self> {
+        let connection = redis::Client::open(url)
+            .and_then(|client| client.get_connection())
+            .map_err(io::Error::other)?;
+        Ok(Self { connection })
+    }
+
+    fn query<T: redis::FromRedisValue>(&mut self, command: &redis::Cmd) -> io::Result<T> {
+        command
+            .query(&mut self.connection)
+            .map_err(io::Error::other)
+    }
+}
+
+#[cfg(feature = "redis")]
+impl SessionStore for RedisStore {
+    fn get(&mut self, key: &str) -> io::Result<Option<i64>> {
+        self.query(redis::cmd("GET").arg(key))
+    }
+
+    fn put(&mut self, key: &str, value: i64, ttl: Option<Duration>) -> io::Result<()> {
+        let mut command = redis::cmd("SET");
+        command.arg(key).arg(value);
+        if let Some(ttl) = ttl {
+            command.arg("PX").arg(ttl.as_millis() as u64);
+        }
+        self.query(&command)
+    }
+
+    fn remove(&mut self, key: &str) -> io::Result<Option<i64>> {
+        self.query(redis::cmd("GETDEL").arg(key))
+    }
+
+    fn expire(&mut self, key: &str, ttl: Duration) -> io::Result<bool> {
+        self.query(redis::cmd("PEXPIRE").arg(key).arg(ttl.as_millis() as u64))
+    }
+
+    /// Retries until no other client changed the key between reading and
+    /// writing it, as INCRBY fails instead of saturating.
+    fn fetch_add(&mut self, key: &str, delta: i64) -> io::Result<i64> {
+        redis::transaction(&mut self.connection, &[key], |connection, pipe| {
+            let previous: Option<i64> = redis::cmd("GET").arg(key).query(connection)?;
+            let previous = previous.unwrap_or_default();
+            let value = sat_add_i64(previous, delta).0;
+            pipe.cmd("SET")
+                .arg(key)
+                .arg(value)
+                .arg("KEEPTTL")
+                .ignore()
+                .query::<Option<()>>(connection)
+                .map(|done| done.map(|()| previous))
+        })
+        .map_err(io::Error::other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs, process, thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{FileStore, MemoryStore, SessionStore};
+
+    /// Exercises the operations of the trait, as every store must behave.
+    fn check(store: &mut dyn SessionStore) {
+        assert_eq!(store.get("a").unwrap(), None);
+        store.put("a", 7, None).unwrap();
+        store.put("b", -1, Some(Duration::from_millis(50))).unwrap();
+        assert_eq!(store.get("a").unwrap(), Some(7));
+        assert_eq!(store.get("b").unwrap(), Some(-1));
+
+        store.put("a", i64::MAX, None).unwrap();
+        assert_eq!(store.get("a").unwrap(), Some(i64::MAX));
+        assert!(store.expire("a", Duration::from_millis(50)).unwrap());
+        assert!(!store.expire("c", Duration::from_millis(50)).unwrap());
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(store.get("a").unwrap(), None);
+        assert_eq!(store.get("b").unwrap(), None);
+
+        store.put("c", 3, None).unwrap();
+        assert_eq!(store.remove("c").unwrap(), Some(3));
+        assert_eq!(store.remove("c").unwrap(), None);
+        assert_eq!(store.get("c").unwrap(), None);
+
+        assert_eq!(store.fetch_add("d", 5).unwrap(), 0);
+        assert_eq!(store.fetch_add("d", -7).unwrap(), 5);
+        assert_eq!(store.fetch_add("d", i64::MIN).unwrap(), -2);
+        assert_eq!(store.get("d").unwrap(), Some(i64::MIN));
+    }
+
+    #[test]
+    fn memory() {
+        check(&mut MemoryStore::new());
+    }
+
+    #[test]
+    fn file() {
+        let path = env::temp_dir().join(format!("tcp1-store-{}.toml", process::id()));
+        check(&mut FileStore::new(&path));
+
+        // Another process would see the values too
+        FileStore::new(&path).put("shared", 42, None).unwrap();
+        assert_eq!(FileStore::new(&path).get("shared").unwrap(), Some(42));
+
+        // Without losing any of the additions of the others
+        let adders: Vec<_> = (0..4)
+            .map(|_| {
+                let mut store = FileStore::new(&path);
+                thread::spawn(move || {
+                    for _ in 0..25 {
+                        store.fetch_add("shared", 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for adder in adders {
+            adder.join().unwrap();
+        }
+        assert_eq!(FileStore::new(&path).get("shared").unwrap(), Some(142));
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("lock")).unwrap();
+    }
+
+    #[test]
+    fn stale_lock() {
+        let path = env::temp_dir().join(format!("tcp1-stale-{}.toml", process::id()));
+        // As left behind by a crashed process
+        fs::write(path.with_extension("lock"), "").unwrap();
+
+        let started = Instant::now();
+        FileStore::new(&path).put("a", 1, None).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(FileStore::new(&path).get("a").unwrap(), Some(1));
+
+        // Removing what is missing does not write the file
+        let written = fs::metadata(&path).unwrap().modified().unwrap();
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(FileStore::new(&path).remove("b").unwrap(), None);
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), written);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("lock")).unwrap();
+    }
+}
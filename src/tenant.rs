@@ -0,0 +1,172 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    num::NonZeroU32,
+    time::{Duration, Instant},
+};
+
+/// Length of the window of the rate limits.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Number of answers remembered per tenant to replay requests sent again
+/// with the same [`IdempotencyKey`](crate::IdempotencyKey).
+const IDEMPOTENCY_CACHE: usize = 1024;
+
+/// A group of clients sharing an API key, with its own accumulator. Build it
+/// with [`Tenant::new`], as it may gain fields.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Tenant {
+    pub name: String,
+    /// Maximum number of operations per minute. `None` means unlimited
+    pub rate_limit: Option<NonZeroU32>,
+    /// Share of the server relative to the other tenants with clients waiting
+    pub weight: NonZeroU32,
+}
+
+impl Tenant {
+    /// A tenant without rate limit, with the weight of one.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            rate_limit: None,
+            weight: NonZeroU32::MIN,
+        }
+    }
+
+    pub fn with_rate_limit(mut self, rate_limit: Option<NonZeroU32>) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    pub fn with_weight(mut self, weight: NonZeroU32) -> Self {
+        self.weight = weight;
+        self
+    }
+}
+
+/// What the server keeps of a tenant between connections.
+#[derive(Debug, Default)]
+pub(crate) struct TenantState {
+    pub acc: i64,
+    pub operations: u64,
+    pub rejections: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    /// Start of the current rate window and operations admitted in it
+    window: Option<(Instant, u32)>,
+    /// Last answers by address of the client and idempotency key, kept between
+    /// connections so that a client can send a request again after reconnecting,
+    /// but not get those of the other clients of the tenant
+    replies: HashMap<(IpAddr, u64), Box<[u8]>>,
+    /// The keys of `replies`, the oldest first
+    order: VecDeque<(IpAddr, u64)>,
+}
+
+impl TenantState {
+    /// Counts an operation arriving at `now`, returning whether it is within the limit.
+    pub fn admit(&mut self, limit: Option<NonZeroU32>, now: Instant) -> bool {
+        let Some(limit) = limit else {
+            return true;
+        };
+        let (start, count) = match self.window {
+            Some((start, count)) if now.duration_since(start) < RATE_WINDOW => (start, count),
+            _ => (now, 0),
+        };
+        let admitted = count < limit.get();
+        self.window = Some((start, count + u32::from(admitted)));
+
+        admitted
+    }
+
+    /// The answer given to the request of `client` with `key`, if still remembered.
+    pub fn replay(&self, client: IpAddr, key: u64) -> Option<Box<[u8]>> {
+        self.replies.get(&(client, key)).cloned()
+    }
+
+    /// Remembers the answer to the request of `client` with `key`, forgetting
+    /// the oldest beyond the capacity.
+    pub fn remember(&mut self, client: IpAddr, key: u64, reply: Box<[u8]>) {
+        if self.replies.insert((client, key), reply).is_some() {
+            return;
+        }
+        self.order.push_back((client, key));
+        if self.order.len() > IDEMPOTENCY_CACHE {
+            if let Some(oldest) = self.order.pop_front() {
+                self.replies.remove(&oldest);
+            }
+        }
+    }
+
+    /// Forgets the answer to the request of `client` with `key`, as that of a
+    /// cancelled one.
+    pub fn forget(&mut self, client: IpAddr, key: u64) {
+        if self.replies.remove(&(client, key)).is_some() {
+            self.order.retain(|&cached| cached != (client, key));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, Instant},
+    };
+
+    use super::{TenantState, IDEMPOTENCY_CACHE};
+
+    #[test]
+    fn replies_by_client() {
+        let mut state = TenantState::default();
+        let alice = IpAddr::from(Ipv4Addr::new(10, 0, 0, 1));
+        let bob = IpAddr::from(Ipv4Addr::new(10, 0, 0, 2));
+        state.remember(alice, 7, [1].into());
+        assert_eq!(state.replay(alice, 7).as_deref(), Some(&[1][..]));
+        assert_eq!(state.replay(bob, 7), None);
+        state.forget(alice, 7);
+        assert_eq!(state.replay(alice, 7), None);
+        state.remember(alice, 7, [1].into());
+
+        for key in 0..IDEMPOTENCY_CACHE as u64 {
+            state.remember(bob, key, [2].into());
+        }
+        // The oldest was forgotten
+        assert_eq!(state.replay(alice, 7), None);
+        assert_eq!(state.replay(bob, 0).as_deref(), Some(&[2][..]));
+    }
+
+    #[test]
+    fn rate_limit() {
+        let mut state = TenantState::default();
+        let start = Instant::now();
+        let limit = 2.try_into().ok();
+        assert!(state.admit(limit, start));
+        assert!(state.admit(limit, start + Duration::from_secs(1)));
+        assert!(!state.admit(limit, start + Duration::from_secs(2)));
+        assert!(state.admit(limit, start + Duration::from_secs(61)));
+        assert!(state.admit(None, start + Duration::from_secs(62)));
+    }
+}
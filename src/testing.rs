@@ -0,0 +1,173 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Helpers to test code that talks over a connection without opening one.
+
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    io::{self, Read, Write},
+    time::Duration,
+};
+
+use crate::net::Stream;
+
+/// What a read or a write of a [`TestStream`] does.
+#[derive(Debug)]
+enum Step {
+    /// Reads return these bytes, as many as fit, leaving the rest for the next one
+    Data(Vec<u8>),
+    /// The write takes at most this many bytes
+    Take(usize),
+    Fail(io::ErrorKind),
+}
+
+/// Stream whose reads and writes follow a script, to reach the paths of
+/// partial transfers, `WouldBlock` and failures at chosen points.
+///
+/// Once their script runs out, reads return the end of the stream and writes
+/// take everything.
+#[derive(Debug, Default)]
+pub struct TestStream {
+    reads: VecDeque<Step>,
+    writes: VecDeque<Step>,
+    written: Vec<u8>,
+    read_timeout: Cell<Option<Duration>>,
+}
+
+impl TestStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Has a read return `bytes`, or as many of them as fit.
+    pub fn reading(mut self, bytes: &[u8]) -> Self {
+        self.reads.push_back(Step::Data(bytes.to_vec()));
+        self
+    }
+
+    /// Has a read fail with an error of `kind`, such as `WouldBlock`.
+    pub fn failing_read(mut self, kind: io::ErrorKind) -> Self {
+        self.reads.push_back(Step::Fail(kind));
+        self
+    }
+
+    /// Has a write take at most `len` bytes.
+    pub fn writing(mut self, len: usize) -> Self {
+        self.writes.push_back(Step::Take(len));
+        self
+    }
+
+    /// Has a write fail with an error of `kind`.
+    pub fn failing_write(mut self, kind: io::ErrorKind) -> Self {
+        self.writes.push_back(Step::Fail(kind));
+        self
+    }
+
+    /// Everything the writes took so far.
+    pub fn written(&self) -> &[u8] {
+        &self.written
+    }
+
+    /// The last timeout set with [`Stream::set_read_timeout`].
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout.get()
+    }
+}
+
+impl Read for TestStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.reads.front_mut() {
+            None => Ok(0),
+            Some(Step::Data(data)) => {
+                let len = buf.len().min(data.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                data.drain(..len);
+                if data.is_empty() {
+                    self.reads.pop_front();
+                }
+                Ok(len)
+            }
+            Some(Step::Fail(kind)) => {
+                let kind = *kind;
+                self.reads.pop_front();
+                Err(kind.into())
+            }
+            Some(Step::Take(_)) => unreachable!("only writes take bytes"),
+        }
+    }
+}
+
+impl Write for TestStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = match self.writes.pop_front() {
+            None => buf.len(),
+            Some(Step::Take(len)) => buf.len().min(len),
+            Some(Step::Fail(kind)) => return Err(kind.into()),
+            Some(Step::Data(_)) => unreachable!("only reads return data"),
+        };
+        self.written.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Stream for TestStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout.set(timeout);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+
+    use super::TestStream;
+
+    #[test]
+    fn script() {
+        let mut stream = TestStream::new()
+            .reading(b"abc")
+            .failing_read(ErrorKind::WouldBlock)
+            .reading(b"d")
+            .writing(2)
+            .failing_write(ErrorKind::Interrupted);
+
+        let mut buffer = [0; 2];
+        assert_eq!(stream.read(&mut buffer).unwrap(), 2);
+        assert_eq!(stream.read(&mut buffer).unwrap(), 1);
+        assert_eq!(buffer, *b"cb");
+        let e = stream.read(&mut buffer).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::WouldBlock);
+        assert_eq!(stream.read(&mut buffer).unwrap(), 1);
+        assert_eq!(stream.read(&mut buffer).unwrap(), 0);
+
+        assert_eq!(stream.write(b"xyz").unwrap(), 2);
+        // write_all goes on after the interruption
+        stream.write_all(b"z!").unwrap();
+        assert_eq!(stream.written(), b"xyz!");
+    }
+}
@@ -0,0 +1,183 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+use std::{
+    io::{self, Read, Write},
+    num::NonZeroU64,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{clock, Clock};
+
+/// Time worth of bytes that go at once, after an idle period.
+const BURST: Duration = Duration::from_millis(10);
+
+/// Token bucket holding the bytes that may go through without waiting.
+#[derive(Debug)]
+struct Bucket {
+    rate: NonZeroU64,
+    tokens: f64,
+    refilled: Instant,
+    clock: Arc<dyn Clock>,
+}
+
+impl Bucket {
+    fn new(rate: NonZeroU64, clock: Arc<dyn Clock>) -> Self {
+        let mut bucket = Self {
+            rate,
+            tokens: 0.0,
+            refilled: clock.now(),
+            clock,
+        };
+        bucket.tokens = bucket.capacity();
+        bucket
+    }
+
+    /// Bytes that fit in a burst, at least one.
+    fn capacity(&self) -> f64 {
+        (self.rate.get() as f64 * BURST.as_secs_f64()).max(1.0)
+    }
+
+    /// Waits until some bytes may go, returning how many, up to `wanted`.
+    fn take(&mut self, wanted: usize) -> usize {
+        loop {
+            let now = self.clock.now();
+            let elapsed = now.duration_since(self.refilled).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.rate.get() as f64).min(self.capacity());
+            self.refilled = now;
+            if self.tokens >= 1.0 {
+                let taken = wanted.min(self.tokens as usize);
+                self.tokens -= taken as f64;
+                return taken;
+            }
+            self.clock.sleep(Duration::from_secs_f64(
+                (1.0 - self.tokens) / self.rate.get() as f64,
+            ));
+        }
+    }
+
+    /// Returns the bytes taken but not used.
+    fn give_back(&mut self, unused: usize) {
+        self.tokens += unused as f64;
+    }
+}
+
+/// Paces what is read from and written to `inner` to `rate` bytes per
+/// second in each direction, so that experiments on a fast network behave as on
+/// a slow link. Only 10ms worth of bytes go at once.
+pub struct ThrottledStream<T> {
+    inner: T,
+    read: Bucket,
+    write: Bucket,
+}
+
+impl<T> ThrottledStream<T> {
+    pub fn new(inner: T, rate: NonZeroU64) -> Self {
+        Self::with_clock(inner, rate, clock::system())
+    }
+
+    /// Like [`ThrottledStream::new`], but waiting on the `clock`.
+    pub fn with_clock(inner: T, rate: NonZeroU64, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            inner,
+            read: Bucket::new(rate, Arc::clone(&clock)),
+            write: Bucket::new(rate, clock),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Read> Read for ThrottledStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let allowed = self.read.take(buf.len());
+        let read = self.inner.read(&mut buf[..allowed]);
+        self.read.give_back(allowed - *read.as_ref().unwrap_or(&0));
+        read
+    }
+}
+
+impl<T: Write> Write for ThrottledStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let allowed = self.write.take(buf.len());
+        let written = self.inner.write(&buf[..allowed]);
+        self.write
+            .give_back(allowed - *written.as_ref().unwrap_or(&0));
+        written
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use crate::{Clock, FakeClock};
+
+    use super::ThrottledStream;
+
+    #[test]
+    fn throttled_writes() {
+        let clock = FakeClock::new();
+        let rate = 10_000.try_into().unwrap();
+        let mut stream = ThrottledStream::with_clock(Vec::new(), rate, Arc::new(clock.clone()));
+        let start = clock.now();
+        // 100 bytes go in the first burst, and the other 400 take 40ms
+        stream.write_all(&[7; 500]).unwrap();
+        let elapsed = clock.since(start);
+        assert!(elapsed >= Duration::from_millis(40), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(41), "{elapsed:?}");
+        assert_eq!(stream.into_inner(), [7; 500]);
+    }
+
+    #[test]
+    fn throttled_reads() {
+        let clock = FakeClock::new();
+        let rate = 10_000.try_into().unwrap();
+        let mut stream =
+            ThrottledStream::with_clock(&[7u8; 300][..], rate, Arc::new(clock.clone()));
+        let mut buffer = [0; 300];
+        // Only a burst at a time
+        assert_eq!(stream.read(&mut buffer).unwrap(), 100);
+        let start = clock.now();
+        stream.read_exact(&mut buffer[100..]).unwrap();
+        assert!(clock.since(start) >= Duration::from_millis(20));
+        assert_eq!(buffer, [7; 300]);
+    }
+}
@@ -20,21 +20,73 @@
  *
  */
 
-use std::num::TryFromIntError;
+use std::{collections::VecDeque, num::TryFromIntError, time::Instant};
 
+use bytes::{Buf, Bytes, BytesMut};
 use thiserror::Error;
 
 #[derive(Clone, Error, Debug)]
 pub enum TlvError {
-    #[error("Unknown tag")]
-    TagUnknown(u8),
-    #[error("Wrong format for tag")]
-    WrongFormat,
+    #[error("Unknown tag {tag} at offset {offset}")]
+    TagUnknown { tag: u8, offset: usize },
+    #[error("Unknown tag name {0}")]
+    NameUnknown(String),
+    #[error(
+        "Truncated TLV at offset {offset}: it needs {needed} bytes but only {remaining} remain"
+    )]
+    Truncated {
+        offset: usize,
+        needed: usize,
+        remaining: usize,
+    },
+    #[error("Frame at offset {offset} needs {needed} bytes, over the limit of {limit}")]
+    FrameTooLarge {
+        offset: usize,
+        needed: usize,
+        limit: usize,
+    },
     #[error("Too much data to be encoded")]
     ExcessiveLength(#[from] TryFromIntError),
+    #[error("{remaining} bytes left after the TLV, at offset {offset}")]
+    Trailing { offset: usize, remaining: usize },
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// Tags of the protocol. The values are part of the wire format:
+///
+/// | Tag | Name           | Data                                 |
+/// |-----|----------------|--------------------------------------|
+/// | 1   | Sum            | two i8 operands                      |
+/// | 2   | Sub            | two i8 operands                      |
+/// | 3   | Mul            | two i8 operands                      |
+/// | 4   | Div            | i8 dividend and non-zero i8 divisor  |
+/// | 5   | Rem            | i8 dividend and non-zero i8 divisor  |
+/// | 6   | Fact           | one i8 operand                       |
+/// | 7   | DivEuclid      | i8 dividend and non-zero i8 divisor  |
+/// | 8   | RemEuclid      | i8 dividend and non-zero i8 divisor  |
+/// | 9   | SumN           | from 1 to 255 i8 operands            |
+/// | 10  | Store          | one byte with the name of a register |
+/// | 11  | Load           | one byte with the name of a register |
+/// | 16  | Numi64         | big-endian i64, plus optional flags  |
+/// | 17  | Ping           | 8 opaque bytes                       |
+/// | 18  | Pong           | the 8 bytes of the ping              |
+/// | 19  | Bye            | nothing                              |
+/// | 20  | Rejection      | one byte with the reason             |
+/// | 21  | IdempotencyKey | 8 opaque bytes, before an operation  |
+/// | 22  | GoAway         | big-endian u32, milliseconds         |
+/// | 23  | TraceContext   | trace id, parent id and flags        |
+/// | 24  | AuditQuery     | nothing                              |
+/// | 25  | AuditDigest    | received and sent SHA-256 digests    |
+/// | 26  | Hello          | u32 capabilities and API key, UTF-8  |
+/// | 27  | Batch          | operation TLVs, one after the other  |
+/// | 28  | AnswerBatch    | 9 bytes per result: value and flags  |
+/// | 29  | Leaderboard    | best players of the quizzes          |
+/// | 30  | Channel        | channel, then the whole frame        |
+/// | 31  | Progress       | one byte, percentage done            |
+/// | 32  | Cancel         | big-endian u64, number of a request  |
+/// | 33  | Auth           | key ID, nonce and MAC                |
+/// | 34  | Deadline       | big-endian u32, milliseconds left    |
+/// | 35  | Priority       | one byte, higher is more urgent      |
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum TlvType {
     Sum = 1,
     Sub = 2,
@@ -42,27 +94,161 @@ pub enum TlvType {
     Div = 4,
     Rem = 5,
     Fact = 6,
+    DivEuclid = 7,
+    RemEuclid = 8,
+    SumN = 9,
+    Store = 10,
+    Load = 11,
     Numi64 = 16,
+    Ping = 17,
+    Pong = 18,
+    Bye = 19,
+    Rejection = 20,
+    IdempotencyKey = 21,
+    GoAway = 22,
+    TraceContext = 23,
+    AuditQuery = 24,
+    AuditDigest = 25,
+    Hello = 26,
+    Batch = 27,
+    AnswerBatch = 28,
+    Leaderboard = 29,
+    Channel = 30,
+    Progress = 31,
+    Cancel = 32,
+    Auth = 33,
+    Deadline = 34,
+    Priority = 35,
 }
 
+impl TlvType {
+    /// Every tag, in increasing order of value.
+    pub const ALL: &'static [TlvType] = &[
+        TlvType::Sum,
+        TlvType::Sub,
+        TlvType::Mul,
+        TlvType::Div,
+        TlvType::Rem,
+        TlvType::Fact,
+        TlvType::DivEuclid,
+        TlvType::RemEuclid,
+        TlvType::SumN,
+        TlvType::Store,
+        TlvType::Load,
+        TlvType::Numi64,
+        TlvType::Ping,
+        TlvType::Pong,
+        TlvType::Bye,
+        TlvType::Rejection,
+        TlvType::IdempotencyKey,
+        TlvType::GoAway,
+        TlvType::TraceContext,
+        TlvType::AuditQuery,
+        TlvType::AuditDigest,
+        TlvType::Hello,
+        TlvType::Batch,
+        TlvType::AnswerBatch,
+        TlvType::Leaderboard,
+        TlvType::Channel,
+        TlvType::Progress,
+        TlvType::Cancel,
+        TlvType::Auth,
+        TlvType::Deadline,
+        TlvType::Priority,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TlvType::Sum => "Sum",
+            TlvType::Sub => "Sub",
+            TlvType::Mul => "Mul",
+            TlvType::Div => "Div",
+            TlvType::Rem => "Rem",
+            TlvType::Fact => "Fact",
+            TlvType::DivEuclid => "DivEuclid",
+            TlvType::RemEuclid => "RemEuclid",
+            TlvType::SumN => "SumN",
+            TlvType::Store => "Store",
+            TlvType::Load => "Load",
+            TlvType::Numi64 => "Numi64",
+            TlvType::Ping => "Ping",
+            TlvType::Pong => "Pong",
+            TlvType::Bye => "Bye",
+            TlvType::Rejection => "Rejection",
+            TlvType::IdempotencyKey => "IdempotencyKey",
+            TlvType::GoAway => "GoAway",
+            TlvType::TraceContext => "TraceContext",
+            TlvType::AuditQuery => "AuditQuery",
+            TlvType::AuditDigest => "AuditDigest",
+            TlvType::Hello => "Hello",
+            TlvType::Batch => "Batch",
+            TlvType::AnswerBatch => "AnswerBatch",
+            TlvType::Leaderboard => "Leaderboard",
+            TlvType::Channel => "Channel",
+            TlvType::Progress => "Progress",
+            TlvType::Cancel => "Cancel",
+            TlvType::Auth => "Auth",
+            TlvType::Deadline => "Deadline",
+            TlvType::Priority => "Priority",
+        }
+    }
+}
+
+impl TlvError {
+    /// Moves the offsets `base` bytes forward, for errors found in the middle of a stream.
+    fn shifted(self, base: usize) -> Self {
+        match self {
+            TlvError::TagUnknown { tag, offset } => TlvError::TagUnknown {
+                tag,
+                offset: base + offset,
+            },
+            TlvError::Truncated {
+                offset,
+                needed,
+                remaining,
+            } => TlvError::Truncated {
+                offset: base + offset,
+                needed,
+                remaining,
+            },
+            e => e,
+        }
+    }
+}
+
+/// The tag of every byte, `None` for those that are not one.
+static TAGS: [Option<TlvType>; 256] = {
+    let mut tags = [None; 256];
+    let mut i = 0;
+    while i < TlvType::ALL.len() {
+        tags[TlvType::ALL[i] as usize] = Some(TlvType::ALL[i]);
+        i += 1;
+    }
+    tags
+};
+
 impl TryFrom<u8> for TlvType {
     type Error = TlvError;
 
     fn try_from(v: u8) -> Result<Self, Self::Error> {
-        match v {
-            x if x == TlvType::Sum as u8 => Ok(TlvType::Sum),
-            x if x == TlvType::Sub as u8 => Ok(TlvType::Sub),
-            x if x == TlvType::Mul as u8 => Ok(TlvType::Mul),
-            x if x == TlvType::Div as u8 => Ok(TlvType::Div),
-            x if x == TlvType::Rem as u8 => Ok(TlvType::Rem),
-            x if x == TlvType::Fact as u8 => Ok(TlvType::Fact),
-            x if x == TlvType::Numi64 as u8 => Ok(TlvType::Numi64),
-            x => Err(TlvError::TagUnknown(x)),
-        }
+        TAGS[usize::from(v)].ok_or(TlvError::TagUnknown { tag: v, offset: 0 })
+    }
+}
+
+/// Looks up a tag by its name, ignoring case.
+impl TryFrom<&str> for TlvType {
+    type Error = TlvError;
+
+    fn try_from(name: &str) -> Result<Self, Self::Error> {
+        TlvType::ALL
+            .iter()
+            .find(|tag| tag.name().eq_ignore_ascii_case(name))
+            .copied()
+            .ok_or_else(|| TlvError::NameUnknown(name.to_string()))
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Tlv<'a> {
     pub tag: TlvType,
     pub length: u8,
@@ -78,6 +264,18 @@ impl<'a> Tlv<'a> {
         })
     }
 
+    /// Parses a buffer that holds exactly one TLV, with nothing after it.
+    pub fn whole(bytes: &'a [u8]) -> Result<Self, TlvError> {
+        let tlv = Tlv::try_from(bytes)?;
+        match bytes.len() - (2 + tlv.length as usize) {
+            0 => Ok(tlv),
+            remaining => Err(TlvError::Trailing {
+                offset: 2 + tlv.length as usize,
+                remaining,
+            }),
+        }
+    }
+
     pub fn encode(self) -> Box<[u8]> {
         [self.tag as u8, self.length]
             .iter()
@@ -91,25 +289,176 @@ impl<'a> TryFrom<&'a [u8]> for Tlv<'a> {
     type Error = TlvError;
 
     fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
+        let needed = 2 + bytes.get(1).copied().unwrap_or_default() as usize;
         match bytes.len() {
-            2.. if bytes.len() >= (bytes[1] + 2).into() => Ok(Tlv {
+            2.. if bytes.len() >= needed => Ok(Tlv {
                 tag: bytes[0].try_into()?,
                 length: bytes[1],
-                data: &bytes[2..(2 + bytes[1]).into()],
+                data: &bytes[2..needed],
+            }),
+            remaining => Err(TlvError::Truncated {
+                offset: 0,
+                needed,
+                remaining,
             }),
-            _ => Err(TlvError::WrongFormat),
         }
     }
 }
 
+/// An owned TLV, as produced by the [`Decoder`]. Its data shares the buffer of
+/// the decoder, so it can be queued or sent to another thread without copies.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub tag: TlvType,
+    pub data: Bytes,
+    /// When the last byte of the frame was handed to the decoder.
+    pub received: Instant,
+}
+
+impl Frame {
+    pub fn as_tlv(&self) -> Tlv<'_> {
+        Tlv {
+            tag: self.tag,
+            length: self.data.len() as u8,
+            data: &self.data,
+        }
+    }
+}
+
+/// Reassembles TLVs from a stream that may split them at any point.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: BytesMut,
+    offset: usize,
+    /// Stream position after each chunk still in the buffer, and when it arrived
+    arrivals: VecDeque<(usize, Instant)>,
+    /// Largest frame buffered, header included
+    limit: Option<usize>,
+    /// Bytes of a frame over the limit still to be dropped
+    skip: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Like [`Decoder::new`], but frames over `limit` bytes, header included,
+    /// are reported as [`TlvError::FrameTooLarge`] and dropped instead of buffered.
+    pub fn with_limit(limit: usize) -> Self {
+        Self {
+            limit: Some(limit),
+            ..Self::default()
+        }
+    }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.extend_at(bytes, Instant::now());
+    }
+
+    /// Like [`Decoder::extend`], for bytes that arrived at `now`, as told by a
+    /// [`Clock`](crate::Clock).
+    pub fn extend_at(&mut self, bytes: &[u8], now: Instant) {
+        let skipped = self.skip.min(bytes.len());
+        self.skip -= skipped;
+        self.offset += skipped;
+        let bytes = &bytes[skipped..];
+        if bytes.is_empty() {
+            return;
+        }
+
+        self.buffer.extend_from_slice(bytes);
+        self.arrivals
+            .push_back((self.offset + self.buffer.len(), now));
+    }
+
+    /// Number of received bytes not yet returned as part of a frame.
+    pub fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// The received bytes not yet returned as part of a frame.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Position in the stream where the next frame starts.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the next complete TLV, or `None` if more data is needed.
+    ///
+    /// A TLV with an unknown tag, or over the limit, is consumed before reporting
+    /// the error, so decoding can go on with the next one.
+    pub fn next_frame(&mut self) -> Result<Option<Frame>, TlvError> {
+        let Some(&length) = self.buffer.get(1) else {
+            return Ok(None);
+        };
+        let length = length as usize;
+        if let Some(limit) = self.limit.filter(|&limit| 2 + length > limit) {
+            let offset = self.offset;
+            let buffered = self.buffer.len().min(2 + length);
+            self.buffer.advance(buffered);
+            self.offset += buffered;
+            self.skip = 2 + length - buffered;
+            return Err(TlvError::FrameTooLarge {
+                offset,
+                needed: 2 + length,
+                limit,
+            });
+        }
+        if self.buffer.len() < 2 + length {
+            return Ok(None);
+        }
+
+        let mut bytes = self.buffer.split_to(2 + length).freeze();
+        let offset = self.offset;
+        self.offset += bytes.len();
+        let received = self.completed(self.offset);
+        Ok(Some(Frame {
+            tag: TlvType::try_from(bytes[0]).map_err(|e| e.shifted(offset))?,
+            data: bytes.split_off(2),
+            received,
+        }))
+    }
+
+    /// Returns when the stream reached position `end`, forgetting the chunks
+    /// that no longer hold pending bytes.
+    fn completed(&mut self, end: usize) -> Instant {
+        while self.arrivals.front().is_some_and(|&(until, _)| until < end) {
+            self.arrivals.pop_front();
+        }
+        self.arrivals
+            .front()
+            .expect("the bytes of a frame arrived at some point")
+            .1
+    }
+}
+
 pub struct TlvIterator<'a> {
-    buf: &'a [u8],
-    index: usize,
+    raw: RawTlvIterator<'a>,
 }
 
 impl<'a> TlvIterator<'a> {
     pub fn process(buf: &'a [u8]) -> Self {
-        Self { buf, index: 0 }
+        Self {
+            raw: RawTlvIterator {
+                buf,
+                index: 0,
+                error: None,
+            },
+        }
+    }
+
+    /// Why the iteration stopped before the end of the buffer, if it did.
+    pub fn error(&self) -> Option<&TlvError> {
+        self.raw.error()
+    }
+
+    /// Iterates the rest of the TLVs as their tag byte and data.
+    pub fn raw(self) -> RawTlvIterator<'a> {
+        self.raw
     }
 }
 
@@ -117,19 +466,70 @@ impl<'a> Iterator for TlvIterator<'a> {
     type Item = Tlv<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match Tlv::try_from(&self.buf[self.index..]) {
-            Ok(tlv) => {
-                self.index += 2 + tlv.length as usize;
-                Some(tlv)
-            }
-            Err(_) => None,
+        let (tag, data) = self.raw.next()?;
+        Some(Tlv {
+            tag: TAGS[usize::from(tag)].expect("the raw iterator checks the tags"),
+            length: data.len() as u8,
+            data,
+        })
+    }
+}
+
+/// Iterates the TLVs of a buffer as `(tag, data)`, without building a [`Tlv`]
+/// for each, for the paths that go through millions of tiny frames. The tags
+/// are checked with a table, and it stops at the first unknown or truncated
+/// TLV, as the [`TlvIterator`] it comes from.
+pub struct RawTlvIterator<'a> {
+    buf: &'a [u8],
+    index: usize,
+    error: Option<TlvError>,
+}
+
+impl RawTlvIterator<'_> {
+    /// Why the iteration stopped before the end of the buffer, if it did.
+    pub fn error(&self) -> Option<&TlvError> {
+        self.error.as_ref()
+    }
+}
+
+impl<'a> Iterator for RawTlvIterator<'a> {
+    type Item = (u8, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.buf[self.index..];
+        if rest.is_empty() || self.error.is_some() {
+            return None;
         }
+
+        let needed = 2 + rest.get(1).copied().unwrap_or_default() as usize;
+        if rest.len() < needed {
+            self.error = Some(TlvError::Truncated {
+                offset: self.index,
+                needed,
+                remaining: rest.len(),
+            });
+            return None;
+        }
+        let tag = rest[0];
+        if TAGS[usize::from(tag)].is_none() {
+            self.error = Some(TlvError::TagUnknown {
+                tag,
+                offset: self.index,
+            });
+            return None;
+        }
+        self.index += needed;
+        Some((tag, &rest[2..needed]))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{Tlv, TlvIterator};
+    use std::{thread, time::Duration};
+
+    use crate::{Tlv, TlvIterator, TlvType};
+
+    use super::{Decoder, TlvError};
 
     #[test]
     fn parse_tlv_err_long() {
@@ -139,7 +539,81 @@ mod tests {
 
     #[test]
     fn parse_tlv_err_type() {
-        let tlv: Result<Tlv, _> = (&[20u8, 8, 0, 0, 0, 0, 0, 0, 0, 1][..]).try_into();
+        let tlv: Result<Tlv, _> = (&[42u8, 8, 0, 0, 0, 0, 0, 0, 0, 1][..]).try_into();
+        assert!(tlv.is_err());
+    }
+
+    #[test]
+    fn whole() {
+        assert_eq!(Tlv::whole(&[19u8, 0]).unwrap().tag, TlvType::Bye);
+        assert!(matches!(
+            Tlv::whole(&[19u8, 0, 19, 0, 19]),
+            Err(TlvError::Trailing {
+                offset: 2,
+                remaining: 3
+            })
+        ));
+        assert!(matches!(
+            Tlv::whole(&[16u8, 8, 0]),
+            Err(TlvError::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn error_offsets() {
+        let mut iterator = TlvIterator::process(&[19u8, 0, 16, 8, 0, 0]);
+        assert!(iterator.next().is_some());
+        assert!(iterator.next().is_none());
+        assert!(matches!(
+            iterator.error(),
+            Some(TlvError::Truncated {
+                offset: 2,
+                needed: 10,
+                remaining: 4
+            })
+        ));
+
+        let mut iterator = TlvIterator::process(&[19u8, 0]);
+        assert!(iterator.next().is_some());
+        assert!(iterator.next().is_none());
+        assert!(iterator.error().is_none());
+
+        let mut decoder = Decoder::new();
+        decoder.extend(&[19u8, 0, 19, 0, 42, 0]);
+        decoder.next_frame().unwrap();
+        decoder.next_frame().unwrap();
+        assert!(matches!(
+            decoder.next_frame(),
+            Err(TlvError::TagUnknown { tag: 42, offset: 4 })
+        ));
+        assert_eq!(decoder.offset(), 6);
+    }
+
+    #[test]
+    fn raw() {
+        let bytes = [19u8, 0, 16, 2, 0, 7, 42, 0];
+        let mut iterator = TlvIterator::process(&bytes);
+        assert_eq!(iterator.next().unwrap().tag, TlvType::Bye);
+        let mut raw = iterator.raw();
+        assert_eq!(raw.next(), Some((16, &[0, 7][..])));
+        assert_eq!(raw.next(), None);
+        assert!(matches!(
+            raw.error(),
+            Some(TlvError::TagUnknown { tag: 42, offset: 6 })
+        ));
+
+        // Every byte is a tag to the table only if it is one to the enum
+        for byte in 0..=u8::MAX {
+            let known = TlvType::ALL.iter().any(|&tag| tag as u8 == byte);
+            assert_eq!(TlvType::try_from(byte).is_ok(), known);
+            let frame = [byte, 0];
+            assert_eq!(TlvIterator::process(&frame).raw().next().is_some(), known);
+        }
+    }
+
+    #[test]
+    fn parse_tlv_err_huge_length() {
+        let tlv: Result<Tlv, _> = (&[16u8, 255, 0][..]).try_into();
         assert!(tlv.is_err());
     }
 
@@ -191,4 +665,136 @@ mod tests {
         );
         assert_eq!(iterator.next(), None);
     }
+
+    #[test]
+    fn decoder_reassembles() {
+        let mut decoder = Decoder::new();
+        decoder.extend(&[16u8, 8, 0, 0]);
+        assert_eq!(decoder.next_frame().unwrap(), None);
+        decoder.extend(&[0, 0, 0, 0, 0, 1, 17]);
+        let frame = decoder.next_frame().unwrap().unwrap();
+        assert_eq!(frame.tag, TlvType::Numi64);
+        assert_eq!(frame.data[..], [0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(decoder.next_frame().unwrap(), None);
+        assert_eq!(decoder.pending(), 1);
+    }
+
+    #[test]
+    fn decoder_limit() {
+        let mut decoder = Decoder::with_limit(10);
+        // A header announcing more than the limit, with the data still to come
+        decoder.extend(&[27]);
+        assert_eq!(decoder.next_frame().unwrap(), None);
+        decoder.extend(&[200, 1, 2]);
+        assert!(matches!(
+            decoder.next_frame(),
+            Err(TlvError::FrameTooLarge {
+                offset: 0,
+                needed: 202,
+                limit: 10
+            })
+        ));
+        assert_eq!(decoder.pending(), 0);
+
+        // Its data are dropped as they arrive, never buffered
+        for _ in 0..19 {
+            decoder.extend(&[0; 10]);
+            assert_eq!(decoder.pending(), 0);
+            assert_eq!(decoder.next_frame().unwrap(), None);
+        }
+        decoder.extend(&[0; 8]);
+        assert_eq!(decoder.next_frame().unwrap(), None);
+        decoder.extend(&[19, 0]);
+        assert_eq!(decoder.next_frame().unwrap().unwrap().tag, TlvType::Bye);
+        assert_eq!(decoder.offset(), 204);
+
+        // Also when the whole frame arrives at once, followed by another one
+        let mut oversized = vec![1u8, 9];
+        oversized.extend([0; 9]);
+        oversized.extend([1, 2, 3, 4]);
+        decoder.extend(&oversized);
+        assert!(matches!(
+            decoder.next_frame(),
+            Err(TlvError::FrameTooLarge { offset: 204, .. })
+        ));
+        assert_eq!(decoder.next_frame().unwrap().unwrap().tag, TlvType::Sum);
+        assert_eq!(decoder.next_frame().unwrap(), None);
+
+        // Frames right at the limit go through
+        decoder.extend(&[16, 8, 0, 0, 0, 0, 0, 0, 0, 7]);
+        assert_eq!(decoder.next_frame().unwrap().unwrap().tag, TlvType::Numi64);
+    }
+
+    #[test]
+    fn decoder_timestamps() {
+        let mut decoder = Decoder::new();
+        decoder.extend(&[17, 0, 19]);
+        let first = decoder.next_frame().unwrap().unwrap();
+        thread::sleep(Duration::from_millis(10));
+        decoder.extend(&[0, 17, 0]);
+        // Received when its last byte arrived, not its first
+        let second = decoder.next_frame().unwrap().unwrap();
+        assert!(second.received >= first.received + Duration::from_millis(10));
+        assert_eq!(
+            decoder.next_frame().unwrap().unwrap().received,
+            second.received
+        );
+    }
+
+    #[test]
+    fn tag_table() {
+        let table = [
+            (1u8, "Sum"),
+            (2, "Sub"),
+            (3, "Mul"),
+            (4, "Div"),
+            (5, "Rem"),
+            (6, "Fact"),
+            (7, "DivEuclid"),
+            (8, "RemEuclid"),
+            (9, "SumN"),
+            (10, "Store"),
+            (11, "Load"),
+            (16, "Numi64"),
+            (17, "Ping"),
+            (18, "Pong"),
+            (19, "Bye"),
+            (20, "Rejection"),
+            (21, "IdempotencyKey"),
+            (22, "GoAway"),
+            (23, "TraceContext"),
+            (24, "AuditQuery"),
+            (25, "AuditDigest"),
+            (26, "Hello"),
+            (27, "Batch"),
+            (28, "AnswerBatch"),
+            (29, "Leaderboard"),
+            (30, "Channel"),
+            (31, "Progress"),
+            (32, "Cancel"),
+            (33, "Auth"),
+            (34, "Deadline"),
+            (35, "Priority"),
+        ];
+        assert_eq!(TlvType::ALL.len(), table.len());
+        for (&tag, (value, name)) in TlvType::ALL.iter().zip(table) {
+            assert_eq!(tag as u8, value);
+            assert_eq!(tag.name(), name);
+            assert_eq!(TlvType::try_from(value).unwrap(), tag);
+            assert_eq!(TlvType::try_from(name).unwrap(), tag);
+        }
+        assert_eq!(TlvType::try_from("numi64").unwrap(), TlvType::Numi64);
+        assert!(TlvType::try_from("Answer").is_err());
+        assert!((0..=u8::MAX)
+            .filter(|v| !table.iter().any(|(value, _)| value == v))
+            .all(|v| TlvType::try_from(v).is_err()));
+    }
+
+    #[test]
+    fn decoder_skips_unknown_tag() {
+        let mut decoder = Decoder::new();
+        decoder.extend(&[42u8, 1, 0, 19, 0]);
+        assert!(decoder.next_frame().is_err());
+        assert_eq!(decoder.next_frame().unwrap().unwrap().tag, TlvType::Bye);
+    }
 }
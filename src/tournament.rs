@@ -0,0 +1,105 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use crate::{Leaderboard, Standing};
+
+/// Scores of the quizzes of a [`crate::Server`], by address of the player, kept
+/// across connections so that every quiz is a round of a tournament. Clones
+/// share the scores, so it also reads them from other threads.
+#[derive(Clone, Debug, Default)]
+pub struct Tournament(Arc<Mutex<HashMap<IpAddr, Standing>>>);
+
+impl Tournament {
+    /// Adds the outcome of a quiz of `player` to its standing.
+    pub(crate) fn record(&self, player: IpAddr, right: usize, asked: usize) {
+        let mut standings = self.0.lock().unwrap();
+        let standing = standings.entry(player).or_insert_with(|| Standing {
+            player: player.to_string(),
+            ..Default::default()
+        });
+        standing.rounds = standing.rounds.saturating_add(1);
+        standing.right = standing
+            .right
+            .saturating_add(right.try_into().unwrap_or(u16::MAX));
+        standing.asked = standing
+            .asked
+            .saturating_add(asked.try_into().unwrap_or(u16::MAX));
+    }
+
+    /// Every player, best first: most right answers, then fewest asked.
+    pub fn standings(&self) -> Vec<Standing> {
+        let mut standings: Vec<Standing> = self.0.lock().unwrap().values().cloned().collect();
+        standings.sort_by(|a, b| {
+            b.right
+                .cmp(&a.right)
+                .then(a.asked.cmp(&b.asked))
+                .then_with(|| a.player.cmp(&b.player))
+        });
+
+        standings
+    }
+
+    /// The best players, as many as fit in a [`Leaderboard`].
+    pub fn leaderboard(&self) -> Leaderboard {
+        let mut standings = self.standings();
+        standings.truncate(Leaderboard::MAX_STANDINGS);
+
+        Leaderboard(standings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::Tournament;
+
+    #[test]
+    fn standings() {
+        let tournament = Tournament::default();
+        let first = IpAddr::from(Ipv4Addr::new(10, 0, 0, 1));
+        let second = IpAddr::from(Ipv6Addr::LOCALHOST);
+        tournament.record(first, 3, 5);
+        tournament.record(second, 4, 5);
+        tournament.record(first, 5, 5);
+
+        let standings = tournament.standings();
+        assert_eq!(standings[0].player, "10.0.0.1");
+        assert_eq!(
+            (standings[0].rounds, standings[0].right, standings[0].asked),
+            (2, 8, 10)
+        );
+        assert_eq!(standings[1].player, "::1");
+
+        for last in 2..10 {
+            tournament.record(IpAddr::from(Ipv4Addr::new(10, 0, 0, last)), 0, 1);
+        }
+        assert_eq!(tournament.standings().len(), 10);
+        assert_eq!(tournament.leaderboard().0[..2], standings[..]);
+    }
+}
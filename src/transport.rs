@@ -0,0 +1,106 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Protocol logic shared by the transports that carry every request whole, in
+//! a message or a stream of its own, so that no reassembly is needed. They use
+//! the same TLVs as TCP, but have no session.
+use crate::{Answer, Operation, Ping, Pong, Rejection, TCPLibError, Tlv, TlvIterator, TlvType};
+
+/// Answers the TLVs of a request, like the TCP server does.
+pub(crate) fn reply(acc: &mut i64, request: &[u8]) -> Vec<u8> {
+    let mut reply = Vec::new();
+    for tlv in TlvIterator::process(request) {
+        answer(acc, tlv, &mut reply);
+    }
+
+    reply
+}
+
+/// Appends the answer to a single TLV to `reply`, if it has one.
+fn answer(acc: &mut i64, tlv: Tlv, reply: &mut Vec<u8>) {
+    match tlv.tag {
+        TlvType::Ping => match Ping::try_from(tlv) {
+            Ok(ping) => reply.extend_from_slice(&Pong::from(ping).encode()),
+            Err(e) => eprintln!("Invalid ping. {e}"),
+        },
+        // Nothing to do with them without a session
+        TlvType::TraceContext | TlvType::IdempotencyKey => (),
+        _ => match Operation::try_from(tlv).and_then(|op| op.reduce()) {
+            Ok(result) => {
+                let answer = Answer::accumulate(*acc, result);
+                *acc = answer.value;
+                reply.extend_from_slice(&answer.encode());
+            }
+            Err(e) => reply.extend_from_slice(&Rejection::from(&e).encode()),
+        },
+    }
+}
+
+/// Reads the answer to an operation, or its rejection.
+pub(crate) fn parse_answer(message: &[u8]) -> Result<Result<Answer, Rejection>, TCPLibError> {
+    let tlv = TlvIterator::process(message)
+        .next()
+        .ok_or(TCPLibError::Generic)?;
+    match tlv.tag {
+        TlvType::Rejection => Ok(Err(tlv.try_into()?)),
+        _ => Ok(Ok(tlv.try_into()?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_answer, reply};
+    use crate::{Answer, Operation, Ping, Pong, Rejection, Tlv, TlvType};
+
+    #[test]
+    fn reply_like_tcp() {
+        let mut acc = i64::MAX - 1;
+        let request = [
+            Ping(*b"12345678").encode(),
+            "3 + 4".parse::<Operation>().unwrap().encode(),
+            Tlv::new(TlvType::Fact, &[21]).unwrap().encode(),
+        ]
+        .concat();
+        assert_eq!(
+            reply(&mut acc, &request),
+            [
+                Pong(*b"12345678").encode(),
+                Answer::saturated(i64::MAX).encode(),
+                Rejection::WrongDomain.encode(),
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn answer_or_rejection() {
+        assert_eq!(
+            parse_answer(&Answer::from(7).encode()).unwrap(),
+            Ok(7.into())
+        );
+        assert_eq!(
+            parse_answer(&Rejection::Overflow.encode()).unwrap(),
+            Err(Rejection::Overflow)
+        );
+        assert!(parse_answer(&[]).is_err());
+    }
+}
@@ -0,0 +1,497 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+/*
+ *
+ * Copyright (c) 2023 Universidade de Vigo
+ *
+ * This program is free software; you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 2 as
+ * published by the Free Software Foundation;
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program; if not, write to the Free Software
+ * Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA  02111-1307  USA
+ *
+ * Author: Miguel Rodríguez Pérez <miguel@det.uvigo.gal>
+ *
+ */
+
+//! Experimental TCP server for Linux built on io_uring. A single thread queues
+//! the accepts, reads and writes of every connection in the ring and handles
+//! them as they complete, so it serves all the clients at once instead of one
+//! after the other. It reassembles the TLVs with the same [`Decoder`] as the
+//! TCP server and answers them with the same chain of middlewares, but without
+//! a session: every connection has an accumulator of its own, and the options
+//! that need a hello or time to pass, such as tenants or delays, do not apply.
+//! Batches, channels, audits and authentication are rejected as disabled.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io,
+    net::{Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    os::fd::{AsRawFd, FromRawFd},
+    ptr,
+    time::{Duration, Instant},
+};
+
+use io_uring::{opcode, squeue, types, IoUring};
+use nix::errno::Errno;
+use socket2::{Domain, Socket, Type};
+
+use crate::{
+    middleware::{self, Handler, Request},
+    net::canonical_peer,
+    server::{is_fd_exhaustion, spare_fd, Registers, EXHAUSTION_BACKOFF},
+    tenant::TenantState,
+    Bye, Capabilities, Deadline, Decoder, Frame, Hello, IdempotencyKey, Ping, Pong, Priority,
+    Rejection, ServerConfig, TlvType,
+};
+
+/// Operations in flight at once, at most.
+const ENTRIES: u32 = 256;
+
+/// Largest read of a connection at once.
+const MAX_READ: usize = 2048;
+
+/// What a completion is about, in the low bits of its user data. The rest is
+/// the index of its connection.
+const ACCEPT: u64 = 0;
+const RECV: u64 = 1;
+const SEND: u64 = 2;
+const BACKOFF: u64 = 3;
+
+struct Connection {
+    stream: TcpStream,
+    peer: SocketAddr,
+    decoder: Decoder,
+    /// With the accumulator of the connection
+    state: TenantState,
+    registers: Registers,
+    /// Where the kernel leaves what it receives
+    buffer: Box<[u8; MAX_READ]>,
+    /// Answers not yet sent
+    out: Vec<u8>,
+    /// For the next operation
+    pending_deadline: Option<Instant>,
+    pending_key: Option<u64>,
+    /// The client said goodbye, so close once `out` is sent
+    closing: bool,
+}
+
+/// Calculator server over TCP on io_uring, serving all the clients at once.
+pub struct UringServer {
+    config: ServerConfig,
+    listener: TcpListener,
+    ring: IoUring,
+    /// Operations waiting for room in the ring, in order
+    pending: VecDeque<squeue::Entry>,
+    /// Freed to accept and close a connection when out of file descriptors
+    spare_fd: Option<File>,
+    /// Wait before accepting again after a failed accept
+    backoff: types::Timespec,
+    /// By the index in the user data of their operations
+    connections: Vec<Option<Connection>>,
+}
+
+impl UringServer {
+    /// Listens on the port of `config`, whose middlewares answer the operations.
+    pub fn bind(config: ServerConfig) -> io::Result<Self> {
+        let listener = Socket::new(Domain::IPV6, Type::STREAM, None)?;
+        listener.set_only_v6(false)?;
+        listener.set_reuse_address(true)?;
+        listener.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, config.port)).into())?;
+        listener.listen(128)?;
+
+        Ok(Self {
+            config,
+            listener: listener.into(),
+            ring: IoUring::new(ENTRIES)?,
+            pending: VecDeque::new(),
+            spare_fd: spare_fd(),
+            backoff: types::Timespec::from(EXHAUSTION_BACKOFF),
+            connections: Vec::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serves clients, all at once, forever, unless the ring fails.
+    pub fn run(&mut self) -> io::Result<()> {
+        self.accept();
+        loop {
+            self.refill();
+            match self.ring.submit_and_wait(1) {
+                Ok(_) => (),
+                Err(e) => match e.raw_os_error().map(Errno::from_raw) {
+                    Some(Errno::EINTR) => continue,
+                    // The kernel waits for room for the completions, taken next
+                    Some(Errno::EBUSY | Errno::EAGAIN) => {
+                        eprintln!("The ring is busy, handling what completed first. {e}")
+                    }
+                    _ => return Err(e),
+                },
+            }
+            let completed: Vec<_> = self
+                .ring
+                .completion()
+                .map(|entry| (entry.user_data(), entry.result()))
+                .collect();
+            for (user_data, result) in completed {
+                let index = (user_data >> 2) as usize;
+                match user_data & 3 {
+                    ACCEPT => self.accepted(result),
+                    RECV => self.received(index, result),
+                    SEND => self.sent(index, result),
+                    _ => self.accept(),
+                }
+            }
+        }
+    }
+
+    /// Queues `entry` in the ring, or after the others waiting for room in it.
+    fn push(&mut self, entry: squeue::Entry) {
+        self.pending.push_back(entry);
+        self.refill();
+    }
+
+    /// Moves as many of the pending operations to the ring as fit.
+    fn refill(&mut self) {
+        while let Some(entry) = self.pending.front() {
+            // SAFETY: the buffers of the operations belong to their connection,
+            // which is only dropped once they complete, and the backoff to the
+            // server, that runs until the ring is dropped
+            if unsafe { self.ring.submission().push(entry) }.is_err() {
+                break;
+            }
+            self.pending.pop_front();
+        }
+    }
+
+    fn accept(&mut self) {
+        let fd = types::Fd(self.listener.as_raw_fd());
+        let accept = opcode::Accept::new(fd, ptr::null_mut(), ptr::null_mut());
+        self.push(accept.build().user_data(ACCEPT))
+    }
+
+    /// Accepts again after a while, so that a failing accept does not spin.
+    fn accept_later(&mut self) {
+        let timeout = opcode::Timeout::new(&self.backoff);
+        self.push(timeout.build().user_data(BACKOFF))
+    }
+
+    fn recv(&mut self, index: usize) {
+        let connection = self.connection(index);
+        let fd = types::Fd(connection.stream.as_raw_fd());
+        let recv = opcode::Recv::new(fd, connection.buffer.as_mut_ptr(), MAX_READ as u32);
+        self.push(recv.build().user_data((index as u64) << 2 | RECV))
+    }
+
+    fn send(&mut self, index: usize) {
+        let connection = self.connection(index);
+        let fd = types::Fd(connection.stream.as_raw_fd());
+        let send = opcode::Send::new(fd, connection.out.as_ptr(), connection.out.len() as u32);
+        self.push(send.build().user_data((index as u64) << 2 | SEND))
+    }
+
+    fn connection(&mut self, index: usize) -> &mut Connection {
+        self.connections[index]
+            .as_mut()
+            .expect("a connection is only closed when none of its operations is in flight")
+    }
+
+    fn accepted(&mut self, result: i32) {
+        if result < 0 {
+            let e = io::Error::from_raw_os_error(-result);
+            if is_fd_exhaustion(&e) {
+                // Without blocking the ring, if the pending connection is gone
+                self.spare_fd = None;
+                let accepted = self.listener.set_nonblocking(true).and_then(|_| {
+                    let accepted = self.listener.accept();
+                    self.listener.set_nonblocking(false)?;
+                    accepted
+                });
+                match accepted {
+                    Ok((_, addr)) => eprintln!(
+                        "Out of file descriptors, closing {}. {e}",
+                        canonical_peer(addr)
+                    ),
+                    Err(_) => eprintln!("Out of file descriptors. {e}"),
+                }
+                self.spare_fd = spare_fd();
+            } else {
+                eprintln!("Could not accept a connection. {e}");
+            }
+            return self.accept_later();
+        }
+
+        // SAFETY: the descriptor was just created by the accept, for us alone
+        let stream = unsafe { TcpStream::from_raw_fd(result) };
+        let peer = match stream.peer_addr() {
+            Ok(peer) => canonical_peer(peer),
+            Err(e) => {
+                eprintln!("Connection closed before serving it. {e}");
+                return self.accept();
+            }
+        };
+        println!("New connection from {peer}");
+        let connection = Connection {
+            stream,
+            peer,
+            decoder: Decoder::new(),
+            state: TenantState::default(),
+            registers: Registers::default(),
+            buffer: Box::new([0; MAX_READ]),
+            out: Vec::new(),
+            pending_deadline: None,
+            pending_key: None,
+            closing: false,
+        };
+        let index = match self.connections.iter().position(Option::is_none) {
+            Some(free) => {
+                self.connections[free] = Some(connection);
+                free
+            }
+            None => {
+                self.connections.push(Some(connection));
+                self.connections.len() - 1
+            }
+        };
+
+        self.recv(index);
+        self.accept()
+    }
+
+    fn received(&mut self, index: usize, result: i32) {
+        let len = match usize::try_from(result) {
+            Ok(0) => return self.close(index, "closed without saying goodbye"),
+            Ok(len) => len,
+            Err(_) => {
+                let e = io::Error::from_raw_os_error(-result);
+                return self.close(index, &format!("aborted. {e}"));
+            }
+        };
+
+        let connection = self.connections[index]
+            .as_mut()
+            .expect("a connection is only closed when none of its operations is in flight");
+        connection.decoder.extend(&connection.buffer[..len]);
+        loop {
+            match connection.decoder.next_frame() {
+                Ok(Some(frame)) if frame.tag == TlvType::Bye => {
+                    connection.out.extend_from_slice(&Bye.encode());
+                    connection.closing = true;
+                    break;
+                }
+                Ok(Some(frame)) => connection.answer(&self.config, &frame),
+                Ok(None) => break,
+                Err(e) => eprintln!("Ignoring message from {}. {e}", connection.peer),
+            }
+        }
+
+        match connection.out.is_empty() {
+            true => self.recv(index),
+            false => self.send(index),
+        }
+    }
+
+    fn sent(&mut self, index: usize, result: i32) {
+        let Ok(len) = usize::try_from(result) else {
+            let e = io::Error::from_raw_os_error(-result);
+            return self.close(index, &format!("aborted. {e}"));
+        };
+
+        let connection = self.connection(index);
+        // The rest is sent next, as a write may be partial
+        connection.out.drain(..len);
+        if !connection.out.is_empty() {
+            self.send(index)
+        } else if connection.closing {
+            self.close(index, "closed")
+        } else {
+            self.recv(index)
+        }
+    }
+
+    fn close(&mut self, index: usize, how: &str) {
+        if let Some(connection) = self.connections[index].take() {
+            println!("Connection from {} {how}", connection.peer);
+        }
+    }
+}
+
+impl Connection {
+    /// Appends the answer to the `frame` to those to send, if it has one,
+    /// passing the operations through the middlewares of the server.
+    fn answer(&mut self, config: &ServerConfig, frame: &Frame) {
+        let tlv = frame.as_tlv();
+        let peer = self.peer;
+        match tlv.tag {
+            TlvType::Ping => match Ping::try_from(tlv) {
+                Ok(ping) => self.out.extend_from_slice(&Pong::from(ping).encode()),
+                Err(e) => eprintln!("Invalid ping from {peer}. {e}"),
+            },
+            TlvType::Hello => match Hello::try_from(tlv) {
+                Ok(hello) => {
+                    if !hello.api_key.is_empty() {
+                        eprintln!("Ignoring the API key from {peer}, as there are no tenants");
+                    }
+                    let hello = Hello {
+                        capabilities: capabilities(config),
+                        challenge: None,
+                        api_key: String::new(),
+                    };
+                    let reply = hello.encode().expect("an empty key always fits");
+                    self.out.extend_from_slice(&reply);
+                }
+                Err(e) => eprintln!("Invalid hello from {peer}. {e}"),
+            },
+            TlvType::Deadline => match Deadline::try_from(tlv) {
+                Ok(Deadline(left)) => {
+                    let left = Duration::from_millis(left.into());
+                    self.pending_deadline = Some(frame.received + left);
+                }
+                Err(e) => eprintln!("Invalid deadline from {peer}. {e}"),
+            },
+            TlvType::IdempotencyKey => match IdempotencyKey::try_from(tlv) {
+                Ok(IdempotencyKey(key)) => self.pending_key = Some(key),
+                Err(e) => eprintln!("Invalid idempotency key from {peer}. {e}"),
+            },
+            // Nothing to do with it without a queue, or with traces without a session
+            TlvType::Priority => {
+                if let Err(e) = Priority::try_from(tlv) {
+                    eprintln!("Invalid priority from {peer}. {e}");
+                }
+            }
+            TlvType::TraceContext => (),
+            // Operations are answered at once, so there is never one to cancel
+            TlvType::Cancel => eprintln!("Ignoring cancel from {peer}, not being calculated"),
+            TlvType::Batch | TlvType::Channel | TlvType::AuditQuery | TlvType::Auth => {
+                eprintln!(
+                    "Rejecting {} from {peer}: not supported with io_uring",
+                    tlv.tag.name()
+                );
+                self.pending_deadline = None;
+                self.pending_key = None;
+                self.out.extend_from_slice(&Rejection::Disabled.encode());
+            }
+            _ => {
+                let mut request = Request {
+                    peer,
+                    tlv,
+                    context: String::new(),
+                    config,
+                    tenant: None,
+                    #[cfg(feature = "auth")]
+                    key_id: None,
+                    state: &mut self.state,
+                    channel_acc: None,
+                    registers: &mut self.registers,
+                    deadline: self.pending_deadline.take(),
+                    idempotency_key: self.pending_key.take(),
+                    now: Instant::now(),
+                    work: Duration::ZERO,
+                    added: None,
+                    replayed: false,
+                };
+                let reply = middleware::chain().call(&mut request);
+                self.out.extend_from_slice(&reply);
+            }
+        }
+    }
+}
+
+/// What the server supports with `config`, beyond the basic operations.
+fn capabilities(config: &ServerConfig) -> Capabilities {
+    let mut capabilities = Capabilities::SUM_N | Capabilities::REGISTERS;
+    let disabled = |tag| config.disabled_operations.contains(&tag);
+    if disabled(TlvType::SumN) {
+        capabilities.remove(Capabilities::SUM_N);
+    }
+    if disabled(TlvType::Store) || disabled(TlvType::Load) {
+        capabilities.remove(Capabilities::REGISTERS);
+    }
+
+    capabilities
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, thread, time::Duration};
+
+    use crate::{Capabilities, Client, ClientError, Priority, Rejection, ServerConfig, TlvType};
+
+    use super::UringServer;
+
+    #[test]
+    fn serves_clients_at_once() {
+        let config = ServerConfig {
+            port: 0,
+            disabled_operations: vec![TlvType::Fact],
+            ..Default::default()
+        };
+        let mut server = match UringServer::bind(config) {
+            Ok(server) => server,
+            Err(e) => {
+                eprintln!("Skipping: no io_uring available. {e}");
+                return;
+            }
+        };
+        let addr = SocketAddr::from(([127, 0, 0, 1], server.local_addr().unwrap().port()));
+        thread::spawn(move || server.run());
+
+        let mut first = Client::connect(addr, None).unwrap();
+        let mut second = Client::connect(addr, None).unwrap();
+        assert_eq!(first.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        assert_eq!(second.compute("2 * 3".parse().unwrap()).unwrap().value, 6);
+        assert_eq!(first.compute("2 * 3".parse().unwrap()).unwrap().value, 13);
+        second.close().unwrap();
+        assert_eq!(first.compute("5 - 1".parse().unwrap()).unwrap().value, 17);
+        // Through the middlewares of the server
+        assert!(matches!(
+            first.compute("3!".parse().unwrap()),
+            Err(ClientError::Rejected(Rejection::Disabled))
+        ));
+        first.close().unwrap();
+    }
+
+    #[test]
+    fn control_frames() {
+        let config = ServerConfig {
+            port: 0,
+            disabled_operations: vec![TlvType::SumN],
+            ..Default::default()
+        };
+        let mut server = match UringServer::bind(config) {
+            Ok(server) => server,
+            Err(e) => {
+                eprintln!("Skipping: no io_uring available. {e}");
+                return;
+            }
+        };
+        let addr = SocketAddr::from(([127, 0, 0, 1], server.local_addr().unwrap().port()));
+        thread::spawn(move || server.run());
+
+        let mut client = Client::connect(addr, None).unwrap();
+        client.prioritize(Priority(3)).unwrap();
+        assert_eq!(client.hello("").unwrap(), Capabilities::REGISTERS);
+        client.set_deadline(Some(Duration::from_secs(1)));
+        // No answer falls behind, as none of them is rejected
+        assert_eq!(client.compute("3 + 4".parse().unwrap()).unwrap().value, 7);
+        assert_eq!(client.compute("2 * 3".parse().unwrap()).unwrap().value, 13);
+        client.set_deadline(Some(Duration::ZERO));
+        assert!(matches!(
+            client.compute("1 + 1".parse().unwrap()),
+            Err(ClientError::Rejected(Rejection::DeadlineExceeded))
+        ));
+        client.set_deadline(None);
+        assert_eq!(client.compute("1 + 1".parse().unwrap()).unwrap().value, 15);
+        client.close().unwrap();
+    }
+}